@@ -0,0 +1,61 @@
+// synthetic data shared by the benchmarks
+use rand::prelude::*;
+use redis_hnsw::hnsw::metrics::{metric_func, MetricFuncs};
+use redis_hnsw::hnsw::{HeapStorage, Index, Node, Storage};
+use std::sync::Arc;
+
+// clusters of the mixture, the queries are drawn from the same clusters as the nodes
+const CLUSTERS: usize = 16;
+const SPREAD: f32 = 0.1; // standard deviation of a cluster around its center
+
+// standard normal sample, Box-Muller
+fn normal(rng: &mut StdRng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON, 1.0);
+    let u2: f32 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+// n vectors from a mixture of gaussians with uniform centers, the centers only depend on dim
+pub fn gaussian_mixture(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(dim as u64);
+    let centers: Vec<Vec<f32>> = (0..CLUSTERS)
+        .map(|_| (0..dim).map(|_| rng.gen::<f32>()).collect())
+        .collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| {
+            let center = &centers[rng.gen_range(0, CLUSTERS)];
+            center
+                .iter()
+                .map(|c| c + SPREAD * normal(&mut rng))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn name(i: usize) -> String {
+    format!("node{}", i)
+}
+
+pub fn build(data: &[Vec<f32>], m: usize, ef_construction: usize) -> Index<f32, f32> {
+    build_in(Arc::new(HeapStorage), data, m, ef_construction)
+}
+
+// the same graph with its vectors in the given storage
+pub fn build_in(
+    storage: Arc<dyn Storage<f32>>,
+    data: &[Vec<f32>],
+    m: usize,
+    ef_construction: usize,
+) -> Index<f32, f32> {
+    let dim = data[0].len();
+    let mfunc = metric_func(MetricFuncs::Euclidean, dim);
+    let mut index = Index::new("bench", Box::new(mfunc), dim, m, ef_construction);
+    index.storage = storage;
+    for (i, v) in data.iter().enumerate() {
+        index
+            .add_node(&name(i), v, |_: String, _: Node<f32>| Ok(()))
+            .unwrap();
+    }
+    index
+}
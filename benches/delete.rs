@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use redis_hnsw::hnsw::Node;
+
+mod common;
+
+const NODES: usize = 2000;
+const DIM: usize = 128;
+const DELETES: usize = 100;
+
+// nodes deleted per second, every deletion reconnects the neighbors of the node
+fn delete(c: &mut Criterion) {
+    let data = common::gaussian_mixture(NODES, DIM, 1);
+    let mut group = c.benchmark_group("delete");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(DELETES as u64));
+    for m in &[8, 16, 32] {
+        let index = common::build(&data, *m, 100);
+        group.bench_with_input(BenchmarkId::new("m", m), &index, |b, index| {
+            b.iter_batched(
+                || index.snapshot().unwrap(),
+                |mut index| {
+                    for i in (0..NODES).step_by(NODES / DELETES) {
+                        index
+                            .delete_node(&common::name(i), |_: String, _: Node<f32>| Ok(()))
+                            .unwrap();
+                    }
+                    index
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, delete);
+criterion_main!(benches);
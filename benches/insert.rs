@@ -0,0 +1,26 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+mod common;
+
+const NODES: usize = 1000;
+const DIM: usize = 128;
+
+// nodes inserted per second into an empty index, neighbor selection dominates at high ef
+fn insert(c: &mut Criterion) {
+    let data = common::gaussian_mixture(NODES, DIM, 1);
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(NODES as u64));
+    for m in &[8, 16, 32] {
+        for ef_construction in &[64, 200, 400] {
+            let id = BenchmarkId::new(format!("m{}", m), format!("ef{}", ef_construction));
+            group.bench_with_input(id, &(*m, *ef_construction), |b, &(m, ef)| {
+                b.iter(|| common::build(&data, m, ef))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert);
+criterion_main!(benches);
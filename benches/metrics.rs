@@ -0,0 +1,49 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::prelude::*;
+use redis_hnsw::hnsw::metrics::{metric_func, sim_func_euc, sim_func_ip, MetricFuncs};
+
+const DIMS: [usize; 4] = [32, 128, 768, 1536];
+
+fn vector(dim: usize, rng: &mut StdRng) -> Vec<f32> {
+    (0..dim).map(|_| rng.gen::<f32>()).collect()
+}
+
+// the scalar kernel before its sums were split per lane, for comparison
+fn sequential_euc(a: &[f32], b: &[f32], _n: usize) -> f32 {
+    -a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .fold(0.0, |acc, x| acc + x)
+}
+
+// distance throughput of the scalar kernels against a single running sum and the kernel
+// selected for this cpu
+fn kernels(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut group = c.benchmark_group("metrics");
+    for &dim in &DIMS {
+        let (a, b) = (vector(dim, &mut rng), vector(dim, &mut rng));
+        let kernels = [
+            (
+                "euclidean_sequential",
+                sequential_euc as fn(&[f32], &[f32], usize) -> f32,
+            ),
+            ("euclidean_scalar", sim_func_euc),
+            (
+                "euclidean_selected",
+                metric_func(MetricFuncs::Euclidean, dim),
+            ),
+            ("ip_scalar", sim_func_ip),
+            ("ip_selected", metric_func(MetricFuncs::InnerProduct, dim)),
+        ];
+        for (name, kernel) in kernels.iter() {
+            group.bench_with_input(BenchmarkId::new(*name, dim), &dim, |bench, &n| {
+                bench.iter(|| kernel(black_box(&a), black_box(&b), n))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, kernels);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis_hnsw::hnsw::{SlabStorage, StorageKind};
+use std::sync::Arc;
+
+mod common;
+
+const NODES: usize = 10000;
+const QUERIES: usize = 100;
+
+// query latency at large dims with the vectors on the heap or in an arena, run once as is and
+// once with `--features prefetch` to compare
+fn prefetch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prefetch");
+    group.throughput(Throughput::Elements(QUERIES as u64));
+    let feature = if cfg!(feature = "prefetch") {
+        "prefetch"
+    } else {
+        "none"
+    };
+    for dim in &[768, 1536] {
+        let data = common::gaussian_mixture(NODES, *dim, 1);
+        let queries = common::gaussian_mixture(QUERIES, *dim, 2);
+        let indexes = vec![
+            (StorageKind::Heap, common::build(&data, 16, 64)),
+            (
+                StorageKind::Arena,
+                common::build_in(Arc::new(SlabStorage::arena(*dim)), &data, 16, 64),
+            ),
+        ];
+        for (kind, index) in &indexes {
+            let id = BenchmarkId::new(format!("dim{}/{}", dim, feature), kind.as_str());
+            group.bench_with_input(id, &queries, |b, q| {
+                b.iter(|| {
+                    for query in q {
+                        index.search_knn(query, 10).unwrap();
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, prefetch);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+mod common;
+
+const NODES: usize = 5000;
+const QUERIES: usize = 100;
+
+// query latency by dimension, search ef and k, the graph of each dimension is built once
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    group.throughput(Throughput::Elements(QUERIES as u64));
+    for dim in &[32, 128, 512] {
+        let data = common::gaussian_mixture(NODES, *dim, 1);
+        let queries = common::gaussian_mixture(QUERIES, *dim, 2);
+        let mut index = common::build(&data, 16, 100);
+        for ef in &[16, 64, 256] {
+            // searches use the ef of the index
+            index.ef_construction = *ef;
+            for k in &[1, 10, 100] {
+                let id = BenchmarkId::new(format!("dim{}/ef{}", dim, ef), format!("k{}", k));
+                group.bench_with_input(id, k, |b, &k| {
+                    b.iter(|| {
+                        for query in &queries {
+                            index.search_knn(query, k).unwrap();
+                        }
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
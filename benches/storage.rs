@@ -0,0 +1,57 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::prelude::*;
+use redis_hnsw::hnsw::metrics::{metric_func, MetricFuncs};
+use redis_hnsw::hnsw::{HeapStorage, Index, Node, SlabStorage, Storage, StorageKind};
+use std::sync::Arc;
+
+const NODES: usize = 10000;
+const DIM: usize = 128;
+const QUERIES: usize = 100;
+
+fn vectors(n: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| (0..DIM).map(|_| rng.gen::<f32>()).collect())
+        .collect()
+}
+
+fn build(storage: Arc<dyn Storage<f32>>, data: &[Vec<f32>]) -> Index<f32, f32> {
+    let mfunc = metric_func(MetricFuncs::Euclidean, DIM);
+    let mut index = Index::new("bench", Box::new(mfunc), DIM, 16, 100);
+    index.storage = storage;
+    for (i, v) in data.iter().enumerate() {
+        index
+            .add_node(&format!("node{}", i), v, |_: String, _: Node<f32>| Ok(()))
+            .unwrap();
+    }
+    index
+}
+
+// search throughput of the same graph with its vectors on the heap or in an arena
+fn search(c: &mut Criterion) {
+    let data = vectors(NODES, 1);
+    let queries = vectors(QUERIES, 2);
+    let mut group = c.benchmark_group("search");
+    let storages: Vec<(StorageKind, Arc<dyn Storage<f32>>)> = vec![
+        (StorageKind::Heap, Arc::new(HeapStorage)),
+        (StorageKind::Arena, Arc::new(SlabStorage::arena(DIM))),
+    ];
+    for (kind, storage) in storages {
+        let index = build(storage, &data);
+        group.bench_with_input(
+            BenchmarkId::new("knn10", kind.as_str()),
+            &queries,
+            |b, q| {
+                b.iter(|| {
+                    for query in q {
+                        index.search_knn(query, 10).unwrap();
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
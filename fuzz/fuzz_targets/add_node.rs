@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// arguments of the command separated by nul bytes
+fuzz_target!(|data: &[u8]| {
+    redis_hnsw::fuzzing::add_node(data);
+});
@@ -0,0 +1,116 @@
+use redis_module::{raw, Context, LogLevel};
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::{mem, ptr};
+
+type AddACLCategoryFn = unsafe extern "C" fn(*mut raw::RedisModuleCtx, *const c_char) -> c_int;
+pub(crate) type GetCommandFn =
+    unsafe extern "C" fn(*mut raw::RedisModuleCtx, *const c_char) -> *mut c_void;
+type SetCommandACLCategoriesFn = unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int;
+
+// custom acl categories, e.g. `ACL SETUSER app +@hnsw-read`
+static CATEGORIES: [&str; 3] = ["hnsw-read", "hnsw-write", "hnsw-admin"];
+
+// category of every module command, keep in sync with create_commands
+static COMMAND_CATEGORIES: [(&str, &str); 45] = [
+    ("hnsw.new", "hnsw-admin"),
+    ("hnsw.get", "hnsw-read"),
+    ("hnsw.list", "hnsw-read"),
+    ("hnsw.exists", "hnsw-read"),
+    ("hnsw.card", "hnsw-read"),
+    ("hnsw.layercard", "hnsw-read"),
+    ("hnsw.stats", "hnsw-read"),
+    ("hnsw.debug.graph", "hnsw-read"),
+    ("hnsw.alter", "hnsw-admin"),
+    ("hnsw.projection.set", "hnsw-admin"),
+    ("hnsw.train.pca", "hnsw-admin"),
+    ("hnsw.setenterpoint", "hnsw-admin"),
+    ("hnsw.follow", "hnsw-admin"),
+    ("hnsw.unfollow", "hnsw-admin"),
+    ("hnsw.freeze", "hnsw-admin"),
+    ("hnsw.unfreeze", "hnsw-admin"),
+    ("hnsw.del", "hnsw-admin"),
+    ("hnsw.replicateto", "hnsw-admin"),
+    ("hnsw.migrate", "hnsw-admin"),
+    ("hnsw.reindex", "hnsw-admin"),
+    ("hnsw.reindex.status", "hnsw-read"),
+    ("hnsw.warmup", "hnsw-admin"),
+    ("hnsw.search", "hnsw-read"),
+    ("hnsw.search.composite", "hnsw-read"),
+    ("hnsw.search.eachof", "hnsw-write"),
+    ("hnsw.export.knngraph", "hnsw-write"),
+    ("hnsw.dedup", "hnsw-read"),
+    ("hnsw.cluster", "hnsw-admin"),
+    ("hnsw.autotune", "hnsw-admin"),
+    ("hnsw.centroid", "hnsw-read"),
+    ("hnsw.node.add", "hnsw-write"),
+    ("hnsw.node.addfrom", "hnsw-write"),
+    ("hnsw.node.update", "hnsw-write"),
+    ("hnsw.node.get", "hnsw-read"),
+    ("hnsw.node.exists", "hnsw-read"),
+    ("hnsw.node.del", "hnsw-write"),
+    ("hnsw.node.mdel", "hnsw-write"),
+    ("hnsw.node.delpattern", "hnsw-write"),
+    ("hnsw.cursor.read", "hnsw-read"),
+    ("hnsw.cursor.del", "hnsw-read"),
+    ("hnsw.selftest", "hnsw-admin"),
+    ("hnsw.config", "hnsw-admin"),
+    ("hnsw.help", "hnsw-read"),
+    ("hnsw.alias", "hnsw-admin"),
+    ("hnsw.jobs", "hnsw-admin"),
+];
+
+// resolves a module api function the bindings don't expose, None if the server lacks it
+pub(crate) unsafe fn get_api<F: Copy>(name: &str) -> Option<F> {
+    let name = CString::new(name).unwrap();
+    let mut func: *mut c_void = ptr::null_mut();
+    let res = raw::RedisModule_GetApi?(name.as_ptr(), &mut func as *mut _ as *mut c_void);
+    if res != raw::REDISMODULE_OK as c_int || func.is_null() {
+        return None;
+    }
+    Some(mem::transmute_copy(&func))
+}
+
+// registers the categories on servers supporting custom acl categories (redis 7.4+),
+// must run while the module is loading
+pub fn register_categories(ctx: &Context) {
+    unsafe {
+        let add_category = get_api::<AddACLCategoryFn>("RedisModule_AddACLCategory");
+        let get_command = get_api::<GetCommandFn>("RedisModule_GetCommand");
+        let set_categories =
+            get_api::<SetCommandACLCategoriesFn>("RedisModule_SetCommandACLCategories");
+        let (add_category, get_command, set_categories) =
+            match (add_category, get_command, set_categories) {
+                (Some(a), Some(g), Some(s)) => (a, g, s),
+                _ => {
+                    ctx.log_debug("acl categories are not supported by this server");
+                    return;
+                }
+            };
+
+        for category in &CATEGORIES {
+            let c = CString::new(*category).unwrap();
+            if add_category(ctx.ctx, c.as_ptr()) != raw::REDISMODULE_OK as c_int {
+                ctx.log(
+                    LogLevel::Warning,
+                    format!("unable to add acl category: {}", category).as_str(),
+                );
+            }
+        }
+
+        for (name, category) in &COMMAND_CATEGORIES {
+            let n = CString::new(*name).unwrap();
+            let command = get_command(ctx.ctx, n.as_ptr());
+            let c = CString::new(*category).unwrap();
+            if command.is_null()
+                || set_categories(command, c.as_ptr()) != raw::REDISMODULE_OK as c_int
+            {
+                ctx.log(
+                    LogLevel::Warning,
+                    format!("unable to set acl category of: {}", name).as_str(),
+                );
+            }
+        }
+    }
+}
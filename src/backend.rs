@@ -0,0 +1,293 @@
+use crate::types::{IndexRedis, NodeRedis, HNSW_INDEX_REDIS_TYPE, HNSW_NODE_REDIS_TYPE};
+
+use redis_module::{raw, Context, LogLevel, RedisError};
+use std::os::raw::c_int;
+
+// keyspace and log the commands run against, the redis context of a command or an
+// in-memory keyspace in tests. the value methods return false if the key holds no value
+// and fail if it holds a value of another type
+pub trait Backend {
+    fn db(&self) -> c_int; // selected database
+    fn flags(&self) -> u32; // REDISMODULE_CTX_FLAGS_* of the context
+    fn log(&self, level: LogLevel, message: &str);
+    fn exists(&self, key: &str) -> bool;
+    fn with_index(&self, key: &str, f: &mut dyn FnMut(&IndexRedis)) -> Result<bool, RedisError>;
+    fn with_index_mut(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(&mut IndexRedis),
+    ) -> Result<bool, RedisError>;
+    fn with_node(&self, key: &str, f: &mut dyn FnMut(&NodeRedis)) -> Result<bool, RedisError>;
+    fn with_node_mut(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(&mut NodeRedis),
+    ) -> Result<bool, RedisError>;
+    fn set_index(&self, key: &str, value: IndexRedis) -> Result<(), RedisError>;
+    fn set_node(&self, key: &str, value: NodeRedis) -> Result<(), RedisError>;
+    fn delete(&self, key: &str) -> Result<(), RedisError>;
+}
+
+// value of f on the value at the key, None if the key holds no value
+impl dyn Backend + '_ {
+    pub fn index<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&IndexRedis) -> R,
+    ) -> Result<Option<R>, RedisError> {
+        let (mut f, mut res) = (Some(f), None);
+        self.with_index(key, &mut |v| res = f.take().map(|f| f(v)))?;
+        Ok(res)
+    }
+
+    pub fn index_mut<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut IndexRedis) -> R,
+    ) -> Result<Option<R>, RedisError> {
+        let (mut f, mut res) = (Some(f), None);
+        self.with_index_mut(key, &mut |v| res = f.take().map(|f| f(v)))?;
+        Ok(res)
+    }
+
+    pub fn node<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&NodeRedis) -> R,
+    ) -> Result<Option<R>, RedisError> {
+        let (mut f, mut res) = (Some(f), None);
+        self.with_node(key, &mut |v| res = f.take().map(|f| f(v)))?;
+        Ok(res)
+    }
+
+    pub fn node_mut<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut NodeRedis) -> R,
+    ) -> Result<Option<R>, RedisError> {
+        let (mut f, mut res) = (Some(f), None);
+        self.with_node_mut(key, &mut |v| res = f.take().map(|f| f(v)))?;
+        Ok(res)
+    }
+}
+
+impl Backend for Context {
+    fn db(&self) -> c_int {
+        unsafe { raw::RedisModule_GetSelectedDb.unwrap()(self.ctx) }
+    }
+
+    fn flags(&self) -> u32 {
+        unsafe { raw::RedisModule_GetContextFlags.unwrap()(self.ctx) as u32 }
+    }
+
+    fn log(&self, level: LogLevel, message: &str) {
+        Context::log(self, level, message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        !self.open_key(key).is_null()
+    }
+
+    fn with_index(&self, key: &str, f: &mut dyn FnMut(&IndexRedis)) -> Result<bool, RedisError> {
+        let rkey = self.open_key(key);
+        let value = rkey.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)?;
+        Ok(value.map(f).is_some())
+    }
+
+    fn with_index_mut(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(&mut IndexRedis),
+    ) -> Result<bool, RedisError> {
+        let rkey = self.open_key_writable(key);
+        let value = rkey.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)?;
+        Ok(value.map(f).is_some())
+    }
+
+    fn with_node(&self, key: &str, f: &mut dyn FnMut(&NodeRedis)) -> Result<bool, RedisError> {
+        let rkey = self.open_key(key);
+        let value = rkey.get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)?;
+        Ok(value.map(f).is_some())
+    }
+
+    fn with_node_mut(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(&mut NodeRedis),
+    ) -> Result<bool, RedisError> {
+        let rkey = self.open_key_writable(key);
+        let value = rkey.get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)?;
+        Ok(value.map(f).is_some())
+    }
+
+    fn set_index(&self, key: &str, value: IndexRedis) -> Result<(), RedisError> {
+        self.open_key_writable(key)
+            .set_value(&HNSW_INDEX_REDIS_TYPE, value)
+    }
+
+    fn set_node(&self, key: &str, value: NodeRedis) -> Result<(), RedisError> {
+        self.open_key_writable(key)
+            .set_value(&HNSW_NODE_REDIS_TYPE, value)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), RedisError> {
+        self.open_key_writable(key).delete()?;
+        Ok(())
+    }
+}
+
+// in-memory keyspace of the command tests
+#[cfg(test)]
+pub mod memory {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    enum Value {
+        Index(Box<IndexRedis>),
+        Node(NodeRedis),
+        Other, // a key of another type, e.g. a string
+    }
+
+    // every keyspace of the tests is a database of its own, so the indexes loaded by
+    // concurrent tests don't share cache entries
+    static NEXT_DB: AtomicI32 = AtomicI32::new(1000);
+
+    pub struct MemoryBackend {
+        db: c_int,
+        pub flags: Cell<u32>,
+        // key whose deletion fails, e.g. to interrupt a change halfway
+        pub failing_delete: RefCell<Option<String>>,
+        keys: RefCell<HashMap<String, Rc<RefCell<Value>>>>,
+    }
+
+    impl MemoryBackend {
+        pub fn new() -> Self {
+            MemoryBackend {
+                db: NEXT_DB.fetch_add(1, Ordering::Relaxed),
+                flags: Cell::new(0),
+                failing_delete: RefCell::new(None),
+                keys: RefCell::new(HashMap::new()),
+            }
+        }
+
+        // stores a key of a type the module doesn't own
+        pub fn set_other(&self, key: &str) {
+            let value = Rc::new(RefCell::new(Value::Other));
+            self.keys.borrow_mut().insert(key.to_owned(), value);
+        }
+
+        pub fn keys(&self) -> Vec<String> {
+            let mut keys = self.keys.borrow().keys().cloned().collect::<Vec<String>>();
+            keys.sort();
+            keys
+        }
+
+        // the map is released before f runs, f may open other keys
+        fn value(&self, key: &str) -> Option<Rc<RefCell<Value>>> {
+            self.keys.borrow().get(key).cloned()
+        }
+
+        fn set(&self, key: &str, value: Value) {
+            let value = Rc::new(RefCell::new(value));
+            self.keys.borrow_mut().insert(key.to_owned(), value);
+        }
+    }
+
+    fn wrong_type() -> RedisError {
+        RedisError::Str("Existing key has wrong Redis type")
+    }
+
+    impl Backend for MemoryBackend {
+        fn db(&self) -> c_int {
+            self.db
+        }
+
+        fn flags(&self) -> u32 {
+            self.flags.get()
+        }
+
+        fn log(&self, _level: LogLevel, _message: &str) {}
+
+        fn exists(&self, key: &str) -> bool {
+            self.keys.borrow().contains_key(key)
+        }
+
+        // reads share the value, a command may read a key while reading another one
+        fn with_index(
+            &self,
+            key: &str,
+            f: &mut dyn FnMut(&IndexRedis),
+        ) -> Result<bool, RedisError> {
+            match self.value(key) {
+                Some(value) => match &*value.borrow() {
+                    Value::Index(ir) => f(ir),
+                    _ => return Err(wrong_type()),
+                },
+                None => return Ok(false),
+            }
+            Ok(true)
+        }
+
+        fn with_index_mut(
+            &self,
+            key: &str,
+            f: &mut dyn FnMut(&mut IndexRedis),
+        ) -> Result<bool, RedisError> {
+            match self.value(key) {
+                Some(value) => match &mut *value.borrow_mut() {
+                    Value::Index(ir) => f(ir),
+                    _ => return Err(wrong_type()),
+                },
+                None => return Ok(false),
+            }
+            Ok(true)
+        }
+
+        fn with_node(&self, key: &str, f: &mut dyn FnMut(&NodeRedis)) -> Result<bool, RedisError> {
+            match self.value(key) {
+                Some(value) => match &*value.borrow() {
+                    Value::Node(nr) => f(nr),
+                    _ => return Err(wrong_type()),
+                },
+                None => return Ok(false),
+            }
+            Ok(true)
+        }
+
+        fn with_node_mut(
+            &self,
+            key: &str,
+            f: &mut dyn FnMut(&mut NodeRedis),
+        ) -> Result<bool, RedisError> {
+            match self.value(key) {
+                Some(value) => match &mut *value.borrow_mut() {
+                    Value::Node(nr) => f(nr),
+                    _ => return Err(wrong_type()),
+                },
+                None => return Ok(false),
+            }
+            Ok(true)
+        }
+
+        fn set_index(&self, key: &str, value: IndexRedis) -> Result<(), RedisError> {
+            self.set(key, Value::Index(Box::new(value)));
+            Ok(())
+        }
+
+        fn set_node(&self, key: &str, value: NodeRedis) -> Result<(), RedisError> {
+            self.set(key, Value::Node(value));
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> Result<(), RedisError> {
+            if self.failing_delete.borrow().as_deref() == Some(key) {
+                return Err(RedisError::Str("delete failed"));
+            }
+            self.keys.borrow_mut().remove(key);
+            Ok(())
+        }
+    }
+}
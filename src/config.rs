@@ -0,0 +1,243 @@
+use crate::backend::Backend;
+use crate::hnsw::KeyLayout;
+
+use redis_module::{LogLevel, RedisError, RedisValue};
+
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+// redis log levels from most to least verbose
+static LOG_LEVELS: [&str; 4] = ["debug", "verbose", "notice", "warning"];
+
+// index into LOG_LEVELS of the least severe level the module logs, notice by default
+// like redis, so debug messages aren't even formatted
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(2);
+
+// vectors of nodes are zstd compressed in RDB, only with the zstd feature
+static RDB_COMPRESSION: AtomicBool = AtomicBool::new(false);
+
+// new indexes use the dotted key layout of older versions instead of the tagged one
+static DOTTED_KEYS: AtomicBool = AtomicBool::new(false);
+
+// directory of the vector files of indexes with mmap storage, the working directory if empty
+static MMAP_DIR: RwLock<String> = RwLock::new(String::new());
+
+// housekeeping tasks of the maintenance timer, see maintenance.rs
+pub static MAINTENANCE_TASKS: [&str; 5] = ["cursors", "snapshots", "cache", "stats", "writes"];
+
+// milliseconds between maintenance runs, 0 disables them
+static MAINTENANCE_INTERVAL: AtomicU64 = AtomicU64::new(1000);
+
+// bit per entry of MAINTENANCE_TASKS, all enabled by default
+static MAINTENANCE_ENABLED: AtomicUsize = AtomicUsize::new(0b11111);
+
+// milliseconds the node keys of neighbors changed by adds may lag the graph, 0 writes them
+// with every add
+static WRITE_WINDOW: AtomicU64 = AtomicU64::new(0);
+
+fn severity(level: &LogLevel) -> usize {
+    match level {
+        LogLevel::Debug => 0,
+        LogLevel::Verbose => 1,
+        LogLevel::Notice => 2,
+        LogLevel::Warning => 3,
+    }
+}
+
+pub fn log_enabled(level: &LogLevel) -> bool {
+    severity(level) >= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn rdb_compression() -> bool {
+    RDB_COMPRESSION.load(Ordering::Relaxed)
+}
+
+// key layout of new indexes, existing ones keep theirs until migrated
+pub fn key_layout() -> KeyLayout {
+    match DOTTED_KEYS.load(Ordering::Relaxed) {
+        true => KeyLayout::Dotted,
+        false => KeyLayout::Tagged,
+    }
+}
+
+pub fn mmap_dir() -> String {
+    match MMAP_DIR.read() {
+        Ok(dir) => dir.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+pub fn maintenance_interval() -> Option<Duration> {
+    match MAINTENANCE_INTERVAL.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+pub fn write_window() -> Option<Duration> {
+    match WRITE_WINDOW.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+pub fn maintenance_enabled(task: &str) -> bool {
+    let enabled = MAINTENANCE_ENABLED.load(Ordering::Relaxed);
+    MAINTENANCE_TASKS
+        .iter()
+        .position(|t| *t == task)
+        .is_some_and(|i| enabled & (1 << i) != 0)
+}
+
+fn maintenance_tasks() -> String {
+    let tasks = MAINTENANCE_TASKS
+        .iter()
+        .filter(|t| maintenance_enabled(t))
+        .copied()
+        .collect::<Vec<&str>>();
+    match tasks.is_empty() {
+        true => "none".to_owned(),
+        false => tasks.join(","),
+    }
+}
+
+// logs `event={event} key=value ...`, the fields are only formatted if the level is enabled
+pub fn log_event(ctx: &dyn Backend, level: LogLevel, event: &str, fields: &[(&str, &dyn Display)]) {
+    if !log_enabled(&level) {
+        return;
+    }
+    let mut msg = format!("event={}", event);
+    for (key, value) in fields {
+        let _ = write!(msg, " {}={}", key, value);
+    }
+    ctx.log(level, &msg);
+}
+
+// every setting, they are saved in RDB files and restored on load
+pub static SETTINGS: [&str; 7] = [
+    "loglevel",
+    "rdbcompression",
+    "keylayout",
+    "mmapdir",
+    "maintenanceinterval",
+    "maintenancetasks",
+    "writewindow",
+];
+
+fn value(name: &str) -> Option<String> {
+    match name.to_ascii_lowercase().as_str() {
+        "loglevel" => Some(LOG_LEVELS[LOG_LEVEL.load(Ordering::Relaxed)].to_owned()),
+        "rdbcompression" => Some(if rdb_compression() { "zstd" } else { "none" }.to_owned()),
+        "keylayout" => Some(key_layout().as_str().to_owned()),
+        "mmapdir" => Some(mmap_dir()),
+        "maintenanceinterval" => Some(MAINTENANCE_INTERVAL.load(Ordering::Relaxed).to_string()),
+        "maintenancetasks" => Some(maintenance_tasks()),
+        "writewindow" => Some(WRITE_WINDOW.load(Ordering::Relaxed).to_string()),
+        _ => None,
+    }
+}
+
+pub fn get(name: &str) -> Result<RedisValue, RedisError> {
+    match value(name) {
+        Some(value) => Ok(value.into()),
+        None => Err(format!("Unknown config: {}", name).into()),
+    }
+}
+
+// names and values of all settings
+pub fn settings() -> Vec<(&'static str, String)> {
+    SETTINGS
+        .iter()
+        .filter_map(|name| Some((*name, value(name)?)))
+        .collect()
+}
+
+pub fn set(name: &str, value: &str) -> Result<(), String> {
+    match name.to_ascii_lowercase().as_str() {
+        "loglevel" => {
+            let level = LOG_LEVELS
+                .iter()
+                .position(|l| l.eq_ignore_ascii_case(value))
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid loglevel: {}, expected one of {}",
+                        value,
+                        LOG_LEVELS.join(", ")
+                    )
+                })?;
+            LOG_LEVEL.store(level, Ordering::Relaxed);
+            Ok(())
+        }
+        "rdbcompression" => {
+            let compress = match value.to_ascii_lowercase().as_str() {
+                "none" => false,
+                "zstd" if cfg!(feature = "zstd") => true,
+                "zstd" => return Err("zstd needs a module built with the zstd feature".to_owned()),
+                _ => {
+                    return Err(format!(
+                        "Invalid rdbcompression: {}, expected none or zstd",
+                        value
+                    ))
+                }
+            };
+            RDB_COMPRESSION.store(compress, Ordering::Relaxed);
+            Ok(())
+        }
+        "keylayout" => {
+            let layout = KeyLayout::parse(value).ok_or_else(|| {
+                format!("Invalid keylayout: {}, expected tagged or dotted", value)
+            })?;
+            DOTTED_KEYS.store(layout == KeyLayout::Dotted, Ordering::Relaxed);
+            Ok(())
+        }
+        // only read when a vector file is created, existing ones stay where they are
+        "mmapdir" => {
+            match MMAP_DIR.write() {
+                Ok(mut dir) => *dir = value.to_owned(),
+                Err(e) => *e.into_inner() = value.to_owned(),
+            }
+            Ok(())
+        }
+        "maintenanceinterval" => {
+            let ms = value.parse::<u64>().map_err(|_| {
+                format!(
+                    "Invalid maintenanceinterval: {}, expected milliseconds",
+                    value
+                )
+            })?;
+            MAINTENANCE_INTERVAL.store(ms, Ordering::Relaxed);
+            Ok(())
+        }
+        "maintenancetasks" => {
+            let mut enabled = 0;
+            for task in value.split(',').map(|t| t.trim().to_ascii_lowercase()) {
+                if task == "none" || task.is_empty() {
+                    continue;
+                }
+                let i = MAINTENANCE_TASKS
+                    .iter()
+                    .position(|t| *t == task)
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid maintenancetasks: {}, expected none or some of {}",
+                            value,
+                            MAINTENANCE_TASKS.join(",")
+                        )
+                    })?;
+                enabled |= 1 << i;
+            }
+            MAINTENANCE_ENABLED.store(enabled, Ordering::Relaxed);
+            Ok(())
+        }
+        "writewindow" => {
+            let ms = value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid writewindow: {}, expected milliseconds", value))?;
+            WRITE_WINDOW.store(ms, Ordering::Relaxed);
+            Ok(())
+        }
+        _ => Err(format!("Unknown config: {}", name)),
+    }
+}
@@ -0,0 +1,231 @@
+use redis_module::native_types::RedisType;
+use redis_module::{raw, Context};
+
+use std::ffi::CString;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::ptr;
+
+use super::acl::get_api;
+use super::hnsw::Relocate;
+use super::types::{IndexRedis, NodeRedis};
+
+// type method version of redis 6.2 adding free_effort, unlink, copy and defrag. older servers
+// only read the fields of the versions they know
+const TYPE_METHOD_VERSION: u64 = 3;
+
+#[repr(C)]
+pub struct DefragCtx {
+    _private: [u8; 0],
+}
+
+type FreeEffortFn =
+    unsafe extern "C" fn(key: *mut raw::RedisModuleString, value: *const c_void) -> usize;
+type UnlinkFn = unsafe extern "C" fn(key: *mut raw::RedisModuleString, value: *const c_void);
+type CopyFn = unsafe extern "C" fn(
+    from: *mut raw::RedisModuleString,
+    to: *mut raw::RedisModuleString,
+    value: *const c_void,
+) -> *mut c_void;
+type DefragFn = unsafe extern "C" fn(
+    ctx: *mut DefragCtx,
+    key: *mut raw::RedisModuleString,
+    value: *mut *mut c_void,
+) -> c_int;
+type DefragAllocFn = unsafe extern "C" fn(*mut DefragCtx, *mut c_void) -> *mut c_void;
+type DefragShouldStopFn = unsafe extern "C" fn(*mut DefragCtx) -> c_int;
+type DefragCursorSetFn = unsafe extern "C" fn(*mut DefragCtx, c_ulong) -> c_int;
+type DefragCursorGetFn = unsafe extern "C" fn(*mut DefragCtx, *mut c_ulong) -> c_int;
+
+// RedisModuleTypeMethods of version 3, the bindings in use stop at version 2
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TypeMethods {
+    pub base: raw::RedisModuleTypeMethods,
+    pub free_effort: Option<FreeEffortFn>,
+    pub unlink: Option<UnlinkFn>,
+    pub copy: Option<CopyFn>,
+    pub defrag: Option<DefragFn>,
+}
+
+impl TypeMethods {
+    pub const fn new(base: raw::RedisModuleTypeMethods) -> Self {
+        TypeMethods {
+            base: raw::RedisModuleTypeMethods {
+                version: TYPE_METHOD_VERSION,
+                ..base
+            },
+            free_effort: None,
+            unlink: None,
+            copy: None,
+            defrag: None,
+        }
+    }
+}
+
+// creates the type with the extended methods instead of RedisType::create_data_type, which
+// only knows the methods of the bindings. must run while the module is loading
+pub fn create_data_type(
+    ctx: &Context,
+    redis_type: &RedisType,
+    name: &str,
+    version: c_int,
+    methods: &TypeMethods,
+) -> Result<(), String> {
+    let type_name = CString::new(name).unwrap();
+    let raw_type = unsafe {
+        raw::RedisModule_CreateDataType.unwrap()(
+            ctx.ctx,
+            type_name.as_ptr(),
+            version,
+            methods as *const TypeMethods as *mut raw::RedisModuleTypeMethods,
+        )
+    };
+    if raw_type.is_null() {
+        return Err(format!("unable to create data type: {}", name));
+    }
+    *redis_type.raw_type.borrow_mut() = raw_type;
+    Ok(())
+}
+
+// moves buffers with RedisModule_DefragAlloc, the module allocates through the redis allocator
+pub struct Defrag {
+    ctx: *mut DefragCtx,
+    alloc: DefragAllocFn,
+    should_stop: Option<DefragShouldStopFn>,
+}
+
+impl Defrag {
+    unsafe fn new(ctx: *mut DefragCtx) -> Option<Self> {
+        Some(Defrag {
+            ctx,
+            alloc: get_api::<DefragAllocFn>("RedisModule_DefragAlloc")?,
+            should_stop: get_api::<DefragShouldStopFn>("RedisModule_DefragShouldStop"),
+        })
+    }
+
+    // moves the boxed value of a key, the old allocation is freed if it moved
+    unsafe fn relocate_value(&mut self, value: *mut *mut c_void) {
+        let moved = (self.alloc)(self.ctx, *value);
+        if !moved.is_null() {
+            *value = moved;
+        }
+    }
+
+    // cursor of a defrag resumed for a large value, 0 when it starts
+    unsafe fn cursor(&self) -> usize {
+        let mut cursor: c_ulong = 0;
+        match get_api::<DefragCursorGetFn>("RedisModule_DefragCursorGet") {
+            Some(get) if get(self.ctx, &mut cursor) == raw::REDISMODULE_OK as c_int => {
+                cursor as usize
+            }
+            _ => 0,
+        }
+    }
+
+    unsafe fn set_cursor(&self, cursor: usize) {
+        if let Some(set) = get_api::<DefragCursorSetFn>("RedisModule_DefragCursorSet") {
+            set(self.ctx, cursor as c_ulong);
+        }
+    }
+}
+
+impl Relocate for Defrag {
+    fn relocate<V>(&mut self, buf: &mut Vec<V>) {
+        if buf.capacity() == 0 || std::mem::size_of::<V>() == 0 {
+            return;
+        }
+        unsafe {
+            let moved = (self.alloc)(self.ctx, buf.as_mut_ptr() as *mut c_void) as *mut V;
+            if !moved.is_null() {
+                // the old buffer was freed by the move, it must not be dropped
+                ptr::write(buf, Vec::from_raw_parts(moved, buf.len(), buf.capacity()));
+            }
+        }
+    }
+
+    fn should_stop(&mut self) -> bool {
+        self.should_stop
+            .is_some_and(|stop| unsafe { stop(self.ctx) != 0 })
+    }
+}
+
+// redis defragments values above active-defrag-max-scan-fields in steps, calling defrag again
+// while it returns 1. the loaded graph costs an allocation per node
+pub unsafe extern "C" fn free_effort_index(
+    _key: *mut raw::RedisModuleString,
+    value: *const c_void,
+) -> usize {
+    (*(value as *const IndexRedis)).node_count
+}
+
+pub unsafe extern "C" fn defrag_index(
+    ctx: *mut DefragCtx,
+    _key: *mut raw::RedisModuleString,
+    value: *mut *mut c_void,
+) -> c_int {
+    let mut defrag = match Defrag::new(ctx) {
+        Some(d) => d,
+        None => return 0,
+    };
+    let cursor = defrag.cursor();
+    if cursor == 0 {
+        defrag.relocate_value(value);
+        let index = &mut *(*value as *mut IndexRedis);
+        defrag.relocate_str(&mut index.name);
+        for f in index.fields.iter_mut() {
+            defrag.relocate_str(f);
+        }
+        defrag.relocate(&mut index.fields);
+        defrag.relocate(&mut index.projection);
+        defrag.relocate(&mut index.projection_offset);
+        for c in index.centroids.iter_mut() {
+            defrag.relocate(c);
+        }
+        defrag.relocate(&mut index.centroids);
+    }
+
+    // the node map can't be moved in place, the loaded graph holds most of the memory
+    let index = &*(*value as *mut IndexRedis);
+    match super::relocate_graph(index.registry_id, cursor, &mut defrag) {
+        Some(next) => {
+            defrag.set_cursor(next);
+            1
+        }
+        None => 0,
+    }
+}
+
+pub unsafe extern "C" fn defrag_node(
+    ctx: *mut DefragCtx,
+    _key: *mut raw::RedisModuleString,
+    value: *mut *mut c_void,
+) -> c_int {
+    let mut defrag = match Defrag::new(ctx) {
+        Some(d) => d,
+        None => return 0,
+    };
+    defrag.relocate_value(value);
+    let node = &mut *(*value as *mut NodeRedis);
+    defrag.relocate(&mut node.data);
+    for v in node.vectors.iter_mut() {
+        defrag.relocate(v);
+    }
+    defrag.relocate(&mut node.vectors);
+    defrag.relocate(&mut node.norms);
+    for layer in node.neighbors.iter_mut() {
+        defrag.relocate(layer);
+    }
+    defrag.relocate(&mut node.neighbors);
+    for layer in node.neighbor_names.iter_mut() {
+        for n in layer.iter_mut() {
+            defrag.relocate_str(n);
+        }
+        defrag.relocate(layer);
+    }
+    defrag.relocate(&mut node.neighbor_names);
+    for t in node.tags.iter_mut() {
+        defrag.relocate_str(t);
+    }
+    defrag.relocate(&mut node.tags);
+    0
+}
@@ -0,0 +1,321 @@
+use crate::acl::{get_api, GetCommandFn};
+
+use redis_module::{raw, Context, LogLevel};
+use redismodule_cmd::{Arg, ArgType, Collection, Command};
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::{mem, ptr};
+
+type SetCommandInfoFn = unsafe extern "C" fn(*mut c_void, *const CommandInfo) -> c_int;
+
+// RedisModuleCommandArgType
+pub const ARG_STRING: c_int = 0;
+pub const ARG_INTEGER: c_int = 1;
+pub const ARG_DOUBLE: c_int = 2;
+pub const ARG_KEY: c_int = 3;
+pub const ARG_PURE_TOKEN: c_int = 6;
+pub const ARG_BLOCK: c_int = 8;
+
+// REDISMODULE_CMD_ARG_*
+pub const ARG_OPTIONAL: c_int = 1;
+pub const ARG_MULTIPLE: c_int = 1 << 1;
+
+// REDISMODULE_CMD_KEY_NOT_KEY, routed in a cluster like a key without being one
+const KEY_NOT_KEY: u64 = 1 << 8;
+// REDISMODULE_KSPEC_BS_INDEX and REDISMODULE_KSPEC_FK_RANGE
+const BEGIN_SEARCH_INDEX: c_int = 2;
+const FIND_KEYS_RANGE: c_int = 2;
+
+// the structs of the module api in redismodule.h, version 1
+#[repr(C)]
+struct CommandInfoVersion {
+    version: c_int,
+    sizeof_historyentry: usize,
+    sizeof_keyspec: usize,
+    sizeof_arg: usize,
+}
+
+#[repr(C)]
+struct CommandHistoryEntry {
+    since: *const c_char,
+    changes: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct KeywordSearch {
+    keyword: *const c_char,
+    startfrom: c_int,
+}
+
+#[repr(C)]
+union BeginSearch {
+    pos: c_int,
+    keyword: KeywordSearch,
+}
+
+#[repr(C)]
+struct FindKeys {
+    // lastkey, keystep and limit of a range, or keynumidx, firstkey and keystep
+    values: [c_int; 3],
+}
+
+#[repr(C)]
+struct CommandKeySpec {
+    notes: *const c_char,
+    flags: u64,
+    begin_search_type: c_int,
+    bs: BeginSearch,
+    find_keys_type: c_int,
+    fk: FindKeys,
+}
+
+#[repr(C)]
+struct CommandArg {
+    name: *const c_char,
+    kind: c_int,
+    key_spec_index: c_int,
+    token: *const c_char,
+    summary: *const c_char,
+    since: *const c_char,
+    flags: c_int,
+    deprecated_since: *const c_char,
+    subargs: *mut CommandArg,
+    display_text: *const c_char,
+}
+
+#[repr(C)]
+struct CommandInfo {
+    version: *const CommandInfoVersion,
+    summary: *const c_char,
+    complexity: *const c_char,
+    since: *const c_char,
+    history: *mut CommandHistoryEntry,
+    tips: *const c_char,
+    arity: c_int,
+    key_specs: *mut CommandKeySpec,
+    args: *mut CommandArg,
+}
+
+// sizes of the structs compiled by redis on 64 bit platforms
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(
+    mem::size_of::<CommandHistoryEntry>() == 16
+        && mem::size_of::<CommandKeySpec>() == 56
+        && mem::size_of::<CommandArg>() == 72
+);
+
+static VERSION: CommandInfoVersion = CommandInfoVersion {
+    version: 1,
+    sizeof_historyentry: mem::size_of::<CommandHistoryEntry>(),
+    sizeof_keyspec: mem::size_of::<CommandKeySpec>(),
+    sizeof_arg: mem::size_of::<CommandArg>(),
+};
+
+// document of an argument as COMMAND DOCS shows it
+#[derive(Debug, PartialEq)]
+pub struct ArgDoc {
+    pub name: String,
+    pub kind: c_int,
+    pub key_spec_index: c_int, // -1 unless the argument is the index
+    pub token: Option<String>,
+    pub summary: String,
+    pub flags: c_int,
+    pub subargs: Vec<ArgDoc>,
+}
+
+// the index names every command but the list, cursor and config ones take first. it isn't a
+// key, but the keys of an index hash to its slot under the tagged key layout
+pub fn names_index(cmd: &Command) -> bool {
+    cmd.args.first().is_some_and(|arg| {
+        arg.kind == ArgType::Arg
+            && arg
+                .desc
+                .to_ascii_lowercase()
+                .starts_with("name of the index")
+    })
+}
+
+// minimum argument count including the command name, keywords and flags are optional or
+// checked by the command itself
+pub fn arity(cmd: &Command) -> c_int {
+    let positional = cmd
+        .args
+        .iter()
+        .filter(|arg| arg.kind == ArgType::Arg && arg.default.is_none())
+        .count();
+    -(positional as c_int + 1)
+}
+
+fn value_kind(arg: &Arg) -> c_int {
+    match arg.type_name.as_str() {
+        "u64" | "i64" | "usize" => ARG_INTEGER,
+        "f64" | "f32" => ARG_DOUBLE,
+        _ => ARG_STRING,
+    }
+}
+
+pub fn arg_docs(cmd: &Command) -> Vec<ArgDoc> {
+    let index = names_index(cmd);
+    cmd.args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let optional = if arg.default.is_some() {
+                ARG_OPTIONAL
+            } else {
+                0
+            };
+            let token = Some(arg.arg.to_ascii_uppercase());
+            let mut doc = ArgDoc {
+                name: arg.arg.clone(),
+                kind: value_kind(arg),
+                key_spec_index: -1,
+                token: None,
+                summary: arg.desc.clone(),
+                flags: optional,
+                subargs: Vec::new(),
+            };
+            match (arg.kind, arg.collection) {
+                (ArgType::Arg, _) if i == 0 && index => {
+                    doc.kind = ARG_KEY;
+                    doc.key_spec_index = 0;
+                }
+                (ArgType::Arg, _) => {}
+                (ArgType::Flag, _) => {
+                    doc.kind = ARG_PURE_TOKEN;
+                    doc.token = token;
+                    doc.flags = ARG_OPTIONAL;
+                }
+                (ArgType::Kwarg, Collection::Unit) => doc.token = token,
+                // vectors are given as their length followed by the values
+                (ArgType::Kwarg, Collection::Vec) => {
+                    doc.subargs = vec![
+                        ArgDoc {
+                            name: "len".to_owned(),
+                            kind: ARG_INTEGER,
+                            key_spec_index: -1,
+                            token: None,
+                            summary: "Number of values.".to_owned(),
+                            flags: 0,
+                            subargs: Vec::new(),
+                        },
+                        ArgDoc {
+                            name: "value".to_owned(),
+                            kind: doc.kind,
+                            key_spec_index: -1,
+                            token: None,
+                            summary: String::new(),
+                            flags: ARG_MULTIPLE,
+                            subargs: Vec::new(),
+                        },
+                    ];
+                    doc.kind = ARG_BLOCK;
+                    doc.token = token;
+                }
+            }
+            doc
+        })
+        .collect()
+}
+
+// owns the strings and arrays the raw structs point to until the info is set
+#[derive(Default)]
+struct Buffers {
+    strings: Vec<CString>,
+    args: Vec<Vec<CommandArg>>,
+}
+
+impl Buffers {
+    fn add(&mut self, s: &str) -> *const c_char {
+        // descriptions never contain nul bytes, one would only drop the text
+        let s = CString::new(s).unwrap_or_default();
+        let p = s.as_ptr();
+        self.strings.push(s);
+        p
+    }
+
+    // zero terminated array of the documents, the heap buffer of each array stays in place
+    // when self.args grows
+    fn args(&mut self, docs: &[ArgDoc]) -> *mut CommandArg {
+        let mut args = Vec::with_capacity(docs.len() + 1);
+        for doc in docs {
+            let subargs = if doc.subargs.is_empty() {
+                ptr::null_mut()
+            } else {
+                self.args(&doc.subargs)
+            };
+            args.push(CommandArg {
+                name: self.add(&doc.name),
+                kind: doc.kind,
+                key_spec_index: doc.key_spec_index,
+                token: doc.token.as_deref().map_or(ptr::null(), |t| self.add(t)),
+                summary: self.add(&doc.summary),
+                since: ptr::null(),
+                flags: doc.flags,
+                deprecated_since: ptr::null(),
+                subargs,
+                display_text: ptr::null(),
+            });
+        }
+        args.push(unsafe { mem::zeroed() });
+        let p = args.as_mut_ptr();
+        self.args.push(args);
+        p
+    }
+}
+
+// registers the summary, arity, key specs and arguments of the commands for COMMAND DOCS
+// and COMMAND INFO on servers supporting it (redis 7.0+), must run while the module is loading
+pub fn register_command_info(ctx: &Context, specs: &[&'static std::thread::LocalKey<Command>]) {
+    unsafe {
+        let get_command = get_api::<GetCommandFn>("RedisModule_GetCommand");
+        let set_info = get_api::<SetCommandInfoFn>("RedisModule_SetCommandInfo");
+        let (get_command, set_info) = match (get_command, set_info) {
+            (Some(g), Some(s)) => (g, s),
+            _ => {
+                ctx.log_debug("command info is not supported by this server");
+                return;
+            }
+        };
+
+        for spec in specs {
+            spec.with(|cmd| {
+                let mut buffers = Buffers::default();
+                let mut key_specs: Vec<CommandKeySpec> = Vec::with_capacity(2);
+                if names_index(cmd) {
+                    key_specs.push(CommandKeySpec {
+                        notes: buffers.add("name of the index, not a key"),
+                        flags: KEY_NOT_KEY,
+                        begin_search_type: BEGIN_SEARCH_INDEX,
+                        bs: BeginSearch { pos: 1 },
+                        find_keys_type: FIND_KEYS_RANGE,
+                        fk: FindKeys { values: [0, 1, 0] },
+                    });
+                }
+                key_specs.push(mem::zeroed());
+                let info = CommandInfo {
+                    version: &VERSION,
+                    summary: buffers.add(&cmd.desc),
+                    complexity: ptr::null(),
+                    since: ptr::null(),
+                    history: ptr::null_mut(),
+                    tips: ptr::null(),
+                    arity: arity(cmd),
+                    key_specs: key_specs.as_mut_ptr(),
+                    args: buffers.args(&arg_docs(cmd)),
+                };
+
+                let name = buffers.add(&cmd.name);
+                let command = get_command(ctx.ctx, name);
+                if command.is_null() || set_info(command, &info) != raw::REDISMODULE_OK as c_int {
+                    ctx.log(
+                        LogLevel::Warning,
+                        format!("unable to set command info of: {}", cmd.name).as_str(),
+                    );
+                }
+            });
+        }
+    }
+}
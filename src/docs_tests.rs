@@ -0,0 +1,53 @@
+use crate::docs::*;
+use crate::*;
+
+#[test]
+fn arity_of_specs() {
+    assert_eq!(arity(&new_index_cmd()), -2);
+    assert_eq!(arity(&get_node_cmd()), -3);
+    assert_eq!(arity(&cursor_read_cmd()), -2);
+}
+
+#[test]
+fn index_arguments() {
+    assert!(names_index(&search_cmd()));
+    assert!(names_index(&new_index_cmd()));
+    assert!(!names_index(&list_cmd()));
+    assert!(!names_index(&cursor_read_cmd()));
+
+    let docs = arg_docs(&get_node_cmd());
+    assert_eq!(docs[0].kind, ARG_KEY);
+    assert_eq!(docs[0].key_spec_index, 0);
+    assert_eq!(docs[1].kind, ARG_STRING);
+    assert_eq!(docs[1].key_spec_index, -1);
+    assert_eq!(docs[1].flags, 0);
+
+    let docs = arg_docs(&cursor_read_cmd());
+    assert_eq!(docs[0].kind, ARG_INTEGER);
+    assert_eq!(docs[0].key_spec_index, -1);
+}
+
+#[test]
+fn keyword_arguments() {
+    let docs = arg_docs(&new_index_cmd());
+    let m = docs.iter().find(|d| d.name == "m").unwrap();
+    assert_eq!(m.kind, ARG_INTEGER);
+    assert_eq!(m.token.as_deref(), Some("M"));
+    assert_eq!(m.flags, ARG_OPTIONAL);
+
+    let normalize = docs.iter().find(|d| d.name == "normalize").unwrap();
+    assert_eq!(normalize.kind, ARG_PURE_TOKEN);
+    assert_eq!(normalize.token.as_deref(), Some("NORMALIZE"));
+    assert_eq!(normalize.flags, ARG_OPTIONAL);
+
+    // vectors are a block of their length and values
+    let docs = arg_docs(&train_pca_cmd());
+    let samples = docs.iter().find(|d| d.name == "samples").unwrap();
+    assert_eq!(samples.kind, ARG_BLOCK);
+    assert_eq!(samples.token.as_deref(), Some("SAMPLES"));
+    assert_eq!(samples.flags, 0);
+    assert_eq!(samples.subargs.len(), 2);
+    assert_eq!(samples.subargs[0].kind, ARG_INTEGER);
+    assert_eq!(samples.subargs[1].kind, ARG_DOUBLE);
+    assert_eq!(samples.subargs[1].flags, ARG_MULTIPLE);
+}
@@ -0,0 +1,186 @@
+use redis_module::{raw, Context, RedisString};
+
+use std::os::raw::{c_char, c_int, c_void};
+use std::time::Duration;
+
+use super::acl::get_api;
+
+// server event ids and subevents of redismodule.h, the bindings in use predate them
+const EVENT_PERSISTENCE: u64 = 1;
+const EVENT_FLUSHDB: u64 = 2;
+const EVENT_LOADING: u64 = 3;
+const EVENT_SWAPDB: u64 = 11;
+
+const SUBEVENT_PERSISTENCE_RDB_START: u64 = 0;
+const SUBEVENT_PERSISTENCE_AOF_START: u64 = 1;
+const SUBEVENT_PERSISTENCE_SYNC_RDB_START: u64 = 2;
+const SUBEVENT_PERSISTENCE_SYNC_AOF_START: u64 = 5;
+const SUBEVENT_FLUSHDB_END: u64 = 1;
+const SUBEVENT_LOADING_ENDED: u64 = 3;
+const SUBEVENT_LOADING_FAILED: u64 = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ServerEvent {
+    id: u64,
+    dataver: u64,
+}
+
+#[repr(C)]
+struct SwapDbInfo {
+    version: u64,
+    dbnum_first: i32,
+    dbnum_second: i32,
+}
+
+type EventCallback = unsafe extern "C" fn(*mut raw::RedisModuleCtx, ServerEvent, u64, *mut c_void);
+type SubscribeFn =
+    unsafe extern "C" fn(*mut raw::RedisModuleCtx, ServerEvent, Option<EventCallback>) -> c_int;
+type JobCallback = unsafe extern "C" fn(*mut raw::RedisModuleCtx, *mut c_void);
+type AddPostNotificationJobFn = unsafe extern "C" fn(
+    *mut raw::RedisModuleCtx,
+    JobCallback,
+    *mut c_void,
+    Option<unsafe extern "C" fn(*mut c_void)>,
+) -> c_int;
+
+// keyspace events that may change or remove a followed hash
+const FOLLOWED_EVENTS: u32 = raw::REDISMODULE_NOTIFY_GENERIC
+    | raw::REDISMODULE_NOTIFY_HASH
+    | raw::REDISMODULE_NOTIFY_EXPIRED
+    | raw::REDISMODULE_NOTIFY_EVICTED;
+
+// flushes, swaps and loads replace keys wholesale, the graphs loaded from the old keys
+// are dropped right away instead of when load_index finds their keys replaced. deferred
+// node keys are written before the dataset is saved
+pub fn subscribe(ctx: &Context) {
+    unsafe {
+        let subscribe = match get_api::<SubscribeFn>("RedisModule_SubscribeToServerEvent") {
+            Some(s) => s,
+            None => {
+                ctx.log_debug("server events are not supported by this server");
+                return;
+            }
+        };
+
+        for id in &[
+            EVENT_PERSISTENCE,
+            EVENT_FLUSHDB,
+            EVENT_LOADING,
+            EVENT_SWAPDB,
+        ] {
+            let event = ServerEvent {
+                id: *id,
+                dataver: 1,
+            };
+            // swapdb events need redis 6.2
+            if subscribe(ctx.ctx, event, Some(on_server_event)) != raw::REDISMODULE_OK as c_int {
+                ctx.log_debug(
+                    format!("server event: {} is not supported by this server", id).as_str(),
+                );
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn on_server_event(
+    ctx: *mut raw::RedisModuleCtx,
+    event: ServerEvent,
+    subevent: u64,
+    data: *mut c_void,
+) {
+    if event.id == EVENT_PERSISTENCE {
+        // sync aof rewrites need redis 7.0
+        if let SUBEVENT_PERSISTENCE_RDB_START
+        | SUBEVENT_PERSISTENCE_AOF_START
+        | SUBEVENT_PERSISTENCE_SYNC_RDB_START
+        | SUBEVENT_PERSISTENCE_SYNC_AOF_START = subevent
+        {
+            super::write_due_nodes(&Context::new(ctx), Duration::ZERO);
+        }
+        return;
+    }
+    // the deferred keys move along with their index keys
+    if event.id == EVENT_SWAPDB && !data.is_null() {
+        let info = &*(data as *const SwapDbInfo);
+        super::swap_deferred(info.dbnum_first, info.dbnum_second);
+        super::write_due_nodes(&Context::new(ctx), Duration::ZERO);
+    }
+    let replaced = match event.id {
+        EVENT_FLUSHDB => subevent == SUBEVENT_FLUSHDB_END,
+        EVENT_LOADING => subevent == SUBEVENT_LOADING_ENDED || subevent == SUBEVENT_LOADING_FAILED,
+        EVENT_SWAPDB => true,
+        _ => false,
+    };
+    if replaced {
+        super::evict_all_indexes();
+    }
+}
+
+// subscribes to changes of the keys followed by hnsw.follow, must run while the module is loading
+pub fn follow_keyspace(ctx: &Context) {
+    let res = unsafe {
+        raw::RedisModule_SubscribeToKeyspaceEvents.unwrap()(
+            ctx.ctx,
+            FOLLOWED_EVENTS as c_int,
+            Some(on_keyspace_event),
+        )
+    };
+    if res != raw::REDISMODULE_OK as c_int {
+        ctx.log_debug("keyspace events are not supported by this server");
+    }
+}
+
+// database and name of a changed key
+struct FollowJob {
+    db: c_int,
+    key: String,
+}
+
+unsafe extern "C" fn on_keyspace_event(
+    ctx: *mut raw::RedisModuleCtx,
+    _type: c_int,
+    _event: *const c_char,
+    key: *mut raw::RedisModuleString,
+) -> c_int {
+    if !super::types::following() {
+        return raw::REDISMODULE_OK as c_int;
+    }
+    let key = match RedisString::from_ptr(key) {
+        Ok(k) => k.to_owned(),
+        Err(_) => return raw::REDISMODULE_OK as c_int,
+    };
+    // node keys are written while syncing a followed key
+    if !super::may_be_followed(&key) {
+        return raw::REDISMODULE_OK as c_int;
+    }
+    let job = FollowJob {
+        db: raw::RedisModule_GetSelectedDb.unwrap()(ctx),
+        key,
+    };
+
+    // keys must not be written from notification callbacks on redis 7.2+,
+    // older servers have no post notification jobs and allow it
+    match get_api::<AddPostNotificationJobFn>("RedisModule_AddPostNotificationJob") {
+        Some(add_job) => {
+            let pd = Box::into_raw(Box::new(job)) as *mut c_void;
+            if add_job(ctx, run_follow_job, pd, Some(free_follow_job))
+                != raw::REDISMODULE_OK as c_int
+            {
+                free_follow_job(pd);
+            }
+        }
+        None => super::sync_followed(&Context::new(ctx), &job.key),
+    }
+    raw::REDISMODULE_OK as c_int
+}
+
+unsafe extern "C" fn run_follow_job(ctx: *mut raw::RedisModuleCtx, pd: *mut c_void) {
+    let job = &*(pd as *mut FollowJob);
+    raw::RedisModule_SelectDb.unwrap()(ctx, job.db);
+    super::sync_followed(&Context::new(ctx), &job.key);
+}
+
+unsafe extern "C" fn free_follow_job(pd: *mut c_void) {
+    drop(Box::from_raw(pd as *mut FollowJob));
+}
@@ -0,0 +1,24 @@
+// entry points of the fuzz targets in fuzz/, they parse the arguments of a command
+// without a redis context and must return an error on malformed input rather than panic
+
+// splits the input into arguments at nul bytes and prepends the command name
+fn args_of(command: &str, data: &[u8]) -> Vec<String> {
+    std::iter::once(command.to_owned())
+        .chain(
+            data.split(|b| *b == 0)
+                .map(|arg| String::from_utf8_lossy(arg).into_owned()),
+        )
+        .collect()
+}
+
+pub fn new_index(data: &[u8]) {
+    let _ = super::parse_new_index(args_of("hnsw.new", data));
+}
+
+pub fn add_node(data: &[u8]) {
+    let _ = super::parse_add_node(args_of("hnsw.node.add", data));
+}
+
+pub fn search(data: &[u8]) {
+    let _ = super::parse_search(args_of("hnsw.search", data));
+}
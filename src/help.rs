@@ -0,0 +1,183 @@
+use redis_module::{RedisError, RedisValue};
+use redismodule_cmd::{Arg, ArgType, Collection, Command};
+
+use std::any::Any;
+
+// name and description of a command, an entry of the command list
+pub fn summary(cmd: &Command) -> RedisValue {
+    RedisValue::Array(vec![cmd.name.as_str().into(), cmd.desc.as_str().into()])
+}
+
+// name, description and arguments of a command, in the order of the spec
+pub fn usage(cmd: &Command) -> RedisValue {
+    RedisValue::Array(vec![
+        "name".into(),
+        cmd.name.as_str().into(),
+        "desc".into(),
+        cmd.desc.as_str().into(),
+        "args".into(),
+        RedisValue::Array(cmd.args.iter().map(arg_usage).collect()),
+    ])
+}
+
+fn arg_usage(arg: &Arg) -> RedisValue {
+    let kind = match arg.kind {
+        ArgType::Arg => "positional",
+        ArgType::Kwarg => "keyword",
+        ArgType::Flag => "flag",
+    };
+    // keywords are matched case-insensitively, they are shown the way clients usually write them
+    let name = match arg.kind {
+        ArgType::Arg => arg.arg.clone(),
+        _ => arg.arg.to_ascii_uppercase(),
+    };
+    RedisValue::Array(vec![
+        "name".into(),
+        name.into(),
+        "kind".into(),
+        kind.into(),
+        "type".into(),
+        arg.type_name.as_str().into(),
+        "multiple".into(),
+        RedisValue::Integer((arg.collection == Collection::Vec) as i64),
+        "required".into(),
+        RedisValue::Integer(arg.default.is_none() as i64),
+        "default".into(),
+        arg.default.as_deref().map_or(RedisValue::None, default_of),
+        "desc".into(),
+        arg.desc.as_str().into(),
+    ])
+}
+
+// defaults of the types the specs use, anything else is shown as nil
+fn default_of(default: &dyn Any) -> RedisValue {
+    if let Some(v) = default.downcast_ref::<u64>() {
+        RedisValue::Integer(*v as i64)
+    } else if let Some(v) = default.downcast_ref::<i64>() {
+        RedisValue::Integer(*v)
+    } else if let Some(v) = default.downcast_ref::<f64>() {
+        RedisValue::Float(*v)
+    } else if let Some(v) = default.downcast_ref::<bool>() {
+        RedisValue::Integer(*v as i64)
+    } else if let Some(v) = default.downcast_ref::<String>() {
+        v.as_str().into()
+    } else if let Some(v) = default.downcast_ref::<Vec<f64>>() {
+        RedisValue::Array(v.iter().map(|x| RedisValue::Float(*x)).collect())
+    } else {
+        RedisValue::None
+    }
+}
+
+// one line syntax of a command, optional arguments in brackets
+pub fn syntax(cmd: &Command) -> String {
+    std::iter::once(cmd.name.clone())
+        .chain(cmd.args.iter().map(|arg| {
+            let name = arg.arg.to_ascii_uppercase();
+            let part = match (arg.kind, arg.collection) {
+                (ArgType::Arg, _) => format!("{{{}}}", arg.arg),
+                (ArgType::Flag, _) => name,
+                (_, Collection::Unit) => format!("{} {{{}}}", name, arg.arg),
+                (_, Collection::Vec) => format!("{} {{len}} {{...{}}}", name, arg.arg),
+            };
+            match (arg.kind, &arg.default) {
+                (ArgType::Arg, _) | (_, None) => part,
+                _ => format!("[{}]", part),
+            }
+        }))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// arguments of a command in the order of its spec, the positional arguments followed by the
+// keywords in lowercase, so keywords can be given in any case and order. malformed arguments
+// are reported by their token along with the syntax of the command
+pub fn normalize(cmd: &Command, args: Vec<String>) -> Result<Vec<String>, RedisError> {
+    let fail = |msg: String| RedisError::String(format!("{}, expected: {}", msg, syntax(cmd)));
+
+    // args[0] is the name of the command
+    let positional = cmd.args.iter().filter(|arg| arg.kind == ArgType::Arg);
+    if let Some(arg) = positional.clone().nth(args.len().saturating_sub(1)) {
+        return Err(fail(format!("Missing {{{}}}", arg.arg)));
+    }
+    let first_keyword = positional.count() + 1;
+
+    let mut keywords: Vec<Option<&[String]>> = vec![None; cmd.args.len()];
+    let mut pos = first_keyword;
+    while pos < args.len() {
+        let token = &args[pos];
+        let i = cmd
+            .args
+            .iter()
+            .position(|arg| arg.kind != ArgType::Arg && arg.arg.eq_ignore_ascii_case(token))
+            .ok_or_else(|| {
+                fail(format!(
+                    "Unexpected argument: {} at position {}",
+                    token, pos
+                ))
+            })?;
+        let arg = &cmd.args[i];
+        let name = arg.arg.to_ascii_uppercase();
+        if keywords[i].is_some() {
+            return Err(fail(format!(
+                "Duplicate argument: {} at position {}",
+                token, pos
+            )));
+        }
+
+        // a vector is given as its length followed by its values
+        let rest = &args[pos + 1..];
+        let len = match (arg.kind, arg.collection, rest.first()) {
+            (ArgType::Flag, _, _) => 0,
+            (_, Collection::Unit, _) | (_, Collection::Vec, None) => 1,
+            (_, Collection::Vec, Some(len)) => len
+                .parse::<usize>()
+                .map_err(|_| fail(format!("Invalid length: {} of {}", len, name)))?
+                .saturating_add(1),
+        };
+        if rest.len() < len {
+            return Err(fail(format!("Missing value of {}", name)));
+        }
+        let values = &rest[..len];
+        let skip = (arg.collection == Collection::Vec) as usize;
+        if let Some(value) = values[skip..]
+            .iter()
+            .find(|value| !is_valid(&arg.type_name, value))
+        {
+            return Err(fail(format!(
+                "Invalid value: {} of {}, expected {}",
+                value, name, arg.type_name
+            )));
+        }
+        keywords[i] = Some(values);
+        pos += 1 + len;
+    }
+
+    let mut normalized = args
+        .iter()
+        .take(first_keyword)
+        .cloned()
+        .collect::<Vec<String>>();
+    for (arg, values) in cmd.args.iter().zip(keywords) {
+        match values {
+            Some(values) => {
+                normalized.push(arg.arg.to_ascii_lowercase());
+                normalized.extend(values.iter().cloned());
+            }
+            None if arg.kind != ArgType::Arg && arg.default.is_none() => {
+                return Err(fail(format!("Missing {}", arg.arg.to_ascii_uppercase())));
+            }
+            None => {}
+        }
+    }
+    Ok(normalized)
+}
+
+// numbers are checked here to name the offending token, other types are left to the parser
+fn is_valid(type_name: &str, value: &str) -> bool {
+    match type_name {
+        "u64" => value.parse::<u64>().is_ok(),
+        "i64" => value.parse::<i64>().is_ok(),
+        "f64" => value.parse::<f64>().is_ok(),
+        _ => true,
+    }
+}
@@ -0,0 +1,134 @@
+use crate::help::*;
+use crate::lib_tests::field;
+use crate::*;
+
+#[test]
+fn usage_of_spec() {
+    let reply = usage(&new_index_cmd());
+    assert_eq!(field(&reply, "name"), &RedisValue::from("hnsw.new"));
+    let args = match field(&reply, "args") {
+        RedisValue::Array(args) => args,
+        r => panic!("unexpected args: {:?}", r),
+    };
+    assert_eq!(args.len(), new_index_cmd().args.len());
+
+    let name = &args[0];
+    assert_eq!(field(name, "name"), &RedisValue::from("name"));
+    assert_eq!(field(name, "kind"), &RedisValue::from("positional"));
+    assert_eq!(field(name, "required"), &RedisValue::Integer(1));
+    assert_eq!(field(name, "default"), &RedisValue::None);
+
+    let m = &args[2];
+    assert_eq!(field(m, "name"), &RedisValue::from("M"));
+    assert_eq!(field(m, "kind"), &RedisValue::from("keyword"));
+    assert_eq!(field(m, "type"), &RedisValue::from("u64"));
+    assert_eq!(field(m, "required"), &RedisValue::Integer(0));
+    assert_eq!(field(m, "default"), &RedisValue::Integer(5));
+
+    assert_eq!(field(&args[6], "name"), &RedisValue::from("NORMALIZE"));
+    assert_eq!(field(&args[6], "kind"), &RedisValue::from("flag"));
+}
+
+#[test]
+fn every_command_listed() {
+    let names = COMMAND_SPECS
+        .iter()
+        .map(|spec| spec.with(|cmd| cmd.name.clone()))
+        .collect::<Vec<String>>();
+    assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+    assert!(names.iter().all(|name| name.starts_with("hnsw.")));
+
+    for spec in &COMMAND_SPECS {
+        let reply = spec.with(summary);
+        assert!(matches!(reply, RedisValue::Array(ref items) if items.len() == 2));
+    }
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn normalize_error(cmd: &Command, given: &[&str]) -> String {
+    match normalize(cmd, args(given)) {
+        Err(RedisError::String(e)) => e,
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn normalize_keywords() {
+    let cmd = search_cmd();
+    assert_eq!(
+        normalize(
+            &cmd,
+            args(&[
+                "hnsw.search",
+                "foo",
+                "Cursor",
+                "QUERY",
+                "2",
+                "1",
+                "0.5",
+                "K",
+                "3"
+            ])
+        )
+        .unwrap(),
+        args(&[
+            "hnsw.search",
+            "foo",
+            "k",
+            "3",
+            "query",
+            "2",
+            "1",
+            "0.5",
+            "cursor"
+        ])
+    );
+    // values equal to keywords are taken as values
+    assert_eq!(
+        normalize(&cmd, args(&["hnsw.search", "k", "filter", "k"])).unwrap(),
+        args(&["hnsw.search", "k", "filter", "k"])
+    );
+
+    let expected = format!(", expected: {}", syntax(&cmd));
+    assert!(syntax(&cmd).starts_with("hnsw.search {index} [K {k}] [QUERY {len} {...query}]"));
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search"]),
+        format!("Missing {{index}}{}", expected)
+    );
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search", "foo", "TOP", "3"]),
+        format!("Unexpected argument: TOP at position 2{}", expected)
+    );
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search", "foo", "k", "3", "K", "4"]),
+        format!("Duplicate argument: K at position 4{}", expected)
+    );
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search", "foo", "K", "three"]),
+        format!("Invalid value: three of K, expected u64{}", expected)
+    );
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search", "foo", "QUERY", "3", "1", "2"]),
+        format!("Missing value of QUERY{}", expected)
+    );
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search", "foo", "QUERY", "x"]),
+        format!("Invalid length: x of QUERY{}", expected)
+    );
+    assert_eq!(
+        normalize_error(&cmd, &["hnsw.search", "foo", "K"]),
+        format!("Missing value of K{}", expected)
+    );
+    assert!(normalize(
+        &cmd,
+        args(&["hnsw.search", "foo", "QUERY", "18446744073709551615"])
+    )
+    .is_err());
+
+    // required keywords
+    let cmd = cluster_cmd();
+    assert!(normalize_error(&cmd, &["hnsw.cluster", "foo"]).starts_with("Missing K, expected"));
+}
@@ -1,19 +1,32 @@
+use super::dedupe::{Dedupe, DuplicatePolicy};
+use super::geo::{GeoFence, Location};
+use super::keys::{node_key, node_prefix};
 use super::metrics;
+use super::names::NameRule;
+use super::projection::Projection;
+use super::storage::{HeapStorage, NodeStore, Relocate, Storage, Vector};
+use super::tags::{Filter, TagStore};
 
 use num::Float;
 use ordered_float::OrderedFloat;
 use owning_ref::{RefMutRefMut, RefRef, RwLockReadGuardRef, RwLockWriteGuardRefMut};
 use rand::prelude::*;
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::cmp::{min, Eq, Ord, Ordering, PartialEq, PartialOrd, Reverse};
-use std::collections::{BinaryHeap, HashMap, HashSet};
-use std::convert::From;
+use std::cmp::{max, min, Eq, Ord, Ordering, PartialEq, PartialOrd, Reverse};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::convert::{From, TryFrom};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
-use std::sync::{Arc, RwLock, Weak};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 // use std::thread;
 
+// highest layer a node can be added at explicitly, random levels practically stay far below
+pub const MAX_LEVEL: usize = 64;
+
 struct SelectParams {
     m: usize,
     lc: usize,
@@ -45,18 +58,185 @@ impl HNSWError {
     }
 }
 
+// aggregation of per-vector similarities for multi-vector nodes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MultiValue {
+    Max,
+    Avg,
+}
+
+impl Default for MultiValue {
+    fn default() -> Self {
+        MultiValue::Max
+    }
+}
+
+// reference vector results must not be similar to, e.g. an item already bought
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotNear {
+    pub data: Vec<f32>,
+    pub radius: f32,  // results at least this similar to data are excluded
+    pub stored: bool, // data is the vector of a node, already projected and normalized
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchParams {
+    pub multivalue: MultiValue,    // how multi-vector nodes are scored
+    pub filter: Option<Filter>,    // only nodes with matching tags and attributes are returned
+    pub within: Option<GeoFence>,  // only nodes located within the fence are returned
+    pub max_visits: usize,         // nodes a search may visit, 0 if unlimited
+    pub timeout: Option<Duration>, // time a search may traverse the graph
+    pub entry: Option<String>,     // node layer 0 is searched from, skipping upper layers
+    pub not_near: Vec<NotNear>,    // nodes too similar to any reference are not returned
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchStats {
+    pub visited: usize,                        // nodes visited across all layers
+    pub distances: usize,                      // distance evaluations across all layers
+    pub layer_time: BTreeMap<usize, Duration>, // time spent traversing each layer
+    pub truncated: bool, // the budget ran out, results are the best found so far
+}
+
+impl SearchStats {
+    // combines the stats of searches over several shards
+    pub fn merge(&mut self, other: &SearchStats) {
+        self.visited += other.visited;
+        self.distances += other.distances;
+        for (layer, time) in &other.layer_time {
+            *self.layer_time.entry(*layer).or_default() += *time;
+        }
+        self.truncated |= other.truncated;
+    }
+}
+
+// nodes and directed edges of one layer of the graph, named without the index prefix
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subgraph {
+    pub layer: usize,
+    pub nodes: Vec<(String, usize)>, // name and top layer of each node, sorted by name
+    pub edges: Vec<(String, String)>, // node to neighbor, only between nodes of the subgraph
+}
+
+// deadline is checked every this many visits
+const DEADLINE_CHECK_INTERVAL: usize = 64;
+
+// nodes visited on a layer, marked by id with the epoch of the layer search so that
+// clearing the marks doesn't touch them
+#[derive(Default)]
+struct Visited {
+    marks: Vec<u32>,
+    epoch: u32,
+}
+
+impl Visited {
+    // forgets all visits, len is the number of node ids of the index
+    fn clear(&mut self, len: usize) {
+        self.epoch = self.epoch.wrapping_add(1);
+        if self.epoch == 0 {
+            self.marks.iter_mut().for_each(|m| *m = 0);
+            self.epoch = 1;
+        }
+        if self.marks.len() < len {
+            self.marks.resize(len, 0);
+        }
+    }
+
+    // false if the node was already visited
+    fn insert(&mut self, id: u32) -> bool {
+        let mark = &mut self.marks[id as usize];
+        if *mark == self.epoch {
+            return false;
+        }
+        *mark = self.epoch;
+        true
+    }
+}
+
+thread_local! {
+    // visited marks of the last traversal on this thread, reused by the next one
+    static VISITED: RefCell<Visited> = RefCell::new(Visited::default());
+}
+
+// visits of a graph traversal, bounded by the budget of the search, along with the
+// scratch buffers reused by the searches of its layers
+struct Traversal<T: Float, R: Float> {
+    stats: SearchStats,
+    max_visits: usize,
+    deadline: Option<Instant>,
+    visited: Visited,
+    candidates: BinaryHeap<SimPair<T, R>>,
+}
+
+impl<T: Float, R: Float> Traversal<T, R> {
+    fn unbounded() -> Self {
+        Traversal {
+            stats: SearchStats::default(),
+            max_visits: 0,
+            deadline: None,
+            visited: VISITED.with(|v| v.take()),
+            candidates: BinaryHeap::new(),
+        }
+    }
+
+    fn new(params: &SearchParams) -> Self {
+        let mut traversal = Traversal::unbounded();
+        traversal.max_visits = params.max_visits;
+        traversal.deadline = params.timeout.map(|t| Instant::now() + t);
+        traversal
+    }
+
+    fn add_time(&mut self, layer: usize, start: Instant) {
+        *self.stats.layer_time.entry(layer).or_default() += start.elapsed();
+    }
+
+    // counts a visit, false once the budget is spent
+    fn visit(&mut self) -> bool {
+        if self.stats.truncated {
+            return false;
+        }
+        if self.max_visits > 0 && self.stats.visited >= self.max_visits {
+            self.stats.truncated = true;
+            return false;
+        }
+        self.stats.visited += 1;
+        if let Some(deadline) = self.deadline {
+            if self.stats.visited.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && Instant::now() >= deadline
+            {
+                self.stats.truncated = true;
+            }
+        }
+        true
+    }
+}
+
+impl<T: Float, R: Float> Drop for Traversal<T, R> {
+    fn drop(&mut self) {
+        let visited = std::mem::take(&mut self.visited);
+        // the pool is gone if the thread is exiting
+        let _ = VISITED.try_with(|v| v.replace(visited));
+    }
+}
+
 pub struct SearchResult<T: Float, R: Float> {
     pub sim: OrderedFloat<R>,
-    pub name: String,
+    pub name: String, // node name without the index prefix
+    pub key: String,  // full name of the node
     pub data: Vec<T>,
+    pub tags: Vec<String>, // tags of the node, e.g. to group results by
+    pub aliases: Vec<(String, String)>, // name and key of results with the same vector, with DEDUPE
 }
 
 impl<T: Float, R: Float> SearchResult<T, R> {
-    fn new(sim: OrderedFloat<R>, name: &str, data: &[T]) -> Self {
+    fn new(sim: OrderedFloat<R>, key: &str, name: &str, data: &[T]) -> Self {
         SearchResult {
             sim,
             name: name.to_owned(),
+            key: key.to_owned(),
             data: data.to_vec(),
+            tags: Vec::new(),
+            aliases: Vec::new(),
         }
     }
 }
@@ -90,13 +270,21 @@ where
 }
 
 type NodeRef<T> = Arc<RwLock<_Node<T>>>;
-type NodeRefWeak<T> = Weak<RwLock<_Node<T>>>;
 
 #[derive(Clone)]
 pub struct _Node<T: Float> {
     pub name: String,
-    pub data: Vec<T>,
-    pub neighbors: Vec<Vec<NodeWeak<T>>>,
+    pub id: u32,              // slot of the node in its index, neighbors refer to it
+    pub data: Vector<T>,      // vector used for graph construction
+    pub vectors: Vec<Vec<T>>, // all vectors of a multi-vector node, empty otherwise
+    pub norms: Vec<T>,        // original norm of each vector of a normalized node
+    pub sq_norm: T,           // squared L2 norm of data, used to bound distances
+    pub level: usize,         // top layer of the node
+    pub tags: Vec<String>,    // tags matched by search filters
+    pub attrs: BTreeMap<String, f64>, // numeric attributes compared by search filters
+    pub location: Option<Location>, // position matched by search geo fences
+    pub version: u64,         // incremented on every update of the node
+    pub neighbors: Vec<Vec<u32>>, // ids of the neighbors on each layer
 }
 
 impl<T> fmt::Debug for _Node<T>
@@ -107,34 +295,70 @@ where
         write!(
             f,
             "name: {}, \
+             id: {}, \
              data: {:?}, \
              neighbors: {:?}",
-            self.name,
-            self.data,
-            self.neighbors
-                .iter()
-                .map(|l| {
-                    l.iter()
-                        .map(|n| n.upgrade().read().name.to_owned())
-                        .collect::<Vec<String>>()
-                })
-                .collect::<Vec<Vec<String>>>(),
+            self.name, self.id, self.data, self.neighbors,
         )
     }
 }
 
 impl<T: Float> _Node<T> {
-    fn push_levels(&mut self, level: usize, capacity: Option<usize>) {
+    fn new(name: &str, data: &[T], capacity: usize) -> Self {
+        _Node {
+            name: name.to_owned(),
+            id: 0,
+            data: data.to_vec().into(),
+            vectors: Vec::new(),
+            norms: Vec::new(),
+            sq_norm: sq_norm(data),
+            level: 0,
+            tags: Vec::new(),
+            attrs: BTreeMap::new(),
+            location: None,
+            version: 1,
+            neighbors: Vec::with_capacity(capacity),
+        }
+    }
+
+    // data with the original magnitude of a normalized single-vector node restored
+    pub fn original_data(&self) -> Vec<T> {
+        match self.norms.as_slice() {
+            [norm] => self.data.iter().map(|x| *x * *norm).collect(),
+            _ => self.data.to_vec(),
+        }
+    }
+
+    // moves every buffer of the node, the node itself stays where its index points to
+    pub fn relocate<R: Relocate>(&mut self, r: &mut R) {
+        r.relocate_str(&mut self.name);
+        self.data.relocate(r);
+        for v in self.vectors.iter_mut() {
+            r.relocate(v);
+        }
+        r.relocate(&mut self.vectors);
+        r.relocate(&mut self.norms);
+        for t in self.tags.iter_mut() {
+            r.relocate_str(t);
+        }
+        r.relocate(&mut self.tags);
+        for layer in self.neighbors.iter_mut() {
+            r.relocate(layer);
+        }
+        r.relocate(&mut self.neighbors);
+    }
+
+    fn push_levels(&mut self, level: usize, capacity: Option<Degrees>) {
         let neighbors = &mut self.neighbors;
         while neighbors.len() < level + 1 {
             match capacity {
-                Some(cap) => neighbors.push(Vec::with_capacity(cap)),
+                Some(cap) => neighbors.push(Vec::with_capacity(cap.on(neighbors.len()))),
                 None => neighbors.push(Vec::new()),
             }
         }
     }
 
-    fn add_neighbor(&mut self, level: usize, neighbor: NodeWeak<T>, capacity: Option<usize>) {
+    fn add_neighbor(&mut self, level: usize, neighbor: u32, capacity: Option<Degrees>) {
         self.push_levels(level, capacity);
         let neighbors = &mut self.neighbors;
         if !neighbors[level].contains(&neighbor) {
@@ -142,36 +366,31 @@ impl<T: Float> _Node<T> {
         }
     }
 
-    fn rm_neighbor(&mut self, level: usize, neighbor: &NodeWeak<T>) {
+    fn rm_neighbor(&mut self, level: usize, neighbor: u32) {
         let neighbors = &mut self.neighbors;
         let index = neighbors[level]
             .iter()
-            .position(|n| *n == *neighbor)
+            .position(|n| *n == neighbor)
             .unwrap();
         neighbors[level].remove(index);
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct NodeWeak<T: Float>(pub NodeRefWeak<T>);
-
-impl<T: Float> PartialEq for NodeWeak<T> {
-    fn eq(&self, other: &Self) -> bool {
-        Weak::ptr_eq(&self.0, &other.0)
-    }
-}
-
-impl<T: Float> Eq for NodeWeak<T> {}
-
-impl<T: Float> Hash for NodeWeak<T> {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.upgrade().read().name.hash(state);
-    }
+// maximum number of neighbors of a node on layer 0 and on the layers above, the capacity
+// its neighbor lists are allocated with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Degrees {
+    pub layer0: usize,
+    pub upper: usize,
 }
 
-impl<T: Float> NodeWeak<T> {
-    pub fn upgrade(&self) -> Node<T> {
-        Node(self.0.upgrade().unwrap())
+impl Degrees {
+    pub fn on(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.layer0
+        } else {
+            self.upper
+        }
     }
 }
 
@@ -188,48 +407,170 @@ impl<T: Float> Eq for Node<T> {}
 
 impl<T: Float> Hash for Node<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.read().name.hash(state);
+        Arc::as_ptr(&self.0).hash(state);
     }
 }
 
 impl<T: Float> Node<T> {
     pub fn new(name: &str, data: &[T], capacity: usize) -> Self {
-        let node = _Node {
-            name: name.to_owned(),
-            data: data.to_vec(),
-            neighbors: Vec::with_capacity(capacity),
-        };
+        Node(Arc::new(RwLock::new(_Node::new(name, data, capacity))))
+    }
+
+    // creates a node holding several vectors, the graph is built on their centroid
+    pub fn new_multi(name: &str, vectors: Vec<Vec<T>>, capacity: usize) -> Self {
+        let mut node = _Node::new(name, &centroid(&vectors), capacity);
+        node.vectors = vectors;
         Node(Arc::new(RwLock::new(node)))
     }
 
-    pub fn read(&self) -> RwLockReadGuardRef<_Node<T>> {
-        RwLockReadGuardRef::new(self.0.try_read().unwrap())
+    // blocks until the node is available, errors only if a writer panicked
+    pub fn read(&self) -> Result<RwLockReadGuardRef<_Node<T>>, HNSWError> {
+        match self.0.read() {
+            Ok(guard) => Ok(RwLockReadGuardRef::new(guard)),
+            Err(_) => Err(HNSWError::Str("Node lock poisoned")),
+        }
+    }
+
+    pub fn write(&self) -> Result<RwLockWriteGuardRefMut<_Node<T>>, HNSWError> {
+        match self.0.write() {
+            Ok(guard) => Ok(RwLockWriteGuardRefMut::new(guard)),
+            Err(_) => Err(HNSWError::Str("Node lock poisoned")),
+        }
+    }
+
+    fn push_levels(&self, level: usize, capacity: Option<Degrees>) -> Result<(), HNSWError> {
+        self.write()?.push_levels(level, capacity);
+        Ok(())
+    }
+
+    fn add_neighbor(
+        &self,
+        level: usize,
+        neighbor: &Node<T>,
+        capacity: Option<Degrees>,
+    ) -> Result<(), HNSWError> {
+        let id = neighbor.id()?;
+        self.write()?.add_neighbor(level, id, capacity);
+        Ok(())
+    }
+
+    fn rm_neighbor(&self, level: usize, neighbor: &Node<T>) -> Result<(), HNSWError> {
+        let id = neighbor.id()?;
+        self.write()?.rm_neighbor(level, id);
+        Ok(())
+    }
+
+    pub fn id(&self) -> Result<u32, HNSWError> {
+        Ok(self.read()?.id)
+    }
+}
+
+// predicate of the nodes a search may return
+type NodeFilter<'a, T> = dyn Fn(&_Node<T>) -> bool + 'a;
+
+// nodes rejected by the filter are traversed but never returned
+fn is_allowed<T: Float>(filter: Option<&NodeFilter<T>>, node: &Node<T>) -> Result<bool, HNSWError> {
+    match filter {
+        Some(filter) => Ok(filter(&*node.read()?)),
+        None => Ok(true),
+    }
+}
+
+fn sq_norm<T: Float>(data: &[T]) -> T {
+    data.iter().fold(T::zero(), |acc, x| acc + *x * *x)
+}
+
+// L2-normalizes every vector of dim in data, returning the normalized data and the norms
+fn normalize<T: Float>(data: &[T], dim: usize) -> (Vec<T>, Vec<T>) {
+    let mut normalized = Vec::with_capacity(data.len());
+    let mut norms = Vec::with_capacity(data.len() / dim);
+    for v in data.chunks(dim) {
+        let norm = sq_norm(v).sqrt();
+        if norm > T::zero() {
+            normalized.extend(v.iter().map(|x| *x / norm));
+        } else {
+            normalized.extend_from_slice(v);
+        }
+        norms.push(norm);
+    }
+    (normalized, norms)
+}
+
+// NaN and infinite values have no order, similarities to them would corrupt the candidate
+// heaps of the graph
+pub fn check_finite<T: Float>(data: &[T]) -> Result<(), HNSWError> {
+    match non_finite(data) {
+        Some(i) => Err(format!(
+            "Vector value: {} at position: {} is not finite",
+            data[i].to_f64().unwrap(),
+            i
+        )
+        .into()),
+        None => Ok(()),
     }
+}
+
+// position of the first NaN or infinite value
+pub fn non_finite<T: Float>(data: &[T]) -> Option<usize> {
+    data.iter().position(|v| !v.is_finite())
+}
 
-    pub fn write(&self) -> RwLockWriteGuardRefMut<_Node<T>> {
-        RwLockWriteGuardRefMut::new(self.0.try_write().unwrap())
+// sum of the vectors scaled by their weights, e.g. `king - man + woman`
+pub fn weighted_sum<T: Float>(terms: &[(Vec<T>, T)]) -> Vec<T> {
+    let mut res = vec![T::zero(); terms.first().map_or(0, |(v, _)| v.len())];
+    for (v, w) in terms {
+        for (r, x) in res.iter_mut().zip(v) {
+            *r = *r + *x * *w;
+        }
     }
+    res
+}
 
-    fn push_levels(&self, level: usize, capacity: Option<usize>) {
-        let mut node = self.0.try_write().unwrap();
-        node.push_levels(level, capacity);
+// groups the nodes of duplicate pairs into connected clusters, largest first
+pub fn duplicate_clusters<R: Float>(pairs: &[(String, String, R)]) -> Vec<Vec<String>> {
+    fn root<'a>(parent: &HashMap<&'a str, &'a str>, mut name: &'a str) -> &'a str {
+        while let Some(p) = parent.get(name) {
+            name = p;
+        }
+        name
     }
 
-    fn add_neighbor(&self, level: usize, neighbor: NodeWeak<T>, capacity: Option<usize>) {
-        let node = &mut self.0.try_write().unwrap();
-        node.add_neighbor(level, neighbor, capacity);
+    let mut parent: HashMap<&str, &str> = HashMap::new();
+    for (a, b, _) in pairs {
+        let (ra, rb) = (root(&parent, a.as_str()), root(&parent, b.as_str()));
+        if ra != rb {
+            parent.insert(ra, rb);
+        }
     }
 
-    fn rm_neighbor(&self, level: usize, neighbor: &NodeWeak<T>) {
-        let node = &mut self.0.try_write().unwrap();
-        node.rm_neighbor(level, neighbor);
+    let mut clusters: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for (a, b, _) in pairs {
+        let cluster = clusters.entry(root(&parent, a.as_str())).or_default();
+        cluster.insert(a.clone());
+        cluster.insert(b.clone());
     }
+    let mut clusters = clusters
+        .into_values()
+        .map(|c| c.into_iter().collect::<Vec<String>>())
+        .collect::<Vec<_>>();
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    clusters
+}
 
-    pub fn downgrade(&self) -> NodeWeak<T> {
-        NodeWeak(Arc::downgrade(&self.0))
+fn centroid<T: Float>(vectors: &[Vec<T>]) -> Vec<T> {
+    let n = T::from(vectors.len()).unwrap();
+    let mut res = vec![T::zero(); vectors[0].len()];
+    for v in vectors {
+        for (r, x) in res.iter_mut().zip(v) {
+            *r = *r + *x;
+        }
     }
+    res.into_iter().map(|x| x / n).collect()
 }
 
+// nodes with their similarity to a query, in no particular order
+type Candidates<T, R> = Vec<(OrderedFloat<R>, Node<T>)>;
+
 type SimPairRef<T, R> = Rc<RefCell<_SimPair<T, R>>>;
 
 #[derive(Debug, Clone)]
@@ -299,6 +640,37 @@ where
     }
 }
 
+// prior level and neighbors of the nodes a change links or unlinks
+type Links<T> = HashMap<Node<T>, (usize, Vec<Vec<u32>>)>;
+
+// graph state before a node change, Index::undo restores it if the change can't be persisted
+pub struct Undo<T: Float> {
+    node_count: usize,
+    max_layer: usize,
+    flat_limit: usize,
+    enterpoint: Option<u32>,
+    entries: HashMap<String, Option<Node<T>>>, // prior entries of the added or removed nodes
+    links: Links<T>,
+    aliases: Option<HashMap<String, String>>, // prior node aliases, None if unchanged
+}
+
+// undo record of the node change in progress, filled by the graph operations between
+// Index::begin_undo and Index::end_undo
+pub struct UndoLog<T: Float>(Mutex<Option<Undo<T>>>);
+
+impl<T: Float> Default for UndoLog<T> {
+    fn default() -> Self {
+        UndoLog(Mutex::new(None))
+    }
+}
+
+// copies of an index, e.g. snapshots, don't record the changes of the original
+impl<T: Float> Clone for UndoLog<T> {
+    fn clone(&self) -> Self {
+        UndoLog::default()
+    }
+}
+
 #[derive(Clone)]
 pub struct Index<T: Float, R: Float> {
     pub name: String,                           // index name
@@ -312,10 +684,29 @@ pub struct Index<T: Float, R: Float> {
     pub level_mult: f64,                        // level generation factor
     pub node_count: usize,                      // count of nodes
     pub max_layer: usize,                       // idx of top layer
-    pub layers: Vec<HashSet<NodeWeak<T>>>,      // distinct nodes in each layer
+    pub layers: Vec<HashSet<u32>>,              // ids of the distinct nodes in each layer
     pub nodes: HashMap<String, Node<T>>,        // hashmap of nodes
-    pub enterpoint: Option<NodeWeak<T>>,        // enterpoint node
-    pub rng_: StdRng,                           // rng for level generation
+    pub slots: Vec<Option<Node<T>>>,            // nodes by id, None for ids free to reuse
+    pub free_ids: Vec<u32>,                     // ids of deleted nodes, reused first
+    pub enterpoint: Option<u32>,                // id of the enterpoint node
+    pub fields: Vec<String>,                    // names of the field sub-indexes
+    pub shards: usize,                          // number of shard sub-indexes, 0 if unsharded
+    pub normalize: bool,                        // L2-normalize data on insert and query
+    pub extend_candidates: bool,                // extend candidates by their neighbors
+    pub keep_pruned_connections: bool,          // fill up to M with pruned candidates
+    pub frozen: bool,                           // reject node mutations
+    pub snapshot_reads: bool,                   // searches fall back to a snapshot while writing
+    pub flat_limit: usize, // node count a flat index is promoted to HNSW above, 0 if HNSW
+    pub duplicates: DuplicatePolicy, // what adding a node equal to an existing one does
+    pub dup_epsilon: f32,  // largest difference of a value of two vectors considered equal
+    pub node_aliases: HashMap<String, String>, // node each alias of the alias policy resolves to
+    pub storage: Arc<dyn Storage<T>>, // where the node vectors live
+    pub epoch: u64,        // incremented by every change of the graph
+    pub tags: TagStore,    // inverted tag sets of the nodes
+    pub names: NameRule,   // constraints on node names
+    pub projection: Option<Projection<T>>, // maps vectors of another dimensionality to data_dim
+    pub rng_: StdRng,      // rng for level generation
+    pub undo: UndoLog<T>,  // undo record of the node change in progress
 }
 
 impl<T: Float, R: Float> Index<T, R> {
@@ -340,8 +731,49 @@ impl<T: Float, R: Float> Index<T, R> {
             max_layer: 0,
             layers: Vec::new(),
             nodes: HashMap::new(),
+            slots: Vec::new(),
+            free_ids: Vec::new(),
             enterpoint: None,
+            fields: Vec::new(),
+            shards: 0,
+            normalize: false,
+            extend_candidates: true,
+            keep_pruned_connections: true,
+            frozen: false,
+            snapshot_reads: false,
+            flat_limit: 0,
+            duplicates: DuplicatePolicy::Allow,
+            dup_epsilon: 0.0,
+            node_aliases: HashMap::new(),
+            storage: Arc::new(HeapStorage),
+            epoch: 0,
+            tags: TagStore::default(),
+            names: NameRule::default(),
+            projection: None,
             rng_: StdRng::from_entropy(),
+            undo: UndoLog::default(),
+        }
+    }
+
+    // relocates the buffers of the nodes from slot start on, returns the slot to resume from
+    // if the relocation stopped early. nodes locked by a search or writer are skipped
+    pub fn relocate_nodes(&self, start: usize, r: &mut impl Relocate) -> Option<usize> {
+        for (id, slot) in self.slots.iter().enumerate().skip(start) {
+            if r.should_stop() {
+                return Some(id);
+            }
+            if let Some(Ok(mut node)) = slot.as_ref().map(|n| n.0.try_write()) {
+                node.relocate(r);
+            }
+        }
+        None
+    }
+
+    // node of an id, the ids of deleted nodes don't resolve
+    pub fn node_at(&self, id: u32) -> Result<&Node<T>, HNSWError> {
+        match self.slots.get(id as usize) {
+            Some(Some(node)) => Ok(node),
+            _ => Err(format!("Node id: {} does not exist", id).into()),
         }
     }
 }
@@ -358,6 +790,11 @@ impl<T: Float, R: Float> fmt::Debug for Index<T, R> {
              level_mult: {}\n\
              node_count: {:?}\n\
              max_layer: {:?}\n\
+             extend_candidates: {}\n\
+             keep_pruned_connections: {}\n\
+             frozen: {}\n\
+             flat_limit: {}\n\
+             shards: {}\n\
              enterpoint: {}\n",
             self.name,
             self.mfunc_kind,
@@ -367,8 +804,17 @@ impl<T: Float, R: Float> fmt::Debug for Index<T, R> {
             self.level_mult,
             self.node_count,
             self.max_layer,
-            match &self.enterpoint {
-                Some(node) => node.upgrade().read().name.clone(),
+            self.extend_candidates,
+            self.keep_pruned_connections,
+            self.frozen,
+            self.flat_limit,
+            self.shards,
+            match self.enterpoint.map(|ep| self.node_at(ep)) {
+                Some(Ok(node)) => match node.read() {
+                    Ok(node) => node.name.clone(),
+                    Err(_) => "<poisoned>".to_owned(),
+                },
+                Some(Err(_)) => "<missing>".to_owned(),
                 None => "null".to_owned(),
             },
         )
@@ -380,59 +826,455 @@ where
     T: Float + Send + Sync + 'static,
     R: Float,
 {
+    // checks add_node would be accepted, before anything is mutated
+    pub fn can_add(&self, name: &str, data: &[T]) -> Result<(), HNSWError> {
+        if self.frozen {
+            return Err("Index is frozen".into());
+        }
+        self.check_dim(data)?;
+        self.names.check(self.node_suffix(name))?;
+        if self.nodes.contains_key(name) || self.node_aliases.contains_key(name) {
+            return Err(format!("Node: {:?} already exists", name).into());
+        }
+        Ok(())
+    }
+
     pub fn add_node(
         &mut self,
         name: &str,
         data: &[T],
-        update_fn: impl Fn(String, Node<T>),
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
     ) -> Result<(), HNSWError> {
-        if data.len() != self.data_dim {
-            return Err(format!("data dimension: {} does not match Index", data.len()).into());
+        self.add_node_at(name, data, None, update_fn)
+    }
+
+    // adds a node at the given top layer instead of a random one, e.g. to reproduce a
+    // published graph topology
+    pub fn add_node_at(
+        &mut self,
+        name: &str,
+        data: &[T],
+        level: Option<usize>,
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
+    ) -> Result<(), HNSWError> {
+        self.can_add(name, data)?;
+        if let Some(l) = level {
+            if l > MAX_LEVEL {
+                return Err(format!("Invalid level: {}, at most {}", l, MAX_LEVEL).into());
+            }
+            if l > 0 && self.is_flat() {
+                return Err("Nodes of a flat index are on layer 0".into());
+            }
+        }
+        if let Some(existing) = self.duplicate_of(name, data)? {
+            if self.duplicates == DuplicatePolicy::Reject {
+                return Err(format!("Node: {:?} duplicates node: {:?}", name, existing).into());
+            }
+            self.record_aliases();
+            self.node_aliases.insert(name.to_owned(), existing);
+            self.epoch += 1;
+            return Ok(());
+        }
+        self.insert_node(name, data, level, update_fn)
+    }
+
+    // links a node without checking it can be added
+    fn insert_node(
+        &mut self,
+        name: &str,
+        data: &[T],
+        level: Option<usize>,
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
+    ) -> Result<(), HNSWError> {
+        // flat indexes only link nodes on layer 0
+        let level = if self.is_flat() { Some(0) } else { level };
+        let data = &self.project(data);
+        self.epoch += 1;
+
+        // the first node starts on layer 0 unless given a level
+        let l = match level {
+            Some(l) => l,
+            None if self.node_count == 0 => 0,
+            None => self.gen_random_level(),
+        };
+        // one neighbor list per layer of the node
+        let node = self.new_node(name, data, l + 1)?;
+        node.write()?.level = l;
+        node.write()?.id = self.next_id()?;
+        self.place(node, &update_fn)?;
+        if self.is_flat() && self.node_count > self.flat_limit {
+            self.promote(&update_fn)?;
+        }
+        Ok(())
+    }
+
+    // node other than name whose vector equals data within dup_epsilon, among the nearest
+    // nodes found for data. None with the allow policy and for multi-vector data
+    pub fn duplicate_of(&self, name: &str, data: &[T]) -> Result<Option<String>, HNSWError> {
+        if self.duplicates == DuplicatePolicy::Allow {
+            return Ok(None);
+        }
+        let projected = self.project(data);
+        if projected.len() != self.data_dim {
+            return Ok(None);
+        }
+        let dedupe = Dedupe::new(self.dup_epsilon)?;
+        for r in self.search_knn(data, self.m.max(1))? {
+            let node = match self.nodes.get(&r.key) {
+                Some(node) if r.key != name => node,
+                _ => continue,
+            };
+            if dedupe.same(&node.read()?.original_data(), &projected) {
+                return Ok(Some(r.key));
+            }
+        }
+        Ok(None)
+    }
+
+    // names resolving to the node with the alias policy, sorted
+    pub fn aliases_of(&self, name: &str) -> Vec<String> {
+        let mut aliases = self
+            .node_aliases
+            .iter()
+            .filter(|(_, node)| *node == name)
+            .map(|(alias, _)| alias.clone())
+            .collect::<Vec<String>>();
+        aliases.sort();
+        aliases
+    }
+
+    // starts recording the state the following node changes can be undone to
+    pub fn begin_undo(&mut self) {
+        let undo = Undo {
+            node_count: self.node_count,
+            max_layer: self.max_layer,
+            flat_limit: self.flat_limit,
+            enterpoint: self.enterpoint,
+            entries: HashMap::new(),
+            links: HashMap::new(),
+            aliases: None,
+        };
+        *self.undo.0.lock().unwrap() = Some(undo);
+    }
+
+    pub fn end_undo(&mut self) -> Option<Undo<T>> {
+        self.undo.0.lock().unwrap().take()
+    }
+
+    // keeps the entry of a node before it is added or removed
+    fn record_entry(&self, name: &str) {
+        if let Some(undo) = self.undo.0.lock().unwrap().as_mut() {
+            if !undo.entries.contains_key(name) {
+                undo.entries
+                    .insert(name.to_owned(), self.nodes.get(name).cloned());
+            }
         }
+    }
+
+    // keeps the node aliases before the first of them changes
+    fn record_aliases(&self) {
+        if let Some(undo) = self.undo.0.lock().unwrap().as_mut() {
+            if undo.aliases.is_none() {
+                undo.aliases = Some(self.node_aliases.clone());
+            }
+        }
+    }
+
+    // keeps the level and neighbors of a node before they change
+    fn record_links(&self, node: &Node<T>) -> Result<(), HNSWError> {
+        if let Some(undo) = self.undo.0.lock().unwrap().as_mut() {
+            if let Entry::Vacant(e) = undo.links.entry(node.clone()) {
+                let n = node.read()?;
+                e.insert((n.level, n.neighbors.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    // reverts the node changes recorded since begin_undo, the layer sets are rebuilt from the
+    // levels of the nodes
+    pub fn undo(&mut self, undo: Undo<T>) -> Result<(), HNSWError> {
+        for (name, prior) in undo.entries {
+            if let Some(current) = self.nodes.remove(&name) {
+                self.tags.remove(&name, &current.read()?.tags);
+            }
+            if let Some(node) = prior {
+                self.tags.add(&name, &node.read()?.tags);
+                self.nodes.insert(name, node);
+            }
+        }
+        for (node, (level, neighbors)) in undo.links {
+            let mut n = node.write()?;
+            n.level = level;
+            n.neighbors = neighbors;
+        }
+        self.node_count = undo.node_count;
+        self.max_layer = undo.max_layer;
+        self.flat_limit = undo.flat_limit;
+        self.enterpoint = undo.enterpoint;
+        if let Some(aliases) = undo.aliases {
+            self.node_aliases = aliases;
+        }
+        self.fill_slots()?;
+
+        self.layers = match self.nodes.is_empty() {
+            true => Vec::new(),
+            false => vec![HashSet::new(); self.max_layer + 1],
+        };
+        for node in self.nodes.values() {
+            let n = node.read()?;
+            self.layers[n.level].insert(n.id);
+        }
+        self.epoch += 1;
+        Ok(())
+    }
+
+    // limits of the number of neighbors of a node
+    pub fn degrees(&self) -> Degrees {
+        Degrees {
+            layer0: self.m_max_0,
+            upper: self.m_max,
+        }
+    }
+
+    // largest number of neighbors a node has on layer 0 and on the layers above
+    pub fn max_degrees(&self) -> Result<Degrees, HNSWError> {
+        let mut max = Degrees {
+            layer0: 0,
+            upper: 0,
+        };
+        for node in self.nodes.values() {
+            let n = node.read()?;
+            for (lc, neighbors) in n.neighbors.iter().enumerate() {
+                match lc {
+                    0 => max.layer0 = max.layer0.max(neighbors.len()),
+                    _ => max.upper = max.upper.max(neighbors.len()),
+                }
+            }
+        }
+        Ok(max)
+    }
+
+    // flat indexes are searched exhaustively and keep every node on layer 0
+    pub fn is_flat(&self) -> bool {
+        self.flat_limit > 0
+    }
 
-        if self.node_count == 0 {
-            let node = Node::new(name, data, self.m_max_0);
-            self.enterpoint = Some(node.downgrade());
+    // turns a flat index into HNSW by linking its nodes on the upper layers at random levels,
+    // as if they had been added to an HNSW index in the order of their names
+    fn promote(
+        &mut self,
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
+    ) -> Result<(), HNSWError> {
+        self.flat_limit = 0;
+        let mut names = self.nodes.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+        for name in &names {
+            let l = self.gen_random_level();
+            if l == 0 {
+                continue;
+            }
+            let node = self.nodes[name].clone();
+            self.record_links(&node)?;
+            node.write()?.level = l;
+            self.layers[0].remove(&node.id()?);
+            self.link(&node, 1, &update_fn)?;
+            update_fn(name.clone(), node)?;
+        }
+        self.epoch += 1;
+        Ok(())
+    }
+
+    // searches start at the enterpoint, which has to be on the top layer
+    pub fn set_enterpoint(&mut self, name: &str) -> Result<(), HNSWError> {
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| format!("Node: {:?} does not exist", name))?;
+        let level = node.read()?.level;
+        if level != self.max_layer {
+            return Err(format!(
+                "Node: {:?} is on layer: {}, not the top layer: {}",
+                name, level, self.max_layer
+            )
+            .into());
+        }
+        self.enterpoint = Some(node.id()?);
+        self.epoch += 1;
+        Ok(())
+    }
+
+    // ids of deleted nodes are reused before new slots are added
+    fn next_id(&mut self) -> Result<u32, HNSWError> {
+        if let Some(id) = self.free_ids.pop() {
+            return Ok(id);
+        }
+        let id = u32::try_from(self.slots.len()).map_err(|_| "Index ran out of node ids")?;
+        self.slots.push(None);
+        Ok(id)
+    }
+
+    fn set_slot(&mut self, node: &Node<T>) -> Result<(), HNSWError> {
+        let id = node.id()? as usize;
+        if self.slots.len() <= id {
+            self.slots.resize(id + 1, None);
+        }
+        self.slots[id] = Some(node.clone());
+        Ok(())
+    }
+
+    // slots and free ids from the ids of the nodes, the lowest free id is reused first
+    fn fill_slots(&mut self) -> Result<(), HNSWError> {
+        let mut slots: Vec<Option<Node<T>>> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.values() {
+            let id = node.id()? as usize;
+            if slots.len() <= id {
+                slots.resize(id + 1, None);
+            }
+            if slots[id].replace(node.clone()).is_some() {
+                return Err(format!("Node id: {} is not unique", id).into());
+            }
+        }
+        self.free_ids = (0..slots.len() as u32)
+            .rev()
+            .filter(|id| slots[*id as usize].is_none())
+            .collect();
+        self.slots = slots;
+        Ok(())
+    }
 
-            let mut layer = HashSet::new();
-            layer.insert(node.downgrade());
-            self.layers.push(layer);
+    // data holding more than one vector of data_dim makes a multi-vector node
+    fn new_node(&self, name: &str, data: &[T], capacity: usize) -> Result<Node<T>, HNSWError> {
+        let (data, norms) = if self.normalize {
+            normalize(data, self.data_dim)
+        } else {
+            (data.to_vec(), Vec::new())
+        };
+        let node = if data.len() > self.data_dim {
+            let vectors = data.chunks(self.data_dim).map(|c| c.to_vec()).collect();
+            Node::new_multi(name, vectors, capacity)
+        } else {
+            Node::new(name, &data, capacity)
+        };
+        node.write()?.norms = norms;
+        self.store_data(&node)?;
+        Ok(node)
+    }
+
+    // hands the data of a node to the storage of the index
+    fn store_data(&self, node: &Node<T>) -> Result<(), HNSWError> {
+        let mut n = node.write()?;
+        let data = std::mem::replace(&mut n.data, Vec::new().into());
+        n.data = self.storage.store(data)?;
+        Ok(())
+    }
 
+    // rebuilds the graph of a loaded index from its persisted nodes, which keep their ids
+    pub fn restore(
+        &mut self,
+        ids: &HashMap<String, u32>,
+        enterpoint: Option<&str>,
+        store: &impl NodeStore<T>,
+    ) -> Result<(), HNSWError> {
+        self.nodes = HashMap::with_capacity(ids.len());
+        for (name, id) in ids {
+            // the neighbor lists are restored below
+            let node = store.load(name, 0)?;
+            self.store_data(&node)?;
+            node.write()?.id = *id;
+            self.tags.add(name, &node.read()?.tags);
             self.nodes.insert(name.to_owned(), node);
-            self.node_count += 1;
+        }
+        self.fill_slots()?;
 
-            return Ok(());
+        for (name, node) in &self.nodes {
+            let neighbors = store.neighbors(name)?;
+            if let Some(id) = neighbors
+                .iter()
+                .flatten()
+                .find(|n| self.node_at(**n).is_err())
+            {
+                return Err(format!("Node: {} links to missing node id: {}", name, id).into());
+            }
+            node.write()?.neighbors = neighbors;
         }
 
-        if self.nodes.get(name).is_some() {
-            return Err(format!("Node: {:?} already exists", name).into());
+        // layers from the level of each node
+        self.layers = Vec::new();
+        for node in self.nodes.values() {
+            let n = node.read()?;
+            while self.layers.len() < n.level + 1 {
+                self.layers.push(HashSet::new());
+            }
+            self.layers[n.level].insert(n.id);
         }
 
-        self.insert(name, data, update_fn)
+        self.enterpoint = match enterpoint {
+            Some(name) => match ids.get(name) {
+                Some(id) => Some(*id),
+                None => return Err(format!("Node: {} does not exist", name).into()),
+            },
+            None => None,
+        };
+        Ok(())
+    }
+
+    // checks delete_node would be accepted, before anything is mutated
+    pub fn can_delete(&self, name: &str) -> Result<(), HNSWError> {
+        if self.frozen {
+            return Err("Index is frozen".into());
+        }
+        if !self.nodes.contains_key(name) && !self.node_aliases.contains_key(name) {
+            return Err(format!("Node: {:?} does not exist", name).into());
+        }
+        Ok(())
     }
 
+    // deleting an alias leaves the node it resolves to, deleting a node also deletes its
+    // aliases
     pub fn delete_node(
         &mut self,
         name: &str,
-        update_fn: impl Fn(String, Node<T>),
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
     ) -> Result<(), HNSWError> {
+        if self.frozen {
+            return Err("Index is frozen".into());
+        }
+        let aliases = match self.node_aliases.contains_key(name) {
+            true => vec![name.to_owned()],
+            false => self.aliases_of(name),
+        };
+        if !aliases.is_empty() {
+            self.record_aliases();
+            for alias in &aliases {
+                self.node_aliases.remove(alias);
+            }
+            if aliases[0] == name {
+                self.epoch += 1;
+                return Ok(());
+            }
+        }
+        self.record_entry(name);
         let node = match self.nodes.remove(name) {
             Some(node) => node,
             None => return Err(format!("Node: {:?} does not exist", name).into()),
         };
+        self.epoch += 1;
         // self.nodes.shrink_to_fit();
         self.node_count -= 1;
+        self.tags.remove(name, &node.read()?.tags);
+        let id = node.id()?;
 
         for lc in (0..(self.max_layer + 1)).rev() {
-            if self.layers[lc].remove(&node.downgrade()) {
+            if self.layers[lc].remove(&id) {
                 break;
             }
         }
 
         let mut updated = HashSet::new();
-        let nr = node.read();
-        for lc in 0..nr.neighbors.len() {
-            let up = self.delete_node_from_neighbors(&node, lc);
+        let num_levels = node.read()?.neighbors.len();
+        for lc in 0..num_levels {
+            let up = self.delete_node_from_neighbors(&node, lc)?;
             for u in up {
                 updated.insert(u);
             }
@@ -440,19 +1282,22 @@ where
 
         // update nodes in redis
         for n in updated {
-            let name = n.read().name.clone();
-            let node = n.clone();
-            update_fn(name, node);
+            let name = n.read()?.name.clone();
+            update_fn(name, n)?;
         }
 
+        // no node links to the id anymore
+        self.slots[id as usize] = None;
+        self.free_ids.push(id);
+
         // update enterpoint if necessary
-        match &self.enterpoint {
-            Some(ep) if node == ep.upgrade() => {
+        match self.enterpoint {
+            Some(ep) if ep == id => {
                 let mut new_ep = None;
                 for lc in (0..(self.max_layer + 1)).rev() {
                     match self.layers[lc].iter().next() {
                         Some(n) => {
-                            new_ep = Some(n.clone());
+                            new_ep = Some(*n);
                             break;
                         }
                         None => {
@@ -474,44 +1319,544 @@ where
         Ok(())
     }
 
+    // replaces the tags of a node
+    pub fn tag_node(&mut self, name: &str, tags: Vec<String>) -> Result<(), HNSWError> {
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| format!("Node: {:?} does not exist", name))?;
+        let mut n = node.write()?;
+        self.tags.remove(name, &n.tags);
+        self.tags.add(name, &tags);
+        n.tags = tags;
+        self.epoch += 1;
+        Ok(())
+    }
+
+    // replaces the numeric attributes of a node
+    pub fn set_attrs(&mut self, name: &str, attrs: BTreeMap<String, f64>) -> Result<(), HNSWError> {
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| format!("Node: {:?} does not exist", name))?;
+        node.write()?.attrs = attrs;
+        self.epoch += 1;
+        Ok(())
+    }
+
+    pub fn set_location(
+        &mut self,
+        name: &str,
+        location: Option<Location>,
+    ) -> Result<(), HNSWError> {
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| format!("Node: {:?} does not exist", name))?;
+        node.write()?.location = location;
+        self.epoch += 1;
+        Ok(())
+    }
+
+    // replaces the data of a node by deleting and inserting it again, the node keeps its name,
+    // tags, attributes and location and its version is incremented
+    pub fn update_node(
+        &mut self,
+        name: &str,
+        data: &[T],
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
+    ) -> Result<(), HNSWError> {
+        self.can_update(name, data)?;
+        let (version, tags, attrs, location) = {
+            let n = self.nodes[name].read()?;
+            (n.version, n.tags.clone(), n.attrs.clone(), n.location)
+        };
+        // the node keeps its aliases
+        let aliases = self.aliases_of(name);
+        self.delete_node(name, &update_fn)?;
+        self.insert_node(name, data, None, &update_fn)?;
+        self.nodes[name].write()?.version = version + 1;
+        self.tag_node(name, tags)?;
+        self.set_attrs(name, attrs)?;
+        self.set_location(name, location)?;
+        for alias in aliases {
+            self.node_aliases.insert(alias, name.to_owned());
+        }
+        Ok(())
+    }
+
+    // checks update_node would be accepted, before anything is mutated. a node can't become
+    // a duplicate of another node unless duplicates are allowed
+    pub fn can_update(&self, name: &str, data: &[T]) -> Result<(), HNSWError> {
+        if let Some(node) = self.node_aliases.get(name) {
+            return Err(format!("Node: {:?} is an alias of node: {:?}", name, node).into());
+        }
+        self.can_delete(name)?;
+        self.check_dim(data)?;
+        match self.duplicate_of(name, data)? {
+            Some(existing) => {
+                Err(format!("Node: {:?} duplicates node: {:?}", name, existing).into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // data holds one vector of data_dim or several for a multi-vector node, of finite values
+    fn check_dim(&self, data: &[T]) -> Result<(), HNSWError> {
+        let projected = self.projection.as_ref().is_some_and(|p| p.accepts(data));
+        if !projected && (data.is_empty() || data.len() % self.data_dim != 0) {
+            return Err(format!("data dimension: {} does not match Index", data.len()).into());
+        }
+        check_finite(data)
+    }
+
+    // data of the projection's dimensionality is projected, other data is used as is
+    pub fn project<'a>(&self, data: &'a [T]) -> Cow<'a, [T]> {
+        match &self.projection {
+            Some(p) if p.accepts(data) => Cow::Owned(p.apply(data)),
+            _ => Cow::Borrowed(data),
+        }
+    }
+
+    // name of a node without the node key prefix of the index
+    pub fn node_suffix<'a>(&self, name: &'a str) -> &'a str {
+        name.strip_prefix(node_prefix(&self.name).as_str())
+            .unwrap_or(name)
+    }
+
+    // node of the name with or without the index prefix
+    fn entry_node(&self, name: &str) -> Result<&Node<T>, HNSWError> {
+        self.nodes
+            .get(&node_key(&self.name, name))
+            .or_else(|| self.nodes.get(name))
+            .ok_or_else(|| format!("Entry node: {:?} does not exist", name).into())
+    }
+
+    // nodes are only in the layer set of their top level but part of every layer below it
+    pub fn layer_card(&self, layer: usize) -> usize {
+        self.layers.iter().skip(layer).map(|l| l.len()).sum()
+    }
+
+    // nodes of each layer by their number of neighbors on it, histograms[l][d] counts the nodes
+    // of layer l with d neighbors there. the histogram of a layer sums to the layer_card
+    pub fn degree_histograms(&self) -> Result<Vec<Vec<usize>>, HNSWError> {
+        let mut histograms: Vec<Vec<usize>> = vec![Vec::new(); self.layers.len()];
+        for node in self.nodes.values() {
+            let n = node.read()?;
+            for (lc, neighbors) in n.neighbors.iter().enumerate() {
+                if histograms.len() <= lc {
+                    histograms.resize(lc + 1, Vec::new());
+                }
+                let histogram = &mut histograms[lc];
+                if histogram.len() <= neighbors.len() {
+                    histogram.resize(neighbors.len() + 1, 0);
+                }
+                histogram[neighbors.len()] += 1;
+            }
+        }
+        Ok(histograms)
+    }
+
+    // the whole layer without a start node, otherwise the nodes of the layer reachable from
+    // start within depth hops, or without bound if depth is None
+    pub fn subgraph(
+        &self,
+        layer: usize,
+        start: Option<&str>,
+        depth: Option<usize>,
+    ) -> Result<Subgraph, HNSWError> {
+        let mut included: HashMap<u32, Node<T>> = HashMap::new();
+        match start {
+            None => {
+                for node in self.nodes.values() {
+                    let nr = node.read()?;
+                    if nr.level >= layer {
+                        included.insert(nr.id, node.clone());
+                    }
+                }
+            }
+            Some(start) => {
+                let node = self.entry_node(start)?;
+                let nr = node.read()?;
+                if nr.level < layer {
+                    return Err(format!(
+                        "Node: {:?} is not on layer {}, its top layer is {}",
+                        start, layer, nr.level
+                    )
+                    .into());
+                }
+                included.insert(nr.id, node.clone());
+                let mut frontier = vec![node.clone()];
+                let mut hops = 0;
+                while !frontier.is_empty() && depth.is_none_or(|d| hops < d) {
+                    let mut next = Vec::new();
+                    for node in &frontier {
+                        for neighbor in node.read()?.neighbors.get(layer).into_iter().flatten() {
+                            if let Entry::Vacant(e) = included.entry(*neighbor) {
+                                let neighbor = self.node_at(*neighbor)?;
+                                e.insert(neighbor.clone());
+                                next.push(neighbor.clone());
+                            }
+                        }
+                    }
+                    frontier = next;
+                    hops += 1;
+                }
+            }
+        }
+
+        let mut graph = Subgraph {
+            layer,
+            ..Subgraph::default()
+        };
+        for node in included.values() {
+            let nr = node.read()?;
+            let name = self.node_suffix(&nr.name);
+            graph.nodes.push((name.to_owned(), nr.level));
+            for neighbor in nr.neighbors.get(layer).into_iter().flatten() {
+                if let Some(neighbor) = included.get(neighbor) {
+                    let nname = neighbor.read()?.name.clone();
+                    graph
+                        .edges
+                        .push((name.to_owned(), self.node_suffix(&nname).to_owned()));
+                }
+            }
+        }
+        graph.nodes.sort();
+        graph.edges.sort();
+        Ok(graph)
+    }
+
+    // pairs of nodes linked on layer 0 with a similarity of at least threshold, most similar
+    // first. a node always keeps its nearest neighbors, so near duplicates are linked
+    pub fn duplicates(
+        &self,
+        threshold: R,
+        limit: usize,
+    ) -> Result<Vec<(String, String, R)>, HNSWError> {
+        let mut found: HashMap<(String, String), R> = HashMap::new();
+        for (name, node) in &self.nodes {
+            let nr = node.read()?;
+            for neighbor in nr.neighbors.first().into_iter().flatten() {
+                let nnr = self.node_at(*neighbor)?.read()?;
+                let sim = (self.mfunc)(&nr.data, &nnr.data, self.data_dim);
+                if sim < threshold {
+                    continue;
+                }
+                // links usually go both ways, each pair is kept once
+                let (a, b) = (self.node_suffix(name), self.node_suffix(&nnr.name));
+                let pair = if a < b { (a, b) } else { (b, a) };
+                found.insert((pair.0.to_owned(), pair.1.to_owned()), sim);
+            }
+        }
+
+        let mut pairs = found
+            .into_iter()
+            .map(|((a, b), sim)| (a, b, sim))
+            .collect::<Vec<_>>();
+        pairs.sort_by(|x, y| {
+            OrderedFloat(y.2)
+                .cmp(&OrderedFloat(x.2))
+                .then_with(|| (&x.0, &x.1).cmp(&(&y.0, &y.1)))
+        });
+        if limit > 0 {
+            pairs.truncate(limit);
+        }
+        Ok(pairs)
+    }
+
+    // reads every vector and link once and searches from evenly spaced nodes, so the memory
+    // of a freshly loaded index is paged in before the first query. returns the searches run
+    pub fn warm_up(&self, queries: usize) -> Result<usize, HNSWError> {
+        let mut sum = T::zero();
+        for node in self.nodes.values() {
+            let nr = node.read()?;
+            sum = nr
+                .vectors
+                .iter()
+                .flatten()
+                .chain(nr.data.iter())
+                .fold(sum, |acc, x| acc + *x);
+            for neighbor in nr.neighbors.iter().flatten() {
+                std::hint::black_box(self.slots.get(*neighbor as usize));
+            }
+        }
+        std::hint::black_box(sum);
+
+        if queries == 0 || self.enterpoint.is_none() {
+            return Ok(0);
+        }
+        let step = (self.nodes.len() / queries).max(1);
+        let mut run = 0;
+        for node in self.nodes.values().step_by(step).take(queries) {
+            let data = node.read()?.data.clone();
+            let params = SearchParams::default();
+            self.search_knn_internal(&data, self.m, self.ef_construction, &params)?;
+            run += 1;
+        }
+        Ok(run)
+    }
+
+    // copy of the index with its nodes linked anew by other build parameters, the nodes are
+    // added in the order of their names and keep their vectors and metadata
+    pub fn rebuilt(&self, m: usize, ef_construction: usize) -> Result<Self, HNSWError> {
+        self.rebuilt_with(m, ef_construction, |_| Ok(()))
+    }
+
+    // rebuilt, calling progress with the number of nodes placed after each node, an error of
+    // progress stops the rebuild
+    pub fn rebuilt_with(
+        &self,
+        m: usize,
+        ef_construction: usize,
+        progress: impl Fn(usize) -> Result<(), HNSWError>,
+    ) -> Result<Self, HNSWError> {
+        let mut index = Index::new(
+            &self.name,
+            Box::new(*self.mfunc),
+            self.data_dim,
+            m,
+            ef_construction,
+        );
+        index.mfunc_kind = self.mfunc_kind;
+        // a layer 0 limit set apart from M keeps its ratio to M
+        index.m_max_0 = (m * self.m_max_0 / self.m.max(1)).max(m);
+        index.fields = self.fields.clone();
+        index.shards = self.shards;
+        index.normalize = self.normalize;
+        index.extend_candidates = self.extend_candidates;
+        index.keep_pruned_connections = self.keep_pruned_connections;
+        index.frozen = self.frozen;
+        index.snapshot_reads = self.snapshot_reads;
+        index.flat_limit = self.flat_limit;
+        index.duplicates = self.duplicates;
+        index.dup_epsilon = self.dup_epsilon;
+        index.node_aliases = self.node_aliases.clone();
+        index.storage = self.storage.clone();
+        index.tags = self.tags.clone();
+        index.names = self.names.clone();
+        index.projection = self.projection.clone();
+        index.epoch = self.epoch + 1;
+
+        let mut names = self.nodes.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+        for name in &names {
+            index.place_copy(self.nodes[name].read()?.clone())?;
+            progress(index.node_count)?;
+        }
+        // nodes keep their ids, the ids free in the index stay free in the copy
+        index.fill_slots()?;
+        Ok(index)
+    }
+
+    // places a copy of a node of another index at a new level, the copy keeps the id, the
+    // vector and the metadata of the node
+    fn place_copy(&mut self, node: _Node<T>) -> Result<(), HNSWError> {
+        let l = if self.is_flat() || self.node_count == 0 {
+            0
+        } else {
+            self.gen_random_level()
+        };
+        let copy = _Node {
+            level: l,
+            neighbors: Vec::new(),
+            ..node
+        };
+        self.place(Node(Arc::new(RwLock::new(copy))), |_, _| Ok(()))
+    }
+
+    // applies the changes made to live since the index was rebuilt from a copy of it: nodes
+    // deleted or whose data changed are unlinked, nodes added or changed are linked anew, and
+    // the metadata of the nodes and the settings of live are taken over
+    pub fn catch_up(&mut self, live: &Self) -> Result<(), HNSWError> {
+        let mut stale = Vec::new();
+        for (name, node) in &self.nodes {
+            let version = node.read()?.version;
+            match live.nodes.get(name) {
+                Some(l) if l.read()?.version == version => {
+                    let l = l.read()?;
+                    let mut n = node.write()?;
+                    n.tags = l.tags.clone();
+                    n.attrs = l.attrs.clone();
+                    n.location = l.location;
+                }
+                _ => stale.push(name.clone()),
+            }
+        }
+        let frozen = std::mem::replace(&mut self.frozen, false);
+        for name in &stale {
+            self.delete_node(name, |_, _| Ok(()))?;
+        }
+        self.frozen = frozen;
+
+        let mut added = live
+            .nodes
+            .keys()
+            .filter(|name| !self.nodes.contains_key(*name))
+            .cloned()
+            .collect::<Vec<String>>();
+        added.sort();
+        for name in &added {
+            self.place_copy(live.nodes[name].read()?.clone())?;
+        }
+        self.fill_slots()?;
+
+        self.normalize = live.normalize;
+        self.extend_candidates = live.extend_candidates;
+        self.keep_pruned_connections = live.keep_pruned_connections;
+        self.frozen = live.frozen;
+        self.snapshot_reads = live.snapshot_reads;
+        self.flat_limit = live.flat_limit;
+        self.duplicates = live.duplicates;
+        self.dup_epsilon = live.dup_epsilon;
+        self.node_aliases = live.node_aliases.clone();
+        self.tags = live.tags.clone();
+        self.names = live.names.clone();
+        self.projection = live.projection.clone();
+        self.epoch = live.epoch + 1;
+        Ok(())
+    }
+
+    // vectors of up to n nodes picked at random, the same seed always picks the same nodes
+    pub fn sample_vectors(&self, n: usize, seed: u64) -> Result<Vec<Vec<T>>, HNSWError> {
+        let mut names = self.nodes.keys().collect::<Vec<&String>>();
+        names.sort();
+        names.shuffle(&mut StdRng::seed_from_u64(seed));
+        names
+            .into_iter()
+            .take(n)
+            .map(|name| Ok(self.nodes[name].read()?.data.to_vec()))
+            .collect()
+    }
+
+    // deep copy of the graph that shares no nodes or locks with the index
+    pub fn snapshot(&self) -> Result<Self, HNSWError> {
+        let mut nodes = HashMap::with_capacity(self.nodes.len());
+        for (name, node) in &self.nodes {
+            let copy = node.read()?.clone();
+            nodes.insert(name.clone(), Node(Arc::new(RwLock::new(copy))));
+        }
+
+        // links, layers and the enterpoint are ids, which the copies keep
+        let mut index = self.clone();
+        index.nodes = nodes;
+        index.fill_slots()?;
+        Ok(index)
+    }
+
     pub fn search_knn(&self, data: &[T], k: usize) -> Result<Vec<SearchResult<T, R>>, HNSWError> {
+        self.search_knn_with(data, k, &SearchParams::default())
+    }
+
+    pub fn search_knn_with(
+        &self,
+        data: &[T],
+        k: usize,
+        params: &SearchParams,
+    ) -> Result<Vec<SearchResult<T, R>>, HNSWError> {
+        self.search_knn_stats(data, k, params).map(|(res, _)| res)
+    }
+
+    // also returns how the graph was traversed, e.g. whether the budget ran out
+    pub fn search_knn_stats(
+        &self,
+        data: &[T],
+        k: usize,
+        params: &SearchParams,
+    ) -> Result<(Vec<SearchResult<T, R>>, SearchStats), HNSWError> {
+        let data = &self.project(data);
         if data.len() != self.data_dim {
             return Err(format!("data dimension: {} does not match Index", data.len()).into());
         }
+        check_finite(data)?;
         if self.enterpoint.is_none() || self.node_count == 0 {
-            return Ok(Vec::new());
+            return Ok((Vec::new(), SearchStats::default()));
+        }
+
+        if self.normalize {
+            let (query, _) = normalize(data, self.data_dim);
+            return self.search_knn_internal(&query, k, self.ef_construction, params);
         }
+        self.search_knn_internal(data, k, self.ef_construction, params)
+    }
 
-        Ok(self.search_knn_internal(data, k, self.ef_construction))
+    // NOTNEAR references in the space of the stored vectors, with their radius
+    fn references(&self, params: &SearchParams) -> Result<Vec<(Vec<T>, R)>, HNSWError> {
+        let mut refs = Vec::with_capacity(params.not_near.len());
+        for nn in &params.not_near {
+            let data = nn
+                .data
+                .iter()
+                .map(|x| T::from(*x).unwrap())
+                .collect::<Vec<T>>();
+            let data = match nn.stored {
+                true => data,
+                false => self.project(&data).into_owned(),
+            };
+            if data.len() != self.data_dim {
+                return Err(
+                    format!("NOTNEAR dimension: {} does not match Index", data.len()).into(),
+                );
+            }
+            let data = match self.normalize && !nn.stored {
+                true => normalize(&data, self.data_dim).0,
+                false => data,
+            };
+            refs.push((data, R::from(nn.radius).unwrap()));
+        }
+        Ok(refs)
     }
 
-    // perform insertion of new nodes into the index
-    fn insert(
+    // adds a node at its level and links it to its nearest neighbors
+    fn place(
         &mut self,
-        name: &str,
-        data: &[T],
-        update_fn: impl Fn(String, Node<T>),
+        node: Node<T>,
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
     ) -> Result<(), HNSWError> {
-        let l = self.gen_random_level();
-        let l_max = self.max_layer;
+        let (name, id, l) = {
+            let nr = node.read()?;
+            (nr.name.clone(), nr.id, nr.level)
+        };
+        self.record_entry(&name);
+        self.set_slot(&node)?;
+        self.nodes.insert(name, node.clone());
+        self.node_count += 1;
 
-        if l_max == 0 {
-            self.nodes
-                .insert(name.to_owned(), Node::new(name, data, self.m_max_0));
-        } else {
-            self.nodes
-                .insert(name.to_owned(), Node::new(name, data, self.m_max));
+        if self.node_count == 1 {
+            self.enterpoint = Some(id);
+            self.max_layer = l;
+            self.layers = vec![HashSet::new(); l + 1];
+            self.layers[l].insert(id);
+            return Ok(());
         }
-        self.node_count += 1;
+        self.link(&node, 0, update_fn)
+    }
 
-        let query = self.nodes.get(name).unwrap();
-        let mut ep = self.enterpoint.as_ref().unwrap().clone();
+    // connects a node on the layers from min_layer up to its level and adds it to the layer
+    // set of its level, making it the enterpoint if it is above the top layer
+    fn link(
+        &mut self,
+        query: &Node<T>,
+        min_layer: usize,
+        update_fn: impl Fn(String, Node<T>) -> Result<(), HNSWError>,
+    ) -> Result<(), HNSWError> {
+        let l_max = self.max_layer;
+        let (id, l) = {
+            let qr = query.read()?;
+            (qr.id, qr.level)
+        };
+        // multi-vector nodes are inserted by their centroid
+        let data = &query.read()?.data.clone();
+        let mut ep = self.node_at(self.enterpoint.unwrap())?.clone();
+        // the searches give the enterpoint a list on layers it has no neighbors on yet
+        self.record_links(&ep)?;
         let mut w: BinaryHeap<SimPair<T, R>>;
+        let mut traversal = Traversal::unbounded();
 
         let mut lc = l_max;
         while lc > l {
-            w = self.search_level(data, &ep.upgrade(), 1, lc);
-            ep = w.pop().unwrap().read().node.downgrade();
+            w = self.search_level(data, &ep, 1, lc, None, &mut traversal)?;
+            ep = w.pop().unwrap().read().node.clone();
 
             if lc == 0 {
                 break;
@@ -520,16 +1865,16 @@ where
         }
 
         let mut updated = HashSet::new();
-        for lc in (0..(min(l_max, l) + 1)).rev() {
-            w = self.search_level(data, &ep.upgrade(), self.ef_construction, lc);
+        for lc in (min_layer..(min(l_max, l) + 1)).rev() {
+            w = self.search_level(data, &ep, self.ef_construction, lc, None, &mut traversal)?;
             let params = SelectParams {
                 m: self.m,
                 lc,
-                extend_candidates: true,
-                keep_pruned_connections: true,
+                extend_candidates: self.extend_candidates,
+                keep_pruned_connections: self.keep_pruned_connections,
             };
-            let mut neighbors = self.select_neighbors(query, &w, params, None);
-            self.connect_neighbors(query, &neighbors, lc);
+            let mut neighbors = self.select_neighbors(query, &w, params, None)?;
+            self.connect_neighbors(query, &neighbors, lc)?;
 
             // add node to list of nodes to be updated in redis
             for npair in &neighbors {
@@ -543,57 +1888,57 @@ where
 
                 let mut econn: BinaryHeap<SimPair<T, R>>;
                 {
-                    let enr = er.node.read();
+                    let enr = er.node.read()?;
                     let eneighbors = &enr.neighbors[lc];
                     econn = BinaryHeap::with_capacity(eneighbors.len());
                     for n in eneighbors {
+                        let n = self.node_at(*n)?;
                         let ensim = OrderedFloat::from((self.mfunc)(
                             &enr.data,
-                            &n.upgrade().read().data,
+                            &n.read()?.data,
                             self.data_dim,
                         ));
-                        let enpair = SimPair::new(ensim, n.upgrade());
+                        let enpair = SimPair::new(ensim, n.clone());
                         econn.push(enpair);
                     }
                 }
 
-                let m_max = if lc == 0 { self.m_max_0 } else { self.m_max };
+                let m_max = self.degrees().on(lc);
                 if econn.len() > m_max {
                     let params = SelectParams {
                         m: m_max,
                         lc,
-                        extend_candidates: true,
-                        keep_pruned_connections: true,
+                        extend_candidates: self.extend_candidates,
+                        keep_pruned_connections: self.keep_pruned_connections,
                     };
-                    let enewconn = self.select_neighbors(&er.node, &econn, params, None);
-                    let up = self.update_node_connections(&er.node, &enewconn, &econn, lc, None);
+                    let enewconn = self.select_neighbors(&er.node, &econn, params, None)?;
+                    let up = self.update_node_connections(&er.node, &enewconn, &econn, lc, None)?;
                     for u in up {
                         updated.insert(u);
                     }
                 }
             }
 
-            ep = w.peek().unwrap().read().node.downgrade();
+            ep = w.peek().unwrap().read().node.clone();
         }
 
         // update nodes in redis
         for n in updated {
-            let name = n.read().name.clone();
-            let node = n.clone();
-            update_fn(name, node);
+            let name = n.read()?.name.clone();
+            update_fn(name, n)?;
         }
 
         // new enterpoint if we're in a higher layer
         if l > l_max {
             self.max_layer = l;
-            self.enterpoint = Some(query.downgrade());
+            self.enterpoint = Some(id);
             while self.layers.len() < l + 1 {
                 self.layers.push(HashSet::new());
             }
         }
 
         // add node to layer set
-        self.layers[l].insert(query.downgrade());
+        self.layers[l].insert(id);
 
         Ok(())
     }
@@ -610,68 +1955,127 @@ where
         ep: &Node<T>,
         ef: usize,
         level: usize,
-    ) -> BinaryHeap<SimPair<T, R>> {
-        let mut v = HashSet::with_capacity(ef);
-
-        {
-            v.insert(ep.clone());
-        }
+        filter: Option<&NodeFilter<T>>,
+        traversal: &mut Traversal<T, R>,
+    ) -> Result<BinaryHeap<SimPair<T, R>>, HNSWError> {
+        traversal.visited.clear(self.slots.len());
+        traversal.visited.insert(ep.id()?);
         let qsim: OrderedFloat<R>;
         {
-            qsim = OrderedFloat::from((self.mfunc)(query, &ep.read().data, self.data_dim));
+            qsim = OrderedFloat::from((self.mfunc)(query, &ep.read()?.data, self.data_dim));
         }
+        traversal.stats.distances += 1;
         let qpair = SimPair::new(qsim, ep.clone());
+        let qnorm = sq_norm(query).sqrt();
 
-        let mut c = BinaryHeap::with_capacity(ef);
-        let mut w = BinaryHeap::with_capacity(ef);
+        let mut c = std::mem::take(&mut traversal.candidates);
+        c.clear();
+        let mut w = BinaryHeap::with_capacity(ef + 1);
         c.push(qpair.clone());
-        w.push(Reverse(qpair));
+        if is_allowed(filter, ep)? {
+            w.push(Reverse(qpair));
+        }
+        // the unvisited neighbors of a candidate are compared to the query in one batch
+        let mut batch: Vec<&Node<T>> = Vec::new();
+        let mut guards = Vec::new();
+        let mut sims: Vec<R> = Vec::new();
 
         while !c.is_empty() {
             let mut cpair = c.pop().unwrap();
-            let mut fpair = w.peek().unwrap();
 
-            {
-                if cpair.read().sim < fpair.0.read().sim {
+            // with a filter W only holds allowed nodes, keep traversing until it is full
+            if let Some(fpair) = w.peek() {
+                if cpair.read().sim < fpair.0.read().sim && (filter.is_none() || w.len() >= ef) {
                     break;
                 }
             }
 
             // update C and W
             {
-                cpair.write().node.push_levels(level, Some(self.m_max_0));
+                cpair
+                    .write()
+                    .node
+                    .push_levels(level, Some(self.degrees()))?;
             }
             let cpr = cpair.read();
-            let neighbors = &cpr.node.read().neighbors[level];
+            let neighbors = &cpr.node.read()?.neighbors[level];
+            batch.clear();
             for neighbor in neighbors {
-                let neighbor = neighbor.upgrade();
-                if !v.contains(&neighbor) {
-                    v.insert(neighbor.clone());
-
-                    fpair = w.peek().unwrap();
-                    let esim = OrderedFloat::from((self.mfunc)(
-                        query,
-                        &neighbor.read().data,
-                        self.data_dim,
-                    ));
-                    if esim > fpair.0.read().sim || w.len() < ef {
-                        let epair = SimPair::new(esim, neighbor.clone());
-                        c.push(epair.clone());
-                        w.push(Reverse(epair));
-
-                        if w.len() > ef {
-                            w.pop();
+                if !traversal.visited.insert(*neighbor) {
+                    continue;
+                }
+                if !traversal.visit() {
+                    break;
+                }
+                let neighbor = self.node_at(*neighbor)?;
+
+                if w.len() >= ef {
+                    if let Some(bound) = self.sim_bound(qnorm, neighbor)? {
+                        if bound < w.peek().unwrap().0.read().sim {
+                            continue;
                         }
                     }
                 }
+                batch.push(neighbor);
+            }
+            for neighbor in &batch {
+                let guard = neighbor.read()?;
+                // the loads of all the neighbor vectors overlap instead of stalling one by one
+                #[cfg(feature = "prefetch")]
+                metrics::prefetch(&guard.data);
+                guards.push(guard);
+            }
+            metrics::batch(
+                *self.mfunc,
+                query,
+                guards.iter().map(|n| &n.data[..]),
+                self.data_dim,
+                &mut sims,
+            );
+            guards.clear();
+            traversal.stats.distances += batch.len();
+
+            for (neighbor, sim) in batch.iter().zip(&sims) {
+                let neighbor = *neighbor;
+                let esim = OrderedFloat::from(*sim);
+                let closer = match w.peek() {
+                    Some(fpair) => esim > fpair.0.read().sim,
+                    None => true,
+                };
+                if closer || w.len() < ef {
+                    let epair = SimPair::new(esim, neighbor.clone());
+                    c.push(epair.clone());
+                    if !is_allowed(filter, neighbor)? {
+                        continue;
+                    }
+                    w.push(Reverse(epair));
+
+                    if w.len() > ef {
+                        w.pop();
+                    }
+                }
+            }
+            if traversal.stats.truncated {
+                break;
             }
         }
 
-        let mut res = BinaryHeap::new();
-        for pair in w {
-            res.push(pair.0);
+        traversal.candidates = c;
+        // Reverse is transparent, the pairs are unwrapped in place
+        let res: Vec<SimPair<T, R>> = w.into_vec().into_iter().map(|pair| pair.0).collect();
+        Ok(BinaryHeap::from(res))
+    }
+
+    // upper bound of the similarity to a node from the triangle inequality,
+    // -|q - x|^2 <= -(|q| - |x|)^2, only available for euclidean indexes
+    fn sim_bound(&self, qnorm: T, node: &Node<T>) -> Result<Option<OrderedFloat<R>>, HNSWError> {
+        match self.mfunc_kind {
+            metrics::MetricFuncs::Euclidean => {
+                let diff = qnorm - node.read()?.sq_norm.sqrt();
+                Ok(R::from(-(diff * diff)).map(OrderedFloat::from))
+            }
+            _ => Ok(None),
         }
-        res
     }
 
     fn select_neighbors(
@@ -680,80 +2084,74 @@ where
         c: &BinaryHeap<SimPair<T, R>>,
         params: SelectParams,
         ignored_node: Option<&Node<T>>,
-    ) -> BinaryHeap<SimPair<T, R>> {
-        let mut r: BinaryHeap<SimPair<T, R>> = BinaryHeap::with_capacity(params.m);
-        let mut w = c.clone();
-        let mut wd = BinaryHeap::new();
+    ) -> Result<BinaryHeap<SimPair<T, R>>, HNSWError> {
+        let ignored = |node: &Node<T>| node == query || ignored_node == Some(node);
 
         // extend candidates by their neighbors
+        let mut extended = Vec::new();
         if params.extend_candidates {
-            let mut ccopy = c.clone();
-
-            let mut v = HashSet::with_capacity(ccopy.capacity());
-            while !ccopy.is_empty() {
-                let epair = ccopy.pop().unwrap();
-                v.insert(epair.read().node.clone());
+            let mut v = HashSet::with_capacity(c.len());
+            for epair in c.iter() {
+                v.insert(epair.read().node.id()?);
             }
 
-            ccopy = c.clone();
-            while !ccopy.is_empty() {
-                let epair = ccopy.pop().unwrap();
-
-                for eneighbor in &epair.read().node.read().neighbors[params.lc] {
-                    let eneighbor = eneighbor.upgrade();
-                    if eneighbor == *query
-                        || (ignored_node.is_some() && eneighbor == *ignored_node.unwrap())
-                    {
+            let qid = query.id()?;
+            let ignored_id = match ignored_node {
+                Some(n) => Some(n.id()?),
+                None => None,
+            };
+            for epair in c.iter() {
+                for eneighbor in &epair.read().node.read()?.neighbors[params.lc] {
+                    if *eneighbor == qid || ignored_id == Some(*eneighbor) {
                         continue;
                     }
 
-                    if !v.contains(&eneighbor) {
+                    if v.insert(*eneighbor) {
+                        let node = self.node_at(*eneighbor)?;
                         let ensim = OrderedFloat::from((self.mfunc)(
-                            &query.read().data,
-                            &eneighbor.read().data,
+                            &query.read()?.data,
+                            &node.read()?.data,
                             self.data_dim,
                         ));
-                        let enpair = SimPair::new(ensim, eneighbor.clone());
-                        w.push(enpair);
-                        v.insert(eneighbor.clone());
+                        extended.push(SimPair::new(ensim, node.clone()));
                     }
                 }
             }
         }
 
-        while !w.is_empty() && r.len() < params.m {
-            let epair = w.pop().unwrap();
-            let enr = epair.read();
+        // walk the candidates best first without copying the heap
+        let mut w: Vec<&SimPair<T, R>> = c.iter().chain(extended.iter()).collect();
+        w.sort_unstable_by(|a, b| b.cmp(a));
 
-            if enr.node == *query || (ignored_node.is_some() && enr.node == *ignored_node.unwrap())
-            {
+        let mut r: BinaryHeap<SimPair<T, R>> = BinaryHeap::with_capacity(params.m);
+        let mut wd = Vec::new();
+        for epair in w {
+            if r.len() >= params.m {
+                break;
+            }
+            let enr = epair.read();
+            if ignored(&enr.node) {
                 continue;
             }
 
             if r.is_empty() || enr.sim > r.peek().unwrap().read().sim {
                 r.push(epair.clone());
             } else {
-                wd.push(epair.clone());
+                wd.push(epair);
             }
         }
 
-        // add back some of the discarded connections
+        // add back some of the discarded connections, wd is already best first
         if params.keep_pruned_connections {
-            while !wd.is_empty() && r.len() < params.m {
-                let ppair = wd.pop().unwrap();
-                {
-                    let pr = ppair.read();
-                    if pr.node == *query
-                        || (ignored_node.is_some() && pr.node == *ignored_node.unwrap())
-                    {
-                        continue;
-                    }
+            for ppair in wd {
+                if r.len() >= params.m {
+                    break;
                 }
-                r.push(ppair);
+                r.push(ppair.clone());
             }
         }
 
-        r
+        Ok(r)
     }
 
     fn connect_neighbors(
@@ -761,16 +2159,18 @@ where
         query: &Node<T>,
         neighbors: &BinaryHeap<SimPair<T, R>>,
         level: usize,
-    ) {
+    ) -> Result<(), HNSWError> {
         let mut neighbors = neighbors.clone();
         while !neighbors.is_empty() {
             let npair = neighbors.pop().unwrap();
             let npr = npair.read();
 
-            query.add_neighbor(level, npr.node.downgrade(), Some(self.m_max_0));
-            npr.node
-                .add_neighbor(level, query.downgrade(), Some(self.m_max_0));
+            self.record_links(query)?;
+            self.record_links(&npr.node)?;
+            query.add_neighbor(level, &npr.node, Some(self.degrees()))?;
+            npr.node.add_neighbor(level, query, Some(self.degrees()))?;
         }
+        Ok(())
     }
 
     fn update_node_connections(
@@ -780,19 +2180,30 @@ where
         old_neighbors: &BinaryHeap<SimPair<T, R>>,
         level: usize,
         ignored_node: Option<&Node<T>>,
-    ) -> HashSet<Node<T>> {
+    ) -> Result<HashSet<Node<T>>, HNSWError> {
         let mut newconn = new_neighbors.clone();
         let mut rmconn = old_neighbors.clone().into_vec();
         let mut updated = HashSet::new();
         updated.insert(node.clone());
+        self.record_links(node)?;
 
-        // bidirectionally connect new neighbors
+        // bidirectionally connect new neighbors, a candidate of the extended set that is at
+        // its degree limit is skipped, linking it would exceed the limit
+        let id = node.id()?;
+        let limit = self.degrees().on(level);
         while !newconn.is_empty() {
             let newpair = newconn.pop().unwrap();
             let npr = newpair.read();
-            node.add_neighbor(level, npr.node.downgrade(), Some(self.m_max_0));
-            npr.node
-                .add_neighbor(level, node.downgrade(), Some(self.m_max_0));
+            let full = match npr.node.read()?.neighbors.get(level) {
+                Some(n) => n.len() >= limit && !n.contains(&id),
+                None => false,
+            };
+            if full {
+                continue;
+            }
+            self.record_links(&npr.node)?;
+            node.add_neighbor(level, &npr.node, Some(self.degrees()))?;
+            npr.node.add_neighbor(level, node, Some(self.degrees()))?;
             updated.insert(npr.node.clone());
             // if new neighbor exists in the old set then we remove it from
             // the set of neighbors to be removed
@@ -805,89 +2216,242 @@ where
         while !rmconn.is_empty() {
             let rmpair = rmconn.pop().unwrap();
             let rmpr = rmpair.read();
-            node.rm_neighbor(level, &rmpr.node.downgrade());
+            node.rm_neighbor(level, &rmpr.node)?;
             // if node to be removed is the ignored node then pass
             match ignored_node {
                 Some(n) if rmpr.node == *n => {
                     continue;
                 }
                 _ => {
-                    rmpr.node.rm_neighbor(level, &node.downgrade());
+                    self.record_links(&rmpr.node)?;
+                    rmpr.node.rm_neighbor(level, node)?;
                     updated.insert(rmpr.node.clone());
                 }
             }
         }
 
-        updated
+        Ok(updated)
     }
 
-    fn delete_node_from_neighbors(&self, node: &Node<T>, lc: usize) -> HashSet<Node<T>> {
-        let r = node.read();
+    fn delete_node_from_neighbors(
+        &self,
+        node: &Node<T>,
+        lc: usize,
+    ) -> Result<HashSet<Node<T>>, HNSWError> {
+        let r = node.read()?;
         let neighbors = &r.neighbors[lc];
         let mut updated = HashSet::new();
 
         for n in neighbors {
-            let n = n.upgrade();
+            let n = self.node_at(*n)?;
             let nnewconn: BinaryHeap<SimPair<T, R>>;
             let mut nconn: BinaryHeap<SimPair<T, R>>;
             {
-                let nr = n.read();
+                let nr = n.read()?;
                 let nneighbors = &nr.neighbors[lc];
                 nconn = BinaryHeap::with_capacity(nneighbors.len());
 
                 for nn in nneighbors {
-                    let nn = nn.upgrade();
+                    let nn = self.node_at(*nn)?;
                     let nnsim =
-                        OrderedFloat::from((self.mfunc)(&nr.data, &nn.read().data, self.data_dim));
-                    let nnpair = SimPair::new(nnsim, nn.to_owned());
+                        OrderedFloat::from((self.mfunc)(&nr.data, &nn.read()?.data, self.data_dim));
+                    let nnpair = SimPair::new(nnsim, nn.clone());
                     nconn.push(nnpair);
                 }
 
-                let m_max = if lc == 0 { self.m_max_0 } else { self.m_max };
+                let m_max = self.degrees().on(lc);
                 let params = SelectParams {
                     m: m_max,
                     lc,
-                    extend_candidates: true,
-                    keep_pruned_connections: true,
+                    extend_candidates: self.extend_candidates,
+                    keep_pruned_connections: self.keep_pruned_connections,
                 };
-                nnewconn = self.select_neighbors(&n, &nconn, params, Some(node));
+                nnewconn = self.select_neighbors(n, &nconn, params, Some(node))?;
             }
             updated.insert(n.clone());
-            let up = self.update_node_connections(&n, &nnewconn, &nconn, lc, Some(node));
+            let up = self.update_node_connections(n, &nnewconn, &nconn, lc, Some(node))?;
             for u in up {
                 updated.insert(u);
             }
         }
 
-        updated
+        Ok(updated)
     }
 
-    fn search_knn_internal(&self, query: &[T], k: usize, ef: usize) -> Vec<SearchResult<T, R>> {
-        let mut ep = self.enterpoint.as_ref().unwrap().clone();
-        let l_max = self.max_layer;
+    // similarity of the query to a node, aggregated over all vectors of multi-vector nodes
+    fn score(&self, query: &[T], node: &_Node<T>, multivalue: MultiValue) -> OrderedFloat<R> {
+        let sims = node
+            .vectors
+            .iter()
+            .map(|v| (self.mfunc)(query, v, self.data_dim));
+        let sim = match multivalue {
+            MultiValue::Max => sims.fold(R::neg_infinity(), R::max),
+            MultiValue::Avg => {
+                sims.fold(R::zero(), |acc, s| acc + s) / R::from(node.vectors.len()).unwrap()
+            }
+        };
+        OrderedFloat::from(sim)
+    }
+
+    fn search_knn_internal(
+        &self,
+        query: &[T],
+        k: usize,
+        ef: usize,
+        params: &SearchParams,
+    ) -> Result<(Vec<SearchResult<T, R>>, SearchStats), HNSWError> {
+        let mut traversal = Traversal::new(params);
+        let refs = self.references(params)?;
+
+        // tag-only filters are resolved from the tag sets and intersected with the candidates
+        // while traversing layer 0, attribute comparisons are evaluated on every visited node
+        let allowed = params
+            .filter
+            .as_ref()
+            .and_then(|filter| self.tags.eval(filter, &self.nodes));
+        if allowed.as_ref().is_some_and(|a| a.is_empty()) {
+            return Ok((Vec::new(), std::mem::take(&mut traversal.stats)));
+        }
+        let matches = |n: &_Node<T>| {
+            let tagged = match (&allowed, &params.filter) {
+                (Some(allowed), _) => allowed.contains(&n.name),
+                (None, Some(filter)) => filter.matches(&n.tags, &n.attrs),
+                (None, None) => true,
+            };
+            // a multi-vector node is too similar if any of its vectors is
+            let near = |(v, radius): &(Vec<T>, R)| match n.vectors.is_empty() {
+                true => (self.mfunc)(v, &n.data, self.data_dim) >= *radius,
+                false => self.score(v, n, MultiValue::Max).into_inner() >= *radius,
+            };
+            tagged
+                && params
+                    .within
+                    .is_none_or(|g| g.contains(n.location.as_ref()))
+                && !refs.iter().any(near)
+        };
+        let filter = if params.filter.is_some() || params.within.is_some() || !refs.is_empty() {
+            Some(&matches as &NodeFilter<T>)
+        } else {
+            None
+        };
+
+        let mut candidates = if self.is_flat() {
+            self.scan(query, params.multivalue, filter, &mut traversal)?
+        } else {
+            self.search_candidates(query, k, ef, params, filter, &mut traversal)?
+        };
+        candidates.sort_by_key(|c| Reverse(c.0));
+
+        let mut res = Vec::with_capacity(k);
+        for (sim, node) in candidates.into_iter().take(k) {
+            let cnr = node.read()?;
+            let mut r = SearchResult::new(
+                sim,
+                &cnr.name,
+                self.node_suffix(&cnr.name),
+                &cnr.original_data(),
+            );
+            r.tags = cnr.tags.clone();
+            res.push(r);
+        }
+        Ok((res, std::mem::take(&mut traversal.stats)))
+    }
+
+    // similarity of the query to every node passing the filter, flat indexes are small
+    // enough to be searched exhaustively
+    fn scan(
+        &self,
+        query: &[T],
+        multivalue: MultiValue,
+        filter: Option<&NodeFilter<T>>,
+        traversal: &mut Traversal<T, R>,
+    ) -> Result<Candidates<T, R>, HNSWError> {
+        let start = Instant::now();
+        let mut candidates = Vec::new();
+        for node in self.nodes.values() {
+            if !traversal.visit() {
+                break;
+            }
+            let nr = node.read()?;
+            if filter.is_some_and(|f| !f(&nr)) {
+                continue;
+            }
+            let sim = if nr.vectors.is_empty() {
+                traversal.stats.distances += 1;
+                OrderedFloat::from((self.mfunc)(query, &nr.data, self.data_dim))
+            } else {
+                traversal.stats.distances += nr.vectors.len();
+                self.score(query, &nr, multivalue)
+            };
+            candidates.push((sim, node.clone()));
+        }
+        traversal.add_time(0, start);
+        Ok(candidates)
+    }
+
+    // candidates for the k nearest nodes found by routing through the upper layers and
+    // traversing layer 0
+    fn search_candidates(
+        &self,
+        query: &[T],
+        k: usize,
+        ef: usize,
+        params: &SearchParams,
+        filter: Option<&NodeFilter<T>>,
+        traversal: &mut Traversal<T, R>,
+    ) -> Result<Candidates<T, R>, HNSWError> {
+        let mut ep = self.node_at(self.enterpoint.unwrap())?.clone();
+        // a caller-provided entry node near the query replaces routing through upper layers
+        let l_max = match &params.entry {
+            Some(entry) => {
+                ep = self.entry_node(entry)?.clone();
+                0
+            }
+            None => self.max_layer,
+        };
 
         let mut lc = l_max;
         while lc > 0 {
-            let w = self.search_level(query, &ep.upgrade(), 1, lc);
-            ep = w.peek().unwrap().read().node.downgrade();
+            let start = Instant::now();
+            let w = self.search_level(query, &ep, 1, lc, None, traversal)?;
+            traversal.add_time(lc, start);
+            ep = w.peek().unwrap().read().node.clone();
             lc -= 1;
         }
 
-        let mut w = self.search_level(query, &ep.upgrade(), ef, 0);
+        // over-fetch until k nodes pass the filter or the whole index has been searched
+        let mut ef = if filter.is_some() { max(ef, k) } else { ef };
+        let mut w = loop {
+            let start = Instant::now();
+            let w = self.search_level(query, &ep, ef, 0, filter, traversal)?;
+            traversal.add_time(0, start);
+            if filter.is_none()
+                || w.len() >= k
+                || ef >= self.node_count
+                || traversal.stats.truncated
+            {
+                break w;
+            }
+            ef = min(ef * 2, self.node_count);
+        };
 
-        let mut res = Vec::with_capacity(k);
-        while res.len() < k && !w.is_empty() {
+        // TODO RERANK {n}: graph distances are exact since vectors aren't quantized yet. once
+        // compressed vectors are used for traversal, fetch n > k candidates here and rescore
+        // them below with the full-precision vectors kept in the node keys
+        // rescore multi-vector nodes by their aggregated similarity
+        let mut candidates = Vec::with_capacity(w.len());
+        while !w.is_empty() {
             let c = w.pop().unwrap();
             let cr = c.read();
-            let cnr = cr.node.read();
-            res.push(SearchResult::new(
-                cr.sim,
-                &((&cnr.name).split('.').collect::<Vec<&str>>())
-                    .last()
-                    .unwrap(),
-                &cnr.data,
-            ));
-        }
-        res
+            let cnr = cr.node.read()?;
+            let sim = if cnr.vectors.is_empty() {
+                cr.sim
+            } else {
+                traversal.stats.distances += cnr.vectors.len();
+                self.score(query, &cnr, params.multivalue)
+            };
+            candidates.push((sim, cr.node.clone()));
+        }
+        Ok(candidates)
     }
 }
@@ -1,8 +1,32 @@
 use crate::hnsw::core::*;
+use crate::hnsw::dedupe::DuplicatePolicy;
+use crate::hnsw::geo::{GeoFence, Location};
 use crate::hnsw::metrics::euclidean;
+use crate::hnsw::names::NameRule;
+use crate::hnsw::projection::Projection;
+use crate::hnsw::storage::{NodeStore, Relocate, SlabStorage, StorageKind, Vector};
+use crate::hnsw::tags::Filter;
+use ordered_float::OrderedFloat;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 // use std::{thread, time};
 
+// result of a node of index foo, for the tests of grouping and deduplicating results
+pub(crate) fn result(name: &str, sim: f32, data: &[f32], tags: &[&str]) -> SearchResult<f32, f32> {
+    SearchResult {
+        sim: OrderedFloat(sim),
+        name: name.to_owned(),
+        key: format!("hnsw:node:{{foo}}:{}", name),
+        data: data.to_vec(),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        aliases: Vec::new(),
+    }
+}
+
+pub(crate) fn names(res: &[SearchResult<f32, f32>]) -> Vec<&str> {
+    res.iter().map(|r| r.name.as_str()).collect()
+}
+
 #[test]
 fn hnsw_test() {
     let n = 100;
@@ -18,7 +42,7 @@ fn hnsw_test() {
     assert_eq!(index.max_layer, 0);
     assert_eq!(index.enterpoint, None);
 
-    let mock_fn = |_s: String, _n: Node<f32>| {};
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
 
     // add node
     for i in 0..n {
@@ -32,11 +56,12 @@ fn hnsw_test() {
     for i in 0..n {
         let node_name = format!("node{}", i);
         let node = index.nodes.get(&node_name).unwrap();
+        // held by the node map and the slot of its id
         let sc = Arc::strong_count(&node.0);
-        if sc > 1 {
+        if sc > 2 {
             println!("{:?}", node);
         }
-        assert_eq!(sc, 1);
+        assert_eq!(sc, 2);
     }
     assert_eq!(index.node_count, n);
     assert_ne!(index.enterpoint, None);
@@ -56,17 +81,17 @@ fn hnsw_test() {
     for i in 0..n {
         let node_name = format!("node{}", i);
         let node = index.nodes.get(&node_name).unwrap().clone();
+        let id = node.read().unwrap().id;
         index.delete_node(&node_name, mock_fn).unwrap();
         assert_eq!(index.node_count, n - i - 1);
         assert_eq!(index.nodes.get(&node_name).is_none(), true);
+        assert!(index.node_at(id).is_err());
         for l in &index.layers {
-            assert_eq!(l.contains(&node.downgrade()), false);
+            assert_eq!(l.contains(&id), false);
         }
         for n in index.nodes.values() {
-            for l in &n.read().neighbors {
-                for nn in l {
-                    assert_ne!(nn.upgrade(), node);
-                }
+            for l in &n.read().unwrap().neighbors {
+                assert!(!l.contains(&id));
             }
         }
         // // sleep for a brief period to make sure all threads are done
@@ -79,3 +104,1252 @@ fn hnsw_test() {
         assert_eq!(sc, 1);
     }
 }
+
+#[test]
+fn multi_vector_test() {
+    let data_dim = 2;
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), data_dim, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    index.add_node("single", &[3.0, 3.0], mock_fn).unwrap();
+    index
+        .add_node("multi", &[0.0, 0.0, 10.0, 10.0], mock_fn)
+        .unwrap();
+    assert!(index.add_node("bad", &[1.0, 1.0, 1.0], mock_fn).is_err());
+
+    // graph is built on the centroid
+    let node = index.nodes.get("multi").unwrap();
+    assert_eq!(node.read().unwrap().data, vec![5.0, 5.0]);
+    assert_eq!(node.read().unwrap().vectors.len(), 2);
+
+    let query = vec![0.0; data_dim];
+    let res = index.search_knn(&query, 2).unwrap();
+    assert_eq!(res[0].name.as_str(), "multi");
+    assert!((res[0].sim.into_inner() - 0.0).abs() < f32::EPSILON);
+
+    let params = SearchParams {
+        multivalue: MultiValue::Avg,
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&query, 2, &params).unwrap();
+    assert_eq!(res[0].name.as_str(), "single");
+    assert!((res[1].sim.into_inner() - -100.0).abs() < f32::EPSILON);
+}
+
+#[test]
+fn normalize_test() {
+    let data_dim = 2;
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), data_dim, 5, 16);
+    index.normalize = true;
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    index.add_node("a", &[3.0, 4.0], mock_fn).unwrap();
+    index.add_node("b", &[0.0, 10.0], mock_fn).unwrap();
+    index.add_node("zero", &[0.0, 0.0], mock_fn).unwrap();
+
+    let node = index.nodes.get("a").unwrap();
+    assert_eq!(node.read().unwrap().data, vec![0.6, 0.8]);
+    assert_eq!(node.read().unwrap().norms, vec![5.0]);
+
+    // direction matters, magnitude does not
+    let res = index.search_knn(&[0.0, 100.0], 3).unwrap();
+    assert_eq!(res[0].name.as_str(), "b");
+    assert!((res[0].sim.into_inner() - 0.0).abs() < f32::EPSILON);
+    assert_eq!(res[0].data, vec![0.0, 10.0]);
+}
+
+#[test]
+fn search_matches_brute_force() {
+    let n = 200;
+    let data_dim = 8;
+    let k = 10;
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), data_dim, 8, 200);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    // points at varying distances from the origin so norm bounds prune candidates
+    let data = (0..n)
+        .map(|i| {
+            (0..data_dim)
+                .map(|d| ((i * 7 + d * 13) % 23) as f32 * (1.0 + i as f32 / 50.0))
+                .collect::<Vec<f32>>()
+        })
+        .collect::<Vec<Vec<f32>>>();
+    for (i, d) in data.iter().enumerate() {
+        index.add_node(&format!("node{}", i), d, mock_fn).unwrap();
+    }
+
+    let query = vec![3.0; data_dim];
+    let mut expected = data
+        .iter()
+        .map(|d| euclidean(&query, d, data_dim))
+        .collect::<Vec<f32>>();
+    expected.sort_by(|a, b| b.partial_cmp(a).unwrap());
+
+    let res = index.search_knn(&query, k).unwrap();
+    assert_eq!(res.len(), k);
+    for (r, e) in res.iter().zip(&expected) {
+        assert!((r.sim.into_inner() - e).abs() < 1e-3);
+    }
+}
+
+// copies every buffer into a new allocation, stops after budget slots
+struct CopyRelocate {
+    slots: usize,
+    budget: usize,
+}
+
+impl Relocate for CopyRelocate {
+    fn relocate<V>(&mut self, buf: &mut Vec<V>) {
+        let mut moved = Vec::with_capacity(buf.capacity());
+        moved.append(buf);
+        *buf = moved;
+    }
+
+    fn should_stop(&mut self) -> bool {
+        self.slots += 1;
+        self.slots > self.budget
+    }
+}
+
+#[test]
+fn relocate_nodes_test() {
+    let n = 50;
+    let data_dim = 4;
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), data_dim, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..n {
+        let data = vec![i as f32; data_dim];
+        index
+            .add_node(&format!("node{}", i), &data, mock_fn)
+            .unwrap();
+    }
+    let query = vec![7.5; data_dim];
+    let before = index.search_knn(&query, 10).unwrap();
+    let ptr = index.node_at(0).unwrap().read().unwrap().data.as_ptr();
+
+    // resumes from the slot the last pass stopped at
+    let mut start = 0;
+    let mut passes = 0;
+    while let Some(next) = index.relocate_nodes(
+        start,
+        &mut CopyRelocate {
+            slots: 0,
+            budget: 20,
+        },
+    ) {
+        assert_eq!(next, start + 20);
+        start = next;
+        passes += 1;
+    }
+    assert_eq!(passes, 2);
+
+    let node = index.node_at(0).unwrap().read().unwrap();
+    assert_ne!(node.data.as_ptr(), ptr);
+    assert_eq!(node.data, vec![0.0; data_dim]);
+    assert_eq!(&node.name, "node0");
+    drop(node);
+    let after = index.search_knn(&query, 10).unwrap();
+    let names = |r: &[SearchResult<f32, f32>]| r.iter().map(|r| r.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&before), names(&after));
+}
+
+#[test]
+fn heuristic_flags_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 4, 5, 16);
+    index.extend_candidates = false;
+    index.keep_pruned_connections = false;
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..50 {
+        let data = vec![i as f32; 4];
+        index
+            .add_node(&format!("node{}", i), &data, mock_fn)
+            .unwrap();
+    }
+    for node in index.nodes.values() {
+        let node = node.read().unwrap();
+        assert!(!node.neighbors[0].is_empty());
+        assert!(node.neighbors[0].len() <= index.m_max_0);
+    }
+
+    let res = index.search_knn(&[10.2; 4], 3).unwrap();
+    assert_eq!(res[0].name, "node10");
+}
+
+#[test]
+fn node_levels_match_layers() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 4, 3, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..100 {
+        let data = vec![i as f32; 4];
+        index
+            .add_node(&format!("node{}", i), &data, mock_fn)
+            .unwrap();
+    }
+    let mut count = 0;
+    for (l, layer) in index.layers.iter().enumerate() {
+        for node in layer {
+            assert_eq!(index.node_at(*node).unwrap().read().unwrap().level, l);
+            count += 1;
+        }
+    }
+    assert_eq!(count, 100);
+}
+
+#[test]
+fn node_ids_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..10 {
+        index
+            .add_node(&format!("node{}", i), &[i as f32, 0.0], mock_fn)
+            .unwrap();
+    }
+    let id_of = |index: &Index<f32, f32>, name: &str| index.nodes[name].id().unwrap();
+    assert_eq!(id_of(&index, "node3"), 3);
+
+    // ids of deleted nodes are reused by the next nodes
+    index.delete_node("node3", mock_fn).unwrap();
+    index.delete_node("node5", mock_fn).unwrap();
+    assert!(index.node_at(3).is_err());
+    index.add_node("new0", &[3.0, 1.0], mock_fn).unwrap();
+    index.add_node("new1", &[5.0, 1.0], mock_fn).unwrap();
+    index.add_node("new2", &[6.0, 1.0], mock_fn).unwrap();
+    assert_eq!(id_of(&index, "new0"), 5);
+    assert_eq!(id_of(&index, "new1"), 3);
+    assert_eq!(id_of(&index, "new2"), 10);
+    assert_eq!(index.node_at(3).unwrap().read().unwrap().name, "new1");
+
+    // copies of the index keep the ids
+    let snapshot = index.snapshot().unwrap();
+    let rebuilt = index.rebuilt(5, 16).unwrap();
+    for name in index.nodes.keys() {
+        assert_eq!(id_of(&snapshot, name), id_of(&index, name));
+        assert_eq!(id_of(&rebuilt, name), id_of(&index, name));
+    }
+}
+
+#[test]
+fn frozen_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    index.add_node("node0", &[0.0, 0.0], mock_fn).unwrap();
+    index.frozen = true;
+    assert!(index.add_node("node1", &[1.0, 1.0], mock_fn).is_err());
+    assert!(index.delete_node("node0", mock_fn).is_err());
+    assert_eq!(index.search_knn(&[0.0, 0.0], 1).unwrap().len(), 1);
+
+    index.frozen = false;
+    index.add_node("node1", &[1.0, 1.0], mock_fn).unwrap();
+    assert_eq!(index.node_count, 2);
+}
+
+#[test]
+fn filtered_search_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..100 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+        let parity = if i % 2 == 0 { "even" } else { "odd" };
+        index
+            .tag_node(&name, vec![parity.to_owned(), format!("mod{}", i % 5)])
+            .unwrap();
+    }
+
+    let params = SearchParams {
+        filter: Some(Filter::parse("TAG:odd AND NOT TAG:mod1").unwrap()),
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&[20.4, 0.0], 3, &params).unwrap();
+    let names = res.iter().map(|r| r.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["node19", "node23", "node17"]);
+
+    let params = SearchParams {
+        filter: Some(Filter::parse("TAG:missing").unwrap()),
+        ..Default::default()
+    };
+    assert!(index
+        .search_knn_with(&[20.0, 0.0], 3, &params)
+        .unwrap()
+        .is_empty());
+
+    // deleted and retagged nodes leave the tag sets
+    index.delete_node("node19", mock_fn).unwrap();
+    index.tag_node("node23", vec!["even".to_owned()]).unwrap();
+    let params = SearchParams {
+        filter: Some(Filter::parse("TAG:odd").unwrap()),
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&[20.4, 0.0], 2, &params).unwrap();
+    let names = res.iter().map(|r| r.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["node21", "node17"]);
+}
+
+#[test]
+fn not_near_search_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..100 {
+        index
+            .add_node(&format!("node{}", i), &[i as f32, 0.0], mock_fn)
+            .unwrap();
+    }
+
+    // similarities are negated squared distances, nodes within 2 of the reference are excluded
+    let params = SearchParams {
+        not_near: vec![NotNear {
+            data: vec![20.0, 0.0],
+            radius: -4.0,
+            stored: false,
+        }],
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&[20.4, 0.0], 3, &params).unwrap();
+    let names = res.iter().map(|r| r.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["node23", "node17", "node24"]);
+
+    let params = SearchParams {
+        not_near: vec![NotNear {
+            data: vec![20.0],
+            radius: 0.0,
+            stored: false,
+        }],
+        ..Default::default()
+    };
+    assert!(index.search_knn_with(&[20.4, 0.0], 3, &params).is_err());
+}
+
+#[test]
+fn warm_up_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    assert_eq!(index.warm_up(10).unwrap(), 0);
+
+    for i in 0..100 {
+        index
+            .add_node(&format!("node{}", i), &[i as f32, 0.0], mock_fn)
+            .unwrap();
+    }
+    assert_eq!(index.warm_up(10).unwrap(), 10);
+    assert_eq!(index.warm_up(0).unwrap(), 0);
+    // one search per node at most
+    assert_eq!(index.warm_up(1000).unwrap(), 100);
+}
+
+// vectors live in the slots of the storage, the graph works the same
+fn check_slab_storage(storage: SlabStorage<f32>, kind: StorageKind) {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    index.storage = Arc::new(storage);
+    assert_eq!(index.storage.kind(), kind);
+
+    for i in 0..50 {
+        index
+            .add_node(&format!("node{}", i), &[i as f32, 0.0], mock_fn)
+            .unwrap();
+    }
+    assert!(matches!(
+        index.nodes["node7"].read().unwrap().data,
+        Vector::Slot { .. }
+    ));
+    let res = index.search_knn(&[7.2, 0.0], 1).unwrap();
+    assert_eq!(res[0].name, "node7");
+
+    // an update frees the slot of the old vector
+    index.update_node("node7", &[100.0, 0.0], mock_fn).unwrap();
+    assert_eq!(index.storage.len(), 50);
+    assert_eq!(
+        &index.nodes["node7"].read().unwrap().data[..],
+        &[100.0, 0.0]
+    );
+}
+
+#[test]
+fn arena_storage_test() {
+    check_slab_storage(SlabStorage::arena(2), StorageKind::Arena);
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn mmap_storage_test() {
+    let path = std::env::temp_dir().join(format!("hnsw-test-{}-index.vectors", std::process::id()));
+    check_slab_storage(SlabStorage::mmap(&path, 2).unwrap(), StorageKind::Mmap);
+    assert!(!path.exists());
+}
+
+// data, level and neighbor ids of a persisted node
+type Persisted = (Vec<f32>, usize, Vec<Vec<u32>>);
+
+// nodes persisted by name, like the node keys of the module
+struct MemStore(HashMap<String, Persisted>);
+
+impl NodeStore<f32> for MemStore {
+    fn load(&self, name: &str, capacity: usize) -> Result<Node<f32>, HNSWError> {
+        let (data, level, _) = self.0.get(name).ok_or("Node does not exist")?;
+        let node = Node::new(name, data, capacity);
+        node.write()?.level = *level;
+        Ok(node)
+    }
+
+    fn neighbors(&self, name: &str) -> Result<Vec<Vec<u32>>, HNSWError> {
+        Ok(self.0[name].2.clone())
+    }
+}
+
+#[test]
+fn restore_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..30 {
+        index
+            .add_node(&format!("node{}", i), &[i as f32, 0.0], mock_fn)
+            .unwrap();
+    }
+    // ids of deleted nodes are free, the restored index reuses them
+    index.delete_node("node7", mock_fn).unwrap();
+    let mut store = MemStore(HashMap::new());
+    let mut ids = HashMap::new();
+    for (name, node) in &index.nodes {
+        let n = node.read().unwrap();
+        store.0.insert(
+            name.clone(),
+            (n.data.to_vec(), n.level, n.neighbors.clone()),
+        );
+        ids.insert(name.clone(), n.id);
+    }
+    let ep = index.enterpoint.unwrap();
+    let ep = index.node_at(ep).unwrap().read().unwrap().name.clone();
+
+    let mut restored: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    restored.max_layer = index.max_layer;
+    restored.node_count = index.node_count;
+    restored.restore(&ids, Some(&ep), &store).unwrap();
+    assert_eq!(restored.nodes.len(), 29);
+    assert_eq!(restored.enterpoint, index.enterpoint);
+    assert_eq!(restored.free_ids, vec![7]);
+    assert_eq!(restored.layers.len(), index.layers.len());
+    for l in 0..index.layers.len() {
+        assert_eq!(restored.layer_card(l), index.layer_card(l));
+    }
+    let names_of =
+        |res: Vec<SearchResult<f32, f32>>| res.into_iter().map(|r| r.name).collect::<Vec<String>>();
+    assert_eq!(
+        names_of(restored.search_knn(&[12.2, 0.0], 3).unwrap()),
+        names_of(index.search_knn(&[12.2, 0.0], 3).unwrap())
+    );
+
+    // a neighbor missing from the store fails the restore
+    store.0.get_mut("node3").unwrap().2 = vec![vec![7]];
+    let mut broken: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    assert!(broken.restore(&ids, Some(&ep), &store).is_err());
+}
+
+#[test]
+fn attribute_filtered_search_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 4);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..100 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+        let mut attrs = BTreeMap::new();
+        attrs.insert("price".to_owned(), (i % 10) as f64);
+        index.set_attrs(&name, attrs).unwrap();
+    }
+
+    // k is larger than ef_construction, the search over-fetches to reach it
+    let params = SearchParams {
+        filter: Some(Filter::parse("price >= 8").unwrap()),
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&[0.0, 0.0], 10, &params).unwrap();
+    let mut names = res.iter().map(|r| r.name.clone()).collect::<Vec<String>>();
+    names.sort();
+    let mut expected = (0..5)
+        .flat_map(|i| vec![format!("node{}", i * 10 + 8), format!("node{}", i * 10 + 9)])
+        .collect::<Vec<String>>();
+    expected.sort();
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn geo_fenced_search_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    // nodes spread along the equator about 11km apart, every third has no location
+    for i in 0..30 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+        if i % 3 != 0 {
+            let location = Location::new(0.0, i as f64 * 0.1).unwrap();
+            index.set_location(&name, Some(location)).unwrap();
+        }
+    }
+
+    // within 25km of node20, the closest vectors to node0 are the closest located ones
+    let center = Location::new(0.0, 2.0).unwrap();
+    let params = SearchParams {
+        within: Some(GeoFence::new(center, 25_000.0).unwrap()),
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&[0.0, 0.0], 5, &params).unwrap();
+    let names = res.iter().map(|r| r.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["node19", "node20", "node22"]);
+}
+
+#[test]
+fn snapshot_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..50 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    let epoch = index.epoch;
+    let snapshot = index.snapshot().unwrap();
+    assert_eq!(snapshot.epoch, epoch);
+    assert_eq!(snapshot.node_count, 50);
+
+    // the snapshot shares no nodes with the index
+    for (name, node) in &index.nodes {
+        assert!(!Arc::ptr_eq(&node.0, &snapshot.nodes[name].0));
+        assert_eq!(Arc::strong_count(&node.0), 2);
+    }
+
+    index.delete_node("node10", mock_fn).unwrap();
+    index.add_node("node50", &[10.0, 0.0], mock_fn).unwrap();
+    assert!(index.epoch > epoch);
+
+    let res = snapshot.search_knn(&[10.0, 0.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "node10");
+    let res = index.search_knn(&[10.0, 0.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "node50");
+}
+
+#[test]
+fn precheck_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    assert!(index.can_add("node0", &[0.0, 0.0]).is_ok());
+    assert!(index.can_add("node0", &[0.0]).is_err());
+    assert!(index.can_delete("node0").is_err());
+
+    index.add_node("node0", &[0.0, 0.0], mock_fn).unwrap();
+    assert!(index.can_add("node0", &[1.0, 0.0]).is_err());
+    assert!(index.add_node("node0", &[1.0, 0.0], mock_fn).is_err());
+    assert!(index.can_delete("node0").is_ok());
+
+    // checks don't mutate the index
+    let epoch = index.epoch;
+    index.frozen = true;
+    assert!(index.can_add("node1", &[1.0, 0.0]).is_err());
+    assert!(index.can_delete("node0").is_err());
+    assert_eq!(index.epoch, epoch);
+}
+
+#[test]
+fn layer_card_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..100 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    assert_eq!(index.layer_card(0), 100);
+    assert_eq!(index.layer_card(index.max_layer + 1), 0);
+
+    // every layer holds the nodes of the layers above it
+    for l in 1..=index.max_layer {
+        let card = index
+            .nodes
+            .values()
+            .filter(|n| n.read().unwrap().level >= l)
+            .count();
+        assert_eq!(index.layer_card(l), card);
+        assert!(index.layer_card(l) <= index.layer_card(l - 1));
+    }
+}
+
+#[test]
+fn weighted_sum_test() {
+    let terms = vec![
+        (vec![1.0_f32, 2.0, 3.0], 1.0),
+        (vec![1.0, 1.0, 0.0], -0.5),
+        (vec![0.0, 2.0, 4.0], 0.25),
+    ];
+    assert_eq!(weighted_sum(&terms), vec![0.5, 2.0, 4.0]);
+    assert!(weighted_sum::<f32>(&[]).is_empty());
+
+    // analogy query lands on the expected node
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..10 {
+        for j in 0..10 {
+            let name = format!("node{}_{}", i, j);
+            index
+                .add_node(&name, &[i as f32, j as f32], mock_fn)
+                .unwrap();
+        }
+    }
+    let data = |name: &str| index.nodes[name].read().unwrap().data.to_vec();
+    let query = weighted_sum(&[
+        (data("node5_5"), 1.0),
+        (data("node2_1"), -1.0),
+        (data("node3_4"), 1.0),
+    ]);
+    let res = index.search_knn(&query, 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "node6_8");
+}
+
+#[test]
+fn update_node_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..50 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    assert_eq!(index.nodes["node10"].read().unwrap().version, 1);
+
+    index.update_node("node10", &[20.5, 0.0], mock_fn).unwrap();
+    index.update_node("node10", &[30.4, 0.0], mock_fn).unwrap();
+    assert_eq!(index.node_count, 50);
+    assert_eq!(index.nodes["node10"].read().unwrap().version, 3);
+
+    let res = index.search_knn(&[30.4, 0.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "node10");
+    let res = index.search_knn(&[10.0, 0.0], 2).unwrap();
+    assert!(res.iter().all(|r| r.name.as_str() != "node10"));
+
+    // rejected updates leave the node untouched
+    assert!(index.update_node("node10", &[1.0], mock_fn).is_err());
+    assert!(index.update_node("node99", &[1.0, 0.0], mock_fn).is_err());
+    assert_eq!(index.nodes["node10"].read().unwrap().version, 3);
+}
+
+#[test]
+fn update_keeps_metadata_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..10 {
+        let name = format!("node{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    let mut attrs = BTreeMap::new();
+    attrs.insert("price".to_owned(), 9.5);
+    let location = Location::new(1.0, 2.0).unwrap();
+    index.tag_node("node3", vec!["red".to_owned()]).unwrap();
+    index.set_attrs("node3", attrs.clone()).unwrap();
+    index.set_location("node3", Some(location)).unwrap();
+
+    // only the vector is replaced
+    index.update_node("node3", &[30.0, 0.0], mock_fn).unwrap();
+    let node = index.nodes["node3"].read().unwrap();
+    assert_eq!(node.tags, vec!["red".to_owned()]);
+    assert_eq!(node.attrs, attrs);
+    assert_eq!(node.location, Some(location));
+    drop(node);
+
+    // the tag filter still finds the node
+    let params = SearchParams {
+        filter: Some(Filter::parse("TAG:red").unwrap()),
+        ..Default::default()
+    };
+    let res = index.search_knn_with(&[30.0, 0.0], 1, &params).unwrap();
+    assert_eq!(res[0].name.as_str(), "node3");
+}
+
+#[test]
+fn node_names_test() {
+    let mut index: Index<f32, f32> = Index::new("hnsw.foo", Box::new(euclidean), 2, 5, 16);
+    index.names = NameRule::new("user\\.[0-9]+", 0).unwrap();
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..20 {
+        let name = format!("hnsw.foo.user.{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    assert!(index
+        .add_node("hnsw.foo.user.x", &[0.0, 0.0], mock_fn)
+        .is_err());
+    assert_eq!(index.node_count, 20);
+
+    // only the index prefix is stripped from result names
+    let res = index.search_knn(&[5.0, 0.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "user.5");
+    assert_eq!(res[0].key.as_str(), "hnsw.foo.user.5");
+    assert_eq!(index.node_suffix("hnsw.foo.user.5"), "user.5");
+    assert_eq!(index.node_suffix("hnsw.foobar.5"), "hnsw.foobar.5");
+}
+
+#[test]
+fn projection_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    // 4 -> 2 dims, sums the halves of a vector
+    index.projection =
+        Some(Projection::new(4, 2, vec![1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0]).unwrap());
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..10 {
+        let name = format!("foo.{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    // projected nodes are stored with the dimensionality of the index
+    index
+        .add_node("foo.p", &[10.0, 10.0, 0.5, 0.5], mock_fn)
+        .unwrap();
+    assert_eq!(index.nodes["foo.p"].read().unwrap().data, vec![20.0, 1.0]);
+    assert!(index.add_node("foo.x", &[1.0, 2.0, 3.0], mock_fn).is_err());
+
+    let res = index.search_knn(&[10.0, 10.0, 0.5, 0.5], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "p");
+    let res = index.search_knn(&[1.0, 1.0, 0.0, 0.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "2");
+    assert!(index.search_knn(&[1.0, 1.0, 0.0], 1).is_err());
+}
+
+#[test]
+fn explicit_level_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    index
+        .add_node_at("node0", &[0.0, 0.0], Some(2), mock_fn)
+        .unwrap();
+    assert_eq!(index.max_layer, 2);
+    assert_eq!(index.layers.len(), 3);
+    for i in 1..20 {
+        let data = [i as f32, i as f32];
+        index
+            .add_node_at(&format!("node{}", i), &data, Some(0), mock_fn)
+            .unwrap();
+    }
+    index
+        .add_node_at("node20", &[20.0, 20.0], Some(4), mock_fn)
+        .unwrap();
+    assert_eq!(index.max_layer, 4);
+    let ep = index.node_at(index.enterpoint.unwrap()).unwrap();
+    assert_eq!(ep.read().unwrap().name, "node20");
+    assert!(index
+        .add_node_at("node21", &[1.0, 1.0], Some(MAX_LEVEL + 1), mock_fn)
+        .is_err());
+
+    let res = index.search_knn(&[3.0, 3.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "node3");
+
+    // only nodes on the top layer can be the enterpoint
+    assert!(index.set_enterpoint("node0").is_err());
+    assert!(index.set_enterpoint("missing").is_err());
+    index
+        .add_node_at("node21", &[21.0, 21.0], Some(4), mock_fn)
+        .unwrap();
+    index.set_enterpoint("node21").unwrap();
+    let ep = index.node_at(index.enterpoint.unwrap()).unwrap();
+    assert_eq!(ep.read().unwrap().name, "node21");
+    let res = index.search_knn(&[3.0, 3.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "node3");
+}
+
+#[test]
+fn search_budget_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..200 {
+        let data = [(i % 20) as f32, (i / 20) as f32];
+        index
+            .add_node(&format!("node{}", i), &data, mock_fn)
+            .unwrap();
+    }
+
+    let (res, stats) = index
+        .search_knn_stats(&[3.0, 3.0], 5, &SearchParams::default())
+        .unwrap();
+    assert_eq!(res.len(), 5);
+    assert!(!stats.truncated);
+    assert!(stats.visited > 5);
+    // every visited node and the entry of each layer is evaluated at most once
+    assert!(stats.distances <= stats.visited + index.max_layer + 1);
+    assert_eq!(stats.layer_time.len(), index.max_layer + 1);
+
+    // the best nodes found before the budget ran out are returned
+    let params = SearchParams {
+        max_visits: 5,
+        ..SearchParams::default()
+    };
+    let (res, stats) = index.search_knn_stats(&[3.0, 3.0], 5, &params).unwrap();
+    assert!(stats.truncated);
+    assert_eq!(stats.visited, 5);
+    assert!(!res.is_empty() && res.len() <= 5);
+
+    let mut merged = SearchStats::default();
+    merged.merge(&stats);
+    merged.merge(&SearchStats {
+        visited: 3,
+        ..SearchStats::default()
+    });
+    assert_eq!(merged.visited, 8);
+    assert_eq!(merged.layer_time.keys().next(), Some(&0));
+    assert!(merged.truncated);
+}
+
+#[test]
+fn search_entry_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..100 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 10) as f32, (i / 10) as f32], mock_fn)
+            .unwrap();
+    }
+    // entries are given with or without the index prefix
+    for entry in &["12", "foo.12", "99"] {
+        let params = SearchParams {
+            entry: Some(entry.to_string()),
+            ..SearchParams::default()
+        };
+        let res = index.search_knn_with(&[2.0, 1.0], 3, &params).unwrap();
+        assert_eq!(res[0].name.as_str(), "12");
+    }
+    let params = SearchParams {
+        entry: Some("missing".to_owned()),
+        ..SearchParams::default()
+    };
+    assert!(index.search_knn_with(&[2.0, 1.0], 3, &params).is_err());
+}
+
+#[test]
+fn subgraph_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..50 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 10) as f32, (i / 10) as f32], mock_fn)
+            .unwrap();
+    }
+
+    let whole = index.subgraph(0, None, None).unwrap();
+    assert_eq!(whole.nodes.len(), 50);
+    assert!(whole.nodes.iter().any(|(n, _)| n == "7"));
+    let upper = index.subgraph(1, None, None).unwrap();
+    assert_eq!(upper.nodes.len(), index.layer_card(1));
+
+    // depth 0 is the start node alone, depth 1 adds its neighbors
+    let start = index.subgraph(0, Some("7"), Some(0)).unwrap();
+    assert_eq!(
+        start.nodes,
+        vec![("7".to_owned(), index.nodes["foo.7"].read().unwrap().level)]
+    );
+    assert!(start.edges.is_empty());
+    let hop = index.subgraph(0, Some("foo.7"), Some(1)).unwrap();
+    let neighbors = index.nodes["foo.7"].read().unwrap().neighbors[0].len();
+    assert_eq!(hop.nodes.len(), neighbors + 1);
+    assert_eq!(
+        hop.edges.iter().filter(|(f, _)| f == "7").count(),
+        neighbors
+    );
+    for (from, to) in &hop.edges {
+        assert!(hop.nodes.iter().any(|(n, _)| n == from));
+        assert!(hop.nodes.iter().any(|(n, _)| n == to));
+    }
+
+    assert!(index.subgraph(0, Some("missing"), None).is_err());
+    assert!(index.subgraph(MAX_LEVEL, Some("7"), None).is_err());
+}
+
+#[test]
+fn duplicates_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..30 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(
+                &name,
+                &[(i % 6) as f32 * 10.0, (i / 6) as f32 * 10.0],
+                mock_fn,
+            )
+            .unwrap();
+    }
+    // near copies of node 0 and node 7
+    index.add_node("foo.0a", &[0.1, 0.0], mock_fn).unwrap();
+    index.add_node("foo.0b", &[0.0, 0.1], mock_fn).unwrap();
+    index.add_node("foo.7a", &[10.0, 10.1], mock_fn).unwrap();
+
+    let pairs = index.duplicates(-0.1, 0).unwrap();
+    let names = pairs
+        .iter()
+        .map(|(a, b, _)| (a.as_str(), b.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(names.len(), 4);
+    for pair in &[("0", "0a"), ("0", "0b"), ("0a", "0b"), ("7", "7a")] {
+        assert!(names.contains(pair));
+    }
+    assert!(pairs.windows(2).all(|w| w[0].2 >= w[1].2));
+    assert_eq!(index.duplicates(-0.1, 2).unwrap().len(), 2);
+
+    let clusters = duplicate_clusters(&pairs);
+    assert_eq!(
+        clusters,
+        vec![
+            vec!["0".to_owned(), "0a".to_owned(), "0b".to_owned()],
+            vec!["7".to_owned(), "7a".to_owned()],
+        ]
+    );
+}
+
+#[test]
+fn flat_index_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    index.flat_limit = 50;
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+
+    for i in 0..50 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 10) as f32, (i / 10) as f32], mock_fn)
+            .unwrap();
+    }
+    assert!(index.is_flat());
+    assert_eq!(index.max_layer, 0);
+    assert_eq!(index.layer_card(0), 50);
+    assert!(index
+        .add_node_at("foo.x", &[0.0, 0.0], Some(1), mock_fn)
+        .is_err());
+
+    // flat searches are exhaustive
+    let (res, stats) = index
+        .search_knn_stats(&[2.0, 1.0], 3, &SearchParams::default())
+        .unwrap();
+    assert_eq!(res[0].name.as_str(), "12");
+    assert_eq!(stats.visited, 50);
+    assert_eq!(stats.distances, 50);
+
+    // crossing the limit links the nodes on upper layers
+    for i in 50..60 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 10) as f32, (i / 10) as f32], mock_fn)
+            .unwrap();
+    }
+    assert!(!index.is_flat());
+    assert!(index.max_layer > 0);
+    assert_eq!(index.layer_card(0), 60);
+    let ep = index.node_at(index.enterpoint.unwrap()).unwrap();
+    assert_eq!(ep.read().unwrap().level, index.max_layer);
+    let upper = index
+        .nodes
+        .values()
+        .filter(|n| n.read().unwrap().level > 0)
+        .count();
+    assert_eq!(index.layer_card(1), upper);
+    let res = index.search_knn(&[2.0, 1.0], 3).unwrap();
+    assert_eq!(res[0].name.as_str(), "12");
+}
+
+#[test]
+fn rebuilt_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..60 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 10) as f32, (i / 10) as f32], mock_fn)
+            .unwrap();
+    }
+    let mut attrs = BTreeMap::new();
+    attrs.insert("w".to_owned(), 2.0);
+    index.set_attrs("foo.12", attrs.clone()).unwrap();
+
+    let rebuilt = index.rebuilt(8, 32).unwrap();
+    assert_eq!(
+        (rebuilt.m, rebuilt.m_max_0, rebuilt.ef_construction),
+        (8, 16, 32)
+    );
+    assert_eq!(rebuilt.node_count, 60);
+    assert_eq!(rebuilt.layer_card(0), 60);
+    assert_eq!(rebuilt.nodes["foo.12"].read().unwrap().attrs, attrs);
+    for node in rebuilt.nodes.values() {
+        let nr = node.read().unwrap();
+        assert_eq!(nr.neighbors.len(), nr.level + 1);
+        assert!(!nr.neighbors[0].is_empty());
+    }
+    let res = rebuilt.search_knn(&[2.0, 1.0], 3).unwrap();
+    assert_eq!(res[0].name.as_str(), "12");
+
+    // an error of the progress callback stops the rebuild
+    let placed = std::cell::Cell::new(0);
+    let stopped = index.rebuilt_with(8, 32, |n| {
+        placed.set(n);
+        match n {
+            10 => Err("Cancelled".into()),
+            _ => Ok(()),
+        }
+    });
+    assert!(stopped.is_err());
+    assert_eq!(placed.get(), 10);
+
+    // the original graph is left alone
+    assert_eq!(index.m, 5);
+    assert!(!Arc::ptr_eq(
+        &index.nodes["foo.12"].0,
+        &rebuilt.nodes["foo.12"].0
+    ));
+
+    // sampling is deterministic by seed
+    let sample = index.sample_vectors(10, 3).unwrap();
+    assert_eq!(sample.len(), 10);
+    assert_eq!(sample, index.sample_vectors(10, 3).unwrap());
+    assert_eq!(index.sample_vectors(100, 3).unwrap().len(), 60);
+}
+
+#[test]
+fn degree_histograms_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 4, 32);
+    assert!(index.degree_histograms().unwrap().is_empty());
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..200 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 20) as f32, (i / 20) as f32], mock_fn)
+            .unwrap();
+    }
+    let histograms = index.degree_histograms().unwrap();
+    assert_eq!(histograms.len(), index.layers.len());
+    for (layer, histogram) in histograms.iter().enumerate() {
+        assert_eq!(histogram.iter().sum::<usize>(), index.layer_card(layer));
+        assert!(histogram.len() <= index.degrees().on(layer) + 1);
+    }
+    // no node of a connected layer 0 is isolated
+    assert_eq!(histograms[0][0], 0);
+}
+
+#[test]
+fn degrees_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 4, 32);
+    index.m_max_0 = 12;
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..200 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 20) as f32, (i / 20) as f32], mock_fn)
+            .unwrap();
+    }
+    let max = index.max_degrees().unwrap();
+    assert!(max.layer0 > 4 && max.layer0 <= 12);
+    assert!(max.upper <= 4);
+    assert_eq!(
+        index.degrees(),
+        Degrees {
+            layer0: 12,
+            upper: 4
+        }
+    );
+
+    // neighbor lists are allocated for the limit of their layer
+    for node in index.nodes.values() {
+        let n = node.read().unwrap();
+        assert_eq!(n.neighbors.len(), n.level + 1);
+        assert!(n.neighbors[0].capacity() >= 12);
+    }
+
+    // the layer 0 limit keeps its ratio to M
+    let rebuilt = index.rebuilt(8, 32).unwrap();
+    assert_eq!(
+        rebuilt.degrees(),
+        Degrees {
+            layer0: 24,
+            upper: 8
+        }
+    );
+}
+
+#[test]
+fn finite_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    index.add_node("foo.a", &[1.0, 0.0], mock_fn).unwrap();
+    assert!(index.add_node("foo.b", &[f32::NAN, 0.0], mock_fn).is_err());
+    assert!(index
+        .add_node("foo.b", &[0.0, f32::INFINITY], mock_fn)
+        .is_err());
+    assert!(index
+        .update_node("foo.a", &[f32::NEG_INFINITY, 0.0], mock_fn)
+        .is_err());
+    assert!(index.search_knn(&[f32::NAN, 0.0], 1).is_err());
+    assert_eq!(index.node_count, 1);
+    assert_eq!(index.search_knn(&[1.0, 0.0], 1).unwrap()[0].name, "a");
+
+    assert!(check_finite(&[1.0_f32, -2.0]).is_ok());
+    assert!(check_finite(&[1.0_f32, f32::NAN]).is_err());
+    assert_eq!(non_finite(&[1.0_f32, 2.0, f32::INFINITY]), Some(2));
+}
+
+#[test]
+fn duplicate_policy_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..10 {
+        let name = format!("foo.{}", i);
+        index.add_node(&name, &[i as f32, 0.0], mock_fn).unwrap();
+    }
+    // allowed by default
+    index.add_node("foo.x", &[3.0, 0.0], mock_fn).unwrap();
+    assert_eq!(index.node_count, 11);
+
+    index.duplicates = DuplicatePolicy::Reject;
+    index.dup_epsilon = 0.1;
+    assert!(index.add_node("foo.y", &[5.05, 0.0], mock_fn).is_err());
+    index.add_node("foo.y", &[5.5, 0.0], mock_fn).unwrap();
+    // an update can't make a node equal to another
+    assert!(index.update_node("foo.y", &[7.0, 0.0], mock_fn).is_err());
+    index.update_node("foo.y", &[5.4, 0.0], mock_fn).unwrap();
+
+    index.duplicates = DuplicatePolicy::Alias;
+    index.add_node("foo.z", &[8.0, 0.05], mock_fn).unwrap();
+    assert_eq!(index.node_count, 12);
+    assert_eq!(index.aliases_of("foo.8"), vec!["foo.z"]);
+    assert!(index.add_node("foo.z", &[20.0, 0.0], mock_fn).is_err());
+    assert!(index.update_node("foo.z", &[20.0, 0.0], mock_fn).is_err());
+    // a node keeps its aliases when updated
+    index.update_node("foo.8", &[8.0, 0.01], mock_fn).unwrap();
+    assert_eq!(index.aliases_of("foo.8"), vec!["foo.z"]);
+
+    // deleting an alias keeps the node
+    index.delete_node("foo.z", mock_fn).unwrap();
+    assert_eq!(index.node_count, 12);
+    assert!(index.node_aliases.is_empty());
+
+    // deleting a node deletes its aliases, an undo restores them
+    index.add_node("foo.z", &[8.0, 0.0], mock_fn).unwrap();
+    index.begin_undo();
+    index.delete_node("foo.8", mock_fn).unwrap();
+    assert!(index.node_aliases.is_empty());
+    let undo = index.end_undo().unwrap();
+    index.undo(undo).unwrap();
+    assert_eq!(index.aliases_of("foo.8"), vec!["foo.z"]);
+}
+
+#[test]
+fn catch_up_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 5, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..30 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 10) as f32, (i / 10) as f32], mock_fn)
+            .unwrap();
+    }
+    let mut rebuilt = index.rebuilt(8, 32).unwrap();
+
+    // changes made to the index while the copy was rebuilt
+    index.delete_node("foo.3", mock_fn).unwrap();
+    index.update_node("foo.4", &[9.0, 9.0], mock_fn).unwrap();
+    index.add_node("foo.30", &[20.0, 20.0], mock_fn).unwrap();
+    let mut attrs = BTreeMap::new();
+    attrs.insert("w".to_owned(), 2.0);
+    index.set_attrs("foo.5", attrs.clone()).unwrap();
+    index.tag_node("foo.6", vec!["red".to_owned()]).unwrap();
+
+    rebuilt.catch_up(&index).unwrap();
+    assert_eq!((rebuilt.m, rebuilt.ef_construction), (8, 32));
+    assert_eq!(rebuilt.node_count, 30);
+    assert!(!rebuilt.nodes.contains_key("foo.3"));
+    assert!(rebuilt.epoch > index.epoch);
+    for (name, node) in &index.nodes {
+        let nr = node.read().unwrap();
+        let copy = rebuilt.nodes[name].read().unwrap();
+        assert_eq!((copy.id, copy.version), (nr.id, nr.version));
+        assert_eq!(&copy.data[..], &nr.data[..]);
+        assert!(rebuilt.node_at(copy.id).is_ok());
+    }
+    assert_eq!(rebuilt.nodes["foo.5"].read().unwrap().attrs, attrs);
+    assert_eq!(rebuilt.nodes["foo.6"].read().unwrap().tags, vec!["red"]);
+    let res = rebuilt.search_knn(&[20.0, 20.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "30");
+    let res = rebuilt.search_knn(&[9.0, 9.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "4");
+}
+
+// level and neighbor names of every node, by name
+fn graph_of(index: &Index<f32, f32>) -> BTreeMap<String, (usize, Vec<Vec<String>>)> {
+    index
+        .nodes
+        .iter()
+        .map(|(name, node)| {
+            let n = node.read().unwrap();
+            let mut neighbors = n
+                .neighbors
+                .iter()
+                .map(|l| {
+                    l.iter()
+                        .map(|id| index.node_at(*id).unwrap().read().unwrap().name.clone())
+                        .collect::<Vec<String>>()
+                })
+                .collect::<Vec<Vec<String>>>();
+            neighbors.iter_mut().for_each(|l| l.sort());
+            (name.clone(), (n.level, neighbors))
+        })
+        .collect()
+}
+
+#[test]
+fn undo_test() {
+    let mut index: Index<f32, f32> = Index::new("foo", Box::new(euclidean), 2, 4, 16);
+    let mock_fn = |_s: String, _n: Node<f32>| Ok(());
+    for i in 0..40 {
+        let name = format!("foo.{}", i);
+        index
+            .add_node(&name, &[(i % 8) as f32, (i / 8) as f32], mock_fn)
+            .unwrap();
+    }
+    index.tag_node("foo.9", vec!["red".to_owned()]).unwrap();
+    let before = graph_of(&index);
+    let (count, layer, enterpoint) = (index.node_count, index.max_layer, index.enterpoint);
+
+    index.begin_undo();
+    index
+        .add_node_at("foo.new", &[3.5, 2.5], Some(3), mock_fn)
+        .unwrap();
+    index.tag_node("foo.new", vec!["red".to_owned()]).unwrap();
+    index.delete_node("foo.9", mock_fn).unwrap();
+    index.update_node("foo.20", &[0.5, 0.5], mock_fn).unwrap();
+    let undo = index.end_undo().unwrap();
+    assert_ne!(graph_of(&index), before);
+
+    index.undo(undo).unwrap();
+    assert_eq!(graph_of(&index), before);
+    assert_eq!(index.node_count, count);
+    assert_eq!(index.max_layer, layer);
+    assert_eq!(index.enterpoint, enterpoint);
+    let layers = index.layers.iter().map(|l| l.len()).sum::<usize>();
+    assert_eq!(layers, 40);
+    assert_eq!(index.nodes["foo.20"].read().unwrap().version, 1);
+
+    let res = index.search_knn(&[1.0, 1.0], 1).unwrap();
+    assert_eq!(res[0].name.as_str(), "9");
+    let params = SearchParams {
+        filter: Some(Filter::parse("TAG:red").unwrap()),
+        ..SearchParams::default()
+    };
+    let res = index.search_knn_with(&[3.5, 2.5], 5, &params).unwrap();
+    assert_eq!(res.len(), 1);
+    assert!(index.end_undo().is_none());
+}
@@ -0,0 +1,112 @@
+use super::core::{HNSWError, SearchResult};
+
+use num::Float;
+
+// initial number of results fetched per requested result of a deduplicated search
+static DEDUPE_OVERFETCH: usize = 2;
+// a deduplicated search stops fetching more results at this multiple of K
+static DEDUPE_MAX_OVERFETCH: usize = 256;
+
+// what adding a node whose vector equals the vector of an existing node does, each value
+// within the epsilon of the index
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DuplicatePolicy {
+    #[default]
+    Allow, // the node is linked like any other
+    Reject, // the add fails
+    Alias,  // the name resolves to the existing node, the graph is unchanged
+}
+
+impl DuplicatePolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy.to_ascii_lowercase().as_str() {
+            "allow" => Some(DuplicatePolicy::Allow),
+            "reject" => Some(DuplicatePolicy::Reject),
+            "alias" => Some(DuplicatePolicy::Alias),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::Allow => "allow",
+            DuplicatePolicy::Reject => "reject",
+            DuplicatePolicy::Alias => "alias",
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        match self {
+            DuplicatePolicy::Allow => 0,
+            DuplicatePolicy::Reject => 1,
+            DuplicatePolicy::Alias => 2,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Result<Self, HNSWError> {
+        match id {
+            0 => Ok(DuplicatePolicy::Allow),
+            1 => Ok(DuplicatePolicy::Reject),
+            2 => Ok(DuplicatePolicy::Alias),
+            _ => Err(format!("Unknown duplicate policy: {}", id).into()),
+        }
+    }
+}
+
+// collapses results whose vectors are equal, each value within epsilon
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dedupe {
+    pub epsilon: f32,
+}
+
+impl Dedupe {
+    pub fn new(epsilon: f32) -> Result<Self, HNSWError> {
+        if epsilon.is_nan() || epsilon < 0.0 {
+            return Err("EPSILON must be a non-negative number".into());
+        }
+        Ok(Dedupe { epsilon })
+    }
+
+    pub fn same<T: Float>(&self, a: &[T], b: &[T]) -> bool {
+        let epsilon = T::from(self.epsilon).unwrap();
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (*x - *y).abs() <= epsilon)
+    }
+
+    // the k most similar distinct results in the order of res, the names of the results
+    // collapsed into one are its aliases
+    pub fn apply<T: Float, R: Float>(
+        &self,
+        res: Vec<SearchResult<T, R>>,
+        k: usize,
+    ) -> Vec<SearchResult<T, R>> {
+        let mut distinct: Vec<SearchResult<T, R>> = Vec::with_capacity(k);
+        for r in res {
+            match distinct.iter().position(|d| self.same(&d.data, &r.data)) {
+                Some(i) => distinct[i].aliases.push((r.name, r.key)),
+                None if distinct.len() < k => distinct.push(r),
+                // results past the k-th distinct vector can only be aliases
+                None => {}
+            }
+        }
+        distinct
+    }
+
+    // searches for more results until k distinct ones remain or the index is exhausted,
+    // search is called with the number of results to fetch
+    pub fn search<T: Float, R: Float, S, E>(
+        &self,
+        k: usize,
+        mut search: impl FnMut(usize) -> Result<(Vec<SearchResult<T, R>>, S), E>,
+    ) -> Result<(Vec<SearchResult<T, R>>, S), E> {
+        let mut fetch = k * DEDUPE_OVERFETCH;
+        loop {
+            let (res, stats) = search(fetch)?;
+            let exhausted = res.len() < fetch || fetch >= k * DEDUPE_MAX_OVERFETCH;
+            let distinct = self.apply(res, k);
+            if distinct.len() == k || exhausted {
+                return Ok((distinct, stats));
+            }
+            fetch *= 2;
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use crate::hnsw::core::SearchResult;
+use crate::hnsw::core_tests::{names, result};
+use crate::hnsw::dedupe::*;
+
+fn aliases(res: &SearchResult<f32, f32>) -> Vec<&str> {
+    res.aliases.iter().map(|(name, _)| name.as_str()).collect()
+}
+
+fn results() -> Vec<SearchResult<f32, f32>> {
+    vec![
+        result("a", -1.0, &[1.0, 0.0], &[]),
+        result("b", -1.0, &[1.0, 0.0], &[]),
+        result("c", -2.0, &[2.0, 0.0], &[]),
+        result("d", -2.0, &[2.0, 0.001], &[]),
+        result("e", -3.0, &[3.0, 0.0], &[]),
+        result("f", -1.0, &[1.0, 0.0], &[]),
+    ]
+}
+
+#[test]
+fn collapse_equal_vectors() {
+    let exact = Dedupe::new(0.0).unwrap().apply(results(), 4);
+    assert_eq!(names(&exact), vec!["a", "c", "d", "e"]);
+    assert_eq!(aliases(&exact[0]), vec!["b", "f"]);
+    assert!(exact[1].aliases.is_empty());
+
+    // later results only add aliases once k distinct vectors are found
+    let near = Dedupe::new(0.01).unwrap().apply(results(), 2);
+    assert_eq!(names(&near), vec!["a", "c"]);
+    assert_eq!(aliases(&near[0]), vec!["b", "f"]);
+    assert_eq!(aliases(&near[1]), vec!["d"]);
+    assert_eq!(near[0].aliases[0].1, "hnsw:node:{foo}:b");
+
+    assert!(Dedupe::new(-1.0).is_err());
+    assert!(Dedupe::new(f32::NAN).is_err());
+}
+
+#[test]
+fn fetch_until_k_distinct() {
+    // every vector is inserted 5 times
+    let mut fetches = Vec::new();
+    let search = |fetch: usize| -> Result<(Vec<SearchResult<f32, f32>>, ()), ()> {
+        fetches.push(fetch);
+        let res = (0..fetch.min(100)).map(|i| {
+            let v = (i / 5) as f32;
+            result(&format!("n{}", i), -v, &[v], &[])
+        });
+        Ok((res.collect(), ()))
+    };
+    let (res, _) = Dedupe::new(0.0).unwrap().search(5, search).unwrap();
+    assert_eq!(names(&res), vec!["n0", "n5", "n10", "n15", "n20"]);
+    assert_eq!(aliases(&res[1]), vec!["n6", "n7", "n8", "n9"]);
+    assert_eq!(fetches, vec![10, 20, 40]);
+}
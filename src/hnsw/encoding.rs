@@ -0,0 +1,152 @@
+use super::core::{check_finite, HNSWError};
+
+// encoding of a vector stored outside the module, e.g. in a hash field
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VectorFormat {
+    Fp32Blob, // little-endian f32s
+    Fp64Blob, // little-endian f64s
+    Text,     // numbers separated by commas or whitespace, e.g. a JSON array
+}
+
+impl VectorFormat {
+    pub fn parse(format: &str) -> Result<Self, HNSWError> {
+        match format.to_ascii_lowercase().as_str() {
+            "fp32blob" => Ok(VectorFormat::Fp32Blob),
+            "fp64blob" => Ok(VectorFormat::Fp64Blob),
+            "text" | "json" => Ok(VectorFormat::Text),
+            _ => Err(format!(
+                "Invalid FORMAT: {}, expected fp32blob, fp64blob or text",
+                format
+            )
+            .into()),
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Result<Vec<f32>, HNSWError> {
+        let data = match self {
+            VectorFormat::Fp32Blob => {
+                decode_blob(bytes, 4, |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))?
+            }
+            VectorFormat::Fp64Blob => decode_blob(bytes, 8, |b| {
+                let mut le = [0u8; 8];
+                le.copy_from_slice(b);
+                f64::from_le_bytes(le) as f32
+            })?,
+            VectorFormat::Text => decode_text(bytes)?,
+        };
+        if data.is_empty() {
+            return Err("Vector is empty".into());
+        }
+        check_finite(&data)?;
+        Ok(data)
+    }
+
+    // bytes decode reads back as data
+    pub fn encode(self, data: &[f32]) -> Vec<u8> {
+        match self {
+            VectorFormat::Fp32Blob => data.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            VectorFormat::Fp64Blob => data
+                .iter()
+                .flat_map(|v| (*v as f64).to_le_bytes())
+                .collect(),
+            VectorFormat::Text => data
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+                .into_bytes(),
+        }
+    }
+}
+
+fn decode_blob(
+    bytes: &[u8],
+    width: usize,
+    f: impl FnMut(&[u8]) -> f32,
+) -> Result<Vec<f32>, HNSWError> {
+    if !bytes.len().is_multiple_of(width) {
+        return Err(format!(
+            "Blob of {} bytes is not a multiple of {} byte values",
+            bytes.len(),
+            width
+        )
+        .into());
+    }
+    Ok(bytes.chunks_exact(width).map(f).collect())
+}
+
+// brackets are ignored, so nested arrays of a JSONPath result are flattened
+fn decode_text(bytes: &[u8]) -> Result<Vec<f32>, HNSWError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "Vector text is not valid UTF-8")?;
+    text.split(|c: char| c == ',' || c == '[' || c == ']' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse::<f32>()
+                .map_err(|_| format!("Invalid vector value: {}", v).into())
+        })
+        .collect()
+}
+
+// first byte of packed vectors, how the rest is encoded
+const PACK_RAW: u8 = 0; // little-endian f32s
+const PACK_ZSTD: u8 = 1; // zstd compressed f32 bits, each xored with the previous value
+                         // zstd level of packed vectors, higher levels barely shrink float data further
+#[cfg(feature = "zstd")]
+const PACK_ZSTD_LEVEL: i32 = 3;
+
+// vectors are saved to RDB as one string instead of a call per value. with compress they
+// are zstd compressed if that makes them smaller, which needs the zstd feature
+pub fn pack_f32s(data: &[f32], compress: bool) -> Vec<u8> {
+    #[cfg(feature = "zstd")]
+    {
+        if compress && !data.is_empty() {
+            let mut prev = 0;
+            let mut deltas = Vec::with_capacity(data.len() * 4);
+            for d in data {
+                let bits = d.to_bits();
+                deltas.extend_from_slice(&(bits ^ prev).to_le_bytes());
+                prev = bits;
+            }
+            if let Ok(zipped) = zstd::stream::encode_all(deltas.as_slice(), PACK_ZSTD_LEVEL) {
+                if zipped.len() < deltas.len() {
+                    let mut packed = Vec::with_capacity(zipped.len() + 1);
+                    packed.push(PACK_ZSTD);
+                    packed.extend_from_slice(&zipped);
+                    return packed;
+                }
+            }
+        }
+    }
+    #[cfg(not(feature = "zstd"))]
+    let _ = compress;
+
+    let mut packed = Vec::with_capacity(data.len() * 4 + 1);
+    packed.push(PACK_RAW);
+    for d in data {
+        packed.extend_from_slice(&d.to_le_bytes());
+    }
+    packed
+}
+
+pub fn unpack_f32s(bytes: &[u8]) -> Result<Vec<f32>, HNSWError> {
+    let f = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    match bytes.split_first() {
+        Some((&PACK_RAW, rest)) => decode_blob(rest, 4, f),
+        #[cfg(feature = "zstd")]
+        Some((&PACK_ZSTD, rest)) => {
+            let deltas =
+                zstd::stream::decode_all(rest).map_err(|e| format!("Corrupt vector: {}", e))?;
+            let mut prev = 0;
+            decode_blob(&deltas, 4, |b| {
+                prev ^= f(b).to_bits();
+                f32::from_bits(prev)
+            })
+        }
+        #[cfg(not(feature = "zstd"))]
+        Some((&PACK_ZSTD, _)) => {
+            Err("Vector is zstd compressed, the module lacks the zstd feature".into())
+        }
+        Some((codec, _)) => Err(format!("Unknown vector encoding: {}", codec).into()),
+        None => Err("Vector is empty".into()),
+    }
+}
@@ -0,0 +1,73 @@
+use crate::hnsw::encoding::*;
+
+#[test]
+fn decode_vectors() {
+    let data = [1.5f32, -2.0, 0.25];
+    let blob32 = data
+        .iter()
+        .flat_map(|d| d.to_le_bytes())
+        .collect::<Vec<u8>>();
+    let blob64 = data
+        .iter()
+        .flat_map(|d| (*d as f64).to_le_bytes())
+        .collect::<Vec<u8>>();
+    assert_eq!(VectorFormat::Fp32Blob.decode(&blob32).unwrap(), data);
+    assert_eq!(VectorFormat::Fp64Blob.decode(&blob64).unwrap(), data);
+    assert_eq!(VectorFormat::Text.decode(b"1.5 -2 0.25").unwrap(), data);
+    // JSONPath results are arrays of matches
+    assert_eq!(
+        VectorFormat::Text.decode(b"[[1.5, -2.0, 0.25]]").unwrap(),
+        data
+    );
+
+    assert!(VectorFormat::Fp32Blob.decode(&blob32[..5]).is_err());
+    assert!(VectorFormat::Fp32Blob.decode(&[]).is_err());
+    assert!(VectorFormat::Text.decode(b"[1, null]").is_err());
+    assert!(VectorFormat::Text.decode(b"1 NaN").is_err());
+
+    assert_eq!(
+        VectorFormat::parse("FP32BLOB").unwrap(),
+        VectorFormat::Fp32Blob
+    );
+    assert!(VectorFormat::parse("fp16blob").is_err());
+}
+
+#[test]
+fn encode_vectors() {
+    let data = [1.5f32, -2.0, 0.1];
+    for format in &[
+        VectorFormat::Fp32Blob,
+        VectorFormat::Fp64Blob,
+        VectorFormat::Text,
+    ] {
+        assert_eq!(format.decode(&format.encode(&data)).unwrap(), data);
+    }
+    assert_eq!(VectorFormat::Fp32Blob.encode(&data).len(), 12);
+    assert_eq!(
+        VectorFormat::Fp32Blob.encode(&[1.0]),
+        vec![0, 0, 0x80, 0x3f]
+    );
+    assert_eq!(VectorFormat::Text.encode(&[1.5, -2.0]), b"1.5,-2");
+}
+
+#[test]
+fn pack_vectors() {
+    let data = (0..64)
+        .map(|i| 1.0 + (i % 4) as f32 * 0.125)
+        .collect::<Vec<f32>>();
+    for compress in &[false, true] {
+        let packed = pack_f32s(&data, *compress);
+        assert_eq!(unpack_f32s(&packed).unwrap(), data);
+        assert_eq!(unpack_f32s(&pack_f32s(&[], *compress)).unwrap(), vec![]);
+    }
+    // repetitive data shrinks if compression is built in
+    if cfg!(feature = "zstd") {
+        assert!(pack_f32s(&data, true).len() < pack_f32s(&data, false).len());
+    }
+    // raw packing is the codec byte and the fp32 blob
+    assert_eq!(pack_f32s(&[1.5, -2.0], false).len(), 9);
+
+    assert!(unpack_f32s(&[]).is_err());
+    assert!(unpack_f32s(&[7, 0, 0, 0, 0]).is_err());
+    assert!(unpack_f32s(&[0, 0, 0]).is_err());
+}
@@ -0,0 +1,115 @@
+use super::core::{HNSWError, Subgraph};
+
+use std::fmt::Write;
+
+// text formats of graph visualization tools
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GraphFormat {
+    Dot,     // Graphviz
+    GraphML, // Gephi, yEd, networkx
+}
+
+impl GraphFormat {
+    pub fn parse(format: &str) -> Result<Self, HNSWError> {
+        match format.to_ascii_lowercase().as_str() {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphML),
+            _ => Err(format!("Invalid FORMAT: {}, expected dot or graphml", format).into()),
+        }
+    }
+
+    pub fn render(self, name: &str, graph: &Subgraph) -> String {
+        match self {
+            GraphFormat::Dot => to_dot(name, graph),
+            GraphFormat::GraphML => to_graphml(name, graph),
+        }
+    }
+}
+
+// layouts of an exported k nearest neighbor graph
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum KnnFormat {
+    Hash,   // field per node, its neighbors as tab separated lines
+    Stream, // entry per edge, for consumers reading incrementally
+}
+
+impl KnnFormat {
+    pub fn parse(format: &str) -> Result<Self, HNSWError> {
+        match format.to_ascii_lowercase().as_str() {
+            "hash" => Ok(KnnFormat::Hash),
+            "stream" => Ok(KnnFormat::Stream),
+            _ => Err(format!("Invalid FORMAT: {}, expected hash or stream", format).into()),
+        }
+    }
+}
+
+// `{neighbor}\t{similarity}` line per neighbor, tabs, newlines and backslashes in names
+// are escaped
+pub fn knn_tsv(neighbors: &[(&str, f64)]) -> String {
+    let mut out = String::new();
+    for (name, sim) in neighbors {
+        let _ = writeln!(out, "{}\t{}", escape_tsv(name), sim);
+    }
+    out
+}
+
+fn to_dot(name: &str, graph: &Subgraph) -> String {
+    let mut out = format!("digraph \"{}\" {{\n", escape_dot(name));
+    for (node, level) in &graph.nodes {
+        let _ = writeln!(out, "  \"{}\" [level={}];", escape_dot(node), level);
+    }
+    for (from, to) in &graph.edges {
+        let _ = writeln!(out, "  \"{}\" -> \"{}\";", escape_dot(from), escape_dot(to));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn to_graphml(name: &str, graph: &Subgraph) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+         <key id=\"level\" for=\"node\" attr.name=\"level\" attr.type=\"int\"/>\n",
+    );
+    let _ = writeln!(
+        out,
+        "  <graph id=\"{}\" edgedefault=\"directed\">",
+        escape_xml(name)
+    );
+    for (node, level) in &graph.nodes {
+        let _ = writeln!(
+            out,
+            "    <node id=\"{}\"><data key=\"level\">{}</data></node>",
+            escape_xml(node),
+            level
+        );
+    }
+    for (from, to) in &graph.edges {
+        let _ = writeln!(
+            out,
+            "    <edge source=\"{}\" target=\"{}\"/>",
+            escape_xml(from),
+            escape_xml(to)
+        );
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_tsv(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
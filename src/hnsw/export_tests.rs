@@ -0,0 +1,36 @@
+use crate::hnsw::core::Subgraph;
+use crate::hnsw::export::*;
+
+#[test]
+fn render_graph() {
+    let graph = Subgraph {
+        layer: 0,
+        nodes: vec![("a".to_owned(), 1), ("b\"<".to_owned(), 0)],
+        edges: vec![("a".to_owned(), "b\"<".to_owned())],
+    };
+
+    let dot = GraphFormat::parse("DOT").unwrap().render("idx", &graph);
+    assert!(dot.starts_with("digraph \"idx\" {\n"));
+    assert!(dot.contains("  \"a\" [level=1];\n"));
+    assert!(dot.contains("  \"a\" -> \"b\\\"<\";\n"));
+    assert!(dot.ends_with("}\n"));
+
+    let graphml = GraphFormat::parse("graphml").unwrap().render("idx", &graph);
+    assert!(graphml.contains("<graph id=\"idx\" edgedefault=\"directed\">"));
+    assert!(graphml.contains("<node id=\"b&quot;&lt;\"><data key=\"level\">0</data></node>"));
+    assert!(graphml.contains("<edge source=\"a\" target=\"b&quot;&lt;\"/>"));
+
+    assert!(GraphFormat::parse("json").is_err());
+}
+
+#[test]
+fn knn_graph() {
+    assert_eq!(knn_tsv(&[]), "");
+    assert_eq!(
+        knn_tsv(&[("a", 0.5), ("b\tc\\", -1.0)]),
+        "a\t0.5\nb\\tc\\\\\t-1\n"
+    );
+
+    assert_eq!(KnnFormat::parse("STREAM").unwrap(), KnnFormat::Stream);
+    assert!(KnnFormat::parse("json").is_err());
+}
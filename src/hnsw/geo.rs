@@ -0,0 +1,52 @@
+use super::core::HNSWError;
+
+// mean earth radius in meters, as used by redis GEO commands
+static EARTH_RADIUS_M: f64 = 6372797.560856;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Location {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Location {
+    pub fn new(lat: f64, lon: f64) -> Result<Self, HNSWError> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(format!("Invalid location: {} {}", lat, lon).into());
+        }
+        Ok(Location { lat, lon })
+    }
+
+    // great-circle distance in meters
+    pub fn distance(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+}
+
+// circle around a location nodes must be within to be returned by a search
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GeoFence {
+    pub center: Location,
+    pub radius: f64, // meters
+}
+
+impl GeoFence {
+    pub fn new(center: Location, radius: f64) -> Result<Self, HNSWError> {
+        if !radius.is_finite() || radius < 0.0 {
+            return Err(format!("Invalid radius: {}", radius).into());
+        }
+        Ok(GeoFence { center, radius })
+    }
+
+    // nodes without a location are never within a fence
+    pub fn contains(&self, location: Option<&Location>) -> bool {
+        match location {
+            Some(l) => self.center.distance(l) <= self.radius,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::hnsw::geo::*;
+
+#[test]
+fn haversine_distance() {
+    let paris = Location::new(48.8566, 2.3522).unwrap();
+    let london = Location::new(51.5074, -0.1278).unwrap();
+    let d = paris.distance(&london);
+    assert!((d - 343_500.0).abs() < 1_000.0, "{}", d);
+    assert!(paris.distance(&paris).abs() < f64::EPSILON);
+
+    assert!(Location::new(91.0, 0.0).is_err());
+    assert!(Location::new(0.0, -181.0).is_err());
+    assert!(Location::new(f64::NAN, 0.0).is_err());
+}
+
+#[test]
+fn fence_contains() {
+    let paris = Location::new(48.8566, 2.3522).unwrap();
+    let london = Location::new(51.5074, -0.1278).unwrap();
+    let fence = GeoFence::new(paris, 10_000.0).unwrap();
+    assert!(fence.contains(Some(&paris)));
+    assert!(!fence.contains(Some(&london)));
+    assert!(!fence.contains(None));
+    assert!(GeoFence::new(paris, 400_000.0)
+        .unwrap()
+        .contains(Some(&london)));
+
+    assert!(GeoFence::new(paris, -1.0).is_err());
+}
@@ -0,0 +1,94 @@
+use super::core::{HNSWError, SearchResult};
+
+use num::Float;
+use std::collections::HashMap;
+
+// initial number of results fetched per requested result of a grouped search
+static GROUP_OVERFETCH: usize = 4;
+// a grouped search stops fetching more results at this multiple of K
+static GROUP_MAX_OVERFETCH: usize = 256;
+
+// what results are grouped by, e.g. the document of chunk nodes
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupBy {
+    Prefix(String), // node name up to the last occurrence of the separator
+    Tag(String),    // first tag of the node starting with the prefix
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grouping {
+    pub by: GroupBy,
+    pub max_per_group: usize,
+}
+
+impl Grouping {
+    pub fn parse(kind: &str, value: &str, max_per_group: usize) -> Result<Self, HNSWError> {
+        if max_per_group == 0 {
+            return Err("MAXPERGROUP must be positive".into());
+        }
+        if value.is_empty() {
+            return Err("GROUPBY requires a separator or tag prefix".into());
+        }
+        let by = match kind.to_ascii_lowercase().as_str() {
+            "prefix" => GroupBy::Prefix(value.to_owned()),
+            "tag" => GroupBy::Tag(value.to_owned()),
+            _ => return Err(format!("Invalid GROUPBY: {}, expected PREFIX or TAG", kind).into()),
+        };
+        Ok(Grouping { by, max_per_group })
+    }
+
+    // group of a result, None if the result is a group of its own
+    pub fn group_of<'a, T: Float, R: Float>(&self, res: &'a SearchResult<T, R>) -> Option<&'a str> {
+        match &self.by {
+            GroupBy::Prefix(sep) => res.name.rsplit_once(sep.as_str()).map(|(g, _)| g),
+            GroupBy::Tag(prefix) => res
+                .tags
+                .iter()
+                .find(|t| t.starts_with(prefix.as_str()))
+                .map(|t| t.as_str()),
+        }
+    }
+
+    // the k most similar results, at most max_per_group of each group, in the order of res
+    pub fn apply<T: Float, R: Float>(
+        &self,
+        res: Vec<SearchResult<T, R>>,
+        k: usize,
+    ) -> Vec<SearchResult<T, R>> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut grouped = Vec::with_capacity(k);
+        for r in res {
+            if grouped.len() == k {
+                break;
+            }
+            if let Some(group) = self.group_of(&r) {
+                let count = counts.entry(group.to_owned()).or_insert(0);
+                if *count == self.max_per_group {
+                    continue;
+                }
+                *count += 1;
+            }
+            grouped.push(r);
+        }
+        grouped
+    }
+
+    // searches for more results until k remain after grouping or the index is exhausted,
+    // search is called with the number of results to fetch
+    pub fn search<T: Float, R: Float, S, E>(
+        &self,
+        k: usize,
+        mut search: impl FnMut(usize) -> Result<(Vec<SearchResult<T, R>>, S), E>,
+    ) -> Result<(Vec<SearchResult<T, R>>, S), E> {
+        let mut fetch = k * GROUP_OVERFETCH;
+        loop {
+            let (res, stats) = search(fetch)?;
+            let exhausted = res.len() < fetch || fetch >= k * GROUP_MAX_OVERFETCH;
+            let grouped = self.apply(res, k);
+            if grouped.len() == k || exhausted {
+                return Ok((grouped, stats));
+            }
+            fetch *= 2;
+        }
+    }
+}
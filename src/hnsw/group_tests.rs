@@ -0,0 +1,70 @@
+use crate::hnsw::core_tests::{names, result};
+use crate::hnsw::group::*;
+
+#[test]
+fn group_by_prefix() {
+    let grouping = Grouping::parse("PREFIX", "#", 2).unwrap();
+    let res = vec![
+        result("doc:1#0", 0.0, &[], &[]),
+        result("doc:1#1", 0.0, &[], &[]),
+        result("doc:1#2", 0.0, &[], &[]),
+        result("doc:2#0", 0.0, &[], &[]),
+        result("single", 0.0, &[], &[]),
+        result("single", 0.0, &[], &[]),
+    ];
+    // names without the separator are groups of their own
+    let grouped = grouping.apply(res, 5);
+    assert_eq!(
+        names(&grouped),
+        vec!["doc:1#0", "doc:1#1", "doc:2#0", "single", "single"]
+    );
+
+    assert!(Grouping::parse("prefix", "#", 0).is_err());
+    assert!(Grouping::parse("prefix", "", 1).is_err());
+    assert!(Grouping::parse("field", "#", 1).is_err());
+}
+
+#[test]
+fn group_by_tag() {
+    let grouping = Grouping::parse("tag", "doc:", 1).unwrap();
+    let res = vec![
+        result("a", 0.0, &[], &["red", "doc:1"]),
+        result("b", 0.0, &[], &["doc:1"]),
+        result("c", 0.0, &[], &["doc:2"]),
+        result("d", 0.0, &[], &["red"]),
+    ];
+    assert_eq!(grouping.group_of(&res[0]), Some("doc:1"));
+    assert_eq!(names(&grouping.apply(res, 10)), vec!["a", "c", "d"]);
+}
+
+#[test]
+fn grouped_search_fetches_more() {
+    let grouping = Grouping::parse("prefix", "#", 1).unwrap();
+    // every document has 10 chunks, ordered by document
+    let all = (0..100)
+        .map(|i| format!("doc{}#{}", i / 10, i % 10))
+        .collect::<Vec<String>>();
+    let fetch = |n: usize| {
+        all.iter()
+            .take(n)
+            .map(|name| result(name, 0.0, &[], &[]))
+            .collect()
+    };
+    let mut fetches = Vec::new();
+    let search = |n: usize| -> Result<_, ()> {
+        fetches.push(n);
+        Ok((fetch(n), ()))
+    };
+    let (res, _) = grouping.search(5, search).unwrap();
+    assert_eq!(
+        names(&res),
+        vec!["doc0#0", "doc1#0", "doc2#0", "doc3#0", "doc4#0"]
+    );
+    assert_eq!(fetches, vec![20, 40, 80]);
+
+    // the index runs out of documents
+    let (res, _) = grouping
+        .search(20, |n| -> Result<_, ()> { Ok((fetch(n), ())) })
+        .unwrap();
+    assert_eq!(res.len(), 10);
+}
@@ -0,0 +1,98 @@
+use super::core::HNSWError;
+
+// every index and node is stored under its own key. the dotted layout of older versions
+// shares one namespace, so node bar of index foo and index foo.bar both map to hnsw.foo.bar.
+// the tagged layout gives indexes and nodes distinct prefixes, and the braces of the index
+// name are a cluster hash tag keeping an index and its nodes in one slot
+pub static DOTTED_PREFIX: &str = "hnsw."; // hnsw.{index} and hnsw.{index}.{node}
+pub static INDEX_PREFIX: &str = "hnsw:idx:"; // hnsw:idx:{index}
+pub static NODE_PREFIX: &str = "hnsw:node:"; // hnsw:node:{index}:{node}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyLayout {
+    Tagged,
+    Dotted,
+}
+
+impl KeyLayout {
+    pub fn parse(layout: &str) -> Option<Self> {
+        match layout.to_ascii_lowercase().as_str() {
+            "tagged" => Some(KeyLayout::Tagged),
+            "dotted" => Some(KeyLayout::Dotted),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyLayout::Tagged => "tagged",
+            KeyLayout::Dotted => "dotted",
+        }
+    }
+}
+
+// key of the index named by the user
+pub fn index_key(layout: KeyLayout, name_suffix: &str) -> String {
+    match layout {
+        KeyLayout::Tagged => format!("{}{{{}}}", INDEX_PREFIX, name_suffix),
+        KeyLayout::Dotted => format!("{}{}", DOTTED_PREFIX, name_suffix),
+    }
+}
+
+// braces would end the hash tag early and let names of different indexes overlap
+pub fn check_index_name(layout: KeyLayout, name_suffix: &str) -> Result<(), HNSWError> {
+    if name_suffix.is_empty() {
+        return Err("Index name can't be empty".into());
+    }
+    if layout == KeyLayout::Tagged && name_suffix.contains(['{', '}']) {
+        return Err(format!("Index name: {:?} can't contain braces", name_suffix).into());
+    }
+    Ok(())
+}
+
+// layout of an index or sub-index key
+pub fn layout_of(index_key: &str) -> KeyLayout {
+    match index_key.starts_with(INDEX_PREFIX) {
+        true => KeyLayout::Tagged,
+        false => KeyLayout::Dotted,
+    }
+}
+
+// prefix of the node keys of an index or sub-index
+pub fn node_prefix(index_key: &str) -> String {
+    match index_key.strip_prefix(INDEX_PREFIX) {
+        Some(rest) => format!("{}{}:", NODE_PREFIX, rest),
+        None => format!("{}.", index_key),
+    }
+}
+
+pub fn node_key(index_key: &str, node_suffix: &str) -> String {
+    format!("{}{}", node_prefix(index_key), node_suffix)
+}
+
+// key of a node of the index at key from once the index moved to key to
+pub fn moved_node_key(name: &str, from: &str, to: &str) -> String {
+    match name.strip_prefix(node_prefix(from).as_str()) {
+        Some(node_suffix) => node_key(to, node_suffix),
+        None => name.to_owned(),
+    }
+}
+
+// name the user gave the index of the key, None for sub-index and node keys of the tagged
+// layout and keys of other types. dotted keys are ambiguous, their type tells them apart
+pub fn index_suffix(key: &str) -> Option<&str> {
+    match key.strip_prefix(INDEX_PREFIX) {
+        Some(rest) => rest
+            .strip_prefix('{')
+            .and_then(|r| r.strip_suffix('}'))
+            .filter(|r| !r.contains('}')),
+        None => key.strip_prefix(DOTTED_PREFIX),
+    }
+}
+
+// true if the key may hold an index or a node
+pub fn is_module_key(key: &str) -> bool {
+    [DOTTED_PREFIX, INDEX_PREFIX, NODE_PREFIX]
+        .iter()
+        .any(|p| key.starts_with(p))
+}
@@ -0,0 +1,56 @@
+use crate::hnsw::keys::*;
+
+#[test]
+fn tagged_keys() {
+    let index = index_key(KeyLayout::Tagged, "foo");
+    assert_eq!(index, "hnsw:idx:{foo}");
+    assert_eq!(layout_of(&index), KeyLayout::Tagged);
+    assert_eq!(node_key(&index, "bar"), "hnsw:node:{foo}:bar");
+    // sub-indexes share the hash tag of their index
+    assert_eq!(
+        node_key(&format!("{}.vec", index), "bar"),
+        "hnsw:node:{foo}.vec:bar"
+    );
+
+    // node bar of foo and index foo.bar no longer share a key
+    let other = index_key(KeyLayout::Tagged, "foo.bar");
+    assert_ne!(other, node_key(&index, "bar"));
+    assert_eq!(index_suffix(&other), Some("foo.bar"));
+    assert_eq!(index_suffix(&index), Some("foo"));
+    assert_eq!(index_suffix("hnsw:idx:{foo}.vec"), None);
+    assert_eq!(index_suffix("hnsw:node:{foo}:bar"), None);
+
+    assert!(check_index_name(KeyLayout::Tagged, "foo.bar:1").is_ok());
+    assert!(check_index_name(KeyLayout::Tagged, "foo}:bar").is_err());
+    assert!(check_index_name(KeyLayout::Tagged, "").is_err());
+}
+
+#[test]
+fn dotted_keys() {
+    let index = index_key(KeyLayout::Dotted, "foo");
+    assert_eq!(index, "hnsw.foo");
+    assert_eq!(layout_of(&index), KeyLayout::Dotted);
+    assert_eq!(node_key(&index, "bar"), "hnsw.foo.bar");
+    assert_eq!(
+        index_key(KeyLayout::Dotted, "foo.bar"),
+        node_key(&index, "bar")
+    );
+    assert_eq!(index_suffix(&index), Some("foo"));
+    assert!(check_index_name(KeyLayout::Dotted, "foo}").is_ok());
+
+    let tagged = index_key(KeyLayout::Tagged, "foo");
+    assert_eq!(
+        moved_node_key("hnsw.foo.bar", &index, &tagged),
+        "hnsw:node:{foo}:bar"
+    );
+    assert_eq!(
+        moved_node_key("hnsw.foo.vec.bar", "hnsw.foo.vec", "hnsw:idx:{foo}.vec"),
+        "hnsw:node:{foo}.vec:bar"
+    );
+
+    assert!(is_module_key("hnsw.foo") && is_module_key("hnsw:node:{foo}:bar"));
+    assert!(!is_module_key("doc:1") && !is_module_key("hnsw"));
+
+    assert_eq!(KeyLayout::parse("DOTTED"), Some(KeyLayout::Dotted));
+    assert!(KeyLayout::parse("flat").is_none());
+}
@@ -0,0 +1,103 @@
+use super::core::HNSWError;
+
+use num::Float;
+use rand::prelude::*;
+use std::cmp::Reverse;
+
+// how the initial centroids are picked
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum KMeansInit {
+    Random, // uniformly sampled vectors
+    Layers, // vectors of the nodes on the highest layers of the graph
+}
+
+impl KMeansInit {
+    pub fn parse(init: &str) -> Result<Self, HNSWError> {
+        match init.to_ascii_lowercase().as_str() {
+            "random" => Ok(KMeansInit::Random),
+            "layers" => Ok(KMeansInit::Layers),
+            _ => Err(format!("Invalid INIT: {}, expected layers or random", init).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KMeans<T: Float> {
+    pub centroids: Vec<Vec<T>>,
+    pub assignments: Vec<usize>, // index of the nearest centroid of each vector
+}
+
+// mini-batch k-means (Sculley 2010) by euclidean distance. levels are the top layers of
+// the nodes of the vectors, the same seed and input always give the same clusters
+pub fn kmeans<T: Float>(
+    vectors: &[&[T]],
+    levels: &[usize],
+    k: usize,
+    init: KMeansInit,
+    max_iter: usize,
+    batch_size: usize,
+    seed: u64,
+) -> Result<KMeans<T>, HNSWError> {
+    if k == 0 || k > vectors.len() {
+        return Err(format!(
+            "K must be between 1 and the number of nodes: {}",
+            vectors.len()
+        )
+        .into());
+    }
+    if batch_size == 0 {
+        return Err("BATCH must be positive".into());
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut order = (0..vectors.len()).collect::<Vec<usize>>();
+    order.shuffle(&mut rng);
+    if init == KMeansInit::Layers {
+        // upper layers are a sample spread over the graph, the sort keeps the shuffled
+        // order within a layer
+        order.sort_by_key(|&i| Reverse(levels[i]));
+    }
+    let mut centroids = order[..k]
+        .iter()
+        .map(|&i| vectors[i].to_vec())
+        .collect::<Vec<Vec<T>>>();
+
+    // each centroid moves towards its batch members by a rate decaying with its count
+    let mut counts = vec![0_usize; k];
+    for _ in 0..max_iter {
+        let batch = (0..batch_size)
+            .map(|_| rng.gen_range(0, vectors.len()))
+            .collect::<Vec<usize>>();
+        let nearest_of = batch
+            .iter()
+            .map(|&i| nearest(&centroids, vectors[i]))
+            .collect::<Vec<usize>>();
+        for (&i, &c) in batch.iter().zip(&nearest_of) {
+            counts[c] += 1;
+            let eta = T::from(counts[c]).unwrap().recip();
+            for (x, v) in centroids[c].iter_mut().zip(vectors[i]) {
+                *x = *x + (*v - *x) * eta;
+            }
+        }
+    }
+
+    let assignments = vectors.iter().map(|v| nearest(&centroids, v)).collect();
+    Ok(KMeans {
+        centroids,
+        assignments,
+    })
+}
+
+fn nearest<T: Float>(centroids: &[Vec<T>], v: &[T]) -> usize {
+    let mut best = (0, T::infinity());
+    for (i, c) in centroids.iter().enumerate() {
+        let dist = c
+            .iter()
+            .zip(v)
+            .fold(T::zero(), |acc, (a, b)| acc + (*a - *b) * (*a - *b));
+        if dist < best.1 {
+            best = (i, dist);
+        }
+    }
+    best.0
+}
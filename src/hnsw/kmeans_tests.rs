@@ -0,0 +1,36 @@
+use crate::hnsw::kmeans::*;
+
+#[test]
+fn kmeans_test() {
+    // two blobs around (0, 0) and (10, 10)
+    let vectors = (0..40)
+        .map(|i| {
+            let offset = if i % 2 == 0 { 0.0 } else { 10.0 };
+            vec![offset + (i % 5) as f32 * 0.1, offset - (i % 3) as f32 * 0.1]
+        })
+        .collect::<Vec<Vec<f32>>>();
+    let refs = vectors
+        .iter()
+        .map(|v| v.as_slice())
+        .collect::<Vec<&[f32]>>();
+    let levels = (0..40).map(|i| i % 3).collect::<Vec<usize>>();
+
+    for init in &[KMeansInit::Random, KMeansInit::Layers] {
+        let res = kmeans(&refs, &levels, 2, *init, 20, 8, 7).unwrap();
+        assert_eq!(res.centroids.len(), 2);
+        assert_eq!(res.assignments.len(), 40);
+        for (i, c) in res.assignments.iter().enumerate() {
+            assert_eq!(*c, res.assignments[i % 2]);
+        }
+        assert_ne!(res.assignments[0], res.assignments[1]);
+        let far = &res.centroids[res.assignments[1]];
+        assert!(far.iter().all(|x| (x - 10.0).abs() < 1.0));
+        // deterministic by seed
+        assert_eq!(res, kmeans(&refs, &levels, 2, *init, 20, 8, 7).unwrap());
+    }
+
+    assert!(kmeans(&refs, &levels, 0, KMeansInit::Random, 20, 8, 7).is_err());
+    assert!(kmeans(&refs, &levels, 41, KMeansInit::Random, 20, 8, 7).is_err());
+    assert!(KMeansInit::parse("LAYERS").is_ok());
+    assert!(KMeansInit::parse("kmeans++").is_err());
+}
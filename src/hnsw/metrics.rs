@@ -4,81 +4,511 @@ use std::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-#[derive(Copy, Clone, Debug)]
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum MetricFuncs {
+    #[default]
     Euclidean,
+    Cosine,
+    InnerProduct,
+}
+
+impl MetricFuncs {
+    // stable id persisted in the rdb, never renumber existing metrics
+    pub fn id(self) -> u64 {
+        match self {
+            MetricFuncs::Euclidean => 0,
+            MetricFuncs::Cosine => 1,
+            MetricFuncs::InnerProduct => 2,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Result<Self, String> {
+        match id {
+            0 => Ok(MetricFuncs::Euclidean),
+            1 => Ok(MetricFuncs::Cosine),
+            2 => Ok(MetricFuncs::InnerProduct),
+            _ => Err(format!("Unknown metric id: {}", id)),
+        }
+    }
+
+    // indexes saved before metric ids stored the debug name of the metric
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "Euclidean" => Ok(MetricFuncs::Euclidean),
+            "Cosine" => Ok(MetricFuncs::Cosine),
+            "InnerProduct" => Ok(MetricFuncs::InnerProduct),
+            _ => Err(format!("Unknown metric: {}", name)),
+        }
+    }
+}
+
+impl MetricFuncs {
+    // similarities of a query to each of the vectors, with the kernel for their dimension
+    pub fn batch<'a>(
+        self,
+        query: &[f32],
+        vectors: impl IntoIterator<Item = &'a [f32]>,
+        sims: &mut Vec<f32>,
+    ) {
+        batch(
+            metric_func(self, query.len()),
+            query,
+            vectors,
+            query.len(),
+            sims,
+        )
+    }
 }
 
 pub type MetricFuncT<T, R> = fn(&[T], &[T], usize) -> R;
 
-pub fn euclidean(v1: &[f32], v2: &[f32], n: usize) -> f32 {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    {
-        // TODO remove the check on array length with more flexible avx func
-        if is_x86_feature_detected!("avx2") && v1.len() % 32 == 0 {
-            return sim_func_avx_euc(v1, v2, n);
+// cache lines at the start of a vector fetched ahead of its distance, the hardware prefetcher
+// follows the sequential reads from there
+#[cfg(feature = "prefetch")]
+const PREFETCH_LINES: usize = 4;
+#[cfg(feature = "prefetch")]
+const CACHE_LINE: usize = 64;
+
+// hints the cpu to start loading a vector into cache, e.g. the neighbors of a candidate while
+// the previous ones are being compared. a no-op on other architectures
+#[cfg(feature = "prefetch")]
+#[inline]
+pub fn prefetch<T>(v: &[T]) {
+    let ptr = v.as_ptr() as *const i8;
+    let len = std::mem::size_of_val(v).min(PREFETCH_LINES * CACHE_LINE);
+    for offset in (0..len).step_by(CACHE_LINE) {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        unsafe {
+            _mm_prefetch::<_MM_HINT_T0>(ptr.add(offset));
+        }
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            std::arch::asm!(
+                "prfm pldl1keep, [{0}]",
+                in(reg) ptr.add(offset),
+                options(nostack, readonly, preserves_flags)
+            );
         }
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+        let _ = (ptr, offset);
     }
-    sim_func_euc(v1, v2, n)
 }
 
-fn hsum_ps_sse3(v: __m128) -> f32 {
-    unsafe {
-        let mut shuf: __m128 = _mm_movehdup_ps(v); // broadcast elements 3,1 to 2,0
-        let mut sums: __m128 = _mm_add_ps(v, shuf);
-        shuf = _mm_movehl_ps(shuf, sums); // high half -> low half
-        sums = _mm_add_ss(sums, shuf);
-        _mm_cvtss_f32(sums)
+// similarities of a query to several vectors in one call, e.g. to the unvisited neighbors of a
+// candidate, replacing the contents of sims
+#[inline]
+pub fn batch<'a, T: 'a, R>(
+    mfunc: MetricFuncT<T, R>,
+    query: &[T],
+    vectors: impl IntoIterator<Item = &'a [T]>,
+    n: usize,
+    sims: &mut Vec<R>,
+) {
+    sims.clear();
+    sims.extend(vectors.into_iter().map(|v| mfunc(query, v, n)));
+}
+
+// best kernel of each metric for the cpu features available at runtime. the simd kernels are
+// private and only selected here, after the cpu was checked for the features they are compiled
+// with
+struct Kernels {
+    euclidean: MetricFuncT<f32, f32>,
+    euclidean_x32: MetricFuncT<f32, f32>, // for dimensions that are multiples of 32
+    cosine: MetricFuncT<f32, f32>,
+    inner_product: MetricFuncT<f32, f32>,
+    simd: &'static str, // instruction set of the kernels, scalar without simd
+}
+
+impl Kernels {
+    fn detect() -> Self {
+        #[allow(unused_mut)]
+        let mut kernels = Kernels {
+            euclidean: sim_func_euc,
+            euclidean_x32: sim_func_euc,
+            cosine: sim_func_cos,
+            inner_product: sim_func_ip,
+            simd: "scalar",
+        };
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                kernels.euclidean_x32 = sim_func_avx_euc;
+                kernels.cosine = sim_func_avx_cos;
+                kernels.inner_product = sim_func_avx_ip;
+                kernels.simd = "avx2";
+            }
+            if is_x86_feature_detected!("avx512f") {
+                kernels.euclidean = sim_func_avx512_euc;
+                kernels.euclidean_x32 = sim_func_avx512_euc;
+                kernels.cosine = sim_func_avx512_cos;
+                kernels.inner_product = sim_func_avx512_ip;
+                kernels.simd = "avx512f";
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                kernels.euclidean = sim_func_neon_euc;
+                kernels.euclidean_x32 = sim_func_neon_euc;
+                kernels.cosine = sim_func_neon_cos;
+                kernels.inner_product = sim_func_neon_ip;
+                kernels.simd = "neon";
+            }
+        }
+
+        kernels
     }
 }
 
-fn hsum256_ps_avx(v: __m256) -> f32 {
-    unsafe {
-        let mut vlow: __m128 = _mm256_castps256_ps128(v);
-        let vhigh: __m128 = _mm256_extractf128_ps(v, 1); // high 128
-        vlow = _mm_add_ps(vlow, vhigh); // add the low 128
-        hsum_ps_sse3(vlow) // and inline the sse3 version, which is optimal for AVX
+lazy_static! {
+    static ref KERNELS: Kernels = Kernels::detect();
+}
+
+// resolves the metric kernels, called once at module load
+pub fn init() {
+    lazy_static::initialize(&KERNELS);
+}
+
+// instruction set of the kernels selected for this cpu
+pub fn simd() -> &'static str {
+    KERNELS.simd
+}
+
+// concrete kernel for vectors of data_dim, so indexes don't dispatch on every distance call
+pub fn metric_func(kind: MetricFuncs, data_dim: usize) -> MetricFuncT<f32, f32> {
+    match kind {
+        // TODO remove the check on array length with more flexible avx func
+        MetricFuncs::Euclidean if data_dim % 32 == 0 => KERNELS.euclidean_x32,
+        MetricFuncs::Euclidean => KERNELS.euclidean,
+        MetricFuncs::Cosine => KERNELS.cosine,
+        MetricFuncs::InnerProduct => KERNELS.inner_product,
     }
 }
 
+#[allow(dead_code)]
+pub fn euclidean(v1: &[f32], v2: &[f32], n: usize) -> f32 {
+    metric_func(MetricFuncs::Euclidean, n)(v1, v2, n)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse3")]
+unsafe fn hsum_ps_sse3(v: __m128) -> f32 {
+    let mut shuf: __m128 = _mm_movehdup_ps(v); // broadcast elements 3,1 to 2,0
+    let mut sums: __m128 = _mm_add_ps(v, shuf);
+    shuf = _mm_movehl_ps(shuf, sums); // high half -> low half
+    sums = _mm_add_ss(sums, shuf);
+    _mm_cvtss_f32(sums)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx")]
+unsafe fn hsum256_ps_avx(v: __m256) -> f32 {
+    let mut vlow: __m128 = _mm256_castps256_ps128(v);
+    let vhigh: __m128 = _mm256_extractf128_ps(v, 1); // high 128
+    vlow = _mm_add_ps(vlow, vhigh); // add the low 128
+    hsum_ps_sse3(vlow) // and inline the sse3 version, which is optimal for AVX
+}
+
 // Multiple accumulators and FMA
 // since FMA has a latency of 5 cycles but 0.5 CPI
 // https://stackoverflow.com/questions/45735679/euclidean-distance-using-intrinsic-instruction
 // TODO: extend functionality for vectors of non-multiples of 32 floats
-pub fn sim_func_avx_euc(a: &[f32], b: &[f32], n: usize) -> f32 {
-    unsafe {
-        let mut euc1: __m256 = _mm256_setzero_ps();
-        let mut euc2: __m256 = _mm256_setzero_ps();
-        let mut euc3: __m256 = _mm256_setzero_ps();
-        let mut euc4: __m256 = _mm256_setzero_ps();
-
-        for i in (0..n).step_by(32) {
-            let v1: __m256 = _mm256_sub_ps(_mm256_loadu_ps(&a[i]), _mm256_loadu_ps(&b[i]));
-            euc1 = _mm256_fmadd_ps(v1, v1, euc1);
-
-            let v2: __m256 = _mm256_sub_ps(_mm256_loadu_ps(&a[i + 8]), _mm256_loadu_ps(&b[i + 8]));
-            euc2 = _mm256_fmadd_ps(v2, v2, euc2);
-
-            let v3: __m256 =
-                _mm256_sub_ps(_mm256_loadu_ps(&a[i + 16]), _mm256_loadu_ps(&b[i + 16]));
-            euc3 = _mm256_fmadd_ps(v3, v3, euc3);
-
-            let v4: __m256 =
-                _mm256_sub_ps(_mm256_loadu_ps(&a[i + 24]), _mm256_loadu_ps(&b[i + 24]));
-            euc4 = _mm256_fmadd_ps(v4, v4, euc4);
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn squared_distance_avx(a: &[f32], b: &[f32], n: usize) -> f32 {
+    assert_eq!(n % 32, 0);
+    assert!(a.len() >= n && b.len() >= n);
+    let mut euc1: __m256 = _mm256_setzero_ps();
+    let mut euc2: __m256 = _mm256_setzero_ps();
+    let mut euc3: __m256 = _mm256_setzero_ps();
+    let mut euc4: __m256 = _mm256_setzero_ps();
+
+    for i in (0..n).step_by(32) {
+        let v1: __m256 = _mm256_sub_ps(_mm256_loadu_ps(&a[i]), _mm256_loadu_ps(&b[i]));
+        euc1 = _mm256_fmadd_ps(v1, v1, euc1);
+
+        let v2: __m256 = _mm256_sub_ps(_mm256_loadu_ps(&a[i + 8]), _mm256_loadu_ps(&b[i + 8]));
+        euc2 = _mm256_fmadd_ps(v2, v2, euc2);
+
+        let v3: __m256 = _mm256_sub_ps(_mm256_loadu_ps(&a[i + 16]), _mm256_loadu_ps(&b[i + 16]));
+        euc3 = _mm256_fmadd_ps(v3, v3, euc3);
+
+        let v4: __m256 = _mm256_sub_ps(_mm256_loadu_ps(&a[i + 24]), _mm256_loadu_ps(&b[i + 24]));
+        euc4 = _mm256_fmadd_ps(v4, v4, euc4);
+    }
+
+    hsum256_ps_avx(_mm256_add_ps(
+        _mm256_add_ps(euc1, euc2),
+        _mm256_add_ps(euc3, euc4),
+    ))
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sim_func_avx_euc(a: &[f32], b: &[f32], n: usize) -> f32 {
+    -unsafe { squared_distance_avx(a, b, n) }
+}
+
+// 16 lanes with four accumulators like the avx kernel, then single vectors and a scalar tail,
+// so any dimension is supported
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512f")]
+unsafe fn squared_distance_avx512(a: &[f32], b: &[f32], n: usize) -> f32 {
+    assert!(a.len() >= n && b.len() >= n);
+    let mut acc: [__m512; 4] = [_mm512_setzero_ps(); 4];
+
+    let blocks = n - n % 64;
+    for i in (0..blocks).step_by(64) {
+        for (j, sum) in acc.iter_mut().enumerate() {
+            let offset = i + 16 * j;
+            let d: __m512 = _mm512_sub_ps(
+                _mm512_loadu_ps(a.as_ptr().add(offset)),
+                _mm512_loadu_ps(b.as_ptr().add(offset)),
+            );
+            *sum = _mm512_fmadd_ps(d, d, *sum);
+        }
+    }
+
+    let mut sum: __m512 =
+        _mm512_add_ps(_mm512_add_ps(acc[0], acc[1]), _mm512_add_ps(acc[2], acc[3]));
+    let chunks = n - n % 16;
+    for i in (blocks..chunks).step_by(16) {
+        let d: __m512 = _mm512_sub_ps(
+            _mm512_loadu_ps(a.as_ptr().add(i)),
+            _mm512_loadu_ps(b.as_ptr().add(i)),
+        );
+        sum = _mm512_fmadd_ps(d, d, sum);
+    }
+
+    _mm512_reduce_add_ps(sum) - sim_func_euc(&a[chunks..n], &b[chunks..n], n - chunks)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sim_func_avx512_euc(a: &[f32], b: &[f32], n: usize) -> f32 {
+    -unsafe { squared_distance_avx512(a, b, n) }
+}
+
+// dot product and squared norms of both vectors in a single pass
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn dot_norms_avx(a: &[f32], b: &[f32], n: usize) -> (f32, f32, f32) {
+    assert!(a.len() >= n && b.len() >= n);
+    let mut dot: __m256 = _mm256_setzero_ps();
+    let mut norm_a: __m256 = _mm256_setzero_ps();
+    let mut norm_b: __m256 = _mm256_setzero_ps();
+
+    let chunks = n - n % 8;
+    for i in (0..chunks).step_by(8) {
+        let va: __m256 = _mm256_loadu_ps(a.as_ptr().add(i));
+        let vb: __m256 = _mm256_loadu_ps(b.as_ptr().add(i));
+        dot = _mm256_fmadd_ps(va, vb, dot);
+        norm_a = _mm256_fmadd_ps(va, va, norm_a);
+        norm_b = _mm256_fmadd_ps(vb, vb, norm_b);
+    }
+
+    let (tail_dot, tail_a, tail_b) = dot_norms(&a[chunks..n], &b[chunks..n]);
+    (
+        hsum256_ps_avx(dot) + tail_dot,
+        hsum256_ps_avx(norm_a) + tail_a,
+        hsum256_ps_avx(norm_b) + tail_b,
+    )
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sim_func_avx_cos(a: &[f32], b: &[f32], n: usize) -> f32 {
+    let (dot, norm_a, norm_b) = unsafe { dot_norms_avx(a, b, n) };
+    cos_from_parts(dot, norm_a, norm_b)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sim_func_avx_ip(a: &[f32], b: &[f32], n: usize) -> f32 {
+    unsafe { dot_norms_avx(a, b, n).0 }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512f")]
+unsafe fn dot_norms_avx512(a: &[f32], b: &[f32], n: usize) -> (f32, f32, f32) {
+    assert!(a.len() >= n && b.len() >= n);
+    let mut dot: __m512 = _mm512_setzero_ps();
+    let mut norm_a: __m512 = _mm512_setzero_ps();
+    let mut norm_b: __m512 = _mm512_setzero_ps();
+
+    let chunks = n - n % 16;
+    for i in (0..chunks).step_by(16) {
+        let va: __m512 = _mm512_loadu_ps(a.as_ptr().add(i));
+        let vb: __m512 = _mm512_loadu_ps(b.as_ptr().add(i));
+        dot = _mm512_fmadd_ps(va, vb, dot);
+        norm_a = _mm512_fmadd_ps(va, va, norm_a);
+        norm_b = _mm512_fmadd_ps(vb, vb, norm_b);
+    }
+
+    let (tail_dot, tail_a, tail_b) = dot_norms(&a[chunks..n], &b[chunks..n]);
+    (
+        _mm512_reduce_add_ps(dot) + tail_dot,
+        _mm512_reduce_add_ps(norm_a) + tail_a,
+        _mm512_reduce_add_ps(norm_b) + tail_b,
+    )
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sim_func_avx512_cos(a: &[f32], b: &[f32], n: usize) -> f32 {
+    let (dot, norm_a, norm_b) = unsafe { dot_norms_avx512(a, b, n) };
+    cos_from_parts(dot, norm_a, norm_b)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn sim_func_avx512_ip(a: &[f32], b: &[f32], n: usize) -> f32 {
+    unsafe { dot_norms_avx512(a, b, n).0 }
+}
+
+// four accumulators of 4 lanes to hide the latency of the FMA, then single vectors and a
+// scalar tail
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn squared_distance_neon(a: &[f32], b: &[f32], n: usize) -> f32 {
+    assert!(a.len() >= n && b.len() >= n);
+    let mut acc = [vdupq_n_f32(0.0); 4];
+
+    let blocks = n - n % 16;
+    for i in (0..blocks).step_by(16) {
+        for (j, sum) in acc.iter_mut().enumerate() {
+            let offset = i + 4 * j;
+            let d = vsubq_f32(
+                vld1q_f32(a.as_ptr().add(offset)),
+                vld1q_f32(b.as_ptr().add(offset)),
+            );
+            *sum = vfmaq_f32(*sum, d, d);
         }
+    }
 
-        let res: f32 = hsum256_ps_avx(_mm256_add_ps(
-            _mm256_add_ps(euc1, euc2),
-            _mm256_add_ps(euc3, euc4),
-        ));
-        -res
+    let mut sum = vaddq_f32(vaddq_f32(acc[0], acc[1]), vaddq_f32(acc[2], acc[3]));
+    let chunks = n - n % 4;
+    for i in (blocks..chunks).step_by(4) {
+        let d = vsubq_f32(vld1q_f32(a.as_ptr().add(i)), vld1q_f32(b.as_ptr().add(i)));
+        sum = vfmaq_f32(sum, d, d);
     }
+
+    vaddvq_f32(sum) - sim_func_euc(&a[chunks..n], &b[chunks..n], n - chunks)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn sim_func_neon_euc(a: &[f32], b: &[f32], n: usize) -> f32 {
+    -unsafe { squared_distance_neon(a, b, n) }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn dot_norms_neon(a: &[f32], b: &[f32], n: usize) -> (f32, f32, f32) {
+    assert!(a.len() >= n && b.len() >= n);
+    let mut dot = vdupq_n_f32(0.0);
+    let mut norm_a = vdupq_n_f32(0.0);
+    let mut norm_b = vdupq_n_f32(0.0);
+
+    let chunks = n - n % 4;
+    for i in (0..chunks).step_by(4) {
+        let va = vld1q_f32(a.as_ptr().add(i));
+        let vb = vld1q_f32(b.as_ptr().add(i));
+        dot = vfmaq_f32(dot, va, vb);
+        norm_a = vfmaq_f32(norm_a, va, va);
+        norm_b = vfmaq_f32(norm_b, vb, vb);
+    }
+
+    let (tail_dot, tail_a, tail_b) = dot_norms(&a[chunks..n], &b[chunks..n]);
+    (
+        vaddvq_f32(dot) + tail_dot,
+        vaddvq_f32(norm_a) + tail_a,
+        vaddvq_f32(norm_b) + tail_b,
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+fn sim_func_neon_cos(a: &[f32], b: &[f32], n: usize) -> f32 {
+    let (dot, norm_a, norm_b) = unsafe { dot_norms_neon(a, b, n) };
+    cos_from_parts(dot, norm_a, norm_b)
 }
 
+#[cfg(target_arch = "aarch64")]
+fn sim_func_neon_ip(a: &[f32], b: &[f32], n: usize) -> f32 {
+    unsafe { dot_norms_neon(a, b, n).0 }
+}
+
+// independent sums of the scalar kernels. a single running sum is a dependency chain the
+// compiler can't reorder, separate sums per lane of a chunk become simd registers on targets
+// without a kernel of their own
+const LANES: usize = 8;
+
+#[inline]
 pub fn sim_func_euc(a: &[f32], b: &[f32], _n: usize) -> f32 {
-    -a.iter()
-        .zip(b)
+    let len = a.len().min(b.len());
+    let (a, b) = (a[..len].chunks_exact(LANES), b[..len].chunks_exact(LANES));
+    let mut sums = [0.0_f32; LANES];
+    let tail = a
+        .remainder()
+        .iter()
+        .zip(b.remainder())
         .map(|(x, y)| (x - y) * (x - y))
-        .fold(0.0, |acc, x| acc + x)
+        .sum::<f32>();
+    for (x, y) in a.zip(b) {
+        for i in 0..LANES {
+            let d = x[i] - y[i];
+            sums[i] += d * d;
+        }
+    }
+    -(sums.iter().sum::<f32>() + tail)
 }
+
+#[inline]
+fn dot_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    let len = a.len().min(b.len());
+    let (a, b) = (a[..len].chunks_exact(LANES), b[..len].chunks_exact(LANES));
+    let (tail_dot, tail_a, tail_b) = a
+        .remainder()
+        .iter()
+        .zip(b.remainder())
+        .fold((0.0, 0.0, 0.0), |(dot, norm_a, norm_b), (x, y)| {
+            (dot + x * y, norm_a + x * x, norm_b + y * y)
+        });
+    let mut dot = [0.0_f32; LANES];
+    let mut norm_a = [0.0_f32; LANES];
+    let mut norm_b = [0.0_f32; LANES];
+    for (x, y) in a.zip(b) {
+        for i in 0..LANES {
+            dot[i] += x[i] * y[i];
+            norm_a[i] += x[i] * x[i];
+            norm_b[i] += y[i] * y[i];
+        }
+    }
+    (
+        dot.iter().sum::<f32>() + tail_dot,
+        norm_a.iter().sum::<f32>() + tail_a,
+        norm_b.iter().sum::<f32>() + tail_b,
+    )
+}
+
+fn cos_from_parts(dot: f32, norm_a: f32, norm_b: f32) -> f32 {
+    let denom = (norm_a * norm_b).sqrt();
+    if denom > 0.0 {
+        dot / denom
+    } else {
+        0.0
+    }
+}
+
+#[inline]
+pub fn sim_func_cos(a: &[f32], b: &[f32], _n: usize) -> f32 {
+    let (dot, norm_a, norm_b) = dot_norms(a, b);
+    cos_from_parts(dot, norm_a, norm_b)
+}
+
+#[inline]
+pub fn sim_func_ip(a: &[f32], b: &[f32], _n: usize) -> f32 {
+    dot_norms(a, b).0
+}
+
+#[cfg(test)]
+#[path = "metrics_kernel_tests.rs"]
+mod kernel_tests;
@@ -0,0 +1,109 @@
+// tests of each simd kernel, skipped on cpus without its features
+use super::*;
+
+fn assert_close(a: f32, b: f32) {
+    assert!((a - b).abs() <= 1e-4 * b.abs().max(1.0), "{} != {}", a, b);
+}
+
+fn vectors(n: usize) -> (Vec<f32>, Vec<f32>) {
+    let v1 = (0..n).map(|i| (i % 7) as f32 * 0.5).collect::<Vec<f32>>();
+    let v2 = (0..n).map(|i| (i % 5) as f32 - 2.0).collect::<Vec<f32>>();
+    (v1, v2)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+fn check_cos_ip(cos: MetricFuncT<f32, f32>, ip: MetricFuncT<f32, f32>) {
+    for n in &[1, 3, 8, 15, 16, 17, 37, 64, 100, 768] {
+        let (v1, v2) = vectors(*n);
+        assert_close(ip(&v1, &v2, *n), sim_func_ip(&v1, &v2, *n));
+        assert_close(cos(&v1, &v2, *n), sim_func_cos(&v1, &v2, *n));
+    }
+}
+
+#[test]
+fn avx_euc_x32() {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if !(is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")) {
+            return;
+        }
+        for n in &[32, 64, 512, 768] {
+            let (v1, v2) = vectors(*n);
+            assert_close(sim_func_avx_euc(&v1, &v2, *n), sim_func_euc(&v1, &v2, *n));
+        }
+        let v1 = vec![1.0; 512];
+        assert!((sim_func_avx_euc(&v1, &v1, 512) - 0.0).abs() < f32::EPSILON);
+        let v1 = vec![0.0; 512];
+        let v2 = vec![1.0; 512];
+        assert!((sim_func_avx_euc(&v1, &v2, 512) - -512.0).abs() < f32::EPSILON);
+        let v2 = vec![512.0; 512];
+        assert!((sim_func_avx_euc(&v1, &v2, 512) - -134217728.0).abs() < f32::EPSILON);
+    }
+}
+
+#[test]
+fn avx_cos_ip() {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+            check_cos_ip(sim_func_avx_cos, sim_func_avx_ip);
+        }
+    }
+}
+
+#[test]
+fn avx512_euc_any_dim() {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+        for n in &[1, 15, 16, 17, 63, 64, 65, 100, 512, 768] {
+            let (v1, v2) = vectors(*n);
+            assert_close(
+                sim_func_avx512_euc(&v1, &v2, *n),
+                sim_func_euc(&v1, &v2, *n),
+            );
+        }
+        let v1 = vec![0.0; 512];
+        let v2 = vec![512.0; 512];
+        assert!((sim_func_avx512_euc(&v1, &v2, 512) - -134217728.0).abs() < f32::EPSILON);
+    }
+}
+
+#[test]
+fn avx512_cos_ip() {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx512f") {
+            check_cos_ip(sim_func_avx512_cos, sim_func_avx512_ip);
+        }
+    }
+}
+
+#[test]
+fn neon_euc_any_dim() {
+    #[cfg(target_arch = "aarch64")]
+    {
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        for n in &[1, 3, 4, 5, 15, 16, 17, 100, 512, 768] {
+            let (v1, v2) = vectors(*n);
+            assert_close(sim_func_neon_euc(&v1, &v2, *n), sim_func_euc(&v1, &v2, *n));
+        }
+        let v1 = vec![0.0; 512];
+        let v2 = vec![1.0; 512];
+        assert!((sim_func_neon_euc(&v1, &v2, 512) - -512.0).abs() < f32::EPSILON);
+    }
+}
+
+#[test]
+fn neon_cos_ip() {
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            check_cos_ip(sim_func_neon_cos, sim_func_neon_ip);
+        }
+    }
+}
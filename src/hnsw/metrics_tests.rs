@@ -4,7 +4,6 @@ use crate::hnsw::metrics;
 fn diff_is_zero() {
     let v1 = vec![1.0; 512];
     let v2 = vec![1.0; 512];
-    assert!((metrics::sim_func_avx_euc(&v1, &v2, 512) - 0.0).abs() < f32::EPSILON);
     assert!((metrics::sim_func_euc(&v1, &v2, 512) - 0.0).abs() < f32::EPSILON);
 }
 
@@ -12,7 +11,6 @@ fn diff_is_zero() {
 fn diff_is_512() {
     let v1 = vec![0.0; 512];
     let v2 = vec![1.0; 512];
-    assert!((metrics::sim_func_avx_euc(&v1, &v2, 512) - -512.0).abs() < f32::EPSILON);
     assert!((metrics::sim_func_euc(&v1, &v2, 512) - -512.0).abs() < f32::EPSILON);
 }
 
@@ -20,7 +18,6 @@ fn diff_is_512() {
 fn diff_is_512_2_x512() {
     let v1 = vec![0.0; 512];
     let v2 = vec![512.0; 512];
-    assert!((metrics::sim_func_avx_euc(&v1, &v2, 512) - -134217728.0).abs() < f32::EPSILON);
     assert!((metrics::sim_func_euc(&v1, &v2, 512) - -134217728.0).abs() < f32::EPSILON);
 }
 
@@ -31,3 +28,105 @@ fn diff_non_x32() {
     // assert_eq!(metrics::sim_func_avx_euc(&v1, &v2, 33), -33.0);
     assert!((metrics::sim_func_euc(&v1, &v2, 33) - -33.0).abs() < f32::EPSILON);
 }
+
+fn assert_close(a: f32, b: f32) {
+    assert!((a - b).abs() <= 1e-4 * b.abs().max(1.0), "{} != {}", a, b);
+}
+
+#[test]
+fn cos_and_ip_non_x32() {
+    let n = 37;
+    let v1 = (0..n).map(|i| i as f32).collect::<Vec<f32>>();
+    let v2 = (0..n).map(|i| (n - i) as f32).collect::<Vec<f32>>();
+    let dot = v1.iter().zip(&v2).map(|(x, y)| x * y).sum::<f32>();
+    let norm1 = v1.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm2 = v2.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let cos = dot / (norm1 * norm2);
+
+    assert_close(metrics::sim_func_ip(&v1, &v2, n), dot);
+    assert_close(metrics::sim_func_cos(&v1, &v2, n), cos);
+    let ip = metrics::metric_func(metrics::MetricFuncs::InnerProduct, n);
+    let cosine = metrics::metric_func(metrics::MetricFuncs::Cosine, n);
+    assert_close(ip(&v1, &v2, n), dot);
+    assert_close(cosine(&v1, &v2, n), cos);
+}
+
+// the simd kernels are only reachable through metric_func, which picks the one for this cpu
+#[test]
+fn detected_euc_any_dim() {
+    for n in &[1, 3, 4, 5, 15, 16, 17, 32, 63, 64, 65, 100, 512, 768] {
+        let v1 = (0..*n).map(|i| (i % 7) as f32 * 0.5).collect::<Vec<f32>>();
+        let v2 = (0..*n).map(|i| (i % 5) as f32 - 2.0).collect::<Vec<f32>>();
+        let euclidean = metrics::metric_func(metrics::MetricFuncs::Euclidean, *n);
+        assert_close(euclidean(&v1, &v2, *n), metrics::sim_func_euc(&v1, &v2, *n));
+    }
+    let v1 = vec![0.0; 512];
+    let v2 = vec![512.0; 512];
+    let euclidean = metrics::metric_func(metrics::MetricFuncs::Euclidean, 512);
+    assert!((euclidean(&v1, &v2, 512) - -134217728.0).abs() < f32::EPSILON);
+}
+
+#[test]
+fn cos_degenerate() {
+    let cosine = metrics::metric_func(metrics::MetricFuncs::Cosine, 64);
+    let zero = vec![0.0; 64];
+    let v1 = vec![1.0; 64];
+    let v2 = vec![2.0; 64];
+    assert_close(cosine(&zero, &v1, 64), 0.0);
+    assert_close(cosine(&v1, &v2, 64), 1.0);
+    assert_close(cosine(&[1.0, 0.0], &[0.0, 1.0], 2), 0.0);
+}
+
+#[test]
+fn dispatch_matches_scalar() {
+    let v1 = vec![0.5; 96];
+    let v2 = vec![1.5; 96];
+    let euclidean = metrics::metric_func(metrics::MetricFuncs::Euclidean, 96);
+    let ip = metrics::metric_func(metrics::MetricFuncs::InnerProduct, 96);
+    assert_close(euclidean(&v1, &v2, 96), metrics::sim_func_euc(&v1, &v2, 96));
+    assert_close(ip(&v1, &v2, 96), metrics::sim_func_ip(&v1, &v2, 96));
+
+    // the avx euclidean kernel is only resolved for multiples of 32, the avx512 one for any
+    let euclidean = metrics::metric_func(metrics::MetricFuncs::Euclidean, 33);
+    assert_close(euclidean(&v1[..33], &v2[..33], 33), -33.0);
+}
+
+#[test]
+fn metric_ids_round_trip() {
+    for kind in &[
+        metrics::MetricFuncs::Euclidean,
+        metrics::MetricFuncs::Cosine,
+        metrics::MetricFuncs::InnerProduct,
+    ] {
+        assert_eq!(metrics::MetricFuncs::from_id(kind.id()).unwrap(), *kind);
+        assert_eq!(
+            metrics::MetricFuncs::from_name(&format!("{:?}", kind)).unwrap(),
+            *kind
+        );
+    }
+    assert!(metrics::MetricFuncs::from_id(3).is_err());
+    assert!(metrics::MetricFuncs::from_name("Hamming").is_err());
+}
+
+#[test]
+fn batch_matches_single_calls() {
+    let query: Vec<f32> = (0..40).map(|i| i as f32 * 0.25).collect();
+    let vectors: Vec<Vec<f32>> = (0..5)
+        .map(|j| (0..40).map(|i| ((i * j) % 7) as f32 - 3.0).collect())
+        .collect();
+    let mut sims = vec![1.0; 2];
+    for kind in &[
+        metrics::MetricFuncs::Euclidean,
+        metrics::MetricFuncs::Cosine,
+        metrics::MetricFuncs::InnerProduct,
+    ] {
+        let mfunc = metrics::metric_func(*kind, 40);
+        kind.batch(&query, vectors.iter().map(|v| &v[..]), &mut sims);
+        assert_eq!(sims.len(), vectors.len());
+        for (v, sim) in vectors.iter().zip(&sims) {
+            assert_close(*sim, mfunc(&query, v, 40));
+        }
+    }
+    metrics::MetricFuncs::Euclidean.batch(&query, std::iter::empty(), &mut sims);
+    assert!(sims.is_empty());
+}
@@ -4,8 +4,86 @@ pub use self::core::*;
 #[cfg(test)]
 mod core_tests;
 
+pub mod dedupe;
+pub use self::dedupe::*;
+
+#[cfg(test)]
+mod dedupe_tests;
+
+pub mod encoding;
+pub use self::encoding::*;
+
+#[cfg(test)]
+mod encoding_tests;
+
+pub mod export;
+pub use self::export::*;
+
+#[cfg(test)]
+mod export_tests;
+
+pub mod geo;
+pub use self::geo::*;
+
+#[cfg(test)]
+mod geo_tests;
+
+pub mod group;
+pub use self::group::*;
+
+#[cfg(test)]
+mod group_tests;
+
+pub mod keys;
+pub use self::keys::*;
+
+#[cfg(test)]
+mod keys_tests;
+
+pub mod kmeans;
+pub use self::kmeans::*;
+
+#[cfg(test)]
+mod kmeans_tests;
+
 pub mod metrics;
 pub use self::metrics::*;
 
 #[cfg(test)]
 mod metrics_tests;
+
+pub mod names;
+pub use self::names::*;
+
+#[cfg(test)]
+mod names_tests;
+
+pub mod projection;
+pub use self::projection::*;
+
+#[cfg(test)]
+mod projection_tests;
+
+pub mod selftest;
+pub use self::selftest::*;
+
+#[cfg(test)]
+mod selftest_tests;
+
+pub mod storage;
+pub use self::storage::*;
+
+#[cfg(test)]
+mod storage_tests;
+
+pub mod tags;
+pub use self::tags::*;
+
+#[cfg(test)]
+mod tags_tests;
+
+pub mod tune;
+pub use self::tune::*;
+
+#[cfg(test)]
+mod tune_tests;
@@ -0,0 +1,98 @@
+use super::core::HNSWError;
+
+use regex::Regex;
+
+// constraints on node names, checked against the name without the index prefix
+#[derive(Debug, Clone, Default)]
+pub struct NameRule {
+    pub pattern: String, // regex the whole name must match, empty if unconstrained
+    pub max_len: usize,  // max length in bytes, 0 if unlimited
+    regex: Option<Regex>,
+}
+
+impl NameRule {
+    pub fn new(pattern: &str, max_len: usize) -> Result<Self, HNSWError> {
+        let regex = match pattern {
+            "" => None,
+            p => Some(
+                Regex::new(&format!("^(?:{})$", p))
+                    .map_err(|e| format!("Invalid NAMEPATTERN: {}", e))?,
+            ),
+        };
+        Ok(NameRule {
+            pattern: pattern.to_owned(),
+            max_len,
+            regex,
+        })
+    }
+
+    pub fn check(&self, name: &str) -> Result<(), HNSWError> {
+        if self.max_len > 0 && name.len() > self.max_len {
+            return Err(format!(
+                "Node name: {:?} is longer than {} bytes",
+                name, self.max_len
+            )
+            .into());
+        }
+        match &self.regex {
+            Some(re) if !re.is_match(name) => Err(format!(
+                "Node name: {:?} does not match NAMEPATTERN: {}",
+                name, self.pattern
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// redis style glob pattern with `*`, `?`, `[abc]`, `[^a-z]` and backslash escapes
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p = pattern.chars().collect::<Vec<char>>();
+    let s = name.chars().collect::<Vec<char>>();
+    glob_match_at(&p, &s)
+}
+
+fn glob_match_at(p: &[char], s: &[char]) -> bool {
+    match p.first() {
+        None => s.is_empty(),
+        Some('*') => {
+            let rest = &p[p.iter().take_while(|c| **c == '*').count()..];
+            (0..=s.len()).any(|i| glob_match_at(rest, &s[i..]))
+        }
+        Some('?') => !s.is_empty() && glob_match_at(&p[1..], &s[1..]),
+        Some('[') => match s.first() {
+            Some(c) => {
+                let (matched, len) = match_class(p, *c);
+                matched && glob_match_at(&p[len..], &s[1..])
+            }
+            None => false,
+        },
+        Some('\\') if p.len() > 1 => s.first() == Some(&p[1]) && glob_match_at(&p[2..], &s[1..]),
+        Some(c) => s.first() == Some(c) && glob_match_at(&p[1..], &s[1..]),
+    }
+}
+
+// matches c against the `[...]` class p starts with, returns the result and the class length
+fn match_class(p: &[char], c: char) -> (bool, usize) {
+    let mut i = 1;
+    let negate = p.get(i) == Some(&'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < p.len() && p[i] != ']' {
+        if p[i] == '\\' && i + 1 < p.len() {
+            i += 1;
+            matched |= p[i] == c;
+        } else if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            let (lo, hi) = (p[i].min(p[i + 2]), p[i].max(p[i + 2]));
+            matched |= lo <= c && c <= hi;
+            i += 2;
+        } else {
+            matched |= p[i] == c;
+        }
+        i += 1;
+    }
+    // an unterminated class extends to the end of the pattern
+    (matched != negate, (i + 1).min(p.len()))
+}
@@ -0,0 +1,43 @@
+use crate::hnsw::names::{glob_match, NameRule};
+
+#[test]
+fn check_names() {
+    let rule = NameRule::default();
+    assert!(rule.check("any.thing:1").is_ok());
+
+    let rule = NameRule::new("doc:[0-9]+", 0).unwrap();
+    assert!(rule.check("doc:123").is_ok());
+    // the whole name must match
+    assert!(rule.check("doc:123x").is_err());
+    assert!(rule.check("xdoc:123").is_err());
+    assert!(rule.check("doc:").is_err());
+
+    // alternations are anchored as a whole
+    let rule = NameRule::new("a|b", 0).unwrap();
+    assert!(rule.check("a").is_ok());
+    assert!(rule.check("ab").is_err());
+
+    let rule = NameRule::new("", 5).unwrap();
+    assert!(rule.check("abcde").is_ok());
+    assert!(rule.check("abcdef").is_err());
+
+    assert!(NameRule::new("doc:[0-9", 0).is_err());
+}
+
+#[test]
+fn glob_names() {
+    assert!(glob_match("*", ""));
+    assert!(glob_match("*", "tenant1.docs"));
+    assert!(glob_match("tenant1.*", "tenant1.docs"));
+    assert!(!glob_match("tenant1.*", "tenant2.docs"));
+    assert!(glob_match("t?nant*s", "tenant1.docs"));
+    assert!(!glob_match("t?nant", "tnant"));
+    assert!(glob_match("tenant[0-9].docs", "tenant7.docs"));
+    assert!(!glob_match("tenant[^0-9].docs", "tenant7.docs"));
+    assert!(glob_match("tenant[abc]", "tenantb"));
+    assert!(!glob_match("tenant[abc]", "tenantd"));
+    assert!(glob_match("a\\*b", "a*b"));
+    assert!(!glob_match("a\\*b", "axb"));
+    assert!(glob_match("*a*a*b", "aaaaaab"));
+    assert!(!glob_match("*a*a*b", "aaaaaa"));
+}
@@ -0,0 +1,176 @@
+use super::core::HNSWError;
+
+use num::Float;
+use rand::prelude::*;
+
+// max rounds of subspace iteration to find the principal components
+static PCA_MAX_ITERATIONS: usize = 100;
+
+// linear map of vectors of from_dim onto the to_dim of an index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection<T: Float> {
+    pub from_dim: usize,
+    pub to_dim: usize,
+    pub matrix: Vec<T>, // to_dim rows of from_dim values
+    pub offset: Vec<T>, // added to every projected vector
+}
+
+impl<T: Float> Projection<T> {
+    pub fn new(from_dim: usize, to_dim: usize, matrix: Vec<T>) -> Result<Self, HNSWError> {
+        if from_dim == 0 || to_dim == 0 {
+            return Err("Projection dimensions must be positive".into());
+        }
+        if matrix.len() != from_dim * to_dim {
+            return Err(format!(
+                "Projection matrix must have {} x {} = {} values, got: {}",
+                to_dim,
+                from_dim,
+                from_dim * to_dim,
+                matrix.len()
+            )
+            .into());
+        }
+        if matrix.iter().any(|v| !v.is_finite()) {
+            return Err("Projection matrix values must be finite".into());
+        }
+        Ok(Projection {
+            from_dim,
+            to_dim,
+            matrix,
+            offset: vec![T::zero(); to_dim],
+        })
+    }
+
+    // an empty offset keeps the current one
+    pub fn with_offset(mut self, offset: Vec<T>) -> Result<Self, HNSWError> {
+        if offset.is_empty() {
+            return Ok(self);
+        }
+        if offset.len() != self.to_dim || offset.iter().any(|v| !v.is_finite()) {
+            return Err("Projection offset must have a finite value per dimension".into());
+        }
+        self.offset = offset;
+        Ok(self)
+    }
+
+    // random projection with entries of +-1/sqrt(to_dim), which preserves distances in
+    // expectation. the same seed always gives the same matrix
+    pub fn random(from_dim: usize, to_dim: usize, seed: u64) -> Result<Self, HNSWError> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let scale = T::from(to_dim).unwrap().sqrt().recip();
+        let matrix = (0..from_dim * to_dim)
+            .map(|_| if rng.gen::<bool>() { scale } else { -scale })
+            .collect();
+        Self::new(from_dim, to_dim, matrix)
+    }
+
+    // projection onto the to_dim principal components of the samples, centered on their mean
+    pub fn pca(
+        samples: &[T],
+        from_dim: usize,
+        to_dim: usize,
+        seed: u64,
+    ) -> Result<Self, HNSWError> {
+        if from_dim == 0 || to_dim == 0 || to_dim > from_dim {
+            return Err(format!("TARGETDIM must be between 1 and {}", from_dim).into());
+        }
+        if samples.is_empty() || !samples.len().is_multiple_of(from_dim) {
+            return Err(
+                format!("samples dimension: {} does not match Index", samples.len()).into(),
+            );
+        }
+        let n = samples.len() / from_dim;
+        if n <= to_dim {
+            return Err(format!(
+                "PCA to {} dimensions needs more than {} samples",
+                to_dim, to_dim
+            )
+            .into());
+        }
+
+        let x = samples
+            .iter()
+            .map(|v| v.to_f64().unwrap())
+            .collect::<Vec<f64>>();
+        let mut mean = vec![0.0; from_dim];
+        for v in x.chunks(from_dim) {
+            for (m, x) in mean.iter_mut().zip(v) {
+                *m += x / n as f64;
+            }
+        }
+        let x = x
+            .chunks(from_dim)
+            .flat_map(|v| v.iter().zip(&mean).map(|(x, m)| x - m))
+            .collect::<Vec<f64>>();
+
+        // subspace iteration, the covariance is applied through the samples as X^T X q
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut q = (0..to_dim)
+            .map(|_| (0..from_dim).map(|_| rng.gen_range(-1.0, 1.0)).collect())
+            .collect::<Vec<Vec<f64>>>();
+        orthonormalize(&mut q);
+        for _ in 0..PCA_MAX_ITERATIONS {
+            let mut z = q
+                .iter()
+                .map(|c| {
+                    let mut z = vec![0.0; from_dim];
+                    for v in x.chunks(from_dim) {
+                        let xc = dot(v, c);
+                        for (z, x) in z.iter_mut().zip(v) {
+                            *z += xc * x;
+                        }
+                    }
+                    z
+                })
+                .collect::<Vec<Vec<f64>>>();
+            orthonormalize(&mut z);
+            let converged = z.iter().zip(&q).all(|(a, b)| 1.0 - dot(a, b).abs() < 1e-9);
+            q = z;
+            if converged {
+                break;
+            }
+        }
+
+        let matrix = q.iter().flatten().map(|v| T::from(*v).unwrap()).collect();
+        let offset = q.iter().map(|c| T::from(-dot(c, &mean)).unwrap()).collect();
+        Self::new(from_dim, to_dim, matrix)?.with_offset(offset)
+    }
+
+    // whether the data consists of vectors to project
+    pub fn accepts(&self, data: &[T]) -> bool {
+        !data.is_empty() && data.len().is_multiple_of(self.from_dim)
+    }
+
+    // projects each from_dim vector of the data
+    pub fn apply(&self, data: &[T]) -> Vec<T> {
+        let mut projected = Vec::with_capacity(data.len() / self.from_dim * self.to_dim);
+        for v in data.chunks(self.from_dim) {
+            for (row, offset) in self.matrix.chunks(self.from_dim).zip(&self.offset) {
+                projected.push(row.iter().zip(v).fold(*offset, |acc, (a, b)| acc + *a * *b));
+            }
+        }
+        projected
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+// modified gram-schmidt, vectors without an independent component are zeroed
+fn orthonormalize(vectors: &mut [Vec<f64>]) {
+    for i in 0..vectors.len() {
+        let (done, rest) = vectors.split_at_mut(i);
+        let v = &mut rest[0];
+        for u in done.iter() {
+            let d = dot(v, u);
+            for (x, y) in v.iter_mut().zip(u) {
+                *x -= d * y;
+            }
+        }
+        let norm = dot(v, v).sqrt();
+        for x in v.iter_mut() {
+            *x = if norm > 1e-12 { *x / norm } else { 0.0 };
+        }
+    }
+}
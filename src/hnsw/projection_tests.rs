@@ -0,0 +1,57 @@
+use crate::hnsw::projection::Projection;
+
+#[test]
+fn project_vectors() {
+    // 3 -> 2 dims, keeps the first and sums the others
+    let p = Projection::new(3, 2, vec![1.0, 0.0, 0.0, 0.0, 1.0, 1.0]).unwrap();
+    assert!(p.accepts(&[1.0, 2.0, 3.0]));
+    assert!(!p.accepts(&[1.0, 2.0]));
+    assert!(!p.accepts(&[]));
+    assert_eq!(p.apply(&[1.0, 2.0, 3.0]), vec![1.0, 5.0]);
+    // every vector of a multi-vector node is projected
+    assert_eq!(
+        p.apply(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        vec![1.0, 5.0, 4.0, 11.0]
+    );
+
+    assert!(Projection::new(3, 2, vec![1.0; 5]).is_err());
+    assert!(Projection::new(0, 2, Vec::<f32>::new()).is_err());
+    assert!(Projection::new(1, 1, vec![f32::NAN]).is_err());
+}
+
+#[test]
+fn random_projection() {
+    let p = Projection::<f32>::random(64, 16, 7).unwrap();
+    assert_eq!(p.matrix.len(), 64 * 16);
+    let scale = 0.25;
+    assert!(p.matrix.iter().all(|v| *v == scale || *v == -scale));
+    assert_eq!(p, Projection::random(64, 16, 7).unwrap());
+    assert_ne!(p, Projection::random(64, 16, 8).unwrap());
+}
+
+#[test]
+fn pca_projection() {
+    // points on the line (1, 1, 0) * t + (0, 0, 5) with a little noise along z
+    let mut samples = Vec::new();
+    for i in 0..10 {
+        let t = i as f64 - 4.5;
+        samples.extend_from_slice(&[t, t, 5.01, t, t, 4.99]);
+    }
+    let p = Projection::pca(&samples, 3, 1, 0).unwrap();
+    let c = std::f64::consts::FRAC_1_SQRT_2;
+    assert!((p.matrix[0].abs() - c).abs() < 1e-6);
+    assert!((p.matrix[1].abs() - c).abs() < 1e-6);
+    assert!(p.matrix[2].abs() < 1e-6);
+    // the mean is projected to the origin, distances along the line are kept
+    assert!(p.apply(&[0.0, 0.0, 5.0])[0].abs() < 1e-6);
+    let d = p.apply(&[3.0, 3.0, 5.0])[0] - p.apply(&[1.0, 1.0, 5.0])[0];
+    assert!((d.abs() - 2.0 * 2.0_f64.sqrt()).abs() < 1e-6);
+
+    let p = Projection::pca(&samples, 3, 2, 0).unwrap();
+    let dot = (0..3).map(|i| p.matrix[i] * p.matrix[3 + i]).sum::<f64>();
+    assert!(dot.abs() < 1e-6);
+
+    assert!(Projection::pca(&samples, 3, 4, 0).is_err());
+    assert!(Projection::pca(&samples[..4], 3, 1, 0).is_err());
+    assert!(Projection::pca(&samples[..6], 3, 2, 0).is_err());
+}
@@ -0,0 +1,178 @@
+use super::core::{HNSWError, Index, Node};
+use super::metrics::{self, MetricFuncT, MetricFuncs};
+
+use rand::prelude::*;
+
+// dimensions the kernels are compared on, multiples of 32 take the unrolled euclidean kernel
+const KERNEL_DIMS: [usize; 5] = [1, 7, 32, 100, 768];
+const KERNEL_PAIRS: usize = 50;
+// relative error allowed for summing in another order
+const KERNEL_TOLERANCE: f32 = 1e-4;
+
+const GRAPH_NODES: usize = 300;
+const GRAPH_DIM: usize = 16;
+// share of the nodes a search for their own vector must find first
+const GRAPH_RECALL: f64 = 0.95;
+
+// outcome of a self-test check, detail says what was measured or what failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+fn check(name: &str, f: impl FnOnce() -> Result<(bool, String), HNSWError>) -> Check {
+    let (passed, detail) = match f() {
+        Ok(res) => res,
+        Err(HNSWError::Str(e)) => (false, e.to_owned()),
+        Err(HNSWError::String(e)) => (false, e),
+    };
+    Check {
+        name: name.to_owned(),
+        passed,
+        detail,
+    }
+}
+
+fn random_vector(rng: &mut StdRng, dim: usize) -> Vec<f32> {
+    (0..dim).map(|_| rng.gen_range(-1.0, 1.0)).collect()
+}
+
+// compares the kernels selected for this cpu with the scalar ones on random vectors
+pub fn check_kernels(seed: u64) -> Vec<Check> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let scalars: [(&str, MetricFuncs, MetricFuncT<f32, f32>); 3] = [
+        (
+            "kernel euclidean",
+            MetricFuncs::Euclidean,
+            metrics::sim_func_euc,
+        ),
+        ("kernel cosine", MetricFuncs::Cosine, metrics::sim_func_cos),
+        ("kernel ip", MetricFuncs::InnerProduct, metrics::sim_func_ip),
+    ];
+    scalars
+        .iter()
+        .map(|(name, kind, scalar)| {
+            let mut worst = 0.0_f32;
+            for &dim in &KERNEL_DIMS {
+                let kernel = metrics::metric_func(*kind, dim);
+                for _ in 0..KERNEL_PAIRS {
+                    let a = random_vector(&mut rng, dim);
+                    let b = random_vector(&mut rng, dim);
+                    let expected = scalar(&a, &b, dim);
+                    let error = (kernel(&a, &b, dim) - expected).abs() / expected.abs().max(1.0);
+                    // NaN is the worst error
+                    if error > worst || error.is_nan() {
+                        worst = error;
+                    }
+                }
+            }
+            check(name, || {
+                Ok((
+                    worst <= KERNEL_TOLERANCE,
+                    format!("{} max relative error: {:e}", metrics::simd(), worst),
+                ))
+            })
+        })
+        .collect()
+}
+
+// builds a small graph of random vectors in memory, searches it and deletes half of it,
+// checking the results and the links of the graph after each step
+pub fn check_graph(seed: u64) -> Vec<Check> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mfunc = metrics::metric_func(MetricFuncs::Euclidean, GRAPH_DIM);
+    let mut index: Index<f32, f32> = Index::new("selftest", Box::new(mfunc), GRAPH_DIM, 8, 64);
+    let mock_fn = |_: String, _: Node<f32>| Ok(());
+    let vectors = (0..GRAPH_NODES)
+        .map(|i| {
+            (
+                format!("selftest.{}", i),
+                random_vector(&mut rng, GRAPH_DIM),
+            )
+        })
+        .collect::<Vec<(String, Vec<f32>)>>();
+
+    let mut checks = vec![check("build", || {
+        for (name, data) in &vectors {
+            index.add_node(name, data, mock_fn)?;
+        }
+        Ok((
+            index.node_count == GRAPH_NODES,
+            format!("{} of {} nodes", index.node_count, GRAPH_NODES),
+        ))
+    })];
+    checks.push(check("search", || recall(&index, &vectors)));
+    checks.push(check("links", || links(&index)));
+
+    let (deleted, kept): (Vec<_>, Vec<_>) = vectors
+        .into_iter()
+        .enumerate()
+        .partition(|(i, _)| i % 2 == 0);
+    let kept = kept
+        .into_iter()
+        .map(|(_, v)| v)
+        .collect::<Vec<(String, Vec<f32>)>>();
+    checks.push(check("delete", || {
+        for (_, (name, _)) in &deleted {
+            index.delete_node(name, mock_fn)?;
+        }
+        for (_, (name, data)) in &deleted {
+            if let Some(r) = index.search_knn(data, 5)?.iter().find(|r| r.key == *name) {
+                return Ok((false, format!("deleted node: {} still found", r.key)));
+            }
+        }
+        Ok((
+            index.node_count == kept.len(),
+            format!("{} of {} nodes left", index.node_count, kept.len()),
+        ))
+    }));
+    checks.push(check("search after delete", || recall(&index, &kept)));
+    checks.push(check("links after delete", || links(&index)));
+    checks
+}
+
+fn recall(
+    index: &Index<f32, f32>,
+    vectors: &[(String, Vec<f32>)],
+) -> Result<(bool, String), HNSWError> {
+    let mut found = 0;
+    for (name, data) in vectors {
+        if index.search_knn(data, 1)?.first().map(|r| &r.key) == Some(name) {
+            found += 1;
+        }
+    }
+    let recall = found as f64 / vectors.len().max(1) as f64;
+    Ok((recall >= GRAPH_RECALL, format!("recall@1: {:.3}", recall)))
+}
+
+// every neighbor is a node of the index and no neighbor list exceeds the limit of its layer
+fn links(index: &Index<f32, f32>) -> Result<(bool, String), HNSWError> {
+    let degrees = index.degrees();
+    let mut count = 0;
+    for (name, node) in &index.nodes {
+        let n = node.read()?;
+        for (lc, neighbors) in n.neighbors.iter().enumerate() {
+            if neighbors.len() > degrees.on(lc) {
+                return Ok((
+                    false,
+                    format!(
+                        "Node: {} has {} neighbors on layer {}",
+                        name,
+                        neighbors.len(),
+                        lc
+                    ),
+                ));
+            }
+            if let Some(id) = neighbors.iter().find(|id| index.node_at(**id).is_err()) {
+                return Ok((
+                    false,
+                    format!("Node: {} links to missing node id: {}", name, id),
+                ));
+            }
+            count += neighbors.len();
+        }
+    }
+    Ok((true, format!("{} links", count)))
+}
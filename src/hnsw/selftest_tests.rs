@@ -0,0 +1,35 @@
+use crate::hnsw::selftest::*;
+
+#[test]
+fn kernels_match_scalar() {
+    let checks = check_kernels(1);
+    assert_eq!(checks.len(), 3);
+    for c in &checks {
+        assert!(c.passed, "{:?}", c);
+    }
+    // the same seed compares the same vectors
+    assert_eq!(check_kernels(1), checks);
+}
+
+#[test]
+fn graph_cycle() {
+    let checks = check_graph(1);
+    let names = checks
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(
+        names,
+        vec![
+            "build",
+            "search",
+            "links",
+            "delete",
+            "search after delete",
+            "links after delete"
+        ]
+    );
+    for c in &checks {
+        assert!(c.passed, "{:?}", c);
+    }
+}
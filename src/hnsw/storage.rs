@@ -0,0 +1,431 @@
+use super::core::{HNSWError, Node};
+
+use num::Float;
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::fmt;
+use std::ops::Deref;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+#[cfg(feature = "memmap2")]
+use memmap2::{MmapOptions, MmapRaw};
+#[cfg(feature = "memmap2")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "memmap2")]
+use std::path::PathBuf;
+
+// slots per segment of an arena, small indexes don't reserve much more than they use
+const ARENA_SEGMENT_SLOTS: usize = 1024;
+// slots per segment of a file, 16384 slots of f32s keep every segment offset 64KiB aligned
+#[cfg(feature = "memmap2")]
+const FILE_SEGMENT_SLOTS: usize = 16384;
+
+// where the vectors of an index live, the graph itself always stays on the heap
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum StorageKind {
+    #[default]
+    Heap,
+    Arena,
+    Mmap,
+}
+
+impl StorageKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind.to_ascii_lowercase().as_str() {
+            "heap" => Some(StorageKind::Heap),
+            "arena" => Some(StorageKind::Arena),
+            "mmap" => Some(StorageKind::Mmap),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageKind::Heap => "heap",
+            StorageKind::Arena => "arena",
+            StorageKind::Mmap => "mmap",
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        match self {
+            StorageKind::Heap => 0,
+            StorageKind::Mmap => 1,
+            StorageKind::Arena => 2,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Result<Self, HNSWError> {
+        match id {
+            0 => Ok(StorageKind::Heap),
+            1 => Ok(StorageKind::Mmap),
+            2 => Ok(StorageKind::Arena),
+            _ => Err(format!("Unknown storage: {}", id).into()),
+        }
+    }
+}
+
+// where an index keeps the vectors of its nodes while it is loaded, the graph links the
+// nodes regardless of where their vectors are
+pub trait Storage<T>: Send + Sync {
+    fn kind(&self) -> StorageKind;
+
+    // takes the vector of a new node, the node keeps the returned one
+    fn store(&self, data: Vector<T>) -> Result<Vector<T>, HNSWError>;
+
+    // vectors held by the storage itself rather than by their nodes
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// vectors are owned by their nodes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStorage;
+
+impl<T> Storage<T> for HeapStorage {
+    fn kind(&self) -> StorageKind {
+        StorageKind::Heap
+    }
+
+    fn store(&self, data: Vector<T>) -> Result<Vector<T>, HNSWError> {
+        Ok(data)
+    }
+
+    fn len(&self) -> usize {
+        0
+    }
+}
+
+// vectors are copied into the slots of a slab, None for indexes without vectors of their own
+pub struct SlabStorage<T> {
+    kind: StorageKind,
+    slab: Option<Arc<Slab<T>>>,
+}
+
+impl<T: Copy> SlabStorage<T> {
+    pub fn arena(dim: usize) -> Self {
+        SlabStorage {
+            kind: StorageKind::Arena,
+            slab: match dim {
+                0 => None,
+                _ => Some(Slab::arena(dim)),
+            },
+        }
+    }
+
+    pub fn mmap(path: &Path, dim: usize) -> Result<Self, HNSWError> {
+        Ok(SlabStorage {
+            kind: StorageKind::Mmap,
+            slab: match dim {
+                0 => None,
+                _ => Some(Slab::file(path, dim)?),
+            },
+        })
+    }
+}
+
+impl<T: Copy + Send + Sync> Storage<T> for SlabStorage<T> {
+    fn kind(&self) -> StorageKind {
+        self.kind
+    }
+
+    fn store(&self, data: Vector<T>) -> Result<Vector<T>, HNSWError> {
+        match &self.slab {
+            Some(slab) => slab.store(&data),
+            None => Ok(data),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slab.as_ref().map_or(0, |s| s.len())
+    }
+}
+
+// persisted nodes an index is restored from when it is loaded, e.g. the node keys of the
+// module. nodes are loaded before their neighbors are, which may not be loaded yet
+pub trait NodeStore<T: Float> {
+    // the node without its neighbors
+    fn load(&self, name: &str, capacity: usize) -> Result<Node<T>, HNSWError>;
+
+    // ids of the neighbors of the node on each of its layers
+    fn neighbors(&self, name: &str) -> Result<Vec<Vec<u32>>, HNSWError>;
+}
+
+// vector of a node, owned or in a slot of a slab. a slot vector points at its slot directly,
+// the handle only keeps the slot reserved
+#[derive(Clone)]
+pub enum Vector<T> {
+    Heap(Vec<T>),
+    Slot {
+        ptr: NonNull<T>,
+        len: usize,
+        slot: Arc<Slot<T>>,
+    },
+}
+
+// slot vectors are only read after they were written, and the slab outlives them
+unsafe impl<T: Send + Sync> Send for Vector<T> {}
+unsafe impl<T: Send + Sync> Sync for Vector<T> {}
+
+impl<T> Deref for Vector<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Vector::Heap(v) => v,
+            Vector::Slot { ptr, len, .. } => unsafe {
+                std::slice::from_raw_parts(ptr.as_ptr(), *len)
+            },
+        }
+    }
+}
+
+impl<T> Vector<T> {
+    // slot vectors stay in their slab, only owned vectors are moved
+    pub fn relocate<R: Relocate>(&mut self, r: &mut R) {
+        if let Vector::Heap(v) = self {
+            r.relocate(v);
+        }
+    }
+}
+
+// moves heap buffers into new allocations, e.g. for the allocator to defragment memory
+pub trait Relocate {
+    fn relocate<V>(&mut self, buf: &mut Vec<V>);
+
+    // whether to stop and resume later, e.g. once a time budget ran out
+    fn should_stop(&mut self) -> bool {
+        false
+    }
+
+    fn relocate_str(&mut self, s: &mut String) {
+        // the bytes are moved as they are, so they stay valid utf-8
+        unsafe { self.relocate(s.as_mut_vec()) }
+    }
+}
+
+impl<T> From<Vec<T>> for Vector<T> {
+    fn from(v: Vec<T>) -> Self {
+        Vector::Heap(v)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Vector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: PartialEq> PartialEq<Vec<T>> for Vector<T> {
+    fn eq(&self, other: &Vec<T>) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Vector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// fixed length vectors in segments that never move, so a slot stays at its address while it
+// is in use. the vectors of an arena sit next to each other on the heap instead of in an
+// allocation per node. the vectors of a file are paged in and out by the OS, the file is only
+// a cache of them, node keys still hold them for persistence, so it is recreated on every
+// load and removed when the last vector in it is dropped
+pub struct Slab<T> {
+    dim: usize,
+    segment_slots: usize, // slots per segment
+    slots: Mutex<Slots<T>>,
+    #[cfg(feature = "memmap2")]
+    file: Option<(File, PathBuf)>, // None for an arena
+}
+
+// segments are only written through slots that aren't shared yet
+unsafe impl<T: Send + Sync> Send for Slab<T> {}
+unsafe impl<T: Send + Sync> Sync for Slab<T> {}
+
+struct Slots<T> {
+    segments: Vec<Segment<T>>,
+    free: Vec<usize>, // slots of dropped vectors, reused before the slab grows
+    next: usize,      // first slot never used
+}
+
+enum Segment<T> {
+    Heap(NonNull<T>, Layout),
+    #[cfg(feature = "memmap2")]
+    Mapped(MmapRaw),
+}
+
+impl<T> Segment<T> {
+    fn ptr(&self) -> *mut T {
+        match self {
+            Segment::Heap(ptr, _) => ptr.as_ptr(),
+            #[cfg(feature = "memmap2")]
+            Segment::Mapped(map) => map.as_mut_ptr() as *mut T,
+        }
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        match self {
+            Segment::Heap(ptr, layout) => unsafe { dealloc(ptr.as_ptr() as *mut u8, *layout) },
+            // unmapped by MmapRaw
+            #[cfg(feature = "memmap2")]
+            Segment::Mapped(_) => (),
+        }
+    }
+}
+
+impl<T: Copy> Slab<T> {
+    pub fn arena(dim: usize) -> Arc<Self> {
+        Arc::new(Slab {
+            dim,
+            segment_slots: ARENA_SEGMENT_SLOTS,
+            slots: Mutex::new(Slots {
+                segments: Vec::new(),
+                free: Vec::new(),
+                next: 0,
+            }),
+            #[cfg(feature = "memmap2")]
+            file: None,
+        })
+    }
+
+    // truncates an existing file, it would be stale anyway
+    #[cfg(feature = "memmap2")]
+    pub fn file(path: &Path, dim: usize) -> Result<Arc<Self>, HNSWError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("Creating vector file {} failed: {}", path.display(), e))?;
+        Ok(Arc::new(Slab {
+            dim,
+            segment_slots: FILE_SEGMENT_SLOTS,
+            slots: Mutex::new(Slots {
+                segments: Vec::new(),
+                free: Vec::new(),
+                next: 0,
+            }),
+            file: Some((file, path.to_owned())),
+        }))
+    }
+
+    #[cfg(not(feature = "memmap2"))]
+    pub fn file(_path: &Path, _dim: usize) -> Result<Arc<Self>, HNSWError> {
+        Err("mmap storage needs a module built with the memmap2 feature".into())
+    }
+
+    // slots in use
+    pub fn len(&self) -> usize {
+        let slots = self.lock();
+        slots.next - slots.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // copies data into a free slot, growing the slab by a segment if there is none
+    pub fn store(self: &Arc<Self>, data: &[T]) -> Result<Vector<T>, HNSWError> {
+        if data.len() != self.dim || self.dim == 0 {
+            return Err(format!(
+                "Vector of dimensionality {} doesn't fit a slab of {}",
+                data.len(),
+                self.dim
+            )
+            .into());
+        }
+
+        let mut slots = self.lock();
+        let id = match slots.free.pop() {
+            Some(id) => id,
+            None => slots.next,
+        };
+        let segment = id / self.segment_slots;
+        if segment == slots.segments.len() {
+            let segment = self.grow(segment)?;
+            slots.segments.push(segment);
+        }
+        if id == slots.next {
+            slots.next += 1;
+        }
+
+        let offset = (id % self.segment_slots) * self.dim;
+        let ptr = unsafe { slots.segments[segment].ptr().add(offset) };
+        // the slot is written before it is shared and never written again
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, self.dim) };
+        Ok(Vector::Slot {
+            ptr: NonNull::new(ptr).ok_or("Slab segment is not allocated")?,
+            len: self.dim,
+            slot: Arc::new(Slot {
+                slab: self.clone(),
+                id,
+            }),
+        })
+    }
+
+    fn grow(&self, segment: usize) -> Result<Segment<T>, HNSWError> {
+        let len = self.segment_slots * self.dim;
+        #[cfg(feature = "memmap2")]
+        {
+            if let Some((file, _)) = &self.file {
+                let bytes = len * std::mem::size_of::<T>();
+                let grow = |e: std::io::Error| format!("Growing vector file failed: {}", e);
+                file.set_len(((segment + 1) * bytes) as u64).map_err(grow)?;
+                let map = MmapOptions::new()
+                    .offset((segment * bytes) as u64)
+                    .len(bytes)
+                    .map_raw(file)
+                    .map_err(grow)?;
+                return Ok(Segment::Mapped(map));
+            }
+        }
+        #[cfg(not(feature = "memmap2"))]
+        let _ = segment;
+
+        let layout = Layout::array::<T>(len).map_err(|_| "Slab segment is too large")?;
+        match NonNull::new(unsafe { alloc(layout) } as *mut T) {
+            Some(ptr) => Ok(Segment::Heap(ptr, layout)),
+            None => handle_alloc_error(layout),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slots<T>> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "memmap2")]
+impl<T> Drop for Slab<T> {
+    fn drop(&mut self) {
+        if let Some((_, path)) = &self.file {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// reservation of a slot, the slot is freed once the last vector in it is dropped
+pub struct Slot<T> {
+    slab: Arc<Slab<T>>,
+    id: usize,
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        let mut slots = self
+            .slab
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        slots.free.push(self.id);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::hnsw::storage::*;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn vector_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("hnsw-test-{}-{}.vectors", std::process::id(), name))
+}
+
+#[test]
+fn storage_kinds() {
+    assert_eq!(StorageKind::parse("MMAP"), Some(StorageKind::Mmap));
+    assert_eq!(StorageKind::parse("disk"), None);
+    for kind in &[StorageKind::Heap, StorageKind::Arena, StorageKind::Mmap] {
+        assert_eq!(StorageKind::from_id(kind.id()).unwrap(), *kind);
+        assert_eq!(StorageKind::parse(kind.as_str()), Some(*kind));
+    }
+    assert!(StorageKind::from_id(3).is_err());
+}
+
+#[test]
+fn heap_vector() {
+    let v: Vector<f32> = vec![1.0, 2.0].into();
+    assert_eq!(&v[..], &[1.0, 2.0]);
+    assert_eq!(v.clone(), v);
+    assert_eq!(format!("{:?}", v), "[1.0, 2.0]");
+}
+
+// stores, frees and reuses slots of the slab
+fn check_slots(slab: &Arc<Slab<f32>>) {
+    let a = slab.store(&[1.0, 2.0]).unwrap();
+    let b = slab.store(&[3.0, 4.0]).unwrap();
+    assert!(matches!(a, Vector::Slot { .. }));
+    assert_eq!((&a[..], &b[..]), (&[1.0, 2.0][..], &[3.0, 4.0][..]));
+    assert_eq!(slab.len(), 2);
+    assert!(slab.store(&[1.0]).is_err());
+
+    // clones share the slot, it is reused once the last one is dropped
+    let c = a.clone();
+    drop(a);
+    assert_eq!(slab.len(), 2);
+    drop(c);
+    assert_eq!(slab.len(), 1);
+    let d = slab.store(&[5.0, 6.0]).unwrap();
+    assert_eq!(&d[..], &[5.0, 6.0]);
+    assert_eq!(&b[..], &[3.0, 4.0]);
+    assert_eq!(slab.len(), 2);
+}
+
+#[test]
+fn arena_vectors() {
+    let arena = Slab::<f32>::arena(2);
+    check_slots(&arena);
+
+    // vectors beyond the first segment keep the addresses of earlier ones valid
+    let arena = Slab::<f32>::arena(1);
+    let vectors = (0..5000)
+        .map(|i| arena.store(&[i as f32]).unwrap())
+        .collect::<Vec<Vector<f32>>>();
+    assert!(vectors.iter().enumerate().all(|(i, v)| v[0] == i as f32));
+    // consecutive slots are next to each other
+    assert_eq!(vectors[1].as_ptr(), vectors[0].as_ptr().wrapping_add(1));
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn mapped_vectors() {
+    let path = vector_path("mapped");
+    let file = Slab::<f32>::file(&path, 2).unwrap();
+    check_slots(&file);
+    let v = file.store(&[7.0, 8.0]).unwrap();
+
+    // the file is removed with the last vector
+    drop(file);
+    assert!(path.exists());
+    drop(v);
+    assert!(!path.exists());
+
+    let file = Slab::<f32>::file(&vector_path("segments"), 1).unwrap();
+    let vectors = (0..20000)
+        .map(|i| file.store(&[i as f32]).unwrap())
+        .collect::<Vec<Vector<f32>>>();
+    assert!(vectors.iter().enumerate().all(|(i, v)| v[0] == i as f32));
+    assert_eq!(file.len(), 20000);
+}
+
+#[cfg(not(feature = "memmap2"))]
+#[test]
+fn mapped_vectors() {
+    assert!(Slab::<f32>::file(&vector_path("mapped"), 2).is_err());
+}
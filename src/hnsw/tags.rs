@@ -0,0 +1,278 @@
+use super::core::HNSWError;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+// inverted sets of node names per tag
+#[derive(Debug, Clone, Default)]
+pub struct TagStore {
+    pub tags: HashMap<String, HashSet<String>>,
+}
+
+impl TagStore {
+    pub fn add(&mut self, node: &str, tags: &[String]) {
+        for tag in tags {
+            self.tags
+                .entry(tag.to_owned())
+                .or_default()
+                .insert(node.to_owned());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str, tags: &[String]) {
+        for tag in tags {
+            if let Some(nodes) = self.tags.get_mut(tag) {
+                nodes.remove(node);
+                if nodes.is_empty() {
+                    self.tags.remove(tag);
+                }
+            }
+        }
+    }
+
+    // names of the nodes matching the filter, all node names are needed to negate a filter.
+    // None if the filter compares attributes, those are only evaluated on visited nodes
+    pub fn eval<V>(&self, filter: &Filter, nodes: &HashMap<String, V>) -> Option<HashSet<String>> {
+        match filter {
+            Filter::Tag(tag) => Some(self.tags.get(tag).cloned().unwrap_or_default()),
+            Filter::Cmp(..) => None,
+            Filter::And(a, b) => {
+                let a = self.eval(a, nodes)?;
+                let b = self.eval(b, nodes)?;
+                Some(a.intersection(&b).cloned().collect())
+            }
+            Filter::Or(a, b) => {
+                let mut a = self.eval(a, nodes)?;
+                a.extend(self.eval(b, nodes)?);
+                Some(a)
+            }
+            Filter::Not(a) => {
+                let a = self.eval(a, nodes)?;
+                Some(nodes.keys().filter(|n| !a.contains(*n)).cloned().collect())
+            }
+        }
+    }
+}
+
+// tags are separated by commas and may not contain whitespace, parentheses or operators
+pub fn parse_tags(tags: &str) -> Result<Vec<String>, HNSWError> {
+    let mut parsed: Vec<String> = Vec::new();
+    for tag in tags.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        if tag.contains(is_separator) {
+            return Err(format!("Invalid tag: {:?}", tag).into());
+        }
+        if !parsed.iter().any(|t| t == tag) {
+            parsed.push(tag.to_owned());
+        }
+    }
+    Ok(parsed)
+}
+
+// numeric attributes are given as comma separated `name=value` pairs
+pub fn parse_attrs(attrs: &str) -> Result<BTreeMap<String, f64>, HNSWError> {
+    let mut parsed = BTreeMap::new();
+    for attr in attrs.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        let (name, value) = match attr.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => {
+                return Err(format!("Invalid attribute: {:?}, expected name=value", attr).into())
+            }
+        };
+        if !is_attr_name(name) {
+            return Err(format!("Invalid attribute name: {:?}", name).into());
+        }
+        let value = match value.parse::<f64>() {
+            Ok(v) if v.is_finite() => v,
+            _ => return Err(format!("Invalid value for attribute: {}", name).into()),
+        };
+        parsed.insert(name.to_owned(), value);
+    }
+    Ok(parsed)
+}
+
+// attribute names are identifiers that can't be mistaken for keywords of a filter
+fn is_attr_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !["and", "or", "not"]
+            .iter()
+            .any(|k| name.eq_ignore_ascii_case(k))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl CmpOp {
+    fn parse(op: &str) -> Option<Self> {
+        match op {
+            "<" => Some(CmpOp::Lt),
+            "<=" => Some(CmpOp::Le),
+            ">" => Some(CmpOp::Gt),
+            ">=" => Some(CmpOp::Ge),
+            "=" | "==" => Some(CmpOp::Eq),
+            "!=" => Some(CmpOp::Ne),
+            _ => None,
+        }
+    }
+
+    fn apply(self, a: f64, b: f64) -> bool {
+        match self {
+            CmpOp::Lt => a < b,
+            CmpOp::Le => a <= b,
+            CmpOp::Gt => a > b,
+            CmpOp::Ge => a >= b,
+            CmpOp::Eq => a == b,
+            CmpOp::Ne => a != b,
+        }
+    }
+}
+
+// filter expression over node tags and numeric attributes,
+// e.g. `TAG:red AND (price < 50 OR NOT TAG:large)`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Tag(String),
+    Cmp(String, CmpOp, f64),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    // NOT binds tighter than AND, which binds tighter than OR
+    pub fn parse(expr: &str) -> Result<Self, HNSWError> {
+        let mut tokens = tokenize(expr).into_iter().peekable();
+        let filter = parse_or(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(filter),
+            Some(t) => Err(format!("Unexpected token in FILTER: {}", t).into()),
+        }
+    }
+
+    // nodes missing a compared attribute never match the comparison
+    pub fn matches(&self, tags: &[String], attrs: &BTreeMap<String, f64>) -> bool {
+        match self {
+            Filter::Tag(tag) => tags.contains(tag),
+            Filter::Cmp(name, op, value) => attrs.get(name).is_some_and(|a| op.apply(*a, *value)),
+            Filter::And(a, b) => a.matches(tags, attrs) && b.matches(tags, attrs),
+            Filter::Or(a, b) => a.matches(tags, attrs) || b.matches(tags, attrs),
+            Filter::Not(a) => !a.matches(tags, attrs),
+        }
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || is_operator(c) || c == '(' || c == ')'
+}
+
+fn is_operator(c: char) -> bool {
+    c == '<' || c == '>' || c == '=' || c == '!'
+}
+
+type Tokens = Peekable<IntoIter<String>>;
+
+fn tokenize(expr: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_separator(c) {
+            token.push(c);
+            continue;
+        }
+        if !token.is_empty() {
+            tokens.push(std::mem::take(&mut token));
+        }
+        if is_operator(c) {
+            // operators are at most 2 characters, e.g. `<=`
+            let mut op = c.to_string();
+            if let Some(&n) = chars.peek() {
+                if n == '=' {
+                    op.push(n);
+                    chars.next();
+                }
+            }
+            tokens.push(op);
+        } else if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn next_is(tokens: &mut Tokens, keyword: &str) -> bool {
+    match tokens.peek() {
+        Some(t) if t.eq_ignore_ascii_case(keyword) => {
+            tokens.next();
+            true
+        }
+        _ => false,
+    }
+}
+
+fn parse_or(tokens: &mut Tokens) -> Result<Filter, HNSWError> {
+    let mut filter = parse_and(tokens)?;
+    while next_is(tokens, "or") {
+        filter = Filter::Or(Box::new(filter), Box::new(parse_and(tokens)?));
+    }
+    Ok(filter)
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<Filter, HNSWError> {
+    let mut filter = parse_not(tokens)?;
+    while next_is(tokens, "and") {
+        filter = Filter::And(Box::new(filter), Box::new(parse_not(tokens)?));
+    }
+    Ok(filter)
+}
+
+fn parse_not(tokens: &mut Tokens) -> Result<Filter, HNSWError> {
+    if next_is(tokens, "not") {
+        return Ok(Filter::Not(Box::new(parse_not(tokens)?)));
+    }
+    if next_is(tokens, "(") {
+        let filter = parse_or(tokens)?;
+        if !next_is(tokens, ")") {
+            return Err("Missing closing parenthesis in FILTER".into());
+        }
+        return Ok(filter);
+    }
+    match tokens.next() {
+        Some(t) if t.len() > 4 && t.get(..4).is_some_and(|p| p.eq_ignore_ascii_case("tag:")) => {
+            Ok(Filter::Tag(t[4..].to_owned()))
+        }
+        Some(t) if is_attr_name(&t) => parse_cmp(tokens, t),
+        Some(t) => {
+            Err(format!("Expected TAG:{{tag}} or a comparison in FILTER, got: {}", t).into())
+        }
+        None => Err("Unexpected end of FILTER".into()),
+    }
+}
+
+// `{attribute} {op} {number}`
+fn parse_cmp(tokens: &mut Tokens, name: String) -> Result<Filter, HNSWError> {
+    let op = tokens
+        .next()
+        .and_then(|op| CmpOp::parse(&op))
+        .ok_or_else(|| format!("Expected a comparison operator after: {}", name))?;
+    let value = match tokens.next().map(|v| v.parse::<f64>()) {
+        Some(Ok(v)) if v.is_finite() => v,
+        _ => return Err(format!("Expected a number to compare: {} with", name).into()),
+    };
+    Ok(Filter::Cmp(name, op, value))
+}
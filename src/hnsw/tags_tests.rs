@@ -0,0 +1,131 @@
+use crate::hnsw::tags::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+fn tag(t: &str) -> Box<Filter> {
+    Box::new(Filter::Tag(t.to_owned()))
+}
+
+#[test]
+fn parse_filter() {
+    assert_eq!(Filter::parse("TAG:red").unwrap(), *tag("red"));
+    assert_eq!(
+        Filter::parse("tag:red and TAG:2024 OR not TAG:large").unwrap(),
+        Filter::Or(
+            Box::new(Filter::And(tag("red"), tag("2024"))),
+            Box::new(Filter::Not(tag("large"))),
+        )
+    );
+    assert_eq!(
+        Filter::parse("TAG:red AND (TAG:2024 OR TAG:large)").unwrap(),
+        Filter::And(tag("red"), Box::new(Filter::Or(tag("2024"), tag("large"))))
+    );
+
+    assert!(Filter::parse("").is_err());
+    assert!(Filter::parse("red").is_err());
+    assert!(Filter::parse("TAG:").is_err());
+    assert!(Filter::parse("TAG:red AND").is_err());
+    assert!(Filter::parse("(TAG:red").is_err());
+    assert!(Filter::parse("TAG:red TAG:blue").is_err());
+}
+
+#[test]
+fn parse_tag_list() {
+    assert_eq!(
+        parse_tags("red, large,2024,,red").unwrap(),
+        vec!["red", "large", "2024"]
+    );
+    assert!(parse_tags("").unwrap().is_empty());
+    assert!(parse_tags("a b").is_err());
+    assert!(parse_tags("a(b)").is_err());
+}
+
+#[test]
+fn eval_filter() {
+    let mut store = TagStore::default();
+    let mut nodes = HashMap::new();
+    for (name, tags) in &[("a", "red,large"), ("b", "red"), ("c", "blue,large")] {
+        store.add(name, &parse_tags(tags).unwrap());
+        nodes.insert(name.to_string(), ());
+    }
+
+    let eval = |store: &TagStore, f: &str| {
+        let mut res = store
+            .eval(&Filter::parse(f).unwrap(), &nodes)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<String>>();
+        res.sort();
+        res
+    };
+    assert_eq!(eval(&store, "TAG:red AND TAG:large"), vec!["a"]);
+    assert_eq!(eval(&store, "TAG:red OR TAG:blue"), vec!["a", "b", "c"]);
+    assert_eq!(eval(&store, "NOT TAG:large"), vec!["b"]);
+    assert!(eval(&store, "TAG:green").is_empty());
+
+    store.remove("a", &parse_tags("red,large").unwrap());
+    assert_eq!(eval(&store, "TAG:red"), vec!["b"]);
+    assert_eq!(
+        store.tags.get("large"),
+        Some(&HashSet::from(["c".to_owned()]))
+    );
+}
+
+#[test]
+fn parse_comparisons() {
+    let cmp = |name: &str, op, v| Box::new(Filter::Cmp(name.to_owned(), op, v));
+    assert_eq!(
+        Filter::parse("price<50 AND ts >= 1700000000").unwrap(),
+        Filter::And(
+            cmp("price", CmpOp::Lt, 50.0),
+            cmp("ts", CmpOp::Ge, 1700000000.0)
+        )
+    );
+    assert_eq!(
+        Filter::parse("TAG:red OR NOT (delta != -1.5)").unwrap(),
+        Filter::Or(
+            tag("red"),
+            Box::new(Filter::Not(cmp("delta", CmpOp::Ne, -1.5)))
+        )
+    );
+
+    assert!(Filter::parse("price <").is_err());
+    assert!(Filter::parse("price < cheap").is_err());
+    assert!(Filter::parse("price => 5").is_err());
+    assert!(Filter::parse("9lives > 5").is_err());
+}
+
+#[test]
+fn parse_attr_list() {
+    let attrs = parse_attrs("price=19.99, ts=1712345").unwrap();
+    assert_eq!(attrs.get("price"), Some(&19.99));
+    assert_eq!(attrs.get("ts"), Some(&1712345.0));
+    assert!(parse_attrs("").unwrap().is_empty());
+    assert!(parse_attrs("price").is_err());
+    assert!(parse_attrs("price=cheap").is_err());
+    assert!(parse_attrs("price=NaN").is_err());
+    assert!(parse_attrs("and=1").is_err());
+    assert!(parse_attrs("a b=1").is_err());
+}
+
+#[test]
+fn match_filter() {
+    let tags = parse_tags("red").unwrap();
+    let mut attrs = BTreeMap::new();
+    attrs.insert("price".to_owned(), 19.99);
+
+    let matches = |f: &str| Filter::parse(f).unwrap().matches(&tags, &attrs);
+    assert!(matches("price < 50"));
+    assert!(matches("price = 19.99 AND TAG:red"));
+    assert!(!matches("price > 50 OR TAG:blue"));
+    // missing attributes never match a comparison
+    assert!(!matches("ts >= 0"));
+    assert!(matches("NOT ts >= 0"));
+
+    let store = TagStore::default();
+    assert!(store
+        .eval(
+            &Filter::parse("TAG:red AND price < 50").unwrap(),
+            &HashMap::<String, ()>::new()
+        )
+        .is_none());
+}
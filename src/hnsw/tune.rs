@@ -0,0 +1,160 @@
+use super::core::{HNSWError, Index};
+use super::metrics::MetricFuncT;
+
+use num::Float;
+use ordered_float::OrderedFloat;
+use rand::prelude::*;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+// build parameters tried by the sweep, ef_construction also bounds the search candidates
+pub const TUNE_M: [usize; 5] = [4, 8, 16, 24, 32];
+pub const TUNE_EF: [usize; 4] = [50, 100, 200, 400];
+
+// share of the sample held out as queries, and the most queries per trial
+const QUERY_SHARE: usize = 10;
+const MAX_QUERIES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub m: usize,
+    pub ef_construction: usize,
+    pub recall: f64,           // mean share of the exact top k found by the search
+    pub build_time: Duration,  // time to add the sample to the trial index
+    pub search_time: Duration, // mean time of a query
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub trials: Vec<Trial>,
+    pub best: usize,   // trial recommended by the sweep
+    pub reached: bool, // the recommended trial meets the target recall
+}
+
+impl Tuning {
+    pub fn best(&self) -> &Trial {
+        &self.trials[self.best]
+    }
+}
+
+// sweeps M and ef_construction over indexes built from a sample of vectors, holding part of
+// the sample out as queries scored against their exact nearest neighbors. the cheapest
+// parameters reaching the target recall are recommended, else the ones with the best recall
+pub fn autotune<T, R>(
+    sample: &[Vec<T>],
+    mfunc: MetricFuncT<T, R>,
+    target_recall: f64,
+    k: usize,
+    seed: u64,
+) -> Result<Tuning, HNSWError>
+where
+    T: Float + Send + Sync + 'static,
+    R: Float,
+{
+    if !(target_recall > 0.0 && target_recall <= 1.0) {
+        return Err("TARGET_RECALL must be within (0, 1]".into());
+    }
+    if k == 0 {
+        return Err("K must be positive".into());
+    }
+    // every QUERY_SHARE-th vector is held out, so a sample concatenated from several
+    // indexes is queried evenly
+    let n_queries = query_count(sample.len());
+    if sample.len() < n_queries + k {
+        return Err(format!("SAMPLE of {} nodes is too small for K: {}", sample.len(), k).into());
+    }
+    let data_dim = sample[0].len();
+    let step = sample.len() / n_queries;
+    let (mut queries, mut base) = (Vec::new(), Vec::new());
+    for (i, v) in sample.iter().enumerate() {
+        if i.is_multiple_of(step) && queries.len() < n_queries {
+            queries.push(v.as_slice());
+        } else {
+            base.push(v.as_slice());
+        }
+    }
+
+    let truth = queries
+        .iter()
+        .map(|q| exact_knn(q, &base, mfunc, k))
+        .collect::<Vec<HashSet<usize>>>();
+
+    let mut trials = Vec::new();
+    for &m in TUNE_M.iter() {
+        for &ef in TUNE_EF.iter() {
+            if ef < k {
+                continue;
+            }
+            let mut index = Index::new("autotune", Box::new(mfunc), data_dim, m, ef);
+            index.rng_ = StdRng::seed_from_u64(seed);
+
+            let start = Instant::now();
+            for (i, v) in base.iter().enumerate() {
+                index.add_node(&i.to_string(), v, |_, _| Ok(()))?;
+            }
+            let build_time = start.elapsed();
+
+            let start = Instant::now();
+            let mut found = 0;
+            for (q, exact) in queries.iter().zip(&truth) {
+                found += index
+                    .search_knn(q, k)?
+                    .iter()
+                    .filter(|r| r.name.parse().is_ok_and(|i: usize| exact.contains(&i)))
+                    .count();
+            }
+            let search_time = start.elapsed() / queries.len() as u32;
+
+            let recall = found as f64 / (queries.len() * k) as f64;
+            trials.push(Trial {
+                m,
+                ef_construction: ef,
+                recall,
+                build_time,
+                search_time,
+            });
+            // a larger ef only costs more once the target is met
+            if recall >= target_recall {
+                break;
+            }
+        }
+    }
+
+    let reaching = (0..trials.len())
+        .filter(|&i| trials[i].recall >= target_recall)
+        .min_by_key(|&i| (trials[i].m * trials[i].ef_construction, trials[i].m));
+    let (best, reached) = match reaching {
+        Some(i) => (i, true),
+        None => {
+            let i = (0..trials.len())
+                .max_by_key(|&i| OrderedFloat(trials[i].recall))
+                .ok_or("no parameters to try")?;
+            (i, false)
+        }
+    };
+    Ok(Tuning {
+        trials,
+        best,
+        reached,
+    })
+}
+
+fn query_count(sample_len: usize) -> usize {
+    (sample_len / QUERY_SHARE).clamp(1, MAX_QUERIES)
+}
+
+// positions of the k most similar base vectors by brute force
+fn exact_knn<T: Float, R: Float>(
+    query: &[T],
+    base: &[&[T]],
+    mfunc: MetricFuncT<T, R>,
+    k: usize,
+) -> HashSet<usize> {
+    let mut sims = base
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (OrderedFloat(mfunc(query, v, query.len())), i))
+        .collect::<Vec<(OrderedFloat<R>, usize)>>();
+    sims.sort_by(|a, b| b.cmp(a));
+    sims.into_iter().take(k).map(|(_, i)| i).collect()
+}
@@ -0,0 +1,33 @@
+use crate::hnsw::metrics::euclidean;
+use crate::hnsw::tune::*;
+
+#[test]
+fn autotune_test() {
+    // a grid is easy enough for every M to find the exact neighbors
+    let sample = (0..200)
+        .map(|i| vec![(i % 20) as f32, (i / 20) as f32])
+        .collect::<Vec<Vec<f32>>>();
+
+    let tuning = autotune(&sample, euclidean, 0.9, 5, 7).unwrap();
+    assert!(tuning.reached);
+    assert!(tuning.best().recall >= 0.9);
+    // every M stops at the first ef reaching the target
+    for t in &tuning.trials {
+        if t.recall >= 0.9 {
+            assert!(!tuning
+                .trials
+                .iter()
+                .any(|o| o.m == t.m && o.ef_construction > t.ef_construction));
+        }
+    }
+    let cost = |t: &Trial| t.m * t.ef_construction;
+    assert!(tuning
+        .trials
+        .iter()
+        .filter(|t| t.recall >= 0.9)
+        .all(|t| cost(t) >= cost(tuning.best())));
+
+    assert!(autotune(&sample, euclidean, 1.5, 5, 7).is_err());
+    assert!(autotune(&sample, euclidean, 0.9, 0, 7).is_err());
+    assert!(autotune(&sample[..5], euclidean, 0.9, 5, 7).is_err());
+}
@@ -0,0 +1,147 @@
+use redis_module::RedisValue;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// finished jobs kept for hnsw.jobs, the oldest are dropped first
+const JOB_HISTORY: usize = 100;
+
+lazy_static! {
+    static ref JOBS: Mutex<BTreeMap<u64, Arc<Job>>> = Mutex::new(BTreeMap::new());
+}
+
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+// task of a background thread, shared with the thread running it. the thread reports its
+// progress and checks is_cancelled between units of work
+pub struct Job {
+    pub id: u64,
+    pub kind: &'static str, // reindex, delete or replicate
+    pub index: String,      // key of the index the job works on
+    pub total: usize,       // units of work, nodes or keys
+    pub cancellable: bool,  // false for jobs that would leave the keyspace inconsistent
+    done: AtomicUsize,
+    cancelled: AtomicBool,
+    started: Instant,
+    end: Mutex<Option<(Duration, Result<(), String>)>>, // None while running
+}
+
+// registers a running job
+pub fn start(kind: &'static str, index: &str, total: usize, cancellable: bool) -> Arc<Job> {
+    let job = Arc::new(Job {
+        id: NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed),
+        kind,
+        index: index.to_owned(),
+        total,
+        cancellable,
+        done: AtomicUsize::new(0),
+        cancelled: AtomicBool::new(false),
+        started: Instant::now(),
+        end: Mutex::new(None),
+    });
+    let mut jobs = lock_jobs();
+    let finished = jobs
+        .values()
+        .filter(|j| !j.is_running())
+        .map(|j| j.id)
+        .collect::<Vec<u64>>();
+    for id in finished
+        .iter()
+        .take(finished.len().saturating_sub(JOB_HISTORY))
+    {
+        jobs.remove(id);
+    }
+    jobs.insert(job.id, job.clone());
+    job
+}
+
+pub fn get(id: u64) -> Option<Arc<Job>> {
+    lock_jobs().get(&id).cloned()
+}
+
+// running and recently finished jobs, oldest first
+pub fn list() -> Vec<Arc<Job>> {
+    lock_jobs().values().cloned().collect()
+}
+
+fn lock_jobs() -> MutexGuard<'static, BTreeMap<u64, Arc<Job>>> {
+    JOBS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Job {
+    pub fn set_done(&self, done: usize) {
+        self.done.store(done, Ordering::Relaxed);
+    }
+
+    pub fn add_done(&self, done: usize) {
+        self.done.fetch_add(done, Ordering::Relaxed);
+    }
+
+    // asks the thread to stop at its next check, false if the job can't be cancelled or
+    // already finished
+    pub fn cancel(&self) -> bool {
+        if !self.cancellable || !self.is_running() {
+            return false;
+        }
+        self.cancelled.store(true, Ordering::Relaxed);
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self, res: Result<(), String>) {
+        *self.end.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some((self.started.elapsed(), res));
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.end
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none()
+    }
+
+    // key-value pairs of the job, its state, running, done, failed or cancelled, its
+    // progress and the time taken
+    pub fn status(&self) -> RedisValue {
+        let end = self.end.lock().unwrap_or_else(PoisonError::into_inner);
+        let (state, elapsed, error) = match &*end {
+            None => ("running", self.started.elapsed(), None),
+            Some((elapsed, Ok(()))) => ("done", *elapsed, None),
+            Some((elapsed, Err(_))) if self.is_cancelled() => ("cancelled", *elapsed, None),
+            Some((elapsed, Err(e))) => ("failed", *elapsed, Some(e.clone())),
+        };
+        let done = self.done.load(Ordering::Relaxed);
+        let progress = match (state, self.total) {
+            ("done", _) | (_, 0) => 100.0,
+            (_, total) => (100.0 * done as f64 / total as f64).min(100.0),
+        };
+        let mut reply: Vec<RedisValue> = vec![
+            "id".into(),
+            (self.id as i64).into(),
+            "kind".into(),
+            self.kind.into(),
+            "index".into(),
+            self.index.as_str().into(),
+            "state".into(),
+            state.into(),
+            "progress".into(),
+            RedisValue::Float(progress),
+            "done".into(),
+            done.into(),
+            "total".into(),
+            self.total.into(),
+            "elapsed_ms".into(),
+            (elapsed.as_millis() as i64).into(),
+        ];
+        if let Some(e) = error {
+            reply.push("error".into());
+            reply.push(e.into());
+        }
+        reply.into()
+    }
+}
@@ -0,0 +1,70 @@
+use crate::jobs::*;
+use crate::lib_tests::field;
+use crate::*;
+
+#[test]
+fn job_lifecycle() {
+    let job = start("reindex", "hnsw:idx:{jobs}", 4, true);
+    assert!(Arc::ptr_eq(&get(job.id).unwrap(), &job));
+    job.set_done(1);
+    let status = job.status();
+    assert_eq!(field(&status, "state"), &RedisValue::from("running"));
+    assert_eq!(field(&status, "progress"), &RedisValue::Float(25.0));
+    assert_eq!(
+        field(&status, "index"),
+        &RedisValue::from("hnsw:idx:{jobs}")
+    );
+
+    job.add_done(3);
+    job.finish(Ok(()));
+    let status = job.status();
+    assert_eq!(field(&status, "state"), &RedisValue::from("done"));
+    assert_eq!(field(&status, "progress"), &RedisValue::Float(100.0));
+    // finished jobs can't be cancelled
+    assert!(!job.cancel());
+
+    let failed = start("replicate", "hnsw:idx:{jobs}", 2, true);
+    failed.finish(Err("Connecting failed".to_owned()));
+    let status = failed.status();
+    assert_eq!(field(&status, "state"), &RedisValue::from("failed"));
+    assert_eq!(
+        field(&status, "error"),
+        &RedisValue::from("Connecting failed")
+    );
+}
+
+#[test]
+fn job_cancel() {
+    let job = start("replicate", "hnsw:idx:{cancel}", 10, true);
+    assert!(!job.is_cancelled());
+    assert!(job.cancel());
+    assert!(job.is_cancelled());
+    // the thread notices at its next check
+    assert!(job.is_running());
+    job.finish(Err("Cancelled".to_owned()));
+    assert_eq!(
+        field(&job.status(), "state"),
+        &RedisValue::from("cancelled")
+    );
+
+    let delete = start("delete", "hnsw:idx:{cancel}", 10, false);
+    assert!(!delete.cancel());
+    assert!(!delete.is_cancelled());
+    delete.finish(Ok(()));
+}
+
+#[test]
+fn job_history() {
+    let running = start("delete", "hnsw:idx:{history}", 1, false);
+    for _ in 0..150 {
+        start("delete", "hnsw:idx:{history}", 1, false).finish(Ok(()));
+    }
+    let jobs = list();
+    let finished = jobs.iter().filter(|j| !j.is_running()).count();
+    // jobs of the other tests may finish meanwhile
+    assert!(finished <= 105);
+    // running jobs are never dropped
+    assert!(jobs.iter().any(|j| j.id == running.id));
+    assert!(jobs.windows(2).all(|w| w[0].id < w[1].id));
+    running.finish(Ok(()));
+}
@@ -1,8 +1,35 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
-mod hnsw;
+mod acl;
+mod backend;
+mod config;
+mod defrag;
+mod docs;
+mod events;
+#[doc(hidden)]
+pub mod fuzzing;
+mod help;
+pub mod hnsw;
+mod jobs;
+mod maintenance;
+mod rdb;
+mod remote;
+mod reply;
 mod types;
 
+#[cfg(test)]
+mod docs_tests;
+#[cfg(test)]
+mod help_tests;
+#[cfg(test)]
+mod jobs_tests;
+#[cfg(test)]
+mod lib_tests;
+#[cfg(test)]
+mod rdb_tests;
+#[cfg(test)]
+mod remote_tests;
+
 #[macro_use]
 extern crate redis_module;
 
@@ -16,65 +43,668 @@ extern crate num;
 extern crate ordered_float;
 extern crate owning_ref;
 
-use hnsw::{Index, Node};
-use redis_module::{Context, RedisError, RedisResult, RedisValue};
-use redismodule_cmd::{rediscmd_doc, ArgType, Collection, Command};
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, RwLock};
+use backend::Backend;
+use config::log_event;
+use hnsw::metrics::MetricFuncs;
+use hnsw::{
+    _Node, autotune, check_index_name, duplicate_clusters, glob_match, index_key, index_suffix,
+    is_module_key, kmeans, knn_tsv, layout_of, moved_node_key, node_key, weighted_sum, Dedupe,
+    DuplicatePolicy, Filter, GeoFence, GraphFormat, Grouping, HNSWError, HeapStorage, Index,
+    KMeansInit, KeyLayout, KnnFormat, Location, MultiValue, NameRule, Node, NotNear, Projection,
+    Relocate, SearchParams, SearchResult, SearchStats, SlabStorage, Storage, StorageKind, Subgraph,
+    Undo, VectorFormat,
+};
+use redis_module::{
+    raw, Context, LogLevel, RedisError, RedisResult, RedisValue, Status, ThreadSafeContext,
+};
+use redismodule_cmd::{rediscmd_doc, ArgType, Collection, Command, Value};
+use remote::Remote;
+use std::cell::RefCell;
+use std::cmp::{min, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::ops::Deref;
+use std::os::raw::c_int;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{
+    Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    TryLockResult,
+};
+use std::thread;
+use std::time::{Duration, Instant};
 use types::*;
 
-static PREFIX: &str = "hnsw";
+// number of node keys deleted per lock of the redis context by HNSW.DEL ASYNC
+static DEL_BATCH_SIZE: usize = 1000;
+// number of commands HNSW.REPLICATETO sends before waiting for their replies
+static REPLICATE_BATCH_SIZE: usize = 1000;
+// how long a command waits for an index locked by another thread before replying BUSY
+static LOCK_TIMEOUT: Duration = Duration::from_millis(50);
+static LOCK_RETRY_INTERVAL: Duration = Duration::from_micros(100);
+// search cursors not read for this long are discarded
+static CURSOR_TTL: Duration = Duration::from_secs(300);
+// minimum age of a search snapshot before it is refreshed
+static SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+// node attribute holding the cluster of hnsw.cluster
+static CLUSTER_ATTR: &str = "cluster";
+// number of the next vector file of an index with mmap storage
+static NEXT_VECTOR_FILE: AtomicU64 = AtomicU64::new(1);
 
 type IndexArc = Arc<RwLock<IndexT>>;
 type IndexT = Index<f32, f32>;
 
 lazy_static! {
-    static ref INDICES: Arc<RwLock<HashMap<String, IndexArc>>> =
+    static ref INDICES: Arc<RwLock<HashMap<CacheKey, CachedIndex>>> =
         Arc::new(RwLock::new(HashMap::new()));
+    // indexes whose node keys are still being deleted in the background
+    static ref DELETING: Arc<RwLock<HashSet<CacheKey>>> = Arc::new(RwLock::new(HashSet::new()));
+    static ref CURSORS: Mutex<Cursors> = Mutex::new(Cursors::default());
+    // read-only copies searched while a writer holds the index
+    static ref SNAPSHOTS: RwLock<HashMap<CacheKey, Arc<Snapshot>>> = RwLock::new(HashMap::new());
+    // rebuilds of hnsw.reindex, kept after they finish for hnsw.reindex.status
+    static ref REINDEXES: Mutex<HashMap<CacheKey, Arc<jobs::Job>>> = Mutex::new(HashMap::new());
+    // node keys whose writes are combined while the write window is open
+    static ref DEFERRED: Mutex<HashMap<CacheKey, Deferred>> = Mutex::new(HashMap::new());
 }
 
-thread_local! {
-    #[rediscmd_doc(clean)]
-    static NEW_INDEX_CMD: Command = command!{
+// the caches are consistent between their operations, a panic while one was locked doesn't
+// invalidate it, so a poisoned lock is recovered instead of failing every later command
+fn read_cache<T>(lock: &RwLock<T>) -> RwLockReadGuard<T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_cache<T>(lock: &RwLock<T>) -> RwLockWriteGuard<T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn lock_cache<T>(lock: &Mutex<T>) -> MutexGuard<T> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// keywords are normalized to the case and order of the spec before parsing
+fn parse_command(cmd: &Command, args: Vec<String>) -> Result<HashMap<String, Value>, RedisError> {
+    cmd.parse_args(help::normalize(cmd, args)?)
+}
+
+// every argument is required or has a default, a missing one is a bug of the command spec,
+// reported to the client instead of panicking
+fn take_arg(parsed: &mut HashMap<String, Value>, name: &str) -> Result<Value, RedisError> {
+    parsed
+        .remove(name)
+        .ok_or_else(|| RedisError::String(format!("Missing argument: {}", name)))
+}
+
+// database and name of an index, indexes of the same name in different databases are
+// separate keys
+type CacheKey = (c_int, String);
+
+fn cache_key(ctx: &dyn Backend, index_name: &str) -> CacheKey {
+    (ctx.db(), index_name.to_owned())
+}
+
+// loaded index and the registry id of the key it was loaded from, a key that was
+// flushed or replaced since has a new id
+struct CachedIndex {
+    id: u64,
+    index: IndexArc,
+}
+
+struct Snapshot {
+    index: IndexT,
+    taken: Instant,
+}
+
+// loaded state logged by the maintenance timer
+struct CacheStats {
+    indexes: usize,
+    nodes: usize, // nodes of the indexes no writer holds
+    snapshots: usize,
+    cursors: usize,
+    deleting: usize, // indexes whose node keys are deleted in the background
+}
+
+// index a search runs on, either the live index or its last snapshot
+enum IndexView<'a> {
+    Live(RwLockReadGuard<'a, IndexT>),
+    Snapshot(Arc<Snapshot>),
+}
+
+impl Deref for IndexView<'_> {
+    type Target = IndexT;
+
+    fn deref(&self) -> &IndexT {
+        match self {
+            IndexView::Live(index) => index,
+            IndexView::Snapshot(snapshot) => &snapshot.index,
+        }
+    }
+}
+
+// entry of a cursor, a search result or a node listed by HNSW.GET
+enum CursorItem {
+    Result(SearchResultRedis),
+    Node(String, usize),   // node name and its top layer
+    Member(usize, String), // layer and the name of a node on it
+}
+
+impl From<CursorItem> for RedisValue {
+    fn from(item: CursorItem) -> Self {
+        let reply: Vec<RedisValue> = match item {
+            CursorItem::Result(sr) => return sr.into(),
+            CursorItem::Node(name, level) => {
+                vec!["name".into(), name.into(), "level".into(), level.into()]
+            }
+            CursorItem::Member(layer, name) => {
+                vec!["layer".into(), layer.into(), "name".into(), name.into()]
+            }
+        };
+        reply.into()
+    }
+}
+
+// search results or index entries kept server-side and returned in chunks
+struct Cursor {
+    results: VecDeque<CursorItem>,
+    count: usize,     // default number of results per read
+    expires: Instant, // cursor is discarded after this
+}
+
+#[derive(Default)]
+struct Cursors {
+    next_id: u64,
+    cursors: HashMap<u64, Cursor>,
+}
+
+impl Cursors {
+    fn purge_expired(&mut self) {
+        let now = Instant::now();
+        self.cursors.retain(|_, c| c.expires > now);
+    }
+
+    fn insert(&mut self, results: VecDeque<CursorItem>, count: usize) -> u64 {
+        self.purge_expired();
+        // cursor id 0 marks an exhausted cursor
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let cursor = Cursor {
+            results,
+            count,
+            expires: Instant::now() + CURSOR_TTL,
+        };
+        self.cursors.insert(self.next_id, cursor);
+        self.next_id
+    }
+
+    // takes the next chunk of results, the reply starts with the cursor id or 0 once exhausted
+    fn read(&mut self, id: u64, count: Option<usize>) -> RedisResult {
+        self.purge_expired();
+        let cursor = self
+            .cursors
+            .get_mut(&id)
+            .ok_or_else(|| format!("Cursor: {} does not exist", id))?;
+        let n = min(count.unwrap_or(cursor.count), cursor.results.len());
+        let chunk = cursor.results.drain(..n).collect::<Vec<CursorItem>>();
+        cursor.expires = Instant::now() + CURSOR_TTL;
+        let next_id = if cursor.results.is_empty() {
+            self.cursors.remove(&id);
+            0
+        } else {
+            id
+        };
+
+        let mut reply: Vec<RedisValue> = Vec::with_capacity(chunk.len() + 2);
+        reply.push((next_id as usize).into());
+        reply.push(chunk.len().into());
+        for sr in chunk {
+            reply.push(sr.into());
+        }
+        Ok(reply.into())
+    }
+}
+
+#[rediscmd_doc(clean)]
+fn new_index_cmd() -> Command {
+    command! {
         name: "hnsw.new",
         desc: "Create a new HNSW index.",
         args: [
             ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
-            ["dim", "Dimensionality of the data.", ArgType::Kwarg, u64, Collection::Unit, None],
+            [
+                "dim",
+                "Dimensionality of the data, required unless the index is created with FIELDs.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
             [
                 "m",
                 "Parameter for the number of neighbors to select for each node.",
                 ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(5_u64))
             ],
+            [
+                "m0",
+                "Maximum number of neighbors of a node on layer 0, 0 for twice M.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
             [
                 "efcon",
                 "Parameter for the size of the dynamic candidate list.",
                 ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(200_u64))
             ],
+            [
+                "metric",
+                "Metric used to compare vectors, euclidean, cosine or ip.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("euclidean".to_owned()))
+            ],
+            [
+                "normalize",
+                "L2-normalize vectors on insert and query.",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "extendcandidates",
+                "Extend the neighbor candidates by their own neighbors, 1 or 0.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("1".to_owned()))
+            ],
+            [
+                "keeppruned",
+                "Fill the neighbor list up to M with pruned candidates, 1 or 0.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("1".to_owned()))
+            ],
+            [
+                "shards",
+                "Number of sub-graphs the nodes are spread over by the hash of their name.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "snapshot",
+                "Search a periodically refreshed snapshot instead of waiting for writers, 1 or 0.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("0".to_owned()))
+            ],
+            [
+                "namepattern",
+                "Regular expression node names must fully match.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "maxnamelen",
+                "Maximum length of node names in bytes, 0 for unlimited.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "project_from",
+                "Dimensionality of vectors randomly projected onto DIM on insert and query, 0 for none.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "kind",
+                "hnsw, or flat for a single layer searched exhaustively until FLATLIMIT nodes.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("hnsw".to_owned()))
+            ],
+            [
+                "flatlimit",
+                "Node count a flat index is promoted to HNSW above.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(1000_u64))
+            ],
+            [
+                "storage",
+                "heap, arena to keep the vectors next to each other, or mmap to keep them in a memory-mapped file.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("heap".to_owned()))
+            ],
+            [
+                "duplicates",
+                "allow nodes equal to an existing node, reject them, or alias them to the existing node.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("allow".to_owned()))
+            ],
+            [
+                "dupepsilon",
+                "Largest difference of a value of two vectors DUPLICATES considers equal.",
+                ArgType::Kwarg, f64, Collection::Unit, Some(Box::new(0.0_f64))
+            ],
         ],
-    };
+    }
+}
+
+#[rediscmd_doc]
+fn alter_index_cmd() -> Command {
+    command! {
+        name: "hnsw.alter",
+        desc: "Change the neighbor selection heuristic of an HNSW index.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "extendcandidates",
+                "Extend the neighbor candidates by their own neighbors, 1 or 0.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "keeppruned",
+                "Fill the neighbor list up to M with pruned candidates, 1 or 0.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "snapshot",
+                "Search a periodically refreshed snapshot instead of waiting for writers, 1 or 0.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn set_projection_cmd() -> Command {
+    command! {
+        name: "hnsw.projection.set",
+        desc: "Set the projection of vectors of another dimensionality onto the DIM of an HNSW index.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "from",
+                "Dimensionality of the projected vectors, 0 removes the projection.",
+                ArgType::Kwarg, u64, Collection::Unit, None
+            ],
+            [
+                "matrix",
+                "Number of values followed by DIM rows of FROM values, a random projection if omitted.",
+                ArgType::Kwarg, f64, Collection::Vec, Some(Box::new(Vec::<f64>::new()))
+            ],
+            [
+                "seed",
+                "Seed of the random projection.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn train_pca_cmd() -> Command {
+    command! {
+        name: "hnsw.train.pca",
+        desc: "Reduce the dimensionality of an empty HNSW index by PCA trained from sample vectors.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "targetdim",
+                "Dimensionality vectors are reduced to.",
+                ArgType::Kwarg, u64, Collection::Unit, None
+            ],
+            [
+                "samples",
+                "Number of values followed by the sample vectors, more than TARGETDIM of them.",
+                ArgType::Kwarg, f64, Collection::Vec, None
+            ],
+            [
+                "seed",
+                "Seed of the initial components.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn freeze_cmd() -> Command {
+    command! {
+        name: "hnsw.freeze",
+        desc: "Make an HNSW index read-only, node mutations are rejected.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn unfreeze_cmd() -> Command {
+    command! {
+        name: "hnsw.unfreeze",
+        desc: "Allow node mutations on a frozen HNSW index again.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
 
-    #[rediscmd_doc]
-    static GET_INDEX_CMD: Command = command!{
+#[rediscmd_doc]
+fn get_index_cmd() -> Command {
+    command! {
         name: "hnsw.get",
         desc: "Retrieve an HNSW index.",
         args: [
             ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "section",
+                "Part of the index to return, params, nodes or layers.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("params".to_owned()))
+            ],
+            [
+                "cursor",
+                "Cursor of the nodes or layers section to continue, 0 to start.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "count",
+                "Number of entries of the nodes or layers section per reply.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(1000_u64))
+            ],
         ],
-    };
+    }
+}
+
+#[rediscmd_doc]
+fn list_cmd() -> Command {
+    command! {
+        name: "hnsw.list",
+        desc: "List the HNSW indexes of the current database.",
+        args: [
+            [
+                "pattern",
+                "Glob-style pattern the index names must match.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("*".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn exists_index_cmd() -> Command {
+    command! {
+        name: "hnsw.exists",
+        desc: "Check if an HNSW index exists.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn card_cmd() -> Command {
+    command! {
+        name: "hnsw.card",
+        desc: "Get the number of nodes in an HNSW index.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn warmup_cmd() -> Command {
+    command! {
+        name: "hnsw.warmup",
+        desc: "Load an HNSW index into memory and warm it up with searches.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "queries",
+                "Number of searches run on the index and each of its sub-indexes.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(100_u64))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn layercard_cmd() -> Command {
+    command! {
+        name: "hnsw.layercard",
+        desc: "Get the number of nodes in a layer of an HNSW index.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            ["layer", "Layer of the graph, 0 holds every node.", ArgType::Arg, u64, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn stats_cmd() -> Command {
+    command! {
+        name: "hnsw.stats",
+        desc: "Get the distribution of the graph of an index.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "stat",
+                "Statistic to compute, degree for the neighbor counts and the size of each layer",
+                ArgType::Arg, String, Collection::Unit, None
+            ],
+            [
+                "field",
+                "Name of the vector field, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn debug_graph_cmd() -> Command {
+    command! {
+        name: "hnsw.debug.graph",
+        desc: "Serialize a layer of the graph, or the part reachable from a node, for visualization.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "format",
+                "Output format, dot or graphml",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("dot".to_owned()))
+            ],
+            [
+                "start",
+                "Node the output is bounded to the reachable part from",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "depth",
+                "Max hops from the start node, -1 for no limit",
+                ArgType::Kwarg, i64, Collection::Unit, Some(Box::new(-1_i64))
+            ],
+            [
+                "layer",
+                "Layer of the graph, 0 holds every node",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "field",
+                "Field sub-index of an index with fields",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
 
-    #[rediscmd_doc]
-    static DEL_INDEX_CMD: Command = command!{
+#[rediscmd_doc]
+fn del_index_cmd() -> Command {
+    command! {
         name: "hnsw.del",
-        desc: "Delete an HNSW index.",
+        desc: "Delete an HNSW index and all of its nodes.",
         args: [
             ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "async",
+                "Delete the node keys in background batches.",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
         ],
-    };
+    }
+}
+
+#[rediscmd_doc]
+fn replicate_cmd() -> Command {
+    command! {
+        name: "hnsw.replicateto",
+        desc: "Copy an HNSW index and its nodes to another Redis server running the module.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            ["host", "Host of the target server.", ArgType::Kwarg, String, Collection::Unit, None],
+            [
+                "port",
+                "Port of the target server.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(6379_u64))
+            ],
+            [
+                "db",
+                "Database of the target server the index is created in.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "auth",
+                "Password of the target server, empty if it needs none.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn migrate_cmd() -> Command {
+    command! {
+        name: "hnsw.migrate",
+        desc: "Move an index of the dotted key layout and its nodes to keys of the tagged layout.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn reindex_cmd() -> Command {
+    command! {
+        name: "hnsw.reindex",
+        desc: "Rebuild the graph of an index in the background and swap it in when done.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "m",
+                "Parameter for the number of neighbors of the new graph, 0 keeps the current one.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "efcon",
+                "Size of the dynamic candidate list of the new graph, 0 keeps the current one.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "metric",
+                "Metric of the new graph, euclidean, cosine or ip, empty keeps the current one.",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn reindex_status_cmd() -> Command {
+    command! {
+        name: "hnsw.reindex.status",
+        desc: "Progress of the last rebuild of an index by hnsw.reindex.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
 
-    #[rediscmd_doc]
-    static ADD_NODE_CMD: Command = command!{
+#[rediscmd_doc]
+fn add_node_cmd() -> Command {
+    command! {
         name: "hnsw.node.add",
         desc: "Add a node to the index.",
         args: [
@@ -82,433 +712,5534 @@ thread_local! {
             ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
             [
                 "data",
-                "Dimensionality followed by a space separated vector of data. Total entries must match `DIM` of index",
-                ArgType::Kwarg, f64, Collection::Vec, None
+                "Dimensionality followed by a space separated vector of data. Total entries must match `DIM` of index, or a multiple of it for multi-vector nodes",
+                ArgType::Kwarg, f64, Collection::Vec, Some(Box::new(Vec::<f64>::new()))
+            ],
+            [
+                "tags",
+                "Comma separated tags of the node, matched by search FILTERs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "attrs",
+                "Comma separated numeric attributes of the node as name=value, compared by search FILTERs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "level",
+                "Top layer of the node instead of a random one",
+                ArgType::Kwarg, i64, Collection::Unit, Some(Box::new(-1_i64))
             ],
         ],
-    };
+    }
+}
 
-    #[rediscmd_doc]
-    static GET_NODE_CMD: Command = command!{
-        name: "hnsw.node.get",
-        desc: "Retrieve a node from the index.",
+#[rediscmd_doc]
+fn add_node_from_cmd() -> Command {
+    command! {
+        name: "hnsw.node.addfrom",
+        desc: "Add a node to the index with a vector read from a hash field or JSON path.",
         args: [
             ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
             ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
+            ["key", "Key holding the vector", ArgType::Kwarg, String, Collection::Unit, None],
+            [
+                "field",
+                "Hash field holding the vector",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "path",
+                "RedisJSON path of the vector",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "format",
+                "Encoding of the vector, fp32blob, fp64blob or text. Defaults to fp32blob for a FIELD and text for a PATH",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "tags",
+                "Comma separated tags of the node, matched by search FILTERs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "attrs",
+                "Comma separated numeric attributes of the node as name=value, compared by search FILTERs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn set_enterpoint_cmd() -> Command {
+    command! {
+        name: "hnsw.setenterpoint",
+        desc: "Make a node on the top layer the enterpoint of searches.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "field",
+                "Field sub-index of an index with fields",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn follow_cmd() -> Command {
+    command! {
+        name: "hnsw.follow",
+        desc: "Keep the nodes of the index in sync with the vectors of hashes matching a pattern.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "pattern",
+                "Glob-style pattern of the followed keys, nodes are named by their key",
+                ArgType::Kwarg, String, Collection::Unit, None
+            ],
+            ["field", "Hash field holding the vector", ArgType::Kwarg, String, Collection::Unit, None],
+            [
+                "format",
+                "Encoding of the vector, fp32blob, fp64blob or text",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("fp32blob".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn unfollow_cmd() -> Command {
+    command! {
+        name: "hnsw.unfollow",
+        desc: "Stop following hashes, the nodes added so far are kept.",
+        args: [
+            ["name", "Name of the index.", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn update_node_cmd() -> Command {
+    command! {
+        name: "hnsw.node.update",
+        desc: "Replace the data of a node in the index.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "data",
+                "Dimensionality followed by a space separated vector of data. Total entries must match `DIM` of index, or a multiple of it for multi-vector nodes",
+                ArgType::Kwarg, f64, Collection::Vec, Some(Box::new(Vec::<f64>::new()))
+            ],
+            [
+                "ifversion",
+                "Only update the node if it is at this version",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn get_node_cmd() -> Command {
+    command! {
+        name: "hnsw.node.get",
+        desc: "Retrieve a node from the index.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "format",
+                "array, or blob for vectors as little-endian f32 bulk strings",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("array".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn exists_node_cmd() -> Command {
+    command! {
+        name: "hnsw.node.exists",
+        desc: "Check if a node exists in the index.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn del_node_cmd() -> Command {
+    command! {
+        name: "hnsw.node.del",
+        desc: "Delete a node from the index.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn mdel_node_cmd() -> Command {
+    command! {
+        name: "hnsw.node.mdel",
+        desc: "Delete many nodes from the index at once, the node names follow the index.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn delpattern_node_cmd() -> Command {
+    command! {
+        name: "hnsw.node.delpattern",
+        desc: "Delete the nodes of the index whose names match a pattern.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "match",
+                "Glob pattern of the node names, e.g. chunk:doc42:*",
+                ArgType::Kwarg, String, Collection::Unit, None
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn search_cmd() -> Command {
+    command! {
+        name: "hnsw.search",
+        desc: "Search the index for the K nearest elements to the query.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "k",
+                "number of nearest neighbors to return",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(5_u64))
+            ],
+            [
+                "query",
+                "Dimensionality followed by a space separated vector of data. Total entries must match `DIM` of index",
+                ArgType::Kwarg, f64, Collection::Vec, Some(Box::new(Vec::<f64>::new()))
+            ],
+            [
+                "field",
+                "Name of the vector field to search, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "multivalue",
+                "Aggregation of similarities for multi-vector nodes, max or avg",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("max".to_owned()))
+            ],
+            [
+                "filter",
+                "Expression over tags and attributes the results must match, e.g. \"TAG:red AND price < 50\"",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "cursor",
+                "Keep the results server-side and return them in chunks through a cursor",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "count",
+                "Number of results per chunk in CURSOR mode",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(1000_u64))
+            ],
+            [
+                "parallel",
+                "Search the shards of a sharded index in parallel threads",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "names",
+                "Names of the returned nodes, suffix for the node name or full for the node key",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("suffix".to_owned()))
+            ],
+            [
+                "maxvisits",
+                "Stop traversing the graph after visiting this many nodes, 0 for no limit",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "timeout",
+                "Stop traversing the graph after this many milliseconds, 0 for no limit",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "entry",
+                "Node near the query to search layer 0 from instead of the enterpoint",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "withstats",
+                "Append how the graph was traversed to the reply",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "maxpergroup",
+                "Results returned per group with GROUPBY",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(1_u64))
+            ],
+            [
+                "dedupe",
+                "Collapse results whose vectors are equal into one, listing the names of the others as its aliases",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "epsilon",
+                "Largest difference of a value of two vectors DEDUPE considers equal",
+                ArgType::Kwarg, f64, Collection::Unit, Some(Box::new(0.0_f64))
+            ],
+            [
+                "format",
+                "Shape of the reply, flat for the count followed by the results, nested or map",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("flat".to_owned()))
+            ],
+            [
+                "withvectors",
+                "Reply the vector of each result",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "vectorformat",
+                "Encoding of the vectors of WITHVECTORS, array or blob for little-endian f32 bulk strings",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn composite_search_cmd() -> Command {
+    command! {
+        name: "hnsw.search.composite",
+        desc: "Search the index for the K nearest elements to the weighted sum of stored nodes.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "k",
+                "number of nearest neighbors to return",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(5_u64))
+            ],
+            [
+                "field",
+                "Name of the vector field to search, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "filter",
+                "Expression over tags and attributes the results must match, e.g. \"TAG:red AND price < 50\"",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "parallel",
+                "Search the shards of a sharded index in parallel threads",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "names",
+                "Names of the returned nodes, suffix for the node name or full for the node key",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("suffix".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn eachof_search_cmd() -> Command {
+    command! {
+        name: "hnsw.search.eachof",
+        desc: "Search the index for the K nearest elements to each of a set of stored nodes.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "k",
+                "number of nearest neighbors to return per node",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(5_u64))
+            ],
+            [
+                "field",
+                "Name of the vector field to search, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "filter",
+                "Expression over tags and attributes the results must match, e.g. \"TAG:red AND price < 50\"",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "parallel",
+                "Search the shards of a sharded index in parallel threads",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "names",
+                "Names of the returned nodes, suffix for the node name or full for the node key",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("suffix".to_owned()))
+            ],
+            [
+                "store",
+                "Prefix of the sorted set keys the results of each node are written to",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "all",
+                "Search for every node of the index",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn knn_graph_cmd() -> Command {
+    command! {
+        name: "hnsw.export.knngraph",
+        desc: "Store the K nearest neighbors of every node of the index in a hash or stream.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "k",
+                "number of nearest neighbors stored per node",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(5_u64))
+            ],
+            ["dest", "Key the graph is written to", ArgType::Kwarg, String, Collection::Unit, None],
+            [
+                "format",
+                "hash for a field per node or stream for an entry per edge",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("hash".to_owned()))
+            ],
+            [
+                "field",
+                "Name of the vector field to search, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "filter",
+                "Expression over tags and attributes the neighbors must match, e.g. \"TAG:red AND price < 50\"",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+            [
+                "parallel",
+                "Search the shards of a sharded index in parallel threads",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "names",
+                "Names of the stored nodes, suffix for the node name or full for the node key",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("suffix".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn dedup_cmd() -> Command {
+    command! {
+        name: "hnsw.dedup",
+        desc: "Find pairs or clusters of nodes whose similarity is at least a threshold.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "threshold",
+                "Minimum similarity of duplicates, on the scale of search results",
+                ArgType::Kwarg, f64, Collection::Unit, None
+            ],
+            [
+                "limit",
+                "Maximum number of pairs or clusters to return, 0 for no limit",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "clusters",
+                "Group the duplicate pairs into clusters of connected nodes",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "field",
+                "Name of the vector field, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn cluster_cmd() -> Command {
+    command! {
+        name: "hnsw.cluster",
+        desc: "Cluster the nodes of an index by mini-batch k-means, keeping the cluster of each node as its cluster attribute.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            ["k", "number of clusters", ArgType::Kwarg, u64, Collection::Unit, None],
+            [
+                "maxiter",
+                "Number of mini-batches the centroids are updated with",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(100_u64))
+            ],
+            [
+                "batch",
+                "Number of nodes sampled per mini-batch",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(256_u64))
+            ],
+            [
+                "init",
+                "Initial centroids, layers for nodes of the upper graph layers or random",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("layers".to_owned()))
+            ],
+            [
+                "seed",
+                "Seed of the sampling.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "field",
+                "Name of the vector field, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn autotune_cmd() -> Command {
+    command! {
+        name: "hnsw.autotune",
+        desc: "Sweep M and ef_construction on a sample of the nodes and recommend the cheapest parameters reaching a recall.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "target_recall",
+                "Share of the exact nearest neighbors the searches should find",
+                ArgType::Kwarg, f64, Collection::Unit, Some(Box::new(0.95_f64))
+            ],
+            [
+                "sample",
+                "Number of nodes sampled, a tenth of them is held out as queries",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(1000_u64))
+            ],
+            [
+                "k",
+                "Number of nearest neighbors recall is measured at",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(10_u64))
+            ],
+            [
+                "seed",
+                "Seed of the sampling and the trial graphs.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+            [
+                "apply",
+                "Rebuild the index with the recommended parameters",
+                ArgType::Flag, bool, Collection::Unit, Some(Box::new(false))
+            ],
+            [
+                "field",
+                "Name of the vector field, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
         ],
+    }
+}
+
+#[rediscmd_doc]
+fn centroid_cmd() -> Command {
+    command! {
+        name: "hnsw.centroid",
+        desc: "Get the mean vector of a set of nodes.",
+        args: [
+            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
+            [
+                "field",
+                "Name of the vector field, required for indexes created with FIELDs",
+                ArgType::Kwarg, String, Collection::Unit, Some(Box::new("".to_owned()))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn cursor_read_cmd() -> Command {
+    command! {
+        name: "hnsw.cursor.read",
+        desc: "Read the next chunk of results from a search cursor.",
+        args: [
+            ["cursor", "id of the cursor", ArgType::Arg, u64, Collection::Unit, None],
+            [
+                "count",
+                "Number of results to return, defaults to the COUNT of the search",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn cursor_del_cmd() -> Command {
+    command! {
+        name: "hnsw.cursor.del",
+        desc: "Discard a search cursor and its remaining results.",
+        args: [
+            ["cursor", "id of the cursor", ArgType::Arg, u64, Collection::Unit, None],
+        ],
+    }
+}
+
+#[rediscmd_doc]
+fn selftest_cmd() -> Command {
+    command! {
+        name: "hnsw.selftest",
+        desc: "Check the SIMD kernels against the scalar ones and build, search and delete a small graph in memory.",
+        args: [
+            [
+                "seed",
+                "Seed of the random vectors.",
+                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(0_u64))
+            ],
+        ],
+    }
+}
+
+// the specs are built once per thread. the doc attribute is on the functions, on a static
+// inside thread_local! it would see the expansion of the macro, which only builds on nightly
+thread_local! {
+    static NEW_INDEX_CMD: Command = new_index_cmd();
+    static ALTER_INDEX_CMD: Command = alter_index_cmd();
+    static SET_PROJECTION_CMD: Command = set_projection_cmd();
+    static TRAIN_PCA_CMD: Command = train_pca_cmd();
+    static FREEZE_CMD: Command = freeze_cmd();
+    static UNFREEZE_CMD: Command = unfreeze_cmd();
+    static GET_INDEX_CMD: Command = get_index_cmd();
+    static LIST_CMD: Command = list_cmd();
+    static EXISTS_INDEX_CMD: Command = exists_index_cmd();
+    static CARD_CMD: Command = card_cmd();
+    static WARMUP_CMD: Command = warmup_cmd();
+    static LAYERCARD_CMD: Command = layercard_cmd();
+    static STATS_CMD: Command = stats_cmd();
+    static DEBUG_GRAPH_CMD: Command = debug_graph_cmd();
+    static DEL_INDEX_CMD: Command = del_index_cmd();
+    static REPLICATE_CMD: Command = replicate_cmd();
+    static MIGRATE_CMD: Command = migrate_cmd();
+    static REINDEX_CMD: Command = reindex_cmd();
+    static REINDEX_STATUS_CMD: Command = reindex_status_cmd();
+    static ADD_NODE_CMD: Command = add_node_cmd();
+    static ADD_NODE_FROM_CMD: Command = add_node_from_cmd();
+    static SET_ENTERPOINT_CMD: Command = set_enterpoint_cmd();
+    static FOLLOW_CMD: Command = follow_cmd();
+    static UNFOLLOW_CMD: Command = unfollow_cmd();
+    static UPDATE_NODE_CMD: Command = update_node_cmd();
+    static GET_NODE_CMD: Command = get_node_cmd();
+    static EXISTS_NODE_CMD: Command = exists_node_cmd();
+    static DEL_NODE_CMD: Command = del_node_cmd();
+    static MDEL_NODE_CMD: Command = mdel_node_cmd();
+    static DELPATTERN_NODE_CMD: Command = delpattern_node_cmd();
+    static SEARCH_CMD: Command = search_cmd();
+    static COMPOSITE_SEARCH_CMD: Command = composite_search_cmd();
+    static EACHOF_SEARCH_CMD: Command = eachof_search_cmd();
+    static KNN_GRAPH_CMD: Command = knn_graph_cmd();
+    static DEDUP_CMD: Command = dedup_cmd();
+    static CLUSTER_CMD: Command = cluster_cmd();
+    static AUTOTUNE_CMD: Command = autotune_cmd();
+    static CENTROID_CMD: Command = centroid_cmd();
+    static CURSOR_READ_CMD: Command = cursor_read_cmd();
+    static CURSOR_DEL_CMD: Command = cursor_del_cmd();
+    static SELFTEST_CMD: Command = selftest_cmd();
+}
+
+// every spec, in the order hnsw.help lists the commands
+static COMMAND_SPECS: [&thread::LocalKey<Command>; 41] = [
+    &NEW_INDEX_CMD,
+    &ALTER_INDEX_CMD,
+    &SET_PROJECTION_CMD,
+    &TRAIN_PCA_CMD,
+    &FREEZE_CMD,
+    &UNFREEZE_CMD,
+    &GET_INDEX_CMD,
+    &LIST_CMD,
+    &EXISTS_INDEX_CMD,
+    &CARD_CMD,
+    &WARMUP_CMD,
+    &LAYERCARD_CMD,
+    &STATS_CMD,
+    &DEBUG_GRAPH_CMD,
+    &DEL_INDEX_CMD,
+    &REPLICATE_CMD,
+    &MIGRATE_CMD,
+    &REINDEX_CMD,
+    &REINDEX_STATUS_CMD,
+    &ADD_NODE_CMD,
+    &ADD_NODE_FROM_CMD,
+    &SET_ENTERPOINT_CMD,
+    &FOLLOW_CMD,
+    &UNFOLLOW_CMD,
+    &UPDATE_NODE_CMD,
+    &GET_NODE_CMD,
+    &EXISTS_NODE_CMD,
+    &DEL_NODE_CMD,
+    &MDEL_NODE_CMD,
+    &DELPATTERN_NODE_CMD,
+    &SEARCH_CMD,
+    &COMPOSITE_SEARCH_CMD,
+    &EACHOF_SEARCH_CMD,
+    &KNN_GRAPH_CMD,
+    &DEDUP_CMD,
+    &CLUSTER_CMD,
+    &AUTOTUNE_CMD,
+    &CENTROID_CMD,
+    &CURSOR_READ_CMD,
+    &CURSOR_DEL_CMD,
+    &SELFTEST_CMD,
+];
+
+// arguments of hnsw.new, parsed without touching the keyspace
+struct NewIndexArgs {
+    name_suffix: String,
+    fields: Vec<(String, usize)>, // name and dimension of each FIELD
+    data_dim: usize,
+    m: usize,
+    m0: usize, // 0 for the default of twice m
+    ef_construction: usize,
+    normalize: bool,
+    extend_candidates: bool,
+    keep_pruned_connections: bool,
+    shards: usize,
+    snapshot_reads: bool,
+    names: NameRule,
+    project_from: usize, // 0 without a projection
+    flat_limit: usize,   // 0 for hnsw indexes
+    storage: StorageKind,
+    metric: MetricFuncs,
+    duplicates: DuplicatePolicy,
+    dup_epsilon: f32,
+}
+
+fn parse_new_index(args: Vec<String>) -> Result<NewIndexArgs, RedisError> {
+    let mut args = args;
+    let fields = take_field_dims(&mut args)?;
+    let mut parsed = NEW_INDEX_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let data_dim = take_arg(&mut parsed, "dim")?.as_u64()? as usize;
+    let m = take_arg(&mut parsed, "m")?.as_u64()? as usize;
+    let m0 = take_arg(&mut parsed, "m0")?.as_u64()? as usize;
+    if m0 > 0 && m0 < m {
+        return Err("M0 must be at least M".into());
+    }
+    let ef_construction = take_arg(&mut parsed, "efcon")?.as_u64()? as usize;
+    let normalize = take_arg(&mut parsed, "normalize")?.as_bool()?;
+    let extend_candidates = parse_switch(
+        "EXTENDCANDIDATES",
+        &take_arg(&mut parsed, "extendcandidates")?.as_string()?,
+    )?
+    .unwrap_or(true);
+    let keep_pruned_connections = parse_switch(
+        "KEEPPRUNED",
+        &take_arg(&mut parsed, "keeppruned")?.as_string()?,
+    )?
+    .unwrap_or(true);
+    let shards = take_arg(&mut parsed, "shards")?.as_u64()? as usize;
+    let snapshot_reads =
+        parse_switch("SNAPSHOT", &take_arg(&mut parsed, "snapshot")?.as_string()?)?
+            .unwrap_or(false);
+    let names = NameRule::new(
+        &take_arg(&mut parsed, "namepattern")?.as_string()?,
+        take_arg(&mut parsed, "maxnamelen")?.as_u64()? as usize,
+    )?;
+    let project_from = take_arg(&mut parsed, "project_from")?.as_u64()? as usize;
+    let flat_limit = take_arg(&mut parsed, "flatlimit")?.as_u64()? as usize;
+    let flat_limit = match take_arg(&mut parsed, "kind")?
+        .as_string()?
+        .to_lowercase()
+        .as_str()
+    {
+        "hnsw" => 0,
+        "flat" if flat_limit == 0 => return Err("FLATLIMIT must be positive".into()),
+        "flat" => flat_limit,
+        k => return Err(format!("Unknown KIND: {}, expected hnsw or flat", k).into()),
+    };
+    let storage = take_arg(&mut parsed, "storage")?.as_string()?;
+    let storage = StorageKind::parse(&storage)
+        .ok_or_else(|| format!("Unknown STORAGE: {}, expected heap, arena or mmap", storage))?;
+    let metric = parse_metric(&take_arg(&mut parsed, "metric")?.as_string()?)?;
+    if project_from > 0 && !fields.is_empty() {
+        return Err("PROJECT_FROM can't be combined with FIELDs".into());
+    }
+    let duplicates = take_arg(&mut parsed, "duplicates")?.as_string()?;
+    let duplicates = DuplicatePolicy::parse(&duplicates).ok_or_else(|| {
+        format!(
+            "Unknown DUPLICATES: {}, expected allow, reject or alias",
+            duplicates
+        )
+    })?;
+    let dup_epsilon = Dedupe::new(take_arg(&mut parsed, "dupepsilon")?.as_f64()? as f32)?.epsilon;
+    // aliases resolve within one sub-index, the nodes of these are spread over several
+    if duplicates == DuplicatePolicy::Alias && (shards > 0 || !fields.is_empty()) {
+        return Err("DUPLICATES alias can't be combined with FIELDs or SHARDS".into());
+    }
+
+    Ok(NewIndexArgs {
+        name_suffix,
+        fields,
+        data_dim,
+        m,
+        m0,
+        ef_construction,
+        normalize,
+        extend_candidates,
+        keep_pruned_connections,
+        shards,
+        snapshot_reads,
+        names,
+        project_from,
+        flat_limit,
+        storage,
+        metric,
+        duplicates,
+        dup_epsilon,
+    })
+}
+
+fn parse_metric(metric: &str) -> Result<MetricFuncs, RedisError> {
+    match metric.to_lowercase().as_str() {
+        "euclidean" | "l2" => Ok(MetricFuncs::Euclidean),
+        "cosine" => Ok(MetricFuncs::Cosine),
+        "ip" => Ok(MetricFuncs::InnerProduct),
+        mf => Err(format!("Unknown metric: {}", mf).into()),
+    }
+}
+
+fn new_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    new_index_on(ctx, parse_new_index(args)?)
+}
+
+fn new_index_on(ctx: &dyn Backend, args: NewIndexArgs) -> RedisResult {
+    check_writable(ctx)?;
+
+    let NewIndexArgs {
+        name_suffix,
+        fields,
+        data_dim,
+        m,
+        m0,
+        ef_construction,
+        normalize,
+        extend_candidates,
+        keep_pruned_connections,
+        shards,
+        snapshot_reads,
+        names,
+        project_from,
+        flat_limit,
+        storage,
+        metric,
+        duplicates,
+        dup_epsilon,
+    } = args;
+    let layout = config::key_layout();
+    check_index_name(layout, &name_suffix)?;
+    let index_name = index_key(layout, &name_suffix);
+    // an index of the other layout would shadow or be shadowed by the new one
+    if let Some(existing) = own_index_key(ctx, &name_suffix) {
+        return Err(format!("Index: {} already exists", &existing).into());
+    }
+    if alias_target(ctx, &name_suffix).is_some() {
+        return Err(format!("Index: {} is the name of an alias", &name_suffix).into());
+    }
+    check_free_key(ctx, &index_name)?;
+    let projection = match project_from {
+        0 => None,
+        from_dim => Some(Projection::random(from_dim, data_dim, 0)?),
+    };
+
+    let build_index = |name: &str, data_dim: usize| {
+        let mut index = Index::new(
+            name,
+            Box::new(hnsw::metrics::metric_func(metric, data_dim)),
+            data_dim,
+            m,
+            ef_construction,
+        );
+        if m0 > 0 {
+            index.m_max_0 = m0;
+        }
+        index.mfunc_kind = metric;
+        index.normalize = normalize;
+        index.extend_candidates = extend_candidates;
+        index.keep_pruned_connections = keep_pruned_connections;
+        index.snapshot_reads = snapshot_reads;
+        index.flat_limit = flat_limit;
+        index.duplicates = duplicates;
+        index.dup_epsilon = dup_epsilon;
+        index.storage = open_storage(storage, data_dim)?;
+        index.names = names.clone();
+        index.projection = projection.clone();
+        Ok::<IndexT, RedisError>(index)
+    };
+
+    if fields.is_empty() {
+        if data_dim == 0 {
+            return Err("DIM is required for indexes without FIELDs".into());
+        }
+        if shards == 0 {
+            return create_index(ctx, build_index(&index_name, data_dim)?);
+        }
+
+        // every shard is backed by its own sub-index
+        for i in 0..shards {
+            check_free_key(ctx, &shard_index_name(&index_name, i))?;
+        }
+        let mut index = build_index(&index_name, data_dim)?;
+        index.shards = shards;
+        create_index(ctx, index)?;
+        for i in 0..shards {
+            create_index(
+                ctx,
+                build_index(&shard_index_name(&index_name, i), data_dim)?,
+            )?;
+        }
+        return Ok("OK".into());
+    }
+    if shards > 0 {
+        return Err("SHARDS can't be combined with FIELDs".into());
+    }
+
+    // every field is backed by its own sub-index
+    let field_names = fields.iter().map(|f| f.0.clone()).collect::<Vec<String>>();
+    for (i, field) in field_names.iter().enumerate() {
+        if field.contains('.') || field_names[..i].contains(field) {
+            return Err(format!("Invalid or duplicate FIELD: {}", field).into());
+        }
+        check_free_key(ctx, &format!("{}.{}", index_name, field))?;
+    }
+    let mut index = build_index(&index_name, 0)?;
+    index.fields = field_names;
+    create_index(ctx, index)?;
+    for (field, dim) in fields {
+        let sub_name = format!("{}.{}", index_name, field);
+        create_index(ctx, build_index(&sub_name, dim)?)?;
+    }
+
+    Ok("OK".into())
+}
+
+fn create_index(ctx: &dyn Backend, index: IndexT) -> RedisResult {
+    // node keys of a deleted index with the same name may still be pending
+    let (db, _) = cache_key(ctx, &index.name);
+    let deleting = read_cache(&DELETING);
+    if deleting.iter().any(|(d_db, d)| {
+        *d_db == db && (index.name == *d || index.name.starts_with(&format!("{}.", d)))
+    }) {
+        return Err(format!("Index: {} is being deleted. Try again later", &index.name).into());
+    }
+    drop(deleting);
+
+    // write to redis
+    match ctx.index(&index.name, |_| ())? {
+        Some(_) => {
+            return Err(RedisError::String(format!(
+                "Index: {} already exists",
+                &index.name
+            )));
+        }
+        None => {
+            log_event(
+                ctx,
+                LogLevel::Verbose,
+                "index_created",
+                &[
+                    ("index", &index.name),
+                    ("dim", &index.data_dim),
+                    ("m", &index.m),
+                    ("ef_construction", &index.ef_construction),
+                ],
+            );
+            let mut index_redis = IndexRedis::try_from(&index)?;
+            register_index(&mut index_redis);
+            let id = index_redis.registry_id;
+            ctx.set_index(&index.name, index_redis)?;
+            // Add index to global hashmap, replacing a stale one of a flushed key
+            let key = cache_key(ctx, &index.name);
+            let cached = CachedIndex {
+                id,
+                index: Arc::new(RwLock::new(index)),
+            };
+            let stale = write_cache(&INDICES).insert(key.clone(), cached);
+            if let Some(stale) = stale {
+                free_cached(&key, stale);
+            }
+        }
+    }
+
+    Ok("OK".into())
+}
+
+// removes the `FIELD {name} DIM {dim}` groups from the arguments of hnsw.new
+fn take_field_dims(args: &mut Vec<String>) -> Result<Vec<(String, usize)>, RedisError> {
+    let mut fields = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        if !args[i].eq_ignore_ascii_case("field") {
+            i = next_option(args, i, &["normalize"]);
+            continue;
+        }
+        if i + 3 >= args.len() || !args[i + 2].eq_ignore_ascii_case("dim") {
+            return Err("FIELD must be followed by {name} DIM {dim}".into());
+        }
+        let group = args.drain(i..i + 4).collect::<Vec<String>>();
+        let dim = group[3]
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid DIM for FIELD: {}", group[1]))?;
+        fields.push((group[1].clone(), dim));
+    }
+    Ok(fields)
+}
+
+// removes the `FIELD {name} DATA {dim} {...data}` groups from the arguments of hnsw.node.add
+fn take_field_data(args: &mut Vec<String>) -> Result<Vec<(String, Vec<f32>)>, RedisError> {
+    let mut fields = Vec::new();
+    let mut i = 3;
+    while i < args.len() {
+        if !args[i].eq_ignore_ascii_case("field") {
+            i = next_option(args, i, &[]);
+            continue;
+        }
+        if i + 3 >= args.len() || !args[i + 2].eq_ignore_ascii_case("data") {
+            return Err("FIELD must be followed by {name} DATA {dim} {...data}".into());
+        }
+        let dim = args[i + 3]
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid DATA for FIELD: {}", args[i + 1]))?;
+        if dim > args.len() - i - 4 {
+            return Err(format!("Missing DATA for FIELD: {}", args[i + 1]).into());
+        }
+        let group = args.drain(i..i + 4 + dim).collect::<Vec<String>>();
+        let data = group[4..]
+            .iter()
+            .map(|d| d.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|_| format!("Invalid DATA for FIELD: {}", group[1]))?;
+        finite_values(&format!("DATA for FIELD: {}", group[1]), &data)?;
+        fields.push((group[1].clone(), data));
+    }
+    Ok(fields)
+}
+
+// position of the option after the one at i, so values named like an option aren't mistaken
+// for one. flags have no value, DATA its dimensionality and as many values, LOCATION two
+fn next_option(args: &[String], i: usize, flags: &[&str]) -> usize {
+    let name = &args[i];
+    if flags.iter().any(|f| name.eq_ignore_ascii_case(f)) {
+        i + 1
+    } else if name.eq_ignore_ascii_case("data") {
+        let dim = args.get(i + 1).and_then(|d| d.parse::<usize>().ok());
+        i + 2 + dim.unwrap_or(0)
+    } else if name.eq_ignore_ascii_case("location") {
+        i + 3
+    } else {
+        i + 2
+    }
+}
+
+// values of a vector argument, f64 values beyond the range of f32 become infinite and are
+// rejected like NaN and infinite values
+fn vector_arg(name: &str, values: Vec<f64>) -> Result<Vec<f32>, RedisError> {
+    let data = values.iter().map(|v| *v as f32).collect::<Vec<f32>>();
+    finite_values(name, &data)?;
+    Ok(data)
+}
+
+// NaN and infinite values would corrupt the ordering of similarities
+fn finite_values(name: &str, data: &[f32]) -> Result<(), RedisError> {
+    match hnsw::non_finite(data) {
+        Some(i) => Err(format!(
+            "Invalid {}: value {} at position {} is not finite",
+            name, data[i], i
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+// removes `{name} {...values}` with n numeric values from the arguments, starting at start
+fn take_values(
+    args: &mut Vec<String>,
+    start: usize,
+    name: &str,
+    n: usize,
+) -> Result<Option<Vec<f64>>, RedisError> {
+    let i = match args
+        .iter()
+        .skip(start)
+        .position(|a| a.eq_ignore_ascii_case(name))
+    {
+        Some(i) => start + i,
+        None => return Ok(None),
+    };
+    if i + n >= args.len() {
+        return Err(format!("{} requires {} values", name.to_uppercase(), n).into());
+    }
+    let values = args
+        .drain(i..i + n + 1)
+        .skip(1)
+        .map(|v| v.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("Invalid {} value", name.to_uppercase()))?;
+    Ok(Some(values))
+}
+
+// removes `{name} {...values}` of n strings from the arguments
+fn take_strings(
+    args: &mut Vec<String>,
+    start: usize,
+    name: &str,
+    n: usize,
+) -> Result<Option<Vec<String>>, RedisError> {
+    let i = match args
+        .iter()
+        .skip(start)
+        .position(|a| a.eq_ignore_ascii_case(name))
+    {
+        Some(i) => start + i,
+        None => return Ok(None),
+    };
+    if i + n >= args.len() {
+        return Err(format!("{} requires {} values", name.to_uppercase(), n).into());
+    }
+    Ok(Some(args.drain(i..i + n + 1).skip(1).collect()))
+}
+
+// parses a 1|0 option, an empty value leaves the option unchanged
+fn parse_switch(name: &str, value: &str) -> Result<Option<bool>, RedisError> {
+    match value {
+        "" => Ok(None),
+        "1" => Ok(Some(true)),
+        "0" => Ok(Some(false)),
+        v => Err(format!("Invalid {}: {}, expected 1 or 0", name, v).into()),
+    }
+}
+
+fn alter_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = ALTER_INDEX_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let extend_candidates = parse_switch(
+        "EXTENDCANDIDATES",
+        &take_arg(&mut parsed, "extendcandidates")?.as_string()?,
+    )?;
+    let keep_pruned_connections = parse_switch(
+        "KEEPPRUNED",
+        &take_arg(&mut parsed, "keeppruned")?.as_string()?,
+    )?;
+    let snapshot_reads =
+        parse_switch("SNAPSHOT", &take_arg(&mut parsed, "snapshot")?.as_string()?)?;
+
+    update_index_with_fields(ctx, &index_name, |index| {
+        if let Some(ec) = extend_candidates {
+            index.extend_candidates = ec;
+        }
+        if let Some(kp) = keep_pruned_connections {
+            index.keep_pruned_connections = kp;
+        }
+        if let Some(sr) = snapshot_reads {
+            index.snapshot_reads = sr;
+        }
+    })?;
+    if snapshot_reads == Some(false) {
+        free_snapshot(write_cache(&SNAPSHOTS).remove(&cache_key(ctx, &index_name)));
+    }
+
+    Ok("OK".into())
+}
+
+fn set_projection(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = SET_PROJECTION_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let from_dim = take_arg(&mut parsed, "from")?.as_u64()? as usize;
+    let matrix = vector_arg("MATRIX", take_arg(&mut parsed, "matrix")?.as_f64vec()?)?;
+    let seed = take_arg(&mut parsed, "seed")?.as_u64()?;
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if !index.fields.is_empty() {
+        return Err(format!(
+            "Index: {} has FIELDs, which can't be projected",
+            &index_name
+        )
+        .into());
+    }
+    let projection = if from_dim == 0 {
+        None
+    } else if matrix.is_empty() {
+        Some(Projection::random(from_dim, index.data_dim, seed)?)
+    } else {
+        Some(Projection::new(from_dim, index.data_dim, matrix)?)
+    };
+    let shards = index.shards;
+    drop(index);
+
+    update_projection(ctx, &index_name, shards, |index| {
+        index.projection = projection.clone();
+    })?;
+
+    Ok("OK".into())
+}
+
+fn train_pca(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = TRAIN_PCA_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let target_dim = take_arg(&mut parsed, "targetdim")?.as_u64()? as usize;
+    let samples = vector_arg("SAMPLES", take_arg(&mut parsed, "samples")?.as_f64vec()?)?;
+    let seed = take_arg(&mut parsed, "seed")?.as_u64()?;
+
+    // stored nodes can't be moved to the reduced space
+    let (mut card, shards) = index_card(ctx, &index_name)?;
+    for i in 0..shards {
+        card += index_card(ctx, &shard_index_name(&index_name, i))?.0;
+    }
+    if card > 0 {
+        return Err(format!("Index: {} must be empty to train PCA", &index_name).into());
+    }
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if !index.fields.is_empty() {
+        return Err(format!(
+            "Index: {} has FIELDs, which can't be projected",
+            &index_name
+        )
+        .into());
+    }
+    // samples have the dimensionality of the vectors given to the index
+    let from_dim = index
+        .projection
+        .as_ref()
+        .map_or(index.data_dim, |p| p.from_dim);
+    let projection = Projection::pca(&samples, from_dim, target_dim, seed)?;
+    drop(index);
+
+    update_projection(ctx, &index_name, shards, |index| {
+        index.data_dim = target_dim;
+        *index.mfunc = hnsw::metrics::metric_func(index.mfunc_kind, target_dim);
+        index.projection = Some(projection.clone());
+    })?;
+
+    Ok("OK".into())
+}
+
+// applies a change of the projection to the index and every shard and persists it,
+// update_index leaves the projection untouched
+fn update_projection(
+    ctx: &Context,
+    index_name: &str,
+    shards: usize,
+    f: impl Fn(&mut IndexT),
+) -> Result<(), RedisError> {
+    let mut names = vec![index_name.to_owned()];
+    names.extend(sub_index_names(index_name, &[], shards));
+    for name in &names {
+        let index = load_index_mut(ctx, name)?;
+        let mut index = write_index(&index)?;
+        f(&mut index);
+        // snapshots with the old projection are replaced on the next refresh
+        index.epoch += 1;
+
+        let key = ctx.open_key_writable(name);
+        match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
+            Some(value) => value.set_projection(&index),
+            None => return Err(format!("Index: {} does not exist", name).into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn freeze_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    set_frozen(ctx, args, true)
+}
+
+fn unfreeze_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    set_frozen(ctx, args, false)
+}
+
+fn set_frozen(ctx: &Context, args: Vec<String>, frozen: bool) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let cmd = if frozen { &FREEZE_CMD } else { &UNFREEZE_CMD };
+    let mut parsed = cmd.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    update_index_with_fields(ctx, &index_name, |index| index.frozen = frozen)?;
+
+    Ok("OK".into())
+}
+
+// applies a change to the index and every field sub-index and persists them
+fn update_index_with_fields(
+    ctx: &Context,
+    index_name: &str,
+    f: impl Fn(&mut IndexT),
+) -> Result<(), RedisError> {
+    let index = load_index_mut(ctx, index_name)?;
+    let index = read_index(&index)?;
+    let mut names = vec![index_name.to_owned()];
+    names.extend(sub_index_names(index_name, &index.fields, index.shards));
+    drop(index);
+    for name in &names {
+        let index = load_index_mut(ctx, name)?;
+        let mut index = write_index(&index)?;
+        f(&mut index);
+        update_index(ctx, name, &index, NodeChange::Unchanged)?;
+    }
+
+    Ok(())
+}
+
+fn get_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = GET_INDEX_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let section = take_arg(&mut parsed, "section")?.as_string()?;
+    let cursor = take_arg(&mut parsed, "cursor")?.as_u64()?;
+    let count = take_arg(&mut parsed, "count")?.as_u64()? as usize;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    if count == 0 {
+        return Err("COUNT must be positive".into());
+    }
+    let section = match section.to_ascii_lowercase().as_str() {
+        "params" => None,
+        "nodes" => Some(false),
+        "layers" => Some(true),
+        _ => {
+            return Err(format!(
+                "Invalid SECTION: {}, expected params, nodes or layers",
+                section
+            )
+            .into())
+        }
+    };
+    // the entries are collected at once on the first page, later pages read the same snapshot
+    if section.is_some() && cursor > 0 {
+        return lock_cache(&CURSORS).read(cursor, Some(count));
+    }
+
+    let index = match load_index(ctx, &index_name) {
+        Ok(index) => index,
+        Err(e) if section.is_none() => return unloadable_index(ctx, &index_name, e),
+        Err(e) => return Err(e),
+    };
+    let index = read_index(&index)?;
+    if let Some(layers) = section {
+        let entries = index_entries(ctx, &index_name, &index, layers)?;
+        let mut cursors = lock_cache(&CURSORS);
+        let id = cursors.insert(entries, count);
+        return cursors.read(id, None);
+    }
+    let mut index_redis = IndexRedis::try_from(&*index)?;
+    index_redis.aliases = aliases_of(ctx, &index_name)?;
+    let mut degrees = index.max_degrees()?;
+    // nodes of a sharded index are counted in its shards
+    for i in 0..index.shards {
+        let shard = match load_index(ctx, &shard_index_name(&index_name, i)) {
+            Ok(shard) => shard,
+            Err(e) => return unloadable_index(ctx, &index_name, e),
+        };
+        let shard = read_index(&shard)?;
+        index_redis.node_count += shard.node_count;
+        let shard_degrees = shard.max_degrees()?;
+        degrees.layer0 = degrees.layer0.max(shard_degrees.layer0);
+        degrees.upper = degrees.upper.max(shard_degrees.upper);
+    }
+    index_redis.max_degrees = Some(degrees);
+
+    Ok(index_redis.into())
+}
+
+// parameters of an index whose key exists but whose graph can't be loaded, e.g. after node
+// keys were lost, with the load error and the number of node keys missing from the index and
+// its shards. a missing index key is still an error
+fn unloadable_index(ctx: &dyn Backend, index_name: &str, err: RedisError) -> RedisResult {
+    let index_redis = match ctx.index(index_name, |ir| ir.clone())? {
+        Some(ir) => ir,
+        None => return Err(err),
+    };
+    let names = (0..index_redis.shards).map(|i| shard_index_name(index_name, i));
+    let mut missing = 0;
+    for name in std::iter::once(index_name.to_owned()).chain(names) {
+        missing += ctx
+            .index(&name, |ir| {
+                ir.nodes.keys().filter(|node| !ctx.exists(node)).count()
+            })?
+            .unwrap_or(0);
+    }
+    let trailer: Vec<RedisValue> = vec![
+        "state".into(),
+        "unloadable".into(),
+        "error".into(),
+        err.to_string().into(),
+        "missing_nodes".into(),
+        missing.into(),
+    ];
+    Ok(append_reply(index_redis.into(), trailer))
+}
+
+// nodes of the index or its shards sorted by name, or by layer from the top down with a node
+// listed on every layer up to its level
+fn index_entries(
+    ctx: &Context,
+    index_name: &str,
+    index: &IndexT,
+    layers: bool,
+) -> Result<VecDeque<CursorItem>, RedisError> {
+    let mut nodes = Vec::with_capacity(index.node_count);
+    for name in searched_index_names(index_name, index.shards) {
+        let (shard, guard);
+        let sub = if name == index_name {
+            index
+        } else {
+            shard = load_index(ctx, &name)?;
+            guard = read_index(&shard)?;
+            &*guard
+        };
+        for (node_name, node) in &sub.nodes {
+            nodes.push((sub.node_suffix(node_name).to_owned(), node.read()?.level));
+        }
+    }
+
+    if !layers {
+        nodes.sort();
+        return Ok(nodes
+            .into_iter()
+            .map(|(name, level)| CursorItem::Node(name, level))
+            .collect());
+    }
+    let mut members = nodes
+        .into_iter()
+        .flat_map(|(name, level)| (0..=level).map(move |l| (Reverse(l), name.clone())))
+        .collect::<Vec<(Reverse<usize>, String)>>();
+    members.sort();
+    Ok(members
+        .into_iter()
+        .map(|(Reverse(l), name)| CursorItem::Member(l, name))
+        .collect())
+}
+
+// indexes are listed from the registry instead of scanning the keyspace
+fn list_indexes(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = LIST_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let pattern = take_arg(&mut parsed, "pattern")?.as_string()?;
+
+    // entries of other databases have no key with their id in this one
+    let mut entries = Vec::new();
+    for entry in registry_entries() {
+        let key = ctx.open_key(&entry.key);
+        match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
+            Some(ir) if ir.registry_id == entry.id => entries.push(entry),
+            _ => (),
+        }
+    }
+    let sub_names = entries
+        .iter()
+        .flat_map(|e| sub_index_names(&e.key, &e.fields, e.shards))
+        .collect::<HashSet<String>>();
+    let node_counts = entries
+        .iter()
+        .map(|e| (e.key.clone(), e.node_count))
+        .collect::<HashMap<String, usize>>();
+
+    let mut reply: Vec<RedisValue> = Vec::new();
+    for entry in entries {
+        let name_suffix = match index_suffix(&entry.key) {
+            Some(n) if !sub_names.contains(&entry.key) && glob_match(&pattern, n) => n,
+            _ => continue,
+        };
+        // nodes of a sharded index are counted in its shards
+        let card = entry.node_count
+            + (0..entry.shards)
+                .filter_map(|i| node_counts.get(&shard_index_name(&entry.key, i)))
+                .sum::<usize>();
+
+        let mut meta: Vec<RedisValue> = vec![
+            "name".into(),
+            name_suffix.into(),
+            "metric".into(),
+            format!("{:?}", entry.mfunc_kind).into(),
+            "data_dim".into(),
+            entry.data_dim.into(),
+            "m".into(),
+            entry.m.into(),
+            "ef_construction".into(),
+            entry.ef_construction.into(),
+            "node_count".into(),
+            card.into(),
+        ];
+        if !entry.fields.is_empty() {
+            meta.push("fields".into());
+            meta.push(entry.fields.clone().into());
+        }
+        if entry.shards > 0 {
+            meta.push("shards".into());
+            meta.push(entry.shards.into());
+        }
+        reply.push(meta.into());
+    }
+
+    Ok(reply.into())
+}
+
+// only the index key is checked, the index isn't loaded
+fn exists_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = EXISTS_INDEX_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    Ok((is_index_key(ctx, &index_name) as usize).into())
+}
+
+fn card_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = CARD_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    let (mut card, shards) = index_card(ctx, &index_name)?;
+    for i in 0..shards {
+        card += index_card(ctx, &shard_index_name(&index_name, i))?.0;
+    }
+
+    Ok(card.into())
+}
+
+// loads the index and its sub-indexes and pages in their memory, so the first search after a
+// restart doesn't pay for it
+fn warmup_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = WARMUP_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let queries = take_arg(&mut parsed, "queries")?.as_u64()? as usize;
+
+    let start = Instant::now();
+    let index = load_index(ctx, &index_name)?;
+    let mut names = {
+        let index = read_index(&index)?;
+        sub_index_names(&index_name, &index.fields, index.shards)
+    };
+    names.insert(0, index_name.clone());
+
+    let (mut nodes, mut searches) = (0, 0);
+    for name in &names {
+        let index = load_index(ctx, name)?;
+        let index = read_index(&index)?;
+        nodes += index.node_count;
+        searches += index.warm_up(queries)?;
+    }
+    let micros = start.elapsed().as_micros() as usize;
+    log_event(
+        ctx,
+        LogLevel::Verbose,
+        "index_warmed",
+        &[
+            ("index", &index_name),
+            ("nodes", &nodes),
+            ("queries", &searches),
+            ("micros", &micros),
+        ],
+    );
+
+    let reply: Vec<RedisValue> = vec![
+        "nodes".into(),
+        nodes.into(),
+        "queries".into(),
+        searches.into(),
+        "micros".into(),
+        micros.into(),
+    ];
+    Ok(reply.into())
+}
+
+// node count and shards of an index, the count is read from the index key if it isn't loaded
+fn index_card(ctx: &Context, index_name: &str) -> Result<(usize, usize), RedisError> {
+    let key = ctx.open_key(index_name);
+    let ir = key
+        .get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)?
+        .ok_or_else(|| format!("Index: {} does not exist", index_name))?;
+
+    let loaded = match read_cache(&INDICES).get(&cache_key(ctx, index_name)) {
+        Some(cached) if cached.id == ir.registry_id => Some(cached.index.clone()),
+        _ => None,
+    };
+    let node_count = match loaded {
+        Some(index) => read_index(&index)?.node_count,
+        None => ir.node_count,
+    };
+
+    Ok((node_count, ir.shards))
+}
+
+// layer membership is only stored in the node keys, so the index is loaded
+fn layercard_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = LAYERCARD_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let layer = take_arg(&mut parsed, "layer")?.as_u64()? as usize;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    let index = load_index(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    let mut card = index.layer_card(layer);
+    for i in 0..index.shards {
+        let shard = load_index(ctx, &shard_index_name(&index_name, i))?;
+        card += read_index(&shard)?.layer_card(layer);
+    }
+
+    Ok(card.into())
+}
+
+fn stats_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = STATS_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let stat = take_arg(&mut parsed, "stat")?.as_string()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    if !stat.eq_ignore_ascii_case("degree") {
+        return Err(format!("Invalid stat: {}, expected degree", stat).into());
+    }
+    degree_stats_on(ctx, &name_suffix, &field)
+}
+
+// the histograms of sharded indexes are summed over the shards
+fn degree_stats_on(ctx: &dyn Backend, name_suffix: &str, field: &str) -> RedisResult {
+    let (index_name, shards) = search_target(ctx, name_suffix, field)?;
+    let mut names = vec![index_name.clone()];
+    names.extend((0..shards).map(|i| shard_index_name(&index_name, i)));
+
+    let mut histograms: Vec<Vec<usize>> = Vec::new();
+    for name in &names {
+        let index = load_index(ctx, name)?;
+        let index = read_index(&index)?;
+        for (layer, histogram) in index.degree_histograms()?.into_iter().enumerate() {
+            if histograms.len() <= layer {
+                histograms.push(Vec::new());
+            }
+            let sum = &mut histograms[layer];
+            if sum.len() < histogram.len() {
+                sum.resize(histogram.len(), 0);
+            }
+            for (degree, count) in histogram.into_iter().enumerate() {
+                sum[degree] += count;
+            }
+        }
+    }
+    Ok(degree_reply(&histograms))
+}
+
+// per layer from 0 up, its node count, mean degree and the pairs of degree and node count of
+// the degrees some node has
+fn degree_reply(histograms: &[Vec<usize>]) -> RedisValue {
+    let layers = histograms
+        .iter()
+        .enumerate()
+        .map(|(layer, histogram)| {
+            let nodes = histogram.iter().sum::<usize>();
+            let links = histogram
+                .iter()
+                .enumerate()
+                .map(|(degree, count)| degree * count)
+                .sum::<usize>();
+            let mean = match nodes {
+                0 => 0.0,
+                n => links as f64 / n as f64,
+            };
+            let mut degrees: Vec<RedisValue> = Vec::new();
+            for (degree, count) in histogram.iter().enumerate().filter(|(_, c)| **c > 0) {
+                degrees.push(degree.into());
+                degrees.push((*count).into());
+            }
+            RedisValue::Array(vec![
+                "layer".into(),
+                layer.into(),
+                "nodes".into(),
+                nodes.into(),
+                "mean_degree".into(),
+                RedisValue::Float(mean),
+                "degrees".into(),
+                degrees.into(),
+            ])
+        })
+        .collect::<Vec<RedisValue>>();
+    RedisValue::Array(vec!["layers".into(), layers.into()])
+}
+
+fn debug_graph(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = DEBUG_GRAPH_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let format = GraphFormat::parse(&take_arg(&mut parsed, "format")?.as_string()?)?;
+    let start = take_arg(&mut parsed, "start")?.as_string()?;
+    let depth = match take_arg(&mut parsed, "depth")?.as_i64()? {
+        d if d < 0 => None,
+        d => Some(d as usize),
+    };
+    let layer = take_arg(&mut parsed, "layer")?.as_u64()? as usize;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let start = if start.is_empty() { None } else { Some(start) };
+
+    let index = load_index(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    // the graph of a sharded index is the union of its shards, a start node is in one of them
+    let target_names = match (index.fields.is_empty(), field.is_empty()) {
+        (true, true) => match (&start, index.shards) {
+            (_, 0) => vec![index_name.clone()],
+            (Some(start), _) => vec![node_index_name(&index, start)],
+            (None, n) => (0..n).map(|i| shard_index_name(&index_name, i)).collect(),
+        },
+        (false, false) => vec![field_index_name(&index, &field)?],
+        (true, false) => return Err(format!("Index: {} has no FIELDs", &index_name).into()),
+        (false, true) => {
+            return Err(format!("Index: {} requires FIELD {{name}}", &index_name).into())
+        }
+    };
+    drop(index);
+
+    let mut graph = Subgraph {
+        layer,
+        ..Subgraph::default()
+    };
+    for target_name in &target_names {
+        let target = load_index(ctx, target_name)?;
+        let part = read_index(&target)?.subgraph(layer, start.as_deref(), depth)?;
+        graph.nodes.extend(part.nodes);
+        graph.edges.extend(part.edges);
+    }
+    graph.nodes.sort();
+    graph.edges.sort();
+
+    Ok(RedisValue::BulkString(format.render(&name_suffix, &graph)))
+}
+
+fn delete_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = DEL_INDEX_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = named_index_key(ctx, &name_suffix);
+    let is_async = take_arg(&mut parsed, "async")?.as_bool()?;
+
+    let node_names = delete_index_internal(ctx, &index_name)?;
+
+    if !is_async {
+        delete_node_keys(ctx, &node_names)?;
+        return Ok(1_usize.into());
+    }
+
+    let deleting = cache_key(ctx, &index_name);
+    write_cache(&DELETING).insert(deleting.clone());
+    // stopping halfway would leave node keys of no index behind
+    let job = jobs::start("delete", &index_name, node_names.len(), false);
+    thread::spawn(move || {
+        let thread_ctx = ThreadSafeContext::new();
+        let mut res = Ok(());
+        for batch in node_names.chunks(DEL_BATCH_SIZE) {
+            let ctx = thread_ctx.lock();
+            // thread safe contexts start out in database 0
+            unsafe { raw::RedisModule_SelectDb.unwrap()(ctx.ctx, deleting.0) };
+            if let Err(e) = delete_node_keys(&*ctx, batch) {
+                log_event(
+                    &*ctx,
+                    LogLevel::Warning,
+                    "async_delete_failed",
+                    &[("index", &index_name), ("error", &format!("{:?}", e))],
+                );
+                res = Err(e.to_string());
+            }
+            job.add_done(batch.len());
+        }
+        write_cache(&DELETING).remove(&deleting);
+        job.finish(res);
+    });
+
+    Ok(1_usize.into())
+}
+
+// the commands are collected under the read lock, so the copy is the index at the time of
+// the command, and sent from a background thread
+fn replicate_to(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = REPLICATE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let host = take_arg(&mut parsed, "host")?.as_string()?;
+    let port = take_arg(&mut parsed, "port")?.as_u64()?;
+    let port = u16::try_from(port).map_err(|_| format!("Invalid PORT: {}", port))?;
+    let db = take_arg(&mut parsed, "db")?.as_u64()?;
+    let auth = take_arg(&mut parsed, "auth")?.as_string()?;
+
+    let mut commands = Vec::new();
+    if !auth.is_empty() {
+        commands.push(vec!["AUTH".to_owned(), auth]);
+    }
+    commands.push(vec!["SELECT".to_owned(), db.to_string()]);
+    let nodes = replica_commands(ctx, &index_name, &name_suffix, &mut commands)?;
+
+    let target = format!("{}:{}/{}", host, port, db);
+    // a cancelled copy stays on the target as far as it was sent
+    let job = jobs::start("replicate", &index_name, commands.len(), true);
+    thread::spawn(move || {
+        let res = Remote::connect(&host, port).and_then(|mut remote| {
+            for batch in commands.chunks(REPLICATE_BATCH_SIZE) {
+                if job.is_cancelled() {
+                    return Err("Cancelled".to_owned());
+                }
+                remote.pipeline(batch)?;
+                job.add_done(batch.len());
+            }
+            Ok(())
+        });
+        let thread_ctx = ThreadSafeContext::new();
+        let ctx = thread_ctx.lock();
+        match &res {
+            Ok(()) => log_event(
+                &*ctx,
+                LogLevel::Notice,
+                "replicated",
+                &[
+                    ("index", &index_name),
+                    ("target", &target),
+                    ("nodes", &nodes),
+                ],
+            ),
+            Err(e) => log_event(
+                &*ctx,
+                LogLevel::Warning,
+                "replicate_failed",
+                &[("index", &index_name), ("target", &target), ("error", e)],
+            ),
+        }
+        job.finish(res);
+    });
+
+    Ok(nodes.into())
+}
+
+// the new graph is built from a copy of the index in a background thread, searches and
+// writes go to the old graph meanwhile. it takes over the changes made since the copy and
+// replaces the old graph under the redis lock
+fn reindex_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = REINDEX_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let m = take_arg(&mut parsed, "m")?.as_u64()? as usize;
+    let ef_construction = take_arg(&mut parsed, "efcon")?.as_u64()? as usize;
+    let metric = take_arg(&mut parsed, "metric")?.as_string()?;
+
+    let original = load_index_mut(ctx, &index_name)?;
+    let (copy, m, ef_construction) = {
+        let index = read_index(&original)?;
+        if index.frozen {
+            return Err(format!("Index: {} is frozen", &index_name).into());
+        }
+        if index.shards > 0 || !index.fields.is_empty() {
+            return Err(format!("Index: {} has SHARDS or FIELDs", &index_name).into());
+        }
+        let mut copy = index.snapshot()?;
+        if !metric.is_empty() {
+            copy.mfunc_kind = parse_metric(&metric)?;
+            copy.mfunc = Box::new(hnsw::metrics::metric_func(copy.mfunc_kind, copy.data_dim));
+        }
+        let m = if m == 0 { index.m } else { m };
+        let ef_construction = if ef_construction == 0 {
+            index.ef_construction
+        } else {
+            ef_construction
+        };
+        (copy, m, ef_construction)
+    };
+
+    let key = cache_key(ctx, &index_name);
+    let job = {
+        let mut reindexes = lock_cache(&REINDEXES);
+        if reindexes.get(&key).is_some_and(|j| j.is_running()) {
+            return Err(format!("Index: {} is being reindexed", &index_name).into());
+        }
+        let job = jobs::start("reindex", &index_name, copy.node_count, true);
+        reindexes.insert(key.clone(), job.clone());
+        job
+    };
+    let id = job.id;
+
+    thread::spawn(move || {
+        let rebuilt = copy.rebuilt_with(m, ef_construction, |placed| {
+            job.set_done(placed);
+            match job.is_cancelled() {
+                true => Err("Cancelled".into()),
+                false => Ok(()),
+            }
+        });
+        let effort = copy.node_count;
+        free_lazily(copy, effort);
+
+        let thread_ctx = ThreadSafeContext::new();
+        let ctx = thread_ctx.lock();
+        // thread safe contexts start out in database 0
+        unsafe { raw::RedisModule_SelectDb.unwrap()(ctx.ctx, key.0) };
+        // a cancel while waiting for the lock still prevents the swap
+        let res = rebuilt
+            .map_err(RedisError::from)
+            .and_then(|rebuilt| match job.is_cancelled() {
+                true => Err("Cancelled".into()),
+                false => swap_rebuilt(&*ctx, &index_name, &original, rebuilt),
+            });
+        match &res {
+            Err(_) if job.is_cancelled() => log_event(
+                &*ctx,
+                LogLevel::Notice,
+                "reindex_cancelled",
+                &[("index", &index_name)],
+            ),
+            Ok(()) => log_event(
+                &*ctx,
+                LogLevel::Notice,
+                "reindexed",
+                &[("index", &index_name), ("m", &m), ("nodes", &job.total)],
+            ),
+            Err(e) => log_event(
+                &*ctx,
+                LogLevel::Warning,
+                "reindex_failed",
+                &[("index", &index_name), ("error", &format!("{:?}", e))],
+            ),
+        }
+        job.finish(res.map_err(|e| e.to_string()));
+    });
+
+    Ok((id as i64).into())
+}
+
+// replaces the graph of the index by one rebuilt from a copy of it, unless the index was
+// deleted or replaced since the copy was taken
+fn swap_rebuilt(
+    ctx: &dyn Backend,
+    index_name: &str,
+    original: &IndexArc,
+    mut rebuilt: IndexT,
+) -> Result<(), RedisError> {
+    let live = load_index_mut(ctx, index_name)?;
+    if !Arc::ptr_eq(&live, original) {
+        return Err(format!("Index: {} was replaced while reindexing", index_name).into());
+    }
+    let mut index = write_index(&live)?;
+    rebuilt.catch_up(&index)?;
+    let old = std::mem::replace(&mut *index, rebuilt);
+    for (node_name, node) in &index.nodes {
+        update_node(ctx, node_name, node)?;
+    }
+    drop_deferred(ctx, index_name);
+    update_index(ctx, index_name, &index, NodeChange::Unchanged)?;
+    let effort = old.node_count;
+    free_lazily(old, effort);
+    Ok(())
+}
+
+fn reindex_status(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = REINDEX_STATUS_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+    let job = lock_cache(&REINDEXES)
+        .get(&cache_key(ctx, &index_name))
+        .cloned()
+        .ok_or_else(|| format!("Index: {} has not been reindexed", &index_name))?;
+    Ok(job.status())
+}
+
+// moves the index and its sub-indexes to the tagged layout, every key is checked before the
+// first one moves
+fn migrate_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = MIGRATE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = named_index_key(ctx, &name_suffix);
+    if layout_of(&index_name) == KeyLayout::Tagged {
+        return Err(format!("Index: {} already uses the tagged key layout", &index_name).into());
+    }
+    check_index_name(KeyLayout::Tagged, &name_suffix)?;
+    let target_name = index_key(KeyLayout::Tagged, &name_suffix);
+
+    let (fields, shards) = match ctx
+        .open_key(&index_name)
+        .get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)?
+    {
+        Some(ir) => (ir.fields.clone(), ir.shards),
+        None => return Err(format!("Index: {} does not exist", &index_name).into()),
+    };
+    let moves = sub_index_names(&index_name, &fields, shards)
+        .into_iter()
+        .zip(sub_index_names(&target_name, &fields, shards))
+        .chain(std::iter::once((index_name.clone(), target_name.clone())))
+        .collect::<Vec<(String, String)>>();
+
+    for (from, to) in &moves {
+        // wait for operations still holding the index, interrupted changes are settled and
+        // deferred node keys written first
+        if let Some(cached) = read_cache(&INDICES).get(&cache_key(ctx, from)) {
+            let _index = write_index(&cached.index)?;
+            write_deferred(ctx, from)?;
+        }
+        recover_index(ctx, from)?;
+        check_free_key(ctx, to)?;
+        if let Some(ir) = ctx
+            .open_key(from)
+            .get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)?
+        {
+            for name in ir.nodes.keys() {
+                check_free_key(ctx, &moved_node_key(name, from, to))?;
+            }
+        }
+    }
+
+    let mut node_count = 0;
+    for (from, to) in &moves {
+        node_count += move_index(ctx, from, to)?;
+        evict_index(&cache_key(ctx, from));
+    }
+    log_event(
+        ctx,
+        LogLevel::Notice,
+        "index_migrated",
+        &[
+            ("index", &index_name),
+            ("to", &target_name),
+            ("nodes", &node_count),
+        ],
+    );
+
+    Ok(node_count.into())
+}
+
+// moves the value of the index key and its node keys, renaming the nodes stored in them
+fn move_index(ctx: &Context, from: &str, to: &str) -> Result<usize, RedisError> {
+    let key = ctx.open_key_writable(from);
+    let mut ir = match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
+        Some(ir) => ir.clone(),
+        None => return Err(format!("Index: {} does not exist", from).into()),
+    };
+    let rename = |name: &String| moved_node_key(name, from, to);
+
+    for name in ir.nodes.keys() {
+        let nkey = ctx.open_key_writable(name);
+        let mut node = match nkey.get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)? {
+            Some(node) => std::mem::take(node),
+            None => return Err(format!("Node: {} does not exist", name).into()),
+        };
+        // neighbors are ids unless the node was saved before them
+        for layer in node.neighbor_names.iter_mut() {
+            *layer = layer.iter().map(rename).collect();
+        }
+        nkey.delete()?;
+        ctx.open_key_writable(&rename(name))
+            .set_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE, node)?;
+    }
+
+    ir.name = to.to_owned();
+    ir.nodes = ir.nodes.iter().map(|(n, id)| (rename(n), *id)).collect();
+    ir.enterpoint = ir.enterpoint.as_ref().map(rename);
+    for layer in ir.layers.iter_mut() {
+        *layer = layer.iter().map(rename).collect();
+    }
+    // the old key unregisters its entry when it is freed
+    register_index(&mut ir);
+    let node_count = ir.nodes.len();
+    key.delete()?;
+    ctx.open_key_writable(to)
+        .set_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE, ir)?;
+
+    Ok(node_count)
+}
+
+// appends the commands recreating the index and its nodes, returning the number of nodes
+fn replica_commands(
+    ctx: &Context,
+    index_name: &str,
+    name_suffix: &str,
+    commands: &mut Vec<Vec<String>>,
+) -> Result<usize, RedisError> {
+    let index = load_index(ctx, index_name)?;
+    let index = read_index(&index)?;
+    if !index.fields.is_empty() {
+        return Err("Indexes with FIELDs can't be replicated".into());
+    }
+    if index.projection.is_some() {
+        return Err("Indexes with projections can't be replicated".into());
+    }
+
+    let switch = |on: bool| if on { "1" } else { "0" }.to_owned();
+    let metric = match index.mfunc_kind {
+        MetricFuncs::Euclidean => "euclidean",
+        MetricFuncs::Cosine => "cosine",
+        MetricFuncs::InnerProduct => "ip",
+    };
+    let mut new = vec![
+        "HNSW.NEW".to_owned(),
+        name_suffix.to_owned(),
+        "DIM".to_owned(),
+        index.data_dim.to_string(),
+        "M".to_owned(),
+        index.m.to_string(),
+        "EFCON".to_owned(),
+        index.ef_construction.to_string(),
+        "METRIC".to_owned(),
+        metric.to_owned(),
+        "EXTENDCANDIDATES".to_owned(),
+        switch(index.extend_candidates),
+        "KEEPPRUNED".to_owned(),
+        switch(index.keep_pruned_connections),
+        "SNAPSHOT".to_owned(),
+        switch(index.snapshot_reads),
+        "SHARDS".to_owned(),
+        index.shards.to_string(),
+        "MAXNAMELEN".to_owned(),
+        index.names.max_len.to_string(),
+        "DUPLICATES".to_owned(),
+        index.duplicates.as_str().to_owned(),
+        "DUPEPSILON".to_owned(),
+        index.dup_epsilon.to_string(),
+    ];
+    if index.normalize {
+        new.push("NORMALIZE".to_owned());
+    }
+    if !index.names.pattern.is_empty() {
+        new.extend(vec!["NAMEPATTERN".to_owned(), index.names.pattern.clone()]);
+    }
+    if index.is_flat() {
+        new.extend(vec![
+            "KIND".to_owned(),
+            "flat".to_owned(),
+            "FLATLIMIT".to_owned(),
+            index.flat_limit.to_string(),
+        ]);
+    }
+    commands.push(new);
+
+    // nodes are added through the parent of a sharded index, which routes them the same way
+    let mut nodes = 0;
+    for name in searched_index_names(index_name, index.shards) {
+        let (shard, guard);
+        let sub = if name == index_name {
+            &*index
+        } else {
+            shard = load_index(ctx, &name)?;
+            guard = read_index(&shard)?;
+            &*guard
+        };
+        let mut node_names = sub.nodes.keys().collect::<Vec<&String>>();
+        node_names.sort();
+        for node_name in node_names {
+            let node = sub.nodes[node_name].read()?;
+            commands.push(add_node_command(
+                name_suffix,
+                sub.node_suffix(node_name),
+                &node,
+            ));
+            nodes += 1;
+        }
+    }
+    // the aliases are added with the vector of their node, which aliases them again
+    let mut aliases = index
+        .node_aliases
+        .iter()
+        .collect::<Vec<(&String, &String)>>();
+    aliases.sort();
+    for (alias, node_name) in aliases {
+        let node = node_of(&index, node_name)?.read()?;
+        commands.push(add_node_command(
+            name_suffix,
+            index.node_suffix(alias),
+            &node,
+        ));
+    }
+    if index.frozen {
+        commands.push(vec!["HNSW.FREEZE".to_owned(), name_suffix.to_owned()]);
+    }
+    Ok(nodes)
+}
+
+// HNSW.NODE.ADD of the node with its original vectors, tags, attributes, location and level
+fn add_node_command(name_suffix: &str, node_suffix: &str, node: &_Node<f32>) -> Vec<String> {
+    let data = match node.vectors.len() {
+        0 => node.original_data(),
+        n => (0..n)
+            .flat_map(|i| {
+                let norm = node.norms.get(i).copied().unwrap_or(1.0);
+                node.vectors[i].iter().map(move |x| x * norm)
+            })
+            .collect(),
+    };
+    let mut cmd = vec![
+        "HNSW.NODE.ADD".to_owned(),
+        name_suffix.to_owned(),
+        node_suffix.to_owned(),
+        "DATA".to_owned(),
+        data.len().to_string(),
+    ];
+    cmd.extend(data.iter().map(|x| x.to_string()));
+    if !node.tags.is_empty() {
+        cmd.extend(vec!["TAGS".to_owned(), node.tags.join(",")]);
+    }
+    if !node.attrs.is_empty() {
+        let attrs = node
+            .attrs
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>();
+        cmd.extend(vec!["ATTRS".to_owned(), attrs.join(",")]);
+    }
+    if let Some(location) = &node.location {
+        cmd.extend(vec![
+            "LOCATION".to_owned(),
+            location.lat.to_string(),
+            location.lon.to_string(),
+        ]);
+    }
+    if node.level > 0 {
+        cmd.extend(vec!["LEVEL".to_owned(), node.level.to_string()]);
+    }
+    cmd
+}
+
+// deletes the index keys of the index and its fields, returning the node keys left to delete
+fn delete_index_internal(ctx: &Context, index_name: &str) -> Result<Vec<String>, RedisError> {
+    // wait for operations still holding the index
+    let key = cache_key(ctx, index_name);
+    if let Some(cached) = read_cache(&INDICES).get(&key) {
+        drop(write_index(&cached.index)?);
+    }
+
+    // node names are read from the stored index so the graph doesn't need to be loaded
+    let rkey = ctx.open_key_writable(index_name);
+    let (mut node_names, sub_names) = match rkey.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
+        Some(index) => (
+            index.nodes.keys().cloned().collect::<Vec<String>>(),
+            sub_index_names(index_name, &index.fields, index.shards),
+        ),
+        None => {
+            return Err(RedisError::String(format!(
+                "Index: {} does not exist",
+                index_name
+            )));
+        }
+    };
+
+    log_event(
+        ctx,
+        LogLevel::Verbose,
+        "index_deleted",
+        &[("index", &index_name), ("nodes", &node_names.len())],
+    );
+    rkey.delete()?;
+    free_snapshot(write_cache(&SNAPSHOTS).remove(&key));
+    evict_index(&key);
+
+    for sub_name in &sub_names {
+        node_names.extend(delete_index_internal(ctx, sub_name)?);
+    }
+
+    Ok(node_names)
+}
+
+// deletes node keys, skipping the ones that no longer exist
+fn delete_node_keys(ctx: &dyn Backend, node_names: &[String]) -> Result<(), RedisError> {
+    for node_name in node_names {
+        if ctx.node(node_name, |_| ())?.is_some() {
+            ctx.delete(node_name)?;
+        }
+    }
+
+    Ok(())
+}
+
+// keys of new indexes and nodes must not exist yet. in the dotted layout node bar of index
+// foo and index foo.bar share a key, either would overwrite the other
+fn check_free_key(ctx: &dyn Backend, key: &str) -> Result<(), RedisError> {
+    match (!ctx.exists(key), is_index_key(ctx, key)) {
+        (true, _) => Ok(()),
+        (false, true) => Err(format!("Index: {} already exists", key).into()),
+        (false, false) => Err(format!("Key: {} already exists", key).into()),
+    }
+}
+
+fn is_index_key(ctx: &dyn Backend, key: &str) -> bool {
+    ctx.index(key, |_| ()).is_ok_and(|v| v.is_some())
+}
+
+// key of the index named by the suffix, None if there is none. indexes created with the
+// dotted layout keep their key until they are migrated by HNSW.MIGRATE
+fn own_index_key(ctx: &dyn Backend, name_suffix: &str) -> Option<String> {
+    [KeyLayout::Dotted, KeyLayout::Tagged]
+        .iter()
+        .map(|layout| index_key(*layout, name_suffix))
+        .find(|key| is_index_key(ctx, key))
+}
+
+// key of the index of the current database the alias resolves to
+fn alias_target(ctx: &dyn Backend, alias: &str) -> Option<String> {
+    types::aliased(alias)
+        .into_iter()
+        // the registry spans all databases
+        .find(|entry| {
+            ctx.index(&entry.key, |ir| ir.registry_id == entry.id)
+                .is_ok_and(|v| v == Some(true))
+        })
+        .map(|entry| entry.key)
+}
+
+// key of the index named by the suffix without resolving aliases, for the commands creating,
+// deleting or moving the index itself
+fn named_index_key(ctx: &dyn Backend, name_suffix: &str) -> String {
+    own_index_key(ctx, name_suffix).unwrap_or_else(|| index_key(KeyLayout::Tagged, name_suffix))
+}
+
+// key of the index named by the suffix or the alias, the tagged key of a new index if
+// there is neither
+fn index_key_of(ctx: &dyn Backend, name_suffix: &str) -> String {
+    own_index_key(ctx, name_suffix)
+        .or_else(|| alias_target(ctx, name_suffix))
+        .unwrap_or_else(|| index_key(KeyLayout::Tagged, name_suffix))
+}
+
+// sub-index backing a shard of the index
+fn shard_index_name(index_name: &str, shard: usize) -> String {
+    format!("{}.shard{}", index_name, shard)
+}
+
+// shard of a node by the FNV-1a hash of its name, which is stable across restarts
+fn shard_of(node_suffix: &str, shards: usize) -> usize {
+    let hash = node_suffix.bytes().fold(0xcbf29ce484222325_u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    (hash % shards as u64) as usize
+}
+
+// sub-index backing the node of a sharded index, or the index itself
+fn node_index_name(index: &IndexT, node_suffix: &str) -> String {
+    match index.shards {
+        0 => index.name.clone(),
+        n => shard_index_name(&index.name, shard_of(node_suffix, n)),
+    }
+}
+
+// indexes holding the graph of a search target, the shards of a sharded index
+fn searched_index_names(index_name: &str, shards: usize) -> Vec<String> {
+    match shards {
+        0 => vec![index_name.to_owned()],
+        n => (0..n).map(|i| shard_index_name(index_name, i)).collect(),
+    }
+}
+
+// names of the field and shard sub-indexes of an index
+fn sub_index_names(index_name: &str, fields: &[String], shards: usize) -> Vec<String> {
+    let mut names = fields
+        .iter()
+        .map(|f| format!("{}.{}", index_name, f))
+        .collect::<Vec<String>>();
+    names.extend((0..shards).map(|i| shard_index_name(index_name, i)));
+    names
+}
+
+// resolves the sub-index backing a field of the index
+fn field_index_name(index: &IndexT, field: &str) -> Result<String, RedisError> {
+    if !index.fields.iter().any(|f| f == field) {
+        return Err(format!("Index: {} has no FIELD: {}", index.name, field).into());
+    }
+    Ok(format!("{}.{}", index.name, field))
+}
+
+// write commands of clients are rejected on replicas, whose graphs would diverge from the
+// master, and while loading, when the indexes may be incomplete. the redis write flag already
+// covers read-only replicas, this also covers writable ones and calls from scripts
+fn check_writable(ctx: &dyn Backend) -> Result<(), RedisError> {
+    let flags = ctx.flags();
+    if flags & raw::REDISMODULE_CTX_FLAGS_LOADING != 0 {
+        return Err("LOADING Redis is loading the dataset in memory".into());
+    }
+    // commands replicated from the master are applied
+    if flags & raw::REDISMODULE_CTX_FLAGS_SLAVE != 0
+        && flags & raw::REDISMODULE_CTX_FLAGS_REPLICATED == 0
+    {
+        return Err("READONLY You can't write against a replica".into());
+    }
+    Ok(())
+}
+
+fn node_of<'a>(index: &'a IndexT, node_name: &str) -> Result<&'a Node<f32>, RedisError> {
+    index
+        .nodes
+        .get(node_name)
+        .ok_or_else(|| format!("Node: {} does not exist", node_name).into())
+}
+
+fn read_index(index: &IndexArc) -> Result<RwLockReadGuard<IndexT>, RedisError> {
+    acquire_lock(|| index.try_read())
+}
+
+fn write_index(index: &IndexArc) -> Result<RwLockWriteGuard<IndexT>, RedisError> {
+    acquire_lock(|| index.try_write())
+}
+
+// searches of indexes with snapshot reads don't wait for writers, they run on the last
+// snapshot instead. without a snapshot they wait like any other reader. snapshots are only
+// taken by the maintenance timer, a search never pays for the copy
+fn view_index<'a>(key: &CacheKey, index: &'a IndexArc) -> Result<IndexView<'a>, RedisError> {
+    match index.try_read() {
+        Ok(guard) => Ok(IndexView::Live(guard)),
+        Err(TryLockError::WouldBlock) => {
+            if let Some(snapshot) = read_cache(&SNAPSHOTS).get(key) {
+                return Ok(IndexView::Snapshot(snapshot.clone()));
+            }
+            Ok(IndexView::Live(read_index(index)?))
+        }
+        Err(TryLockError::Poisoned(e)) => Err(e.to_string().into()),
+    }
+}
+
+// copies the index if it changed since its snapshot was taken at least SNAPSHOT_INTERVAL ago
+fn refresh_snapshot(key: &CacheKey, index: &IndexT) -> Result<(), RedisError> {
+    if !index.snapshot_reads {
+        if read_cache(&SNAPSHOTS).contains_key(key) {
+            free_snapshot(write_cache(&SNAPSHOTS).remove(key));
+        }
+        return Ok(());
+    }
+    if let Some(snapshot) = read_cache(&SNAPSHOTS).get(key) {
+        if snapshot.index.epoch == index.epoch || snapshot.taken.elapsed() < SNAPSHOT_INTERVAL {
+            return Ok(());
+        }
+    }
+
+    let snapshot = Snapshot {
+        index: index.snapshot()?,
+        taken: Instant::now(),
+    };
+    let old = write_cache(&SNAPSHOTS).insert(key.clone(), Arc::new(snapshot));
+    free_snapshot(old);
+    Ok(())
+}
+
+// the last search holding an old snapshot may still be running, it's freed with the last reference
+fn free_snapshot(snapshot: Option<Arc<Snapshot>>) {
+    if let Some(snapshot) = snapshot {
+        let effort = snapshot.index.node_count;
+        free_lazily(snapshot, effort);
+    }
+}
+
+// retries a contended lock until LOCK_TIMEOUT instead of failing on the first attempt
+fn acquire_lock<G>(try_lock: impl Fn() -> TryLockResult<G>) -> Result<G, RedisError> {
+    let start = Instant::now();
+    loop {
+        match try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(e)) => return Err(e.to_string().into()),
+            Err(TryLockError::WouldBlock) if start.elapsed() < LOCK_TIMEOUT => {
+                thread::sleep(LOCK_RETRY_INTERVAL)
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err("BUSY index is locked by another operation, try again later".into())
+            }
+        }
+    }
+}
+
+// loads the index for a read, an index whose key journals an interrupted change can't be
+// loaded until a write command recovered it
+fn load_index(ctx: &dyn Backend, index_name: &str) -> Result<IndexArc, RedisError> {
+    load_index_with(ctx, index_name, false)
+}
+
+// loads the index for a write command that passed check_writable, the keys of a change
+// interrupted before the index key was saved are recovered first
+fn load_index_mut(ctx: &dyn Backend, index_name: &str) -> Result<IndexArc, RedisError> {
+    load_index_with(ctx, index_name, true)
+}
+
+fn load_index_with(
+    ctx: &dyn Backend,
+    index_name: &str,
+    recover: bool,
+) -> Result<IndexArc, RedisError> {
+    // a cached index is only used while it was loaded from the current key
+    let key = cache_key(ctx, index_name);
+    let id = match ctx.index(index_name, |index_redis| index_redis.registry_id)? {
+        Some(id) => id,
+        None => {
+            evict_index(&key);
+            return Err(format!("Index: {} does not exist", index_name).into());
+        }
+    };
+
+    // loaded indexes only need the map read lock, each index has its own lock
+    if let Some(cached) = read_cache(&INDICES).get(&key) {
+        if cached.id == id {
+            return Ok(cached.index.clone());
+        }
+    }
+
+    let mut indices = write_cache(&INDICES);
+    // check again, the index may have been loaded while waiting for the write lock
+    if let Some(cached) = indices.get(&key) {
+        if cached.id == id {
+            return Ok(cached.index.clone());
+        }
+    }
+    // the key was flushed or replaced since the index was loaded
+    if let Some(stale) = indices.remove(&key) {
+        free_cached(&key, stale);
+    }
+
+    // repair the keys of a node change that was interrupted before the index was saved,
+    // reads of readonly commands and replicas leave the keys alone
+    if recover {
+        recover_index(ctx, index_name)?;
+    } else if ctx.index(index_name, |ir| ir.pending.is_some())? == Some(true) {
+        return Err(format!(
+            "TRYAGAIN Index: {} has an interrupted change, the next write to it recovers it",
+            index_name
+        )
+        .into());
+    }
+
+    // get index from redis
+    let index = ctx
+        .index(index_name, |index_redis| make_index(ctx, index_redis))?
+        .ok_or_else(|| format!("Index: {} does not exist", index_name))??;
+    log_event(
+        ctx,
+        LogLevel::Verbose,
+        "index_loaded",
+        &[("index", &index_name), ("nodes", &index.node_count)],
+    );
+    let index = Arc::new(RwLock::new(index));
+    let cached = CachedIndex {
+        id,
+        index: index.clone(),
+    };
+    indices.insert(key, cached);
+
+    Ok(index)
+}
+
+// drops the loaded index and its snapshot, the next load_index reads the key again
+fn evict_index(key: &CacheKey) {
+    if let Some(cached) = write_cache(&INDICES).remove(key) {
+        free_cached(key, cached);
+    }
+}
+
+// relocates the graph loaded from the index key of the registry id, see Index::relocate_nodes.
+// a graph held by a writer is left for the next defrag cycle, snapshots aren't relocated
+fn relocate_graph(registry_id: u64, start: usize, r: &mut impl Relocate) -> Option<usize> {
+    let indices = read_cache(&INDICES);
+    let cached = indices.values().find(|c| c.id == registry_id)?;
+    let index = cached.index.try_read().ok()?;
+    index.relocate_nodes(start, r)
+}
+
+// drops every loaded index and snapshot, their keys were replaced wholesale
+fn evict_all_indexes() {
+    let indices = std::mem::take(&mut *write_cache(&INDICES));
+    for (key, cached) in indices {
+        free_cached(&key, cached);
+    }
+    let snapshots = std::mem::take(&mut *write_cache(&SNAPSHOTS));
+    for snapshot in snapshots.into_values() {
+        free_snapshot(Some(snapshot));
+    }
+}
+
+// drops expired search cursors, cursor commands only purge them when they are used
+fn purge_cursors() {
+    if let Ok(mut cursors) = CURSORS.try_lock() {
+        cursors.purge_expired();
+    }
+}
+
+// takes the due snapshots of indexes no writer holds and drops the snapshots of indexes whose
+// snapshot reads were turned off
+fn refresh_snapshots(ctx: &Context) {
+    let loaded = read_cache(&INDICES)
+        .iter()
+        .map(|(key, cached)| (key.clone(), cached.index.clone()))
+        .collect::<Vec<(CacheKey, IndexArc)>>();
+    for (key, index) in loaded {
+        let index = match index.try_read() {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+        if let Err(e) = refresh_snapshot(&key, &index) {
+            log_event(
+                ctx,
+                LogLevel::Warning,
+                "snapshot_failed",
+                &[("index", &key.1), ("error", &format!("{:?}", e))],
+            );
+        }
+    }
+}
+
+// drops loaded indexes whose key was deleted or replaced without a command on the index,
+// e.g. by DEL or RESTORE, they would otherwise stay in memory until the next use
+fn evict_stale_indexes(ctx: &Context) {
+    let loaded = read_cache(&INDICES)
+        .iter()
+        .map(|(key, cached)| (key.clone(), cached.id))
+        .collect::<Vec<(CacheKey, u64)>>();
+    let selected = unsafe { raw::RedisModule_GetSelectedDb.unwrap()(ctx.ctx) };
+    for (key, id) in loaded {
+        unsafe { raw::RedisModule_SelectDb.unwrap()(ctx.ctx, key.0) };
+        let current = ctx
+            .open_key(&key.1)
+            .get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)
+            .ok()
+            .flatten()
+            .map(|index| index.registry_id);
+        if current != Some(id) {
+            log_event(
+                ctx,
+                LogLevel::Verbose,
+                "stale_index_evicted",
+                &[("index", &key.1), ("db", &key.0)],
+            );
+            evict_index(&key);
+        }
+    }
+    unsafe { raw::RedisModule_SelectDb.unwrap()(ctx.ctx, selected) };
+}
+
+fn cache_stats() -> CacheStats {
+    let indices = read_cache(&INDICES);
+    CacheStats {
+        indexes: indices.len(),
+        nodes: indices
+            .values()
+            .filter_map(|cached| cached.index.try_read().ok().map(|i| i.node_count))
+            .sum(),
+        snapshots: read_cache(&SNAPSHOTS).len(),
+        cursors: lock_cache(&CURSORS).cursors.len(),
+        deleting: read_cache(&DELETING).len(),
+    }
+}
+
+// the deferred keys of an evicted graph are dropped, its key was deleted or replaced. a graph
+// evicted by a failed change writes them first, see Journal
+fn free_cached(key: &CacheKey, cached: CachedIndex) {
+    free_snapshot(write_cache(&SNAPSHOTS).remove(key));
+    lock_cache(&DEFERRED).remove(key);
+    // the in-memory graph holds a lock and an allocation per node
+    let effort = cached.index.try_read().map(|i| i.node_count).unwrap_or(0);
+    free_lazily(cached.index, effort);
+}
+
+// rolls back an interrupted add or completes an interrupted delete, either way the journaled
+// node is dropped along with every reference to it from the other nodes. an interrupted
+// update keeps the node, which existed before
+fn recover_index(ctx: &dyn Backend, index_name: &str) -> Result<(), RedisError> {
+    ctx.index_mut(index_name, |ir| recover_change(ctx, index_name, ir))?
+        .unwrap_or(Ok(()))
+}
+
+fn recover_change(
+    ctx: &dyn Backend,
+    index_name: &str,
+    ir: &mut IndexRedis,
+) -> Result<(), RedisError> {
+    let change = match ir.pending.take() {
+        Some(change) => change,
+        None => return Ok(()),
+    };
+    let node_name = change.node_name().to_owned();
+    if let PendingChange::Update(_) = change {
+        log_event(
+            ctx,
+            LogLevel::Warning,
+            "interrupted_update_checked",
+            &[("index", &index_name), ("node", &node_name)],
+        );
+        // the node and its neighbors were rewritten in place, only links to ids the index
+        // key doesn't list are dropped
+        prune_neighbors(ctx, ir, None)?;
+        return Ok(());
+    }
+    log_event(
+        ctx,
+        LogLevel::Warning,
+        "interrupted_change_dropped",
+        &[("index", &index_name), ("node", &node_name)],
+    );
+
+    ir.nodes.remove(&node_name);
+    // the aliases of a dropped node go with it
+    ir.node_aliases
+        .retain(|alias, node| *alias != node_name && *node != node_name);
+    delete_node_keys(ctx, std::slice::from_ref(&node_name))?;
+
+    let top = prune_neighbors(ctx, ir, Some(&node_name))?;
+    if ir.enterpoint.as_deref() == Some(node_name.as_str()) {
+        ir.enterpoint = top.as_ref().map(|(_, name)| name.clone());
+    }
+    ir.max_layer = top.map(|(level, _)| level).unwrap_or(0);
+    ir.node_count = ir.nodes.len();
+
+    Ok(())
+}
+
+// prunes the neighbors the index key doesn't list from every node key, and those named after
+// the dropped node, returns the top layer and a node on it. an interrupted add may have linked
+// the id of the node before the index key recorded it
+fn prune_neighbors(
+    ctx: &dyn Backend,
+    ir: &IndexRedis,
+    dropped: Option<&str>,
+) -> Result<Option<(usize, String)>, RedisError> {
+    let ids = ir.nodes.values().collect::<HashSet<&u32>>();
+    let mut top: Option<(usize, &String)> = None;
+    for name in ir.nodes.keys() {
+        let level = ctx
+            .node_mut(name, |node| {
+                for layer in node.neighbors.iter_mut() {
+                    layer.retain(|n| ids.contains(n));
+                }
+                for layer in node.neighbor_names.iter_mut() {
+                    layer.retain(|n| Some(n.as_str()) != dropped);
+                }
+                node.level
+            })?
+            .ok_or_else(|| format!("Node: {} does not exist", name))?;
+        match top {
+            Some((top_level, _)) if top_level >= level => (),
+            _ => top = Some((level, name)),
+        }
+    }
+    Ok(top.map(|(level, name)| (level, name.clone())))
+}
+
+// vector files are numbered, names stay unique while the server runs
+fn open_storage(kind: StorageKind, data_dim: usize) -> Result<Arc<dyn Storage<f32>>, RedisError> {
+    match kind {
+        StorageKind::Heap => Ok(Arc::new(HeapStorage)),
+        StorageKind::Arena => Ok(Arc::new(SlabStorage::arena(data_dim))),
+        StorageKind::Mmap => {
+            let id = NEXT_VECTOR_FILE.fetch_add(1, Ordering::Relaxed);
+            let file = format!("hnsw-{}-{}.vectors", std::process::id(), id);
+            let path = Path::new(&config::mmap_dir()).join(file);
+            Ok(Arc::new(SlabStorage::mmap(&path, data_dim)?))
+        }
+    }
+}
+
+fn make_index(ctx: &dyn Backend, ir: &IndexRedis) -> Result<IndexT, RedisError> {
+    let mut index: IndexT = ir.clone().into();
+    index.storage = open_storage(ir.storage, ir.data_dim)?;
+    index.restore(&ir.nodes, ir.enterpoint.as_deref(), &NodeKeys::new(ctx, ir))?;
+    Ok(index)
+}
+
+// guards the in-memory change of a command, the index is evicted unless the change is
+// committed, so the next load_index reads the keys again. a change is journaled in the index
+// key while its node keys are written, load_index recovers the keys of a change that failed
+// halfway
+struct Journal<'a> {
+    ctx: &'a dyn Backend,
+    key: CacheKey,
+    committed: bool,
+}
+
+impl<'a> Journal<'a> {
+    fn new(ctx: &'a dyn Backend, index_name: &str) -> Self {
+        Journal {
+            ctx,
+            key: cache_key(ctx, index_name),
+            committed: false,
+        }
+    }
+
+    fn record(&self, ctx: &dyn Backend, change: PendingChange) -> Result<(), RedisError> {
+        ctx.index_mut(&self.key.1, |value| value.pending = Some(change))?
+            .ok_or_else(|| format!("Index: {} does not exist", self.key.1).into())
+    }
+
+    // update_index clears the journaled change from the index key
+    fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for Journal<'_> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        // the deferred keys hold the values of earlier changes, they don't need the graph
+        if let Err(e) = write_deferred(self.ctx, &self.key.1) {
+            log_event(
+                self.ctx,
+                LogLevel::Warning,
+                "deferred_write_failed",
+                &[("index", &self.key.1), ("error", &format!("{:?}", e))],
+            );
+        }
+        evict_index(&self.key);
+    }
+}
+
+// node change computed on the in-memory graph, no key is written until apply. dropping it
+// unapplied rolls the graph back and leaves the keys untouched, so a failing command inside
+// MULTI doesn't leave half of its writes behind
+struct Prepared<'a> {
+    index: RwLockWriteGuard<'a, IndexT>,
+    change: PendingChange,
+    nodes: Vec<(String, NodeRedis)>, // the changed node and its changed neighbors
+    removed: Vec<String>,            // nodes a bulk delete removes besides the journaled one
+    undo: Option<Undo<f32>>,
+    journal: Journal<'a>,
+}
+
+impl Prepared<'_> {
+    fn version(&self) -> Result<u64, RedisError> {
+        Ok(node_of(&self.index, self.change.node_name())?
+            .read()?
+            .version)
+    }
+
+    // a failed write reverts the graph and the keys written so far, the error is returned
+    fn apply(self, ctx: &dyn Backend) -> Result<(), RedisError> {
+        let Prepared {
+            mut index,
+            change,
+            nodes,
+            removed,
+            undo,
+            journal,
+        } = self;
+        let names = nodes
+            .iter()
+            .map(|(name, _)| name.clone())
+            .chain(removed.iter().cloned())
+            .collect::<Vec<String>>();
+        let err = match persist(ctx, &index, &journal, &change, nodes, &removed) {
+            Ok(()) => {
+                journal.commit();
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        log_event(
+            ctx,
+            LogLevel::Warning,
+            "node_change_reverted",
+            &[("index", &index.name), ("node", &change.node_name())],
+        );
+        // without an undo record the journal evicts the index and load_index recovers the keys
+        if let Some(undo) = undo {
+            if index.undo(undo).is_ok() && restore_keys(ctx, &index, &names, &change).is_ok() {
+                journal.commit();
+            }
+        }
+        Err(err)
+    }
+}
+
+// writes the node keys and the index key of a prepared change. while the write window is open
+// an add only writes the added node and defers the writes of the neighbors it changed, any
+// other change writes the deferred keys of the index first. the keys of the nodes removed
+// besides the journaled one are deleted once the index key no longer lists them, an
+// interrupted bulk delete leaves them in the graph instead of listing missing keys
+fn persist(
+    ctx: &dyn Backend,
+    index: &IndexT,
+    journal: &Journal<'_>,
+    change: &PendingChange,
+    nodes: Vec<(String, NodeRedis)>,
+    removed: &[String],
+) -> Result<(), RedisError> {
+    let window = match change {
+        PendingChange::Add(_) => config::write_window(),
+        _ => None,
+    };
+    if window.is_none() {
+        write_deferred(ctx, &index.name)?;
+    }
+    let (nodes, deferred): (Vec<_>, Vec<_>) = nodes
+        .into_iter()
+        .partition(|(name, _)| window.is_none() || name == change.node_name());
+    journal.record(ctx, change.clone())?;
+    for (name, value) in nodes {
+        write_node(ctx, &name, value)?;
+    }
+    let node_change = match change {
+        PendingChange::Add(name) => NodeChange::Added(name),
+        PendingChange::Delete(name) => {
+            delete_node_redis(ctx, name)?;
+            NodeChange::Removed(name, removed)
+        }
+        PendingChange::Update(_) => NodeChange::Unchanged,
+    };
+    update_index(ctx, &index.name, index, node_change)?;
+    delete_node_keys(ctx, removed)?;
+    match window {
+        Some(window) => defer_writes(ctx, &index.name, deferred, window),
+        None => Ok(()),
+    }
+}
+
+// node keys of an index that lag its graph, with their values as of the last committed
+// change of each, and when the first of them was deferred. the values are written without
+// the graph, which may be evicted or halfway through a failed change by then
+struct Deferred {
+    nodes: BTreeMap<String, NodeRedis>,
+    since: Instant,
+}
+
+// adds the changed nodes to the deferred keys of the index, a later value of a node replaces
+// the earlier one. they are all written once the window since the first one has passed
+fn defer_writes(
+    ctx: &dyn Backend,
+    index_name: &str,
+    nodes: Vec<(String, NodeRedis)>,
+    window: Duration,
+) -> Result<(), RedisError> {
+    if nodes.is_empty() {
+        return Ok(());
+    }
+    let due = {
+        let mut deferred = lock_cache(&DEFERRED);
+        let entry = deferred
+            .entry(cache_key(ctx, index_name))
+            .or_insert_with(|| Deferred {
+                nodes: BTreeMap::new(),
+                since: Instant::now(),
+            });
+        entry.nodes.extend(nodes);
+        entry.since.elapsed() >= window
+    };
+    match due {
+        true => write_deferred(ctx, index_name),
+        false => Ok(()),
+    }
+}
+
+// writes the deferred node keys of an index, each once however many adds changed it
+fn write_deferred(ctx: &dyn Backend, index_name: &str) -> Result<(), RedisError> {
+    let deferred = match lock_cache(&DEFERRED).remove(&cache_key(ctx, index_name)) {
+        Some(deferred) => deferred,
+        None => return Ok(()),
+    };
+    log_event(
+        ctx,
+        LogLevel::Debug,
+        "deferred_nodes_written",
+        &[("index", &index_name), ("nodes", &deferred.nodes.len())],
+    );
+    // deletes write the deferred keys first, so none of the nodes was deleted since
+    for (name, value) in deferred.nodes {
+        write_node(ctx, &name, value)?;
+    }
+    Ok(())
+}
+
+// drops the deferred keys of an index whose node keys were all just written from its graph
+fn drop_deferred(ctx: &dyn Backend, index_name: &str) {
+    lock_cache(&DEFERRED).remove(&cache_key(ctx, index_name));
+}
+
+// value of a node whose key lags the graph, read commands reply it instead of the key
+fn deferred_node(ctx: &dyn Backend, node_name: &str) -> Option<NodeRedis> {
+    lock_cache(&DEFERRED)
+        .iter()
+        .filter(|(key, _)| key.0 == ctx.db())
+        .find_map(|(_, deferred)| deferred.nodes.get(node_name).cloned())
+}
+
+// moves the deferred keys of two swapped databases along with their keys
+fn swap_deferred(first: c_int, second: c_int) {
+    let mut deferred = lock_cache(&DEFERRED);
+    let swapped = std::mem::take(&mut *deferred)
+        .into_iter()
+        .map(|((db, name), d)| match db {
+            db if db == first => ((second, name), d),
+            db if db == second => ((first, name), d),
+            _ => ((db, name), d),
+        })
+        .collect();
+    *deferred = swapped;
+}
+
+// writes the deferred keys whose window has passed, every deferred key with a zero window,
+// e.g. before the dataset is saved
+fn write_due_nodes(ctx: &Context, window: Duration) {
+    let due = lock_cache(&DEFERRED)
+        .iter()
+        .filter(|(_, deferred)| deferred.since.elapsed() >= window)
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<CacheKey>>();
+    let selected = unsafe { raw::RedisModule_GetSelectedDb.unwrap()(ctx.ctx) };
+    for key in due {
+        unsafe { raw::RedisModule_SelectDb.unwrap()(ctx.ctx, key.0) };
+        if let Err(e) = write_deferred(ctx, &key.1) {
+            log_event(
+                ctx,
+                LogLevel::Warning,
+                "deferred_write_failed",
+                &[("index", &key.1), ("error", &format!("{:?}", e))],
+            );
+        }
+    }
+    unsafe { raw::RedisModule_SelectDb.unwrap()(ctx.ctx, selected) };
+}
+
+// writes the keys a failed change may have written from the reverted graph, which also
+// clears the journaled change
+fn restore_keys(
+    ctx: &dyn Backend,
+    index: &IndexT,
+    names: &[String],
+    change: &PendingChange,
+) -> Result<(), RedisError> {
+    let changed = names.iter().map(String::as_str).chain([change.node_name()]);
+    for name in changed {
+        match index.nodes.get(name) {
+            Some(node) => {
+                write_node(ctx, name, NodeRedis::try_from(node)?)?;
+            }
+            None => delete_node_keys(ctx, &[name.to_owned()])?,
+        }
+    }
+    update_index(ctx, &index.name, index, NodeChange::Reverted)
+}
+
+// collects the names of the nodes a graph operation changes instead of writing their keys
+#[derive(Default)]
+struct Changed(RefCell<BTreeSet<String>>);
+
+impl Changed {
+    fn up(&self, name: String, _node: Node<f32>) -> Result<(), HNSWError> {
+        self.0.borrow_mut().insert(name);
+        Ok(())
+    }
+
+    // values of the changed nodes still in the index, every node is converted before the
+    // first key is written
+    fn values(self, index: &IndexT) -> Result<Vec<(String, NodeRedis)>, RedisError> {
+        let mut values = Vec::new();
+        for name in self.0.into_inner() {
+            if let Some(node) = index.nodes.get(&name) {
+                let value = NodeRedis::try_from(node)?;
+                values.push((name, value));
+            }
+        }
+        Ok(values)
+    }
+}
+
+// change to the node set of an index persisted by update_index
+enum NodeChange<'a> {
+    Added(&'a str),
+    Removed(&'a str, &'a [String]), // the journaled node and the others of a bulk delete
+    Reverted,                       // a failed change may have removed any of the nodes
+    Unchanged,
+}
+
+// persists the index in place, only the changed node is written instead of the whole node set
+fn update_index(
+    ctx: &dyn Backend,
+    index_name: &str,
+    index: &IndexT,
+    change: NodeChange,
+) -> Result<(), RedisError> {
+    let updated = ctx.index_mut(index_name, |value| {
+        value.update_params(index)?;
+        match change {
+            NodeChange::Added(node_name) => {
+                let id = node_of(index, node_name)?.id()?;
+                value.nodes.insert(node_name.to_owned(), id);
+            }
+            NodeChange::Removed(node_name, others) => {
+                value.nodes.remove(node_name);
+                for name in others {
+                    value.nodes.remove(name);
+                }
+            }
+            NodeChange::Reverted => {
+                value.nodes = index
+                    .nodes
+                    .iter()
+                    .map(|(name, node)| Ok((name.clone(), node.id()?)))
+                    .collect::<Result<HashMap<String, u32>, HNSWError>>()?;
+            }
+            NodeChange::Unchanged => (),
+        }
+        Ok::<(), RedisError>(())
+    })?;
+    match updated {
+        Some(res) => res,
+        None => Err(RedisError::String(format!(
+            "Index: {} does not exist",
+            index_name
+        ))),
+    }
+}
+
+// arguments of hnsw.node.add, parsed without touching the keyspace
+struct AddNodeArgs {
+    index_suffix: String,
+    node_suffix: String,
+    data: Vec<f32>,
+    field_data: Vec<(String, Vec<f32>)>, // data of each FIELD
+    meta: NodeMeta,
+    level: Option<usize>, // random unless given
+}
+
+fn parse_add_node(args: Vec<String>) -> Result<AddNodeArgs, RedisError> {
+    let mut args = args;
+    let field_data = take_field_data(&mut args)?;
+    let location = match take_values(&mut args, 3, "location", 2)? {
+        Some(v) => Some(Location::new(v[0], v[1])?),
+        None => None,
+    };
+    let mut parsed = ADD_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+    let data = vector_arg("DATA", take_arg(&mut parsed, "data")?.as_f64vec()?)?;
+    let meta = NodeMeta {
+        tags: Some(hnsw::parse_tags(
+            &take_arg(&mut parsed, "tags")?.as_string()?,
+        )?),
+        attrs: Some(hnsw::parse_attrs(
+            &take_arg(&mut parsed, "attrs")?.as_string()?,
+        )?),
+        location,
+    };
+    let level = match take_arg(&mut parsed, "level")?.as_i64()? {
+        -1 => None,
+        l if l >= 0 => Some(l as usize),
+        l => return Err(format!("Invalid LEVEL: {}", l).into()),
+    };
+
+    Ok(AddNodeArgs {
+        index_suffix,
+        node_suffix,
+        data,
+        field_data,
+        meta,
+        level,
+    })
+}
+
+fn add_node(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    add_node_on(ctx, parse_add_node(args)?)
+}
+
+fn add_node_on(ctx: &dyn Backend, args: AddNodeArgs) -> RedisResult {
+    check_writable(ctx)?;
+
+    let AddNodeArgs {
+        index_suffix,
+        node_suffix,
+        data,
+        field_data,
+        meta,
+        level,
+    } = args;
+    let index_name = index_key_of(ctx, &index_suffix);
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if index.fields.is_empty() {
+        if !field_data.is_empty() {
+            return Err(format!("Index: {} has no FIELDs", &index_name).into());
+        }
+        let target_name = node_index_name(&index, &node_suffix);
+        drop(index);
+        add_node_internal(ctx, &target_name, &node_suffix, &data, &meta, level)?;
+        return Ok("OK".into());
+    }
+
+    if field_data.is_empty() || !data.is_empty() {
+        return Err(format!("Index: {} requires FIELD {{name}} DATA", &index_name).into());
+    }
+    // validate all fields before modifying any sub-index
+    let mut sub_names = Vec::with_capacity(field_data.len());
+    for (field, data) in &field_data {
+        let sub_name = field_index_name(&index, field)?;
+        let sub_index = load_index_mut(ctx, &sub_name)?;
+        let sub_index = read_index(&sub_index)?;
+        if data.len() % sub_index.data_dim != 0 {
+            return Err(format!(
+                "data dimension: {} does not match FIELD: {}",
+                data.len(),
+                field
+            )
+            .into());
+        }
+        sub_names.push(sub_name);
+    }
+    drop(index);
+    // every field is added in memory before any key is written
+    let subs = sub_names
+        .iter()
+        .map(|name| load_index_mut(ctx, name))
+        .collect::<Result<Vec<IndexArc>, RedisError>>()?;
+    let mut prepared = Vec::with_capacity(subs.len());
+    for (sub, (_, data)) in subs.iter().zip(&field_data) {
+        prepared.push(prepare_add(ctx, sub, &node_suffix, data, &meta, level)?);
+    }
+    for change in prepared {
+        change.apply(ctx)?;
+    }
+
+    Ok("OK".into())
+}
+
+// tags, attributes and location given to hnsw.node.add and hnsw.node.update, the ones not
+// given are left as they are
+#[derive(Default)]
+struct NodeMeta {
+    tags: Option<Vec<String>>,
+    attrs: Option<BTreeMap<String, f64>>,
+    location: Option<Location>,
+}
+
+impl NodeMeta {
+    fn apply(&self, index: &mut IndexT, node_name: &str) -> Result<(), HNSWError> {
+        if let Some(tags) = &self.tags {
+            index.tag_node(node_name, tags.clone())?;
+        }
+        if let Some(attrs) = &self.attrs {
+            index.set_attrs(node_name, attrs.clone())?;
+        }
+        match self.location {
+            Some(location) => index.set_location(node_name, Some(location)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn add_node_internal(
+    ctx: &dyn Backend,
+    index_name: &str,
+    node_suffix: &str,
+    data: &[f32],
+    meta: &NodeMeta,
+    level: Option<usize>,
+) -> Result<(), RedisError> {
+    let index = load_index_mut(ctx, index_name)?;
+    let prepared = prepare_add(ctx, &index, node_suffix, data, meta, level)?;
+    prepared.apply(ctx)
+}
+
+fn prepare_add<'a>(
+    ctx: &'a dyn Backend,
+    index: &'a IndexArc,
+    node_suffix: &str,
+    data: &[f32],
+    meta: &NodeMeta,
+    level: Option<usize>,
+) -> Result<Prepared<'a>, RedisError> {
+    let mut index = write_index(index)?;
+    let node_name = node_key(&index.name, node_suffix);
+
+    index.can_add(&node_name, data)?;
+    check_free_key(ctx, &node_name)?;
+    let journal = Journal::new(ctx, &index.name);
+    let changed = Changed::default();
+    index.begin_undo();
+
+    log_event(
+        ctx,
+        LogLevel::Debug,
+        "node_added",
+        &[("index", &index.name), ("node", &node_name)],
+    );
+    index
+        .add_node_at(&node_name, data, level, |name, node| changed.up(name, node))
+        .map_err(|e| e.error_string())?;
+    if index.node_aliases.contains_key(&node_name) {
+        return Ok(alias_change(index, journal, node_name));
+    }
+    meta.apply(&mut index, &node_name)?;
+    changed.0.borrow_mut().insert(node_name.clone());
+
+    Ok(Prepared {
+        nodes: changed.values(&index)?,
+        removed: Vec::new(),
+        undo: index.end_undo(),
+        index,
+        change: PendingChange::Add(node_name),
+        journal,
+    })
+}
+
+fn add_node_from(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = ADD_NODE_FROM_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+    let source = take_arg(&mut parsed, "key")?.as_string()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let path = take_arg(&mut parsed, "path")?.as_string()?;
+    let format = take_arg(&mut parsed, "format")?.as_string()?;
+
+    let index_name = index_key_of(ctx, &index_suffix);
+
+    let data = match (field.is_empty(), path.is_empty()) {
+        (false, true) => {
+            let format = match format.as_str() {
+                "" => VectorFormat::Fp32Blob,
+                f => VectorFormat::parse(f)?,
+            };
+            let bytes = read_hash_field(ctx, &source, &field)?
+                .ok_or_else(|| format!("Field: {} of key: {} does not exist", field, source))?;
+            format.decode(&bytes)?
+        }
+        (true, false) => {
+            let format = match format.as_str() {
+                "" => VectorFormat::Text,
+                f => VectorFormat::parse(f)?,
+            };
+            let bytes = read_json_path(ctx, &source, &path)?;
+            format.decode(&bytes)?
+        }
+        _ => return Err("Expected either FIELD {field} or PATH {path}".into()),
+    };
+    let meta = NodeMeta {
+        tags: Some(hnsw::parse_tags(
+            &take_arg(&mut parsed, "tags")?.as_string()?,
+        )?),
+        attrs: Some(hnsw::parse_attrs(
+            &take_arg(&mut parsed, "attrs")?.as_string()?,
+        )?),
+        location: None,
+    };
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if !index.fields.is_empty() {
+        return Err(format!("Index: {} requires FIELD {{name}} DATA", &index_name).into());
+    }
+    let target_name = node_index_name(&index, &node_suffix);
+    drop(index);
+    add_node_internal(ctx, &target_name, &node_suffix, &data, &meta, None)?;
+
+    Ok("OK".into())
+}
+
+// raw bytes of a hash field, blobs need not be valid UTF-8. None if the key or field is missing
+fn read_hash_field(ctx: &Context, key: &str, field: &str) -> Result<Option<Vec<u8>>, RedisError> {
+    let value = match ctx.open_key(key).hash_get(field)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let mut len = 0;
+    let bytes = unsafe {
+        let ptr = raw::RedisModule_StringPtrLen.unwrap()(value.inner, &mut len);
+        std::slice::from_raw_parts(ptr as *const u8, len).to_vec()
+    };
+    Ok(Some(bytes))
+}
+
+// requires the RedisJSON module
+fn read_json_path(ctx: &Context, key: &str, path: &str) -> Result<Vec<u8>, RedisError> {
+    match ctx.call("JSON.GET", &[key, path])? {
+        RedisValue::SimpleString(s) | RedisValue::BulkString(s) => Ok(s.into_bytes()),
+        _ => Err(format!("Path: {} of key: {} does not exist", path, key).into()),
+    }
+}
+
+fn set_enterpoint(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = SET_ENTERPOINT_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    let target_name = match (index.fields.is_empty(), field.is_empty()) {
+        (true, true) => node_index_name(&index, &node_suffix),
+        (false, false) => field_index_name(&index, &field)?,
+        (true, false) => return Err(format!("Index: {} has no FIELDs", &index_name).into()),
+        (false, true) => {
+            return Err(format!("Index: {} requires FIELD {{name}}", &index_name).into())
+        }
+    };
+    drop(index);
+
+    let node_name = node_key(&target_name, &node_suffix);
+    let index = load_index_mut(ctx, &target_name)?;
+    let mut index = write_index(&index)?;
+    index.set_enterpoint(&node_name)?;
+    update_index(ctx, &target_name, &index, NodeChange::Unchanged)?;
+
+    Ok("OK".into())
+}
+
+fn follow_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = FOLLOW_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let pattern = take_arg(&mut parsed, "pattern")?.as_string()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let format = take_arg(&mut parsed, "format")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    if pattern.is_empty() || field.is_empty() {
+        return Err("PATTERN and FIELD must not be empty".into());
+    }
+    VectorFormat::parse(&format)?;
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if !index.fields.is_empty() {
+        return Err(format!("Index: {} with FIELDs can't follow keys", &index_name).into());
+    }
+    drop(index);
+
+    let rule = FollowRule {
+        pattern,
+        field,
+        format: format.to_ascii_lowercase(),
+    };
+    set_follow(ctx, &index_name, Some(rule))?;
+
+    Ok("OK".into())
+}
+
+fn unfollow_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = UNFOLLOW_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let name_suffix = take_arg(&mut parsed, "name")?.as_string()?;
+    let index_name = index_key_of(ctx, &name_suffix);
+
+    set_follow(ctx, &index_name, None)?;
+
+    Ok("OK".into())
+}
+
+fn set_follow(
+    ctx: &Context,
+    index_name: &str,
+    follow: Option<FollowRule>,
+) -> Result<(), RedisError> {
+    let key = ctx.open_key_writable(index_name);
+    match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
+        Some(value) => value.set_follow(follow),
+        None => return Err(format!("Index: {} does not exist", index_name).into()),
+    }
+    Ok(())
+}
+
+// true if an index follows the key, node keys are never followed
+fn may_be_followed(key: &str) -> bool {
+    !is_module_key(key) && !types::followers(key).is_empty()
+}
+
+// adds, updates or deletes the node of a changed key in every index of the current
+// database following it. errors are logged, the command changing the key succeeded already
+fn sync_followed(ctx: &Context, key: &str) {
+    // the node keys the sync writes aren't replicated or appended to the aof, replicas and
+    // aof loads sync from the hashes they receive
+    for entry in types::followers(key) {
+        // the registry spans all databases
+        let in_db = ctx
+            .open_key(&entry.key)
+            .get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)
+            .ok()
+            .flatten()
+            .is_some_and(|ir| ir.registry_id == entry.id);
+        let rule = match (&entry.follow, in_db) {
+            (Some(rule), true) => rule,
+            _ => continue,
+        };
+        if let Err(e) = sync_followed_node(ctx, &entry.key, key, rule) {
+            log_event(
+                ctx,
+                LogLevel::Warning,
+                "follow_sync_failed",
+                &[
+                    ("index", &entry.key),
+                    ("key", &key),
+                    ("error", &format!("{:?}", e)),
+                ],
+            );
+        }
+    }
+}
+
+fn sync_followed_node(
+    ctx: &Context,
+    index_name: &str,
+    key: &str,
+    rule: &FollowRule,
+) -> Result<(), RedisError> {
+    let index = load_index_mut(ctx, index_name)?;
+    let index = read_index(&index)?;
+    let target_name = node_index_name(&index, key);
+    drop(index);
+    let node_name = node_key(&target_name, key);
+    let target = load_index_mut(ctx, &target_name)?;
+    let exists = read_index(&target)?.nodes.contains_key(&node_name);
+
+    // the node follows the field, whichever way the key changed. an update keeps the tags,
+    // attributes and location of the node
+    let meta = NodeMeta::default();
+    match read_hash_field(ctx, key, &rule.field)? {
+        Some(bytes) => {
+            let data = VectorFormat::parse(&rule.format)?.decode(&bytes)?;
+            if exists {
+                replace_node_internal(ctx, &target_name, key, &data, &meta, None)?;
+            } else {
+                add_node_internal(ctx, &target_name, key, &data, &meta, None)?;
+            }
+        }
+        None if exists => delete_node_internal(ctx, &target_name, key)?,
+        None => (),
+    }
+    Ok(())
+}
+
+fn replace_node(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut args = args;
+    let field_data = take_field_data(&mut args)?;
+    let location = match take_values(&mut args, 3, "location", 2)? {
+        Some(v) => Some(Location::new(v[0], v[1])?),
+        None => None,
+    };
+    // tags and attributes are only replaced when given
+    let tags = match take_strings(&mut args, 3, "tags", 1)? {
+        Some(v) => Some(hnsw::parse_tags(&v[0])?),
+        None => None,
+    };
+    let attrs = match take_strings(&mut args, 3, "attrs", 1)? {
+        Some(v) => Some(hnsw::parse_attrs(&v[0])?),
+        None => None,
+    };
+    let mut parsed = UPDATE_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+
+    let index_name = index_key_of(ctx, &index_suffix);
+
+    let data = vector_arg("DATA", take_arg(&mut parsed, "data")?.as_f64vec()?)?;
+    let meta = NodeMeta {
+        tags,
+        attrs,
+        location,
+    };
+    // versions start at 1, 0 skips the check
+    let if_version = match take_arg(&mut parsed, "ifversion")?.as_u64()? {
+        0 => None,
+        v => Some(v),
+    };
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if index.fields.is_empty() {
+        if !field_data.is_empty() {
+            return Err(format!("Index: {} has no FIELDs", &index_name).into());
+        }
+        let target_name = node_index_name(&index, &node_suffix);
+        drop(index);
+        let version =
+            replace_node_internal(ctx, &target_name, &node_suffix, &data, &meta, if_version)?;
+        return Ok((version as usize).into());
+    }
+
+    if field_data.is_empty() || !data.is_empty() {
+        return Err(format!("Index: {} requires FIELD {{name}} DATA", &index_name).into());
+    }
+    // the version of a node with fields is the highest of its field nodes,
+    // validate all fields before modifying any sub-index
+    let mut sub_names = Vec::with_capacity(field_data.len());
+    let mut current = 0;
+    for (field, data) in &field_data {
+        let sub_name = field_index_name(&index, field)?;
+        let node_name = node_key(&sub_name, &node_suffix);
+        let sub_index = load_index_mut(ctx, &sub_name)?;
+        let sub_index = read_index(&sub_index)?;
+        sub_index.can_update(&node_name, data)?;
+        current = current.max(node_of(&sub_index, &node_name)?.read()?.version);
+        sub_names.push(sub_name);
+    }
+    check_version(&node_suffix, current, if_version)?;
+    drop(index);
+    // every field is updated in memory before any key is written
+    let subs = sub_names
+        .iter()
+        .map(|name| load_index_mut(ctx, name))
+        .collect::<Result<Vec<IndexArc>, RedisError>>()?;
+    let mut prepared = Vec::with_capacity(subs.len());
+    for (sub, (_, data)) in subs.iter().zip(&field_data) {
+        prepared.push(prepare_replace(ctx, sub, &node_suffix, data, &meta, None)?);
+    }
+    let mut version = 0;
+    for change in prepared {
+        version = version.max(change.version()?);
+        change.apply(ctx)?;
+    }
+
+    Ok((version as usize).into())
+}
+
+// replaces the data and metadata of a node, returns its new version
+fn replace_node_internal(
+    ctx: &Context,
+    index_name: &str,
+    node_suffix: &str,
+    data: &[f32],
+    meta: &NodeMeta,
+    if_version: Option<u64>,
+) -> Result<u64, RedisError> {
+    let index = load_index_mut(ctx, index_name)?;
+    let prepared = prepare_replace(ctx, &index, node_suffix, data, meta, if_version)?;
+    let version = prepared.version()?;
+    prepared.apply(ctx)?;
+    Ok(version)
+}
+
+fn prepare_replace<'a>(
+    ctx: &'a Context,
+    index: &'a IndexArc,
+    node_suffix: &str,
+    data: &[f32],
+    meta: &NodeMeta,
+    if_version: Option<u64>,
+) -> Result<Prepared<'a>, RedisError> {
+    let mut index = write_index(index)?;
+    let node_name = node_key(&index.name, node_suffix);
+
+    index.can_update(&node_name, data)?;
+    let node = node_of(&index, &node_name)?;
+    check_version(&node_name, node.read()?.version, if_version)?;
+    check_unshared(&node_name, node)?;
+    let journal = Journal::new(ctx, &index.name);
+    let changed = Changed::default();
+    index.begin_undo();
+
+    log_event(
+        ctx,
+        LogLevel::Debug,
+        "node_updated",
+        &[("index", &index.name), ("node", &node_name)],
+    );
+    index
+        .update_node(&node_name, data, |name, node| changed.up(name, node))
+        .map_err(|e| e.error_string())?;
+    meta.apply(&mut index, &node_name)?;
+    changed.0.borrow_mut().insert(node_name.clone());
+
+    Ok(Prepared {
+        nodes: changed.values(&index)?,
+        removed: Vec::new(),
+        undo: index.end_undo(),
+        index,
+        change: PendingChange::Update(node_name),
+        journal,
+    })
+}
+
+// optimistic concurrency check of IFVERSION
+fn check_version(node_name: &str, version: u64, expected: Option<u64>) -> Result<(), RedisError> {
+    match expected {
+        Some(e) if e != version => {
+            Err(format!("Node: {} is at version {}, not {}", node_name, version, e).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+// nodes referenced by a running search can't be removed from the graph. the node map and
+// the slot of its id hold a reference each
+fn check_unshared(node_name: &str, node: &Node<f32>) -> Result<(), RedisError> {
+    if Arc::strong_count(&node.0) > 2 {
+        return Err(format!(
+            "{} is being accessed, unable to delete. Try again later",
+            node_name
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn delete_node(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = DEL_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+    delete_node_on(ctx, &index_suffix, &node_suffix)
+}
+
+fn delete_node_on(ctx: &dyn Backend, index_suffix: &str, node_suffix: &str) -> RedisResult {
+    check_writable(ctx)?;
+
+    let index_name = index_key_of(ctx, index_suffix);
+
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    let fields = index.fields.clone();
+    let target_name = node_index_name(&index, node_suffix);
+    drop(index);
+    if fields.is_empty() {
+        delete_node_internal(ctx, &target_name, node_suffix)?;
+        return Ok(1_usize.into());
+    }
+
+    // remove the node from every field it was added to, in memory before any key is written
+    let mut subs = Vec::with_capacity(fields.len());
+    for field in &fields {
+        let sub_name = format!("{}.{}", index_name, field);
+        let sub_index = load_index_mut(ctx, &sub_name)?;
+        let exists = read_index(&sub_index)?
+            .nodes
+            .contains_key(&node_key(&sub_name, node_suffix));
+        if exists {
+            subs.push(sub_index);
+        }
+    }
+    if subs.is_empty() {
+        return Err(format!("Node: {} does not exist", node_suffix).into());
+    }
+    let mut prepared = Vec::with_capacity(subs.len());
+    for sub in &subs {
+        prepared.push(prepare_delete(ctx, sub, node_suffix)?);
+    }
+    for change in prepared {
+        change.apply(ctx)?;
+    }
+
+    Ok(1_usize.into())
+}
+
+fn delete_node_internal(
+    ctx: &dyn Backend,
+    index_name: &str,
+    node_suffix: &str,
+) -> Result<(), RedisError> {
+    let index = load_index_mut(ctx, index_name)?;
+    let prepared = prepare_delete(ctx, &index, node_suffix)?;
+    prepared.apply(ctx)
+}
+
+fn prepare_delete<'a>(
+    ctx: &'a dyn Backend,
+    index: &'a IndexArc,
+    node_suffix: &str,
+) -> Result<Prepared<'a>, RedisError> {
+    let mut index = write_index(index)?;
+    let node_name = node_key(&index.name, node_suffix);
+
+    index.can_delete(&node_name)?;
+    log_event(
+        ctx,
+        LogLevel::Debug,
+        "node_deleted",
+        &[("index", &index.name), ("node", &node_name)],
+    );
+    if index.node_aliases.contains_key(&node_name) {
+        index.delete_node(&node_name, |_, _| Ok(()))?;
+        let journal = Journal::new(ctx, &index.name);
+        return Ok(alias_change(index, journal, node_name));
+    }
+    let node = node_of(&index, &node_name)?;
+    check_unshared(&node_name, node)?;
+    let journal = Journal::new(ctx, &index.name);
+    let changed = Changed::default();
+    index.begin_undo();
+
+    index
+        .delete_node(&node_name, |name, node| changed.up(name, node))
+        .map_err(|e| e.error_string())?;
+
+    Ok(Prepared {
+        nodes: changed.values(&index)?,
+        removed: Vec::new(),
+        undo: index.end_undo(),
+        index,
+        change: PendingChange::Delete(node_name),
+        journal,
+    })
+}
+
+fn delete_nodes(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    // the node names run to the last argument
+    let mut args = args;
+    let node_suffixes = args.split_off(min(2, args.len()));
+    let mut parsed = MDEL_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    if node_suffixes.is_empty() {
+        return Err("Missing node names".into());
+    }
+    delete_nodes_on(ctx, &index_suffix, &node_suffixes)
+}
+
+fn delete_pattern(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = DELPATTERN_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let pattern = take_arg(&mut parsed, "match")?.as_string()?;
+    delete_pattern_on(ctx, &index_suffix, &pattern)
+}
+
+// deletes the nodes of every graph of the index whose names match the glob pattern
+fn delete_pattern_on(ctx: &dyn Backend, index_suffix: &str, pattern: &str) -> RedisResult {
+    check_writable(ctx)?;
+
+    let index_name = index_key_of(ctx, index_suffix);
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    let graphs = match index.fields.is_empty() {
+        true => searched_index_names(&index_name, index.shards),
+        false => index
+            .fields
+            .iter()
+            .map(|field| format!("{}.{}", index_name, field))
+            .collect(),
+    };
+    drop(index);
+
+    let mut matched = BTreeSet::new();
+    for graph_name in &graphs {
+        let graph = load_index_mut(ctx, graph_name)?;
+        let graph = read_index(&graph)?;
+        let names = graph.nodes.keys().chain(graph.node_aliases.keys());
+        matched.extend(
+            names
+                .map(|name| graph.node_suffix(name))
+                .filter(|suffix| glob_match(pattern, suffix))
+                .map(str::to_owned),
+        );
+    }
+    let matched = matched.into_iter().collect::<Vec<String>>();
+    delete_nodes_on(ctx, index_suffix, &matched)
+}
+
+// deletes the nodes that exist and replies their number. the nodes of each graph, a shard or
+// every FIELD sub-index, are removed under one lock and its index key written once
+fn delete_nodes_on(ctx: &dyn Backend, index_suffix: &str, node_suffixes: &[String]) -> RedisResult {
+    check_writable(ctx)?;
+
+    let index_name = index_key_of(ctx, index_suffix);
+    let index = load_index_mut(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    let mut targets: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if index.fields.is_empty() {
+        for suffix in node_suffixes {
+            targets
+                .entry(node_index_name(&index, suffix))
+                .or_default()
+                .push(suffix.clone());
+        }
+    } else {
+        for field in &index.fields {
+            let sub_name = format!("{}.{}", index_name, field);
+            targets.insert(sub_name, node_suffixes.to_vec());
+        }
+    }
+    drop(index);
+
+    // every graph is changed in memory before any key is written
+    let subs = targets
+        .keys()
+        .map(|name| load_index_mut(ctx, name))
+        .collect::<Result<Vec<IndexArc>, RedisError>>()?;
+    let mut prepared = Vec::with_capacity(subs.len());
+    let mut deleted = BTreeSet::new();
+    for (sub, suffixes) in subs.iter().zip(targets.values()) {
+        if let Some((change, names)) = prepare_delete_many(ctx, sub, suffixes)? {
+            deleted.extend(names);
+            prepared.push(change);
+        }
+    }
+    for change in prepared {
+        change.apply(ctx)?;
+    }
+
+    Ok(deleted.len().into())
+}
+
+// removes the nodes of one graph the node names resolve to, the neighbors they change are
+// repaired and collected once for all of them. aliases go first since deleting a node also
+// deletes its aliases, the first node is journaled and the others removed with it
+fn prepare_delete_many<'a>(
+    ctx: &'a dyn Backend,
+    index: &'a IndexArc,
+    node_suffixes: &[String],
+) -> Result<Option<(Prepared<'a>, Vec<String>)>, RedisError> {
+    let mut index = write_index(index)?;
+    let mut aliases = BTreeSet::new();
+    let mut nodes = BTreeSet::new();
+    for suffix in node_suffixes {
+        let node_name = node_key(&index.name, suffix);
+        if index.node_aliases.contains_key(&node_name) {
+            aliases.insert(node_name);
+        } else if let Some(node) = index.nodes.get(&node_name) {
+            check_unshared(&node_name, node)?;
+            nodes.insert(node_name);
+        }
+    }
+    let names = aliases.into_iter().chain(nodes.iter().cloned());
+    let names = names.collect::<Vec<String>>();
+    let first = match names.first() {
+        Some(name) => name.clone(),
+        None => return Ok(None),
+    };
+    index.can_delete(&first)?;
+    log_event(
+        ctx,
+        LogLevel::Debug,
+        "nodes_deleted",
+        &[("index", &index.name), ("nodes", &names.len())],
+    );
+    let journal = Journal::new(ctx, &index.name);
+    let changed = Changed::default();
+    index.begin_undo();
+
+    for node_name in &names {
+        if let Err(e) = index.delete_node(node_name, |name, node| changed.up(name, node)) {
+            // no key was written yet, the nodes deleted so far are put back
+            if let Some(undo) = index.end_undo() {
+                if index.undo(undo).is_ok() {
+                    journal.commit();
+                }
+            }
+            return Err(e.error_string().into());
+        }
+    }
+    let suffixes = names
+        .iter()
+        .map(|name| index.node_suffix(name).to_owned())
+        .collect::<Vec<String>>();
+    let mut nodes = nodes.into_iter();
+    let change = match nodes.next() {
+        Some(node_name) => PendingChange::Delete(node_name),
+        // deleted aliases only change the index key
+        None => PendingChange::Update(first),
+    };
+
+    let prepared = Prepared {
+        nodes: changed.values(&index)?,
+        removed: nodes.collect(),
+        undo: index.end_undo(),
+        index,
+        change,
+        journal,
+    };
+    Ok(Some((prepared, suffixes)))
+}
+
+// an alias added or deleted only changes the index key, journaled as an update of the
+// alias. a failed write evicts the index, which reloads without the change
+fn alias_change<'a>(
+    mut index: RwLockWriteGuard<'a, IndexT>,
+    journal: Journal<'a>,
+    alias: String,
+) -> Prepared<'a> {
+    index.end_undo();
+    Prepared {
+        index,
+        change: PendingChange::Update(alias),
+        nodes: Vec::new(),
+        removed: Vec::new(),
+        undo: None,
+        journal,
+    }
+}
+
+fn delete_node_redis(ctx: &dyn Backend, node_name: &str) -> Result<(), RedisError> {
+    match ctx.node(node_name, |_| ())? {
+        Some(_) => ctx.delete(node_name)?,
+        None => {
+            return Err(RedisError::String(format!(
+                "Node: {} does not exist",
+                node_name
+            )));
+        }
+    };
+
+    Ok(())
+}
+
+// checks the node aliases of the index and the node keys the node would be stored in, without
+// loading the index or the node
+fn exists_node(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = EXISTS_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+    exists_node_on(ctx, &index_suffix, &node_suffix)
+}
+
+fn exists_node_on(ctx: &dyn Backend, index_suffix: &str, node_suffix: &str) -> RedisResult {
+    let index_name = index_key_of(ctx, index_suffix);
+    let alias = node_key(&index_name, node_suffix);
+    if let Ok(Some(true)) = ctx.index(&index_name, |ir| ir.node_aliases.contains_key(&alias)) {
+        return Ok(1_usize.into());
+    }
+    let node_names = ctx.index(&index_name, |ir| {
+        if ir.shards > 0 {
+            let shard_name = shard_index_name(&index_name, shard_of(node_suffix, ir.shards));
+            vec![node_key(&shard_name, node_suffix)]
+        } else if !ir.fields.is_empty() {
+            ir.fields
+                .iter()
+                .map(|f| node_key(&format!("{}.{}", index_name, f), node_suffix))
+                .collect()
+        } else {
+            vec![node_key(&index_name, node_suffix)]
+        }
+    });
+    let node_names = match node_names {
+        Ok(Some(node_names)) => node_names,
+        _ => return Ok(0_usize.into()),
+    };
+    for node_name in &node_names {
+        if ctx.node(node_name, |_| ())?.is_some() {
+            return Ok(1_usize.into());
+        }
+    }
+
+    Ok(0_usize.into())
+}
+
+fn get_node(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+
+    let mut parsed = GET_NODE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let node_suffix = take_arg(&mut parsed, "node")?.as_string()?;
+    let format = take_arg(&mut parsed, "format")?.as_string()?;
+    let blob = match format.to_ascii_lowercase().as_str() {
+        "array" => false,
+        "blob" => true,
+        _ => return Err(format!("Invalid FORMAT: {}, expected array or blob", format).into()),
     };
+    get_node_on(ctx, &index_suffix, &node_suffix, blob)
+}
+
+fn get_node_on(
+    ctx: &dyn Backend,
+    index_suffix: &str,
+    node_suffix: &str,
+    blob: bool,
+) -> RedisResult {
+    let index_name = index_key_of(ctx, index_suffix);
+    let node_name = node_key(&index_name, node_suffix);
+
+    if let Some(value) = deferred_node(ctx, &node_name) {
+        return node_reply(ctx, &index_name, &value, blob);
+    }
+    if let Some(reply) = ctx.node(&node_name, |value| {
+        node_reply(ctx, &index_name, value, blob)
+    })? {
+        return reply;
+    }
+
+    let index = load_index(ctx, &index_name)?;
+    let index = read_index(&index)?;
+    if let Some(existing) = index.node_aliases.get(&node_name).cloned() {
+        drop(index);
+        return match ctx.node(&existing, |value| node_reply(ctx, &index_name, value, blob))? {
+            Some(reply) => reply,
+            None => Err(format!("Node: {} does not exist", &existing).into()),
+        };
+    }
+    if index.shards > 0 {
+        let shard_name = node_index_name(&index, node_suffix);
+        let node_name = node_key(&shard_name, node_suffix);
+        return match ctx.node(&node_name, |value| {
+            node_reply(ctx, &shard_name, value, blob)
+        })? {
+            Some(reply) => reply,
+            None => Err(format!("Node: {} does not exist", &node_name).into()),
+        };
+    }
+
+    // nodes of indexes with fields are replied per field
+    let mut reply: Vec<RedisValue> = Vec::new();
+    for field in &index.fields {
+        let field_index = format!("{}.{}", index_name, field);
+        let node_name = node_key(&field_index, node_suffix);
+        if let Some(value) = ctx.node(&node_name, |value| {
+            node_reply(ctx, &field_index, value, blob)
+        })? {
+            reply.push(field.as_str().into());
+            reply.push(value?);
+        }
+    }
+    if reply.is_empty() {
+        return Err(format!("Node: {} does not exist", &node_name).into());
+    }
+
+    Ok(reply.into())
+}
+
+// neighbors are stored by id, the index of the node names them
+fn node_reply(ctx: &dyn Backend, index_name: &str, node: &NodeRedis, blob: bool) -> RedisResult {
+    let index = load_index(ctx, index_name)?;
+    let index = read_index(&index)?;
+    Ok(node.reply(blob, |id| Ok(index.node_at(id)?.read()?.name.clone()))?)
+}
+
+// persists a node changed by an index operation, errors fail the operation
+fn update_node(ctx: &dyn Backend, name: &str, node: &Node<f32>) -> Result<(), HNSWError> {
+    let nr = NodeRedis::try_from(node)?;
+    write_node(ctx, name, nr).map_err(|e| format!("{:?}", e))?;
+    Ok(())
+}
+
+fn write_node(ctx: &dyn Backend, key: &str, node: NodeRedis) -> RedisResult {
+    // an existing value is updated in place, the node is only stored in a new key
+    let mut node = Some(node);
+    ctx.node_mut(key, |value| {
+        if let Some(node) = node.take() {
+            value.data = node.data;
+            value.vectors = node.vectors;
+            value.norms = node.norms;
+            value.level = node.level;
+            value.tags = node.tags;
+            value.attrs = node.attrs;
+            value.location = node.location;
+            value.version = node.version;
+            value.neighbors = node.neighbors;
+            value.neighbor_names = node.neighbor_names;
+        }
+    })?;
+    if let Some(node) = node {
+        ctx.set_node(key, node)?;
+    }
+    Ok(key.into())
+}
 
-    #[rediscmd_doc]
-    static DEL_NODE_CMD: Command = command!{
-        name: "hnsw.node.del",
-        desc: "Delete a node from the index.",
-        args: [
-            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
-            ["node", "name of the node", ArgType::Arg, String, Collection::Unit, None],
-        ],
+// arguments of hnsw.search, parsed without touching the keyspace
+struct SearchArgs {
+    index_suffix: String,
+    k: usize,
+    data: Vec<f32>,
+    centroid: Option<Vec<String>>, // nodes whose mean is the query
+    not_near: Vec<(Reference, f32)>,
+    params: SearchParams, // NOTNEAR references are resolved once the index is known
+    field: String,
+    cursor: bool,
+    count: usize,
+    parallel: bool,
+    full_names: bool,
+    with_stats: bool,
+    grouping: Option<Grouping>,
+    dedupe: Option<Dedupe>,
+    format: ReplyFormat,
+    with_vectors: bool,
+    blob: bool, // vectors as f32 blobs instead of arrays
+}
+
+fn parse_search(args: Vec<String>) -> Result<SearchArgs, RedisError> {
+    let mut args = args;
+    let centroid = take_node_names(&mut args, "centroid")?;
+    let within = match take_values(&mut args, 2, "within", 3)? {
+        Some(v) => Some(GeoFence::new(Location::new(v[0], v[1])?, v[2])?),
+        None => None,
     };
+    let not_near = take_not_near(&mut args)?;
+    let group_by = take_strings(&mut args, 2, "groupby", 2)?;
+    let mut parsed = SEARCH_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    #[rediscmd_doc]
-    static SEARCH_CMD: Command = command!{
-        name: "hnsw.search",
-        desc: "Search the index for the K nearest elements to the query.",
-        args: [
-            ["index", "name of the index", ArgType::Arg, String, Collection::Unit, None],
-            [
-                "k",
-                "number of nearest neighbors to return",
-                ArgType::Kwarg, u64, Collection::Unit, Some(Box::new(5_u64))
-            ],
-            [
-                "query",
-                "Dimensionality followed by a space separated vector of data. Total entries must match `DIM` of index",
-                ArgType::Kwarg, f64, Collection::Vec, None
-            ],
-        ],
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let k = take_arg(&mut parsed, "k")?.as_u64()? as usize;
+    let data = vector_arg("QUERY", take_arg(&mut parsed, "query")?.as_f64vec()?)?;
+    let multivalue = match take_arg(&mut parsed, "multivalue")?
+        .as_string()?
+        .to_lowercase()
+        .as_str()
+    {
+        "max" => MultiValue::Max,
+        "avg" => MultiValue::Avg,
+        mv => return Err(format!("Unknown multivalue aggregation: {}", mv).into()),
+    };
+    let filter = match take_arg(&mut parsed, "filter")?.as_string()?.as_str() {
+        "" => None,
+        f => Some(Filter::parse(f)?),
+    };
+    let max_visits = take_arg(&mut parsed, "maxvisits")?.as_u64()? as usize;
+    let timeout = match take_arg(&mut parsed, "timeout")?.as_u64()? {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+    let entry = match take_arg(&mut parsed, "entry")?.as_string()? {
+        e if e.is_empty() => None,
+        e => Some(e),
+    };
+    let params = SearchParams {
+        multivalue,
+        filter,
+        within,
+        max_visits,
+        timeout,
+        entry,
+        not_near: Vec::new(),
+    };
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let cursor = take_arg(&mut parsed, "cursor")?.as_bool()?;
+    let count = take_arg(&mut parsed, "count")?.as_u64()? as usize;
+    if cursor && count == 0 {
+        return Err("COUNT must be positive".into());
+    }
+    let parallel = take_arg(&mut parsed, "parallel")?.as_bool()?;
+    let full_names = parse_names(&take_arg(&mut parsed, "names")?.as_string()?)?;
+    let with_stats = take_arg(&mut parsed, "withstats")?.as_bool()?;
+    let max_per_group = take_arg(&mut parsed, "maxpergroup")?.as_u64()? as usize;
+    let grouping = match group_by {
+        Some(g) => Some(Grouping::parse(&g[0], &g[1], max_per_group)?),
+        None => None,
+    };
+    let epsilon = take_arg(&mut parsed, "epsilon")?.as_f64()? as f32;
+    let dedupe = if take_arg(&mut parsed, "dedupe")?.as_bool()? {
+        Some(Dedupe::new(epsilon)?)
+    } else if epsilon != 0.0 {
+        return Err("EPSILON requires DEDUPE".into());
+    } else {
+        None
     };
+    let format = parse_format(&take_arg(&mut parsed, "format")?.as_string()?)?;
+    if cursor && format != ReplyFormat::Flat {
+        return Err("FORMAT can't be combined with CURSOR".into());
+    }
+    let with_vectors = take_arg(&mut parsed, "withvectors")?.as_bool()?;
+    let blob = match take_arg(&mut parsed, "vectorformat")?
+        .as_string()?
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "" | "array" => false,
+        "blob" => true,
+        f => return Err(format!("Invalid VECTORFORMAT: {}, expected array or blob", f).into()),
+    };
+    if blob && !with_vectors {
+        return Err("VECTORFORMAT requires WITHVECTORS".into());
+    }
+
+    Ok(SearchArgs {
+        index_suffix,
+        k,
+        data,
+        centroid,
+        not_near,
+        params,
+        field,
+        cursor,
+        count,
+        parallel,
+        full_names,
+        with_stats,
+        grouping,
+        dedupe,
+        format,
+        with_vectors,
+        blob,
+    })
 }
 
-fn new_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+// the reply and its format, reply::search_knn writes a map as one to resp3 clients
+fn search_knn(ctx: &Context, args: Vec<String>) -> Result<(RedisValue, ReplyFormat), RedisError> {
     ctx.auto_memory();
+    let args = parse_search(args)?;
+    let format = args.format;
+    Ok((search_knn_on(ctx, args)?, format))
+}
 
-    let mut parsed = NEW_INDEX_CMD.with(|cmd| cmd.parse_args(args))?;
-
-    let name_suffix = parsed.remove("name").unwrap().as_string()?;
-    let index_name = format!("{}.{}", PREFIX, name_suffix);
-    let data_dim = parsed.remove("dim").unwrap().as_u64()? as usize;
-    let m = parsed.remove("m").unwrap().as_u64()? as usize;
-    let ef_construction = parsed.remove("efcon").unwrap().as_u64()? as usize;
+fn search_knn_on(ctx: &dyn Backend, args: SearchArgs) -> RedisResult {
+    let SearchArgs {
+        index_suffix,
+        k,
+        data,
+        centroid,
+        not_near,
+        params,
+        field,
+        cursor,
+        count,
+        parallel,
+        full_names,
+        with_stats,
+        grouping,
+        dedupe,
+        format,
+        with_vectors,
+        blob,
+    } = args;
+    // the reply tells whether a budgeted search was cut short
+    let budgeted = params.max_visits > 0 || params.timeout.is_some();
 
-    // write to redis
-    let key = ctx.open_key_writable(&index_name);
-    match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
-        Some(_) => {
-            return Err(RedisError::String(format!(
-                "Index: {} already exists",
-                &index_name
-            )));
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    let data = match (centroid, data.is_empty()) {
+        (Some(nodes), true) => centroid_of(ctx, &index_name, shards, &nodes)?,
+        (None, false) => data,
+        _ => return Err("Exactly one of QUERY or CENTROID is required".into()),
+    };
+    let mut params = params;
+    for (reference, radius) in not_near {
+        params.not_near.push(match reference {
+            Reference::Vector(data) => NotNear {
+                data,
+                radius,
+                stored: false,
+            },
+            Reference::Node(node_suffix) => NotNear {
+                data: stored_data(ctx, &index_name, shards, &node_suffix)?,
+                radius,
+                stored: true,
+            },
+        });
+    }
+    let run = |k| run_search(ctx, &index_name, shards, &data, k, &params, parallel);
+    // duplicates are collapsed before grouping, so they don't use up the results of a group
+    let search = |k| match &dedupe {
+        Some(dedupe) => dedupe.search(k, run),
+        None => run(k),
+    };
+    let (res, stats) = match &grouping {
+        Some(grouping) => grouping.search(k, search)?,
+        None => search(k)?,
+    };
+    let mut results = result_names(&res, full_names);
+    if dedupe.is_some() {
+        for (sr, r) in results.iter_mut().zip(&res) {
+            let aliases = r
+                .aliases
+                .iter()
+                .map(|(name, key)| if full_names { key } else { name }.clone());
+            sr.aliases = Some(aliases.collect());
         }
-        None => {
-            // create index
-            let index = Index::new(
-                &index_name,
-                Box::new(hnsw::metrics::euclidean),
-                data_dim,
-                m,
-                ef_construction,
-            );
-            ctx.log_debug(format!("{:?}", index).as_str());
-            key.set_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE, index.clone().into())?;
-            // Add index to global hashmap
-            INDICES
-                .write()
-                .unwrap()
-                .insert(index_name, Arc::new(RwLock::new(index)));
+    }
+    if with_vectors {
+        // the vectors hnsw.node.get replies, at their original magnitude
+        for (sr, r) in results.iter_mut().zip(&res) {
+            sr.vector = ctx.node(&r.key, |node| node.original_data())?;
+            sr.blob = blob;
+        }
+    }
+    let reply = if cursor {
+        let results = results.into_iter().map(CursorItem::Result).collect();
+        let mut cursors = lock_cache(&CURSORS);
+        let id = cursors.insert(results, count);
+        cursors.read(id, None)?
+    } else {
+        formatted_reply(results, format)
+    };
+    let mut trailer: Vec<RedisValue> = Vec::new();
+    if budgeted {
+        trailer.push("truncated".into());
+        trailer.push((stats.truncated as usize).into());
+    }
+    if with_stats {
+        trailer.push("stats".into());
+        trailer.push(stats_reply(&stats));
+    }
+
+    Ok(append_reply(reply, trailer))
+}
+
+// appends key-value pairs describing the search to its reply
+fn append_reply(reply: RedisValue, trailer: Vec<RedisValue>) -> RedisValue {
+    match reply {
+        RedisValue::Array(mut items) => {
+            items.extend(trailer);
+            RedisValue::Array(items)
         }
+        reply => reply,
     }
+}
 
-    Ok("OK".into())
+// layers are listed top layer first, times are in microseconds
+fn stats_reply(stats: &SearchStats) -> RedisValue {
+    let layers = stats
+        .layer_time
+        .iter()
+        .rev()
+        .map(|(layer, time)| vec![*layer, time.as_micros() as usize].into())
+        .collect::<Vec<RedisValue>>();
+    vec![
+        "visited".into(),
+        stats.visited.into(),
+        "distances".into(),
+        stats.distances.into(),
+        "layers".into(),
+        RedisValue::Array(layers),
+    ]
+    .into()
 }
 
-fn get_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+fn search_composite(ctx: &Context, args: Vec<String>) -> RedisResult {
     ctx.auto_memory();
 
-    let mut parsed = GET_INDEX_CMD.with(|cmd| cmd.parse_args(args))?;
+    let mut args = args;
+    let nodes = take_weighted_nodes(&mut args)?;
+    let mut parsed = COMPOSITE_SEARCH_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    let name_suffix = parsed.remove("name").unwrap().as_string()?;
-    let index_name = format!("{}.{}", PREFIX, name_suffix);
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let k = take_arg(&mut parsed, "k")?.as_u64()? as usize;
+    let filter = match take_arg(&mut parsed, "filter")?.as_string()?.as_str() {
+        "" => None,
+        f => Some(Filter::parse(f)?),
+    };
+    let params = SearchParams {
+        filter,
+        ..Default::default()
+    };
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let parallel = take_arg(&mut parsed, "parallel")?.as_bool()?;
+    let full_names = parse_names(&take_arg(&mut parsed, "names")?.as_string()?)?;
 
-    let index = load_index(ctx, &index_name)?;
-    let index = index.try_read().map_err(|e| e.to_string())?;
-    ctx.log_debug(format!("Index: {:?}", index).as_str());
-    ctx.log_debug(format!("Layers: {:?}", index.layers.len()).as_str());
-    ctx.log_debug(format!("Nodes: {:?}", index.nodes.len()).as_str());
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
 
-    let index_redis: IndexRedis = index.clone().into();
+    // the query is the weighted sum of the stored vectors of the nodes
+    let mut terms = Vec::with_capacity(nodes.len());
+    for (node_suffix, weight) in &nodes {
+        terms.push((stored_data(ctx, &index_name, shards, node_suffix)?, *weight));
+    }
+    let data = weighted_sum(&terms);
 
-    Ok(index_redis.into())
+    let (res, _) = run_search(ctx, &index_name, shards, &data, k, &params, parallel)?;
+    Ok(search_reply(result_names(&res, full_names)))
 }
 
-fn delete_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+// one search per node, the node itself is left out of its results
+fn search_eachof(ctx: &Context, args: Vec<String>) -> RedisResult {
     ctx.auto_memory();
+    check_writable(ctx)?;
 
-    let mut parsed = DEL_INDEX_CMD.with(|cmd| cmd.parse_args(args))?;
+    let mut args = args;
+    let nodes = take_node_names(&mut args, "nodes")?;
+    let mut parsed = EACHOF_SEARCH_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    let name_suffix = parsed.remove("name").unwrap().as_string()?;
-    let index_name = format!("{}.{}", PREFIX, name_suffix);
-
-    // get index from global hashmap
-    load_index(ctx, &index_name)?;
-    let mut indices = INDICES.write().unwrap();
-    let index = indices
-        .remove(&index_name)
-        .ok_or_else(|| format!("Index: {} does not exist", name_suffix))?;
-    let index = index.try_read().map_err(|e| e.to_string())?;
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let k = take_arg(&mut parsed, "k")?.as_u64()? as usize;
+    let filter = match take_arg(&mut parsed, "filter")?.as_string()?.as_str() {
+        "" => None,
+        f => Some(Filter::parse(f)?),
+    };
+    let params = SearchParams {
+        filter,
+        ..Default::default()
+    };
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let parallel = take_arg(&mut parsed, "parallel")?.as_bool()?;
+    let full_names = parse_names(&take_arg(&mut parsed, "names")?.as_string()?)?;
+    let store = take_arg(&mut parsed, "store")?.as_string()?;
+    let all = take_arg(&mut parsed, "all")?.as_bool()?;
 
-    for (node_name, _) in index.nodes.iter() {
-        delete_node_redis(ctx, &node_name)?;
-    }
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    let nodes = match (nodes, all) {
+        (Some(nodes), false) => nodes,
+        (None, true) => node_suffixes(ctx, &index_name, shards)?,
+        _ => return Err("Exactly one of NODES {...nodes} or ALL is required".into()),
+    };
 
-    // get index from redis
-    ctx.log_debug(format!("deleting index: {}", &index_name).as_str());
-    let rkey = ctx.open_key_writable(&index_name);
+    let mut reply: Vec<RedisValue> = Vec::new();
+    for node_suffix in &nodes {
+        let res = nearest_of(ctx, &index_name, shards, node_suffix, k, &params, parallel)?;
+        let res = result_names(&res, full_names);
 
-    match rkey.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
-        Some(_) => rkey.delete()?,
-        None => {
-            return Err(RedisError::String(format!(
-                "Index: {} does not exist",
-                name_suffix
-            )));
+        if store.is_empty() {
+            reply.push(node_suffix.as_str().into());
+            reply.push(search_reply(res));
+        } else {
+            store_results(ctx, &format!("{}{}", store, node_suffix), &res)?;
         }
-    };
+    }
 
-    Ok(1_usize.into())
+    if store.is_empty() {
+        Ok(reply.into())
+    } else {
+        Ok(nodes.len().into())
+    }
 }
 
-fn load_index(ctx: &Context, index_name: &str) -> Result<IndexArc, RedisError> {
-    let mut indices = INDICES.write().unwrap();
-    // check if index is in global hashmap
-    let index = match indices.entry(index_name.to_string()) {
-        Entry::Occupied(o) => o.into_mut(),
-        // if index isn't present, load it from redis
-        Entry::Vacant(v) => {
-            // get index from redis
-            ctx.log_debug(format!("get key: {}", &index_name).as_str());
-            let rkey = ctx.open_key(&index_name);
+// the K nearest nodes to a stored node, without the node itself
+fn nearest_of(
+    ctx: &Context,
+    index_name: &str,
+    shards: usize,
+    node_suffix: &str,
+    k: usize,
+    params: &SearchParams,
+    parallel: bool,
+) -> Result<Vec<SearchResult<f32, f32>>, RedisError> {
+    let data = stored_data(ctx, index_name, shards, node_suffix)?;
+    let (mut res, _) = run_search(ctx, index_name, shards, &data, k + 1, params, parallel)?;
+    res.retain(|r| r.name != node_suffix);
+    res.truncate(k);
+    Ok(res)
+}
+
+// a search per node like HNSW.SEARCH.EACHOF ALL, written to one key so graph analytics
+// read the whole graph at once
+fn export_knn_graph(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
 
-            let index_redis = rkey
-                .get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)?
-                .ok_or_else(|| format!("Index: {} does not exist", index_name))?;
+    let mut parsed = KNN_GRAPH_CMD.with(|cmd| parse_command(cmd, args))?;
 
-            let index = make_index(ctx, index_redis)?;
-            v.insert(Arc::new(RwLock::new(index)))
-        }
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let k = take_arg(&mut parsed, "k")?.as_u64()? as usize;
+    let dest = take_arg(&mut parsed, "dest")?.as_string()?;
+    let format = KnnFormat::parse(&take_arg(&mut parsed, "format")?.as_string()?)?;
+    let filter = match take_arg(&mut parsed, "filter")?.as_string()?.as_str() {
+        "" => None,
+        f => Some(Filter::parse(f)?),
     };
+    let params = SearchParams {
+        filter,
+        ..Default::default()
+    };
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+    let parallel = take_arg(&mut parsed, "parallel")?.as_bool()?;
+    let full_names = parse_names(&take_arg(&mut parsed, "names")?.as_string()?)?;
+
+    // the graph would replace an index or node key
+    if is_module_key(&dest) {
+        return Err(format!("DEST: {} is a key of the module", dest).into());
+    }
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    let nodes = node_suffixes(ctx, &index_name, shards)?;
+
+    ctx.call("DEL", &[&dest])?;
+    let mut edges = 0;
+    for node_suffix in &nodes {
+        let res = nearest_of(ctx, &index_name, shards, node_suffix, k, &params, parallel)?;
+        let res = result_names(&res, full_names);
+        let name = match full_names {
+            true => searched_node_key(&index_name, shards, node_suffix),
+            false => node_suffix.clone(),
+        };
+        edges += res.len();
+
+        match format {
+            KnnFormat::Hash => {
+                let neighbors = res
+                    .iter()
+                    .map(|r| (r.name.as_str(), r.sim))
+                    .collect::<Vec<(&str, f64)>>();
+                ctx.call("HSET", &[&dest, &name, &knn_tsv(&neighbors)])?;
+            }
+            KnnFormat::Stream => {
+                for r in &res {
+                    let sim = r.sim.to_string();
+                    ctx.call(
+                        "XADD",
+                        &[&dest, "*", "from", &name, "to", &r.name, "sim", &sim],
+                    )?;
+                }
+            }
+        }
+    }
+    log_event(
+        ctx,
+        LogLevel::Verbose,
+        "knn_graph_exported",
+        &[("index", &index_name), ("dest", &dest), ("edges", &edges)],
+    );
 
-    Ok(index.clone())
+    Ok(edges.into())
 }
 
-fn make_index(ctx: &Context, ir: &IndexRedis) -> Result<IndexT, RedisError> {
-    let mut index: IndexT = ir.clone().into();
+// replaces the key with a sorted set of the result names scored by similarity
+fn store_results(ctx: &Context, key: &str, res: &[SearchResultRedis]) -> Result<(), RedisError> {
+    ctx.call("DEL", &[key])?;
+    if res.is_empty() {
+        return Ok(());
+    }
+    let scores = res
+        .iter()
+        .map(|r| r.sim.to_string())
+        .collect::<Vec<String>>();
+    let mut args = vec![key];
+    for (score, r) in scores.iter().zip(res) {
+        args.push(score);
+        args.push(&r.name);
+    }
+    ctx.call("ZADD", &args)?;
+    Ok(())
+}
+
+// names without the index prefix of every node of the index and its shards
+fn node_suffixes(
+    ctx: &Context,
+    index_name: &str,
+    shards: usize,
+) -> Result<Vec<String>, RedisError> {
+    let mut names = Vec::new();
+    for sub_name in &searched_index_names(index_name, shards) {
+        let index = load_index(ctx, sub_name)?;
+        let index = read_index(&index)?;
+        names.extend(index.nodes.keys().map(|n| index.node_suffix(n).to_owned()));
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn dedup(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
 
-    index.nodes = HashMap::with_capacity(ir.node_count);
-    for node_name in &ir.nodes {
-        let key = ctx.open_key(&node_name);
+    let mut parsed = DEDUP_CMD.with(|cmd| parse_command(cmd, args))?;
 
-        let nr = key
-            .get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)?
-            .ok_or_else(|| format!("Node: {} does not exist", node_name))?;
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let threshold = take_arg(&mut parsed, "threshold")?.as_f64()? as f32;
+    let limit = take_arg(&mut parsed, "limit")?.as_u64()? as usize;
+    let clusters = take_arg(&mut parsed, "clusters")?.as_bool()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
 
-        let node = Node::new(node_name, &nr.data, index.m_max_0);
-        index.nodes.insert(node_name.to_owned(), node);
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    // pairs are limited after merging the shards or grouping them into clusters
+    let mut pairs = Vec::new();
+    for sub_name in &searched_index_names(&index_name, shards) {
+        let index = load_index(ctx, sub_name)?;
+        pairs.extend(read_index(&index)?.duplicates(threshold, 0)?);
     }
+    pairs.sort_by(|x, y| {
+        y.2.total_cmp(&x.2)
+            .then_with(|| (&x.0, &x.1).cmp(&(&y.0, &y.1)))
+    });
 
-    // reconstruct nodes
-    for node_name in &ir.nodes {
-        let target = index.nodes.get(node_name).unwrap();
+    let mut reply: Vec<RedisValue> = if clusters {
+        duplicate_clusters(&pairs)
+            .into_iter()
+            .map(|c| RedisValue::Array(c.into_iter().map(RedisValue::BulkString).collect()))
+            .collect()
+    } else {
+        pairs
+            .into_iter()
+            .map(|(a, b, sim)| {
+                RedisValue::Array(vec![
+                    RedisValue::BulkString(a),
+                    RedisValue::BulkString(b),
+                    (sim as f64).into(),
+                ])
+            })
+            .collect()
+    };
+    if limit > 0 {
+        reply.truncate(limit);
+    }
 
-        let key = ctx.open_key(&node_name);
+    Ok(reply.into())
+}
 
-        let nr = key
-            .get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)?
-            .ok_or_else(|| format!("Node: {} does not exist", node_name))?;
-        for layer in &nr.neighbors {
-            let mut node_layer = Vec::with_capacity(layer.len());
-            for neighbor in layer {
-                let nn = index
-                    .nodes
-                    .get(neighbor)
-                    .ok_or_else(|| format!("Node: {} does not exist", neighbor))?;
-                node_layer.push(nn.downgrade());
-            }
-            target.write().neighbors.push(node_layer);
-        }
+// the cluster of each node is kept as a node attribute, so searches can filter by cluster
+fn cluster_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = CLUSTER_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let k = take_arg(&mut parsed, "k")?.as_u64()? as usize;
+    let max_iter = take_arg(&mut parsed, "maxiter")?.as_u64()? as usize;
+    let batch_size = take_arg(&mut parsed, "batch")?.as_u64()? as usize;
+    let init = KMeansInit::parse(&take_arg(&mut parsed, "init")?.as_string()?)?;
+    let seed = take_arg(&mut parsed, "seed")?.as_u64()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    let indices = searched_index_names(&index_name, shards)
+        .iter()
+        .map(|name| load_index_mut(ctx, name))
+        .collect::<Result<Vec<IndexArc>, RedisError>>()?;
+    let mut guards = indices
+        .iter()
+        .map(write_index)
+        .collect::<Result<Vec<_>, RedisError>>()?;
+    if guards.iter().any(|g| g.frozen) {
+        return Err(format!("Index: {} is frozen", &index_name).into());
     }
 
-    // reconstruct layers
-    for layer in &ir.layers {
-        let mut node_layer = HashSet::with_capacity(layer.len());
-        for node_name in layer {
-            let node = index
-                .nodes
-                .get(node_name)
-                .ok_or_else(|| format!("Node: {} does not exist", node_name))?;
-            node_layer.insert(node.downgrade());
+    // nodes are ordered by name so the same seed gives the same clusters
+    let mut names = Vec::new();
+    let mut levels = Vec::new();
+    let mut vectors = Vec::new();
+    for (i, index) in guards.iter().enumerate() {
+        let mut nodes = index.nodes.iter().collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, node) in nodes {
+            let nr = node.read()?;
+            names.push((i, name.clone()));
+            levels.push(nr.level);
+            vectors.push(nr.data.to_vec());
         }
-        index.layers.push(node_layer);
     }
+    let refs = vectors
+        .iter()
+        .map(|v| v.as_slice())
+        .collect::<Vec<&[f32]>>();
+    let clusters = kmeans(&refs, &levels, k, init, max_iter, batch_size, seed)?;
 
-    // set enterpoint
-    index.enterpoint = match &ir.enterpoint {
-        Some(node_name) => {
-            let node = index
-                .nodes
-                .get(node_name)
-                .ok_or_else(|| format!("Node: {} does not exist", node_name))?;
-            Some(node.downgrade())
-        }
-        None => None,
-    };
+    let mut assignments: Vec<RedisValue> = Vec::with_capacity(names.len() * 2);
+    for ((i, name), cluster) in names.iter().zip(&clusters.assignments) {
+        let index = &mut guards[*i];
+        let mut attrs = index.nodes[name].read()?.attrs.clone();
+        attrs.insert(CLUSTER_ATTR.to_owned(), *cluster as f64);
+        index.set_attrs(name, attrs)?;
+        update_node(ctx, name, &index.nodes[name])?;
+        assignments.push(index.node_suffix(name).into());
+        assignments.push((*cluster).into());
+    }
+    for index in &guards {
+        drop_deferred(ctx, &index.name);
+    }
 
-    Ok(index)
+    let key = ctx.open_key_writable(&index_name);
+    match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
+        Some(value) => value.set_centroids(clusters.centroids.clone()),
+        None => return Err(format!("Index: {} does not exist", &index_name).into()),
+    }
+
+    let centroids = clusters
+        .centroids
+        .iter()
+        .map(|c| c.iter().map(|x| *x as f64).collect::<Vec<f64>>().into())
+        .collect::<Vec<RedisValue>>();
+    let reply: Vec<RedisValue> = vec![
+        "centroids".into(),
+        centroids.into(),
+        "assignments".into(),
+        assignments.into(),
+    ];
+    Ok(reply.into())
 }
 
-fn update_index(ctx: &Context, index_name: &str, index: &IndexT) -> Result<(), RedisError> {
-    let key = ctx.open_key_writable(index_name);
-    match key.get_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE)? {
-        Some(_) => {
-            ctx.log_debug(format!("update index: {}", index_name).as_str());
-            key.set_value::<IndexRedis>(&HNSW_INDEX_REDIS_TYPE, index.clone().into())?;
+// APPLY builds the new graph beside the old one and swaps it in under the write lock, so
+// searches keep running on the old graph until the rebuild is done
+fn autotune_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    check_writable(ctx)?;
+
+    let mut parsed = AUTOTUNE_CMD.with(|cmd| parse_command(cmd, args))?;
+
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let target_recall = take_arg(&mut parsed, "target_recall")?.as_f64()?;
+    let sample = take_arg(&mut parsed, "sample")?.as_u64()? as usize;
+    let k = take_arg(&mut parsed, "k")?.as_u64()? as usize;
+    let seed = take_arg(&mut parsed, "seed")?.as_u64()?;
+    let apply = take_arg(&mut parsed, "apply")?.as_bool()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
+
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    let names = searched_index_names(&index_name, shards);
+    let indices = names
+        .iter()
+        .map(|name| load_index_mut(ctx, name))
+        .collect::<Result<Vec<IndexArc>, RedisError>>()?;
+
+    // each index contributes to the sample by its share of the nodes
+    let tuning = {
+        let guards = indices
+            .iter()
+            .map(read_index)
+            .collect::<Result<Vec<_>, RedisError>>()?;
+        let total = guards.iter().map(|g| g.node_count).sum::<usize>().max(1);
+        let mut vectors = Vec::with_capacity(sample);
+        for index in &guards {
+            let n = (sample * index.node_count).div_ceil(total);
+            vectors.extend(index.sample_vectors(n, seed)?);
         }
-        None => {
-            return Err(RedisError::String(format!(
-                "Index: {} does not exist",
-                index_name
-            )));
+        vectors.truncate(sample);
+        autotune(&vectors, *guards[0].mfunc, target_recall, k, seed)?
+    };
+    let best = tuning.best();
+
+    if apply {
+        // the parent of a sharded index holds no nodes but reports the parameters
+        let mut rebuilt = names.iter().cloned().zip(indices).collect::<Vec<_>>();
+        if shards > 0 {
+            rebuilt.push((index_name.clone(), load_index_mut(ctx, &index_name)?));
+        }
+        for (name, index) in &rebuilt {
+            let mut index = write_index(index)?;
+            if index.frozen {
+                return Err(format!("Index: {} is frozen", name).into());
+            }
+            *index = index.rebuilt(best.m, best.ef_construction)?;
+            for (node_name, node) in &index.nodes {
+                update_node(ctx, node_name, node)?;
+            }
+            drop_deferred(ctx, name);
+            update_index(ctx, name, &index, NodeChange::Unchanged)?;
         }
     }
-    Ok(())
+
+    let trials = tuning
+        .trials
+        .iter()
+        .map(|t| {
+            let trial: Vec<RedisValue> = vec![
+                "m".into(),
+                t.m.into(),
+                "ef_construction".into(),
+                t.ef_construction.into(),
+                "recall".into(),
+                t.recall.into(),
+                "build_time_us".into(),
+                (t.build_time.as_micros() as i64).into(),
+                "search_time_us".into(),
+                (t.search_time.as_micros() as i64).into(),
+            ];
+            trial.into()
+        })
+        .collect::<Vec<RedisValue>>();
+    let reply: Vec<RedisValue> = vec![
+        "m".into(),
+        best.m.into(),
+        "ef_construction".into(),
+        best.ef_construction.into(),
+        "recall".into(),
+        best.recall.into(),
+        "reached".into(),
+        (tuning.reached as i64).into(),
+        "applied".into(),
+        (apply as i64).into(),
+        "trials".into(),
+        trials.into(),
+    ];
+    Ok(reply.into())
 }
 
-fn add_node(ctx: &Context, args: Vec<String>) -> RedisResult {
+fn centroid(ctx: &Context, args: Vec<String>) -> RedisResult {
     ctx.auto_memory();
 
-    let mut parsed = ADD_NODE_CMD.with(|cmd| cmd.parse_args(args))?;
+    let mut args = args;
+    let nodes = take_node_names(&mut args, "nodes")?.ok_or("NODES is required")?;
+    let mut parsed = CENTROID_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    let index_suffix = parsed.remove("index").unwrap().as_string()?;
-    let node_suffix = parsed.remove("node").unwrap().as_string()?;
+    let index_suffix = take_arg(&mut parsed, "index")?.as_string()?;
+    let field = take_arg(&mut parsed, "field")?.as_string()?;
 
-    let index_name = format!("{}.{}", PREFIX, index_suffix);
-    let node_name = format!("{}.{}.{}", PREFIX, index_suffix, node_suffix);
+    let (index_name, shards) = search_target(ctx, &index_suffix, &field)?;
+    let data = centroid_of(ctx, &index_name, shards, &nodes)?;
 
-    let dataf64 = parsed.remove("data").unwrap().as_f64vec()?;
-    let data = dataf64.iter().map(|d| *d as f32).collect::<Vec<f32>>();
+    Ok(data.iter().map(|x| *x as f64).collect::<Vec<f64>>().into())
+}
 
-    let index = load_index(ctx, &index_name)?;
-    let mut index = index.try_write().map_err(|e| e.to_string())?;
+// reference of a NOTNEAR exclusion as given to HNSW.SEARCH
+enum Reference {
+    Vector(Vec<f32>),
+    Node(String), // resolved to its stored vector once the searched index is known
+}
 
-    let up = |name: String, node: Node<f32>| {
-        write_node(ctx, &name, (&node).into()).unwrap();
-    };
+// removes every `NOTNEAR {dim} {...data} RADIUS {r}` and `NOTNEAR NODE {node} RADIUS {r}`
+// from the arguments
+fn take_not_near(args: &mut Vec<String>) -> Result<Vec<(Reference, f32)>, RedisError> {
+    let mut not_near = Vec::new();
+    while let Some(i) = args
+        .iter()
+        .skip(2)
+        .position(|a| a.eq_ignore_ascii_case("notnear"))
+    {
+        let i = i + 2;
+        let invalid = || {
+            RedisError::from(
+                "NOTNEAR requires {dim} {...data} or NODE {node}, followed by RADIUS {r}",
+            )
+        };
+        let (reference, next) = match args.get(i + 1) {
+            Some(a) if a.eq_ignore_ascii_case("node") => {
+                let node = args.get(i + 2).ok_or_else(invalid)?;
+                (Reference::Node(node.clone()), i + 3)
+            }
+            Some(dim) => {
+                let dim = dim.parse::<usize>().map_err(|_| invalid())?;
+                let end = dim.checked_add(i + 2).ok_or_else(invalid)?;
+                let data = args
+                    .get(i + 2..end)
+                    .ok_or_else(invalid)?
+                    .iter()
+                    .map(|v| v.parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|_| "Invalid NOTNEAR value")?;
+                finite_values("NOTNEAR", &data)?;
+                (Reference::Vector(data), end)
+            }
+            None => return Err(invalid()),
+        };
+        let radius = match (args.get(next), args.get(next + 1)) {
+            (Some(r), Some(radius)) if r.eq_ignore_ascii_case("radius") => {
+                match radius.parse::<f32>() {
+                    Ok(r) if r.is_finite() => r,
+                    _ => return Err(format!("Invalid RADIUS: {}", radius).into()),
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        args.drain(i..next + 2);
+        not_near.push((reference, radius));
+    }
+    Ok(not_near)
+}
 
-    ctx.log_debug(format!("Adding node: {} to Index: {}", &node_name, &index_name).as_str());
-    index
-        .add_node(&node_name, &data, up)
-        .map_err(|e| e.error_string())?;
+// mean of the stored vectors of the nodes
+fn centroid_of(
+    ctx: &dyn Backend,
+    index_name: &str,
+    shards: usize,
+    node_suffixes: &[String],
+) -> Result<Vec<f32>, RedisError> {
+    let weight = 1.0 / node_suffixes.len() as f32;
+    let mut terms = Vec::with_capacity(node_suffixes.len());
+    for node_suffix in node_suffixes {
+        terms.push((stored_data(ctx, index_name, shards, node_suffix)?, weight));
+    }
+    Ok(weighted_sum(&terms))
+}
 
-    // write node to redis
-    let node = index.nodes.get(&node_name).unwrap();
-    write_node(ctx, &node_name, node.into())?;
+// index of a search target holding the node, its shard if sharded
+fn searched_index_name(index_name: &str, shards: usize, node_suffix: &str) -> String {
+    match shards {
+        0 => index_name.to_owned(),
+        n => shard_index_name(index_name, shard_of(node_suffix, n)),
+    }
+}
 
-    // update index in redis
-    update_index(ctx, &index_name, &index)?;
+fn searched_node_key(index_name: &str, shards: usize, node_suffix: &str) -> String {
+    node_key(
+        &searched_index_name(index_name, shards, node_suffix),
+        node_suffix,
+    )
+}
 
-    Ok("OK".into())
+// vector the graph holds for the node, normalized if the index normalizes
+fn stored_data(
+    ctx: &dyn Backend,
+    index_name: &str,
+    shards: usize,
+    node_suffix: &str,
+) -> Result<Vec<f32>, RedisError> {
+    let sub_name = searched_index_name(index_name, shards, node_suffix);
+    let node_name = node_key(&sub_name, node_suffix);
+    let index = load_index(ctx, &sub_name)?;
+    let index = read_index(&index)?;
+    let node = index
+        .nodes
+        .get(&node_name)
+        .ok_or_else(|| format!("Node: {} does not exist", node_name))?;
+    let data = node.read()?.data.to_vec();
+    Ok(data)
 }
 
-fn delete_node(ctx: &Context, args: Vec<String>) -> RedisResult {
-    ctx.auto_memory();
+// removes `{name} {...nodes}` from the arguments, the node names run to the last argument
+fn take_node_names(args: &mut Vec<String>, name: &str) -> Result<Option<Vec<String>>, RedisError> {
+    let i = match args
+        .iter()
+        .skip(2)
+        .position(|a| a.eq_ignore_ascii_case(name))
+    {
+        Some(i) => i + 2,
+        None => return Ok(None),
+    };
+    let nodes = args.drain(i..).skip(1).collect::<Vec<String>>();
+    if nodes.is_empty() {
+        return Err(format!("{} requires node names", name.to_uppercase()).into());
+    }
+    Ok(Some(nodes))
+}
 
-    let mut parsed = DEL_NODE_CMD.with(|cmd| cmd.parse_args(args))?;
+// removes `NODES {node} {weight} ...` from the arguments, the pairs run to the last argument
+fn take_weighted_nodes(args: &mut Vec<String>) -> Result<Vec<(String, f32)>, RedisError> {
+    let i = match args
+        .iter()
+        .skip(2)
+        .position(|a| a.eq_ignore_ascii_case("nodes"))
+    {
+        Some(i) => i + 2,
+        None => return Err("NODES is required".into()),
+    };
+    let pairs = args.drain(i..).skip(1).collect::<Vec<String>>();
+    if pairs.is_empty() || pairs.len() % 2 != 0 {
+        return Err("NODES requires {node} {weight} pairs".into());
+    }
+    pairs
+        .chunks(2)
+        .map(|p| match p[1].parse::<f32>() {
+            Ok(w) if w.is_finite() => Ok((p[0].clone(), w)),
+            _ => Err(format!("Invalid weight for node: {}", p[0]).into()),
+        })
+        .collect()
+}
 
-    let index_suffix = parsed.remove("index").unwrap().as_string()?;
-    let node_suffix = parsed.remove("node").unwrap().as_string()?;
+// name and shards of the index a search runs on, the sub-index backing the field if given
+fn search_target(
+    ctx: &dyn Backend,
+    index_suffix: &str,
+    field: &str,
+) -> Result<(String, usize), RedisError> {
+    let index_name = index_key_of(ctx, index_suffix);
+    let index = load_index(ctx, &index_name)?;
+    let parent = read_index(&index)?;
+    match (parent.fields.is_empty(), field.is_empty()) {
+        (true, true) => Ok((index_name, parent.shards)),
+        (true, false) => Err(format!("Index: {} has no FIELDs", &index_name).into()),
+        (false, true) => Err(format!("Index: {} requires a FIELD", &index_name).into()),
+        (false, false) => Ok((field_index_name(&parent, field)?, parent.shards)),
+    }
+}
 
-    let index_name = format!("{}.{}", PREFIX, index_suffix);
-    let node_name = format!("{}.{}.{}", PREFIX, index_suffix, node_suffix);
+fn run_search(
+    ctx: &dyn Backend,
+    index_name: &str,
+    shards: usize,
+    data: &[f32],
+    k: usize,
+    params: &SearchParams,
+    parallel: bool,
+) -> Result<(Vec<SearchResult<f32, f32>>, SearchStats), RedisError> {
+    log_event(
+        ctx,
+        LogLevel::Debug,
+        "search",
+        &[("index", &index_name), ("k", &k)],
+    );
 
-    let index = load_index(ctx, &index_name)?;
-    let mut index = index.try_write().map_err(|e| e.to_string())?;
+    if shards > 0 {
+        return search_shards(ctx, index_name, shards, data, k, params, parallel);
+    }
+    let index = load_index(ctx, index_name)?;
+    let index = view_index(&cache_key(ctx, index_name), &index)?;
+    index
+        .search_knn_stats(data, k, params)
+        .map_err(RedisError::from)
+}
 
-    let node = index.nodes.get(&node_name).unwrap();
-    if Arc::strong_count(&node.0) > 1 {
-        return Err(format!(
-            "{} is being accessed, unable to delete. Try again later",
-            &node_name
-        )
-        .into());
+// NAMES of a search, true if results are named by their full node key
+fn parse_names(names: &str) -> Result<bool, RedisError> {
+    match names.to_lowercase().as_str() {
+        "suffix" => Ok(false),
+        "full" => Ok(true),
+        n => Err(format!("Unknown NAMES: {}, expected full or suffix", n).into()),
     }
+}
 
-    let up = |name: String, node: Node<f32>| {
-        write_node(ctx, &name, (&node).into()).unwrap();
-    };
+fn result_names(res: &[SearchResult<f32, f32>], full_names: bool) -> Vec<SearchResultRedis> {
+    res.iter()
+        .map(|r| {
+            if full_names {
+                SearchResultRedis::with_key(r)
+            } else {
+                r.into()
+            }
+        })
+        .collect()
+}
 
-    index
-        .delete_node(&node_name, up)
-        .map_err(|e| e.error_string())?;
+// shape of a search reply. flat is the reply of older versions, the number of results followed
+// by the results, which some clients mistake for a result. nested replies the array of results
+// instead and map the results under a `results` key, both followed by the key-value pairs of
+// TRUNCATED and WITHSTATS
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ReplyFormat {
+    Flat,
+    Nested,
+    Map,
+}
 
-    delete_node_redis(ctx, &node_name)?;
+fn parse_format(format: &str) -> Result<ReplyFormat, RedisError> {
+    match format.to_lowercase().as_str() {
+        "flat" => Ok(ReplyFormat::Flat),
+        "nested" => Ok(ReplyFormat::Nested),
+        "map" => Ok(ReplyFormat::Map),
+        f => Err(format!("Unknown FORMAT: {}, expected flat, nested or map", f).into()),
+    }
+}
 
-    // update index in redis
-    update_index(ctx, &index_name, &index)?;
+// maps are arrays of alternating keys and values, the resp2 form of a map. resp3 clients get
+// them as maps from reply::search_knn
+fn formatted_reply(res: Vec<SearchResultRedis>, format: ReplyFormat) -> RedisValue {
+    if format == ReplyFormat::Flat {
+        return search_reply(res);
+    }
+    let results = RedisValue::Array(res.into_iter().map(RedisValue::from).collect());
+    match format {
+        ReplyFormat::Map => RedisValue::Array(vec!["results".into(), results]),
+        _ => RedisValue::Array(vec![results]),
+    }
+}
 
-    Ok(1_usize.into())
+fn search_reply(res: Vec<SearchResultRedis>) -> RedisValue {
+    let mut reply: Vec<RedisValue> = Vec::new();
+    reply.push(res.len().into());
+    for sr in res {
+        reply.push(sr.into());
+    }
+    reply.into()
 }
 
-fn delete_node_redis(ctx: &Context, node_name: &str) -> Result<(), RedisError> {
-    ctx.log_debug(format!("del key: {}", node_name).as_str());
-    let rkey = ctx.open_key_writable(node_name);
-    match rkey.get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)? {
-        Some(_) => rkey.delete()?,
-        None => {
-            return Err(RedisError::String(format!(
-                "Node: {} does not exist",
-                node_name
-            )));
-        }
+// searches every shard for the k nearest nodes and merges their results
+fn search_shards(
+    ctx: &dyn Backend,
+    index_name: &str,
+    shards: usize,
+    data: &[f32],
+    k: usize,
+    params: &SearchParams,
+    parallel: bool,
+) -> Result<(Vec<SearchResult<f32, f32>>, SearchStats), RedisError> {
+    let names = (0..shards)
+        .map(|i| shard_index_name(index_name, i))
+        .collect::<Vec<String>>();
+    let indices = names
+        .iter()
+        .map(|name| load_index(ctx, name))
+        .collect::<Result<Vec<IndexArc>, RedisError>>()?;
+    let guards = names
+        .iter()
+        .zip(&indices)
+        .map(|(name, index)| view_index(&cache_key(ctx, name), index))
+        .collect::<Result<Vec<IndexView>, RedisError>>()?;
+
+    // the entry node is only in its own shard, the others start from their enterpoint
+    let shard_params = (0..shards)
+        .map(|i| match &params.entry {
+            Some(entry) if shard_of(entry, shards) != i => SearchParams {
+                entry: None,
+                ..params.clone()
+            },
+            _ => params.clone(),
+        })
+        .collect::<Vec<SearchParams>>();
+
+    let results = if parallel {
+        thread::scope(|s| {
+            let handles = guards
+                .iter()
+                .zip(&shard_params)
+                .map(|(index, params)| s.spawn(move || index.search_knn_stats(data, k, params)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err("Shard search panicked".into()))
+                })
+                .collect::<Vec<_>>()
+        })
+    } else {
+        guards
+            .iter()
+            .zip(&shard_params)
+            .map(|(index, params)| index.search_knn_stats(data, k, params))
+            .collect::<Vec<_>>()
     };
 
-    Ok(())
+    // every shard has its own budget
+    let mut merged = Vec::with_capacity(k * shards);
+    let mut stats = SearchStats::default();
+    for res in results {
+        let (res, shard_stats) = res?;
+        merged.extend(res);
+        stats.merge(&shard_stats);
+    }
+    merged.sort_by_key(|r| Reverse(r.sim));
+    merged.truncate(k);
+    Ok((merged, stats))
 }
 
-fn get_node(ctx: &Context, args: Vec<String>) -> RedisResult {
-    ctx.auto_memory();
+fn cursor_read(_ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut parsed = CURSOR_READ_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    let mut parsed = GET_NODE_CMD.with(|cmd| cmd.parse_args(args))?;
+    let id = take_arg(&mut parsed, "cursor")?.as_u64()?;
+    let count = match take_arg(&mut parsed, "count")?.as_u64()? as usize {
+        0 => None,
+        n => Some(n),
+    };
 
-    let index_suffix = parsed.remove("index").unwrap().as_string()?;
-    let node_suffix = parsed.remove("node").unwrap().as_string()?;
+    lock_cache(&CURSORS).read(id, count)
+}
 
-    let node_name = format!("{}.{}.{}", PREFIX, index_suffix, node_suffix);
+fn cursor_del(_ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut parsed = CURSOR_DEL_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    ctx.log_debug(format!("get key: {}", node_name).as_str());
+    let id = take_arg(&mut parsed, "cursor")?.as_u64()?;
 
-    let key = ctx.open_key(&node_name);
+    let mut cursors = lock_cache(&CURSORS);
+    cursors.purge_expired();
+    Ok((cursors.cursors.remove(&id).is_some() as usize).into())
+}
 
-    let value = key
-        .get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)?
-        .ok_or_else(|| format!("Node: {} does not exist", &node_name))?;
+fn selftest(ctx: &Context, args: Vec<String>) -> RedisResult {
+    let mut parsed = SELFTEST_CMD.with(|cmd| parse_command(cmd, args))?;
 
-    Ok(value.into())
+    let seed = take_arg(&mut parsed, "seed")?.as_u64()?;
+    selftest_on(ctx, seed)
 }
 
-fn write_node<'a>(ctx: &'a Context, key: &str, node: NodeRedis) -> RedisResult {
-    ctx.log_debug(format!("set key: {}", key).as_str());
-    let rkey = ctx.open_key_writable(key);
+// no key is read or written, the graph only lives for the duration of the command
+fn selftest_on(ctx: &dyn Backend, seed: u64) -> RedisResult {
+    let mut checks = hnsw::check_kernels(seed);
+    checks.extend(hnsw::check_graph(seed));
+    let failed = checks
+        .iter()
+        .filter(|c| !c.passed)
+        .map(|c| c.name.as_str())
+        .collect::<Vec<&str>>();
+    if !failed.is_empty() {
+        log_event(
+            ctx,
+            LogLevel::Warning,
+            "selftest_failed",
+            &[
+                ("simd", &hnsw::metrics::simd()),
+                ("checks", &failed.join(",")),
+            ],
+        );
+    }
+
+    let checks = checks
+        .iter()
+        .map(|c| {
+            RedisValue::Array(vec![
+                "name".into(),
+                c.name.as_str().into(),
+                "passed".into(),
+                (c.passed as usize).into(),
+                "detail".into(),
+                c.detail.as_str().into(),
+            ])
+        })
+        .collect::<Vec<RedisValue>>();
+    Ok(RedisValue::Array(vec![
+        "passed".into(),
+        (failed.is_empty() as usize).into(),
+        "simd".into(),
+        hnsw::metrics::simd().into(),
+        "checks".into(),
+        checks.into(),
+    ]))
+}
 
-    match rkey.get_value::<NodeRedis>(&HNSW_NODE_REDIS_TYPE)? {
-        Some(value) => {
-            value.data = node.data;
-            value.neighbors = node.neighbors;
+// HNSW.CONFIG GET {name} | SET {name} {value}
+fn module_config(_ctx: &Context, args: Vec<String>) -> RedisResult {
+    let op = args.get(1).map(|a| a.to_ascii_lowercase());
+    match (op.as_deref(), args.len()) {
+        (Some("get"), 3) => config::get(&args[2]),
+        (Some("set"), 4) => {
+            config::set(&args[2], &args[3])?;
+            Ok("OK".into())
         }
-        None => {
-            rkey.set_value(&HNSW_NODE_REDIS_TYPE, node)?;
+        _ => Err("Expected GET {name} or SET {name} {value}".into()),
+    }
+}
+
+// the command list, or the usage of one command
+fn help(_ctx: &Context, args: Vec<String>) -> RedisResult {
+    match args.len() {
+        1 => Ok(RedisValue::Array(
+            COMMAND_SPECS
+                .iter()
+                .map(|spec| spec.with(help::summary))
+                .collect(),
+        )),
+        2 => {
+            let name = args[1].to_ascii_lowercase();
+            COMMAND_SPECS
+                .iter()
+                .find_map(|spec| spec.with(|cmd| (cmd.name == name).then(|| help::usage(cmd))))
+                .ok_or_else(|| format!("Unknown command: {}", args[1]).into())
         }
+        _ => Err(RedisError::WrongArity),
     }
-    Ok(key.into())
 }
 
-fn search_knn(ctx: &Context, args: Vec<String>) -> RedisResult {
+fn module_jobs(ctx: &Context, args: Vec<String>) -> RedisResult {
     ctx.auto_memory();
+    let op = args.get(1).map(|a| a.to_ascii_lowercase());
+    let job = |id: &str| {
+        id.parse::<u64>()
+            .ok()
+            .and_then(jobs::get)
+            .ok_or_else(|| RedisError::String(format!("Job: {} does not exist", id)))
+    };
+    match (op.as_deref(), args.len()) {
+        (Some("list"), 2) => Ok(jobs::list()
+            .iter()
+            .map(|j| j.status())
+            .collect::<Vec<_>>()
+            .into()),
+        (Some("status"), 3) => Ok(job(&args[2])?.status()),
+        (Some("cancel"), 3) => match job(&args[2])?.cancel() {
+            true => Ok("OK".into()),
+            false => Err(format!("Job: {} can't be cancelled", &args[2]).into()),
+        },
+        _ => Err("Expected LIST, STATUS {id} or CANCEL {id}".into()),
+    }
+}
 
-    let mut parsed = SEARCH_CMD.with(|cmd| cmd.parse_args(args))?;
+fn alias_index(ctx: &Context, args: Vec<String>) -> RedisResult {
+    ctx.auto_memory();
+    let op = args.get(1).map(|a| a.to_ascii_lowercase());
+    match (op.as_deref(), args.len()) {
+        (Some("add"), 4) => add_alias(ctx, &args[2], &args[3], false),
+        (Some("set"), 4) => add_alias(ctx, &args[2], &args[3], true),
+        (Some("del"), 3) => del_alias(ctx, &args[2]),
+        _ => Err("Expected ADD {alias} {index}, SET {alias} {index} or DEL {alias}".into()),
+    }
+}
 
-    let index_suffix = parsed.remove("index").unwrap().as_string()?;
-    let k = parsed.remove("k").unwrap().as_u64()? as usize;
-    let dataf64 = parsed.remove("query").unwrap().as_f64vec()?;
-    let data = dataf64.iter().map(|d| *d as f32).collect::<Vec<f32>>();
+// points the alias at the index, SET moves it from the index it points at instead of failing
+fn add_alias(ctx: &dyn Backend, alias: &str, index_suffix: &str, replace: bool) -> RedisResult {
+    check_writable(ctx)?;
+    check_index_name(KeyLayout::Tagged, alias)?;
+    if let Some(existing) = own_index_key(ctx, alias) {
+        return Err(format!("Alias: {} is the name of index {}", alias, existing).into());
+    }
+    // aliases of aliases aren't resolved
+    let index_name = own_index_key(ctx, index_suffix)
+        .ok_or_else(|| format!("Index: {} does not exist", index_suffix))?;
 
-    let index_name = format!("{}.{}", PREFIX, index_suffix);
-    let index = load_index(ctx, &index_name)?;
-    let index = index.try_read().map_err(|e| e.to_string())?;
+    match alias_target(ctx, alias) {
+        Some(current) if current == index_name && replace => return Ok("OK".into()),
+        Some(_) if !replace => return Err(format!("Alias: {} already exists", alias).into()),
+        Some(current) => remove_alias(ctx, &current, alias)?,
+        None => (),
+    }
+    ctx.index_mut(&index_name, |ir| {
+        let mut aliases = ir.aliases.clone();
+        aliases.push(alias.to_owned());
+        ir.set_aliases(aliases);
+    })?;
+    Ok("OK".into())
+}
 
-    ctx.log_debug(
-        format!(
-            "Searching for {} nearest nodes in Index: {}",
-            k, &index_name
-        )
-        .as_str(),
+fn del_alias(ctx: &dyn Backend, alias: &str) -> RedisResult {
+    check_writable(ctx)?;
+    let index_name =
+        alias_target(ctx, alias).ok_or_else(|| format!("Alias: {} does not exist", alias))?;
+    remove_alias(ctx, &index_name, alias)?;
+    Ok("OK".into())
+}
+
+// the aliases are kept on the index key, the index loaded from it doesn't carry them
+fn aliases_of(ctx: &dyn Backend, index_name: &str) -> Result<Vec<String>, RedisError> {
+    Ok(ctx
+        .index(index_name, |ir| ir.aliases.clone())?
+        .unwrap_or_default())
+}
+
+fn remove_alias(ctx: &dyn Backend, index_name: &str, alias: &str) -> Result<(), RedisError> {
+    ctx.index_mut(index_name, |ir| {
+        let aliases = ir.aliases.iter().filter(|a| *a != alias).cloned().collect();
+        ir.set_aliases(aliases);
+    })?;
+    Ok(())
+}
+
+// commands are created here instead of by redis_module! so acl categories can be
+// attached to them while the module is loading
+fn create_commands(ctx: *mut raw::RedisModuleCtx) -> c_int {
+    redis_command!(ctx, "hnsw.new", new_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.get", get_index, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.list", list_indexes, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.exists", exists_index, "readonly fast", 0, 0, 0);
+    redis_command!(ctx, "hnsw.card", card_index, "readonly fast", 0, 0, 0);
+    redis_command!(ctx, "hnsw.layercard", layercard_index, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.stats", stats_index, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.debug.graph", debug_graph, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.alter", alter_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.projection.set", set_projection, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.train.pca", train_pca, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.setenterpoint", set_enterpoint, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.follow", follow_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.unfollow", unfollow_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.freeze", freeze_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.unfreeze", unfreeze_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.del", delete_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.replicateto", replicate_to, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.migrate", migrate_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.reindex", reindex_index, "write", 0, 0, 0);
+    redis_command!(
+        ctx,
+        "hnsw.reindex.status",
+        reindex_status,
+        "readonly fast",
+        0,
+        0,
+        0
+    );
+    redis_command!(ctx, "hnsw.warmup", warmup_index, "readonly", 0, 0, 0);
+    if reply::create_search_command(ctx, "readonly") != raw::Status::Ok as c_int {
+        return raw::Status::Err as c_int;
+    }
+    redis_command!(
+        ctx,
+        "hnsw.search.composite",
+        search_composite,
+        "readonly",
+        0,
+        0,
+        0
+    );
+    redis_command!(ctx, "hnsw.search.eachof", search_eachof, "write", 0, 0, 0);
+    redis_command!(
+        ctx,
+        "hnsw.export.knngraph",
+        export_knn_graph,
+        "write",
+        0,
+        0,
+        0
+    );
+    redis_command!(ctx, "hnsw.dedup", dedup, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.cluster", cluster_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.autotune", autotune_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.centroid", centroid, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.node.add", add_node, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.node.addfrom", add_node_from, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.node.update", replace_node, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.node.get", get_node, "readonly", 0, 0, 0);
+    redis_command!(
+        ctx,
+        "hnsw.node.exists",
+        exists_node,
+        "readonly fast",
+        0,
+        0,
+        0
+    );
+    redis_command!(ctx, "hnsw.node.del", delete_node, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.node.mdel", delete_nodes, "write", 0, 0, 0);
+    redis_command!(
+        ctx,
+        "hnsw.node.delpattern",
+        delete_pattern,
+        "write",
+        0,
+        0,
+        0
     );
+    redis_command!(ctx, "hnsw.cursor.read", cursor_read, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.cursor.del", cursor_del, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.selftest", selftest, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.config", module_config, "readonly", 0, 0, 0);
+    redis_command!(ctx, "hnsw.help", help, "readonly fast", 0, 0, 0);
+    redis_command!(ctx, "hnsw.alias", alias_index, "write", 0, 0, 0);
+    redis_command!(ctx, "hnsw.jobs", module_jobs, "readonly", 0, 0, 0);
+    raw::Status::Ok as c_int
+}
 
-    match index.search_knn(&data, k) {
-        Ok(res) => {
-            let mut reply: Vec<RedisValue> = Vec::new();
-            reply.push(res.len().into());
-            for r in &res {
-                let sr: SearchResultRedis = r.into();
-                reply.push(sr.into());
-            }
-            Ok(reply.into())
-        }
-        Err(e) => Err(e.error_string().into()),
+#[allow(clippy::ptr_arg)]
+fn init(ctx: &Context, _args: &Vec<String>) -> Status {
+    // resolve the SIMD kernels once instead of on every distance call
+    hnsw::metrics::init();
+    if let Err(e) = types::create_data_types(ctx) {
+        ctx.log(LogLevel::Warning, &e);
+        return Status::Err;
+    }
+    if create_commands(ctx.ctx) != raw::Status::Ok as c_int {
+        return Status::Err;
     }
+    acl::register_categories(ctx);
+    docs::register_command_info(ctx, &COMMAND_SPECS);
+    events::subscribe(ctx);
+    events::follow_keyspace(ctx);
+    maintenance::schedule(ctx);
+    Status::Ok
 }
 
 redis_module! {
     name: "hnsw",
     version: 1,
-    data_types: [
-        HNSW_INDEX_REDIS_TYPE,
-        HNSW_NODE_REDIS_TYPE,
-    ],
-    commands: [
-        ["hnsw.new", new_index, "write", 0, 0, 0],
-        ["hnsw.get", get_index, "readonly", 0, 0, 0],
-        ["hnsw.del", delete_index, "write", 0, 0, 0],
-        ["hnsw.search", search_knn, "readonly", 0, 0, 0],
-        ["hnsw.node.add", add_node, "write", 0, 0, 0],
-        ["hnsw.node.get", get_node, "readonly", 0, 0, 0],
-        ["hnsw.node.del", delete_node, "write", 0, 0, 0],
-    ],
+    // created by init, the bindings don't know the defrag type method
+    data_types: [],
+    init: init,
+    commands: [],
 }
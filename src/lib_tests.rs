@@ -0,0 +1,780 @@
+use crate::backend::memory::MemoryBackend;
+use crate::backend::Backend;
+use crate::*;
+
+fn new_args(name: &str, data_dim: usize) -> NewIndexArgs {
+    NewIndexArgs {
+        name_suffix: name.to_owned(),
+        fields: Vec::new(),
+        data_dim,
+        m: 5,
+        m0: 0,
+        ef_construction: 200,
+        normalize: false,
+        extend_candidates: true,
+        keep_pruned_connections: true,
+        shards: 0,
+        snapshot_reads: false,
+        names: NameRule::new("", 0).unwrap(),
+        project_from: 0,
+        flat_limit: 0,
+        storage: StorageKind::Heap,
+        metric: MetricFuncs::Euclidean,
+        duplicates: DuplicatePolicy::Allow,
+        dup_epsilon: 0.0,
+    }
+}
+
+fn add_args(index: &str, node: &str, data: &[f32]) -> AddNodeArgs {
+    AddNodeArgs {
+        index_suffix: index.to_owned(),
+        node_suffix: node.to_owned(),
+        data: data.to_vec(),
+        field_data: Vec::new(),
+        meta: NodeMeta::default(),
+        level: None,
+    }
+}
+
+fn search_args(index: &str, k: usize, data: &[f32]) -> SearchArgs {
+    SearchArgs {
+        index_suffix: index.to_owned(),
+        k,
+        data: data.to_vec(),
+        centroid: None,
+        not_near: Vec::new(),
+        params: SearchParams::default(),
+        field: String::new(),
+        cursor: false,
+        count: 0,
+        parallel: false,
+        full_names: false,
+        with_stats: false,
+        grouping: None,
+        dedupe: None,
+        format: ReplyFormat::Flat,
+        with_vectors: false,
+        blob: false,
+    }
+}
+
+// value of a key in a key-value reply
+pub(crate) fn field<'a>(reply: &'a RedisValue, key: &str) -> &'a RedisValue {
+    let items = match reply {
+        RedisValue::Array(items) => items,
+        r => panic!("unexpected reply: {:?}", r),
+    };
+    let pos = items
+        .iter()
+        .position(|item| *item == RedisValue::from(key))
+        .unwrap();
+    &items[pos + 1]
+}
+
+// names of the results of a search reply, best first
+fn result_names(reply: RedisValue) -> Vec<String> {
+    let items = match reply {
+        RedisValue::Array(items) => items,
+        r => panic!("unexpected reply: {:?}", r),
+    };
+    items[1..]
+        .iter()
+        .map(|item| match item {
+            RedisValue::Array(fields) => match &fields[3] {
+                RedisValue::BulkString(name) => name.clone(),
+                r => panic!("unexpected name: {:?}", r),
+            },
+            r => panic!("unexpected result: {:?}", r),
+        })
+        .collect()
+}
+
+fn search(keys: &MemoryBackend, index: &str, data: &[f32]) -> Vec<String> {
+    result_names(search_knn_on(keys, search_args(index, 3, data)).unwrap())
+}
+
+// indexes of the tests are named by the test, the cache of loaded indexes is shared
+fn add_nodes(keys: &MemoryBackend, index: &str, n: usize) {
+    new_index_on(keys, new_args(index, 2)).unwrap();
+    for i in 0..n {
+        let node = format!("n{}", i);
+        add_node_on(keys, add_args(index, &node, &[i as f32, 0.0])).unwrap();
+    }
+}
+
+#[test]
+fn node_lifecycle() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "life", 10);
+    let index_name = index_key_of(&keys, "life");
+    // the index key and a key per node
+    assert_eq!(keys.keys().len(), 11);
+    assert!(keys.exists(&node_key(&index_name, "n3")));
+
+    assert_eq!(search(&keys, "life", &[3.2, 0.0]), vec!["n3", "n4", "n2"]);
+    assert!(matches!(
+        get_node_on(&keys, "life", "n3", false),
+        Ok(RedisValue::Array(_))
+    ));
+    assert!(matches!(
+        exists_node_on(&keys, "life", "n3"),
+        Ok(RedisValue::Integer(1))
+    ));
+
+    delete_node_on(&keys, "life", "n3").unwrap();
+    assert!(!keys.exists(&node_key(&index_name, "n3")));
+    assert!(matches!(
+        exists_node_on(&keys, "life", "n3"),
+        Ok(RedisValue::Integer(0))
+    ));
+    assert!(get_node_on(&keys, "life", "n3", false).is_err());
+    assert_eq!(search(&keys, "life", &[3.2, 0.0]), vec!["n4", "n2", "n5"]);
+}
+
+#[test]
+fn node_blob_format() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "blob", 3);
+    let data = |blob| match get_node_on(&keys, "blob", "n2", blob) {
+        Ok(RedisValue::Array(reply)) => reply[1].clone(),
+        r => panic!("unexpected reply: {:?}", r),
+    };
+    assert!(matches!(data(false), RedisValue::Array(v) if v.len() == 2));
+    match data(true) {
+        RedisValue::BulkString(s) => {
+            assert_eq!(s.len(), 8);
+            assert_eq!(
+                VectorFormat::Fp32Blob.decode(s.as_bytes()).unwrap(),
+                vec![2.0, 0.0]
+            );
+        }
+        r => panic!("unexpected data: {:?}", r),
+    }
+}
+
+#[test]
+fn reload_from_keys() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "reload", 20);
+    delete_node_on(&keys, "reload", "n7").unwrap();
+    let expected = search(&keys, "reload", &[7.0, 0.0]);
+
+    // the next command reads the graph back from the keys
+    evict_index(&cache_key(&keys, &index_key_of(&keys, "reload")));
+    assert_eq!(search(&keys, "reload", &[7.0, 0.0]), expected);
+    add_node_on(&keys, add_args("reload", "n7", &[7.0, 0.0])).unwrap();
+    assert_eq!(search(&keys, "reload", &[7.0, 0.0])[0], "n7");
+}
+
+#[test]
+fn search_with_vectors() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "vectors", 5);
+    let first = |blob| {
+        let mut args = search_args("vectors", 1, &[3.0, 0.0]);
+        args.with_vectors = true;
+        args.blob = blob;
+        match search_knn_on(&keys, args).unwrap() {
+            RedisValue::Array(items) => items[1].clone(),
+            r => panic!("unexpected reply: {:?}", r),
+        }
+    };
+
+    let result = first(false);
+    assert_eq!(field(&result, "name"), &RedisValue::from("n3"));
+    assert_eq!(
+        field(&result, "data"),
+        &RedisValue::Array(vec![RedisValue::Float(3.0), RedisValue::Float(0.0)])
+    );
+    let bytes = [3.0_f32.to_le_bytes(), 0.0_f32.to_le_bytes()].concat();
+    let blob = RedisValue::BulkString(unsafe { String::from_utf8_unchecked(bytes) });
+    assert_eq!(field(&first(true), "data"), &blob);
+}
+
+#[test]
+fn search_formats() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "formats", 5);
+    let reply = |format, with_stats| {
+        let mut args = search_args("formats", 3, &[1.0, 0.0]);
+        args.format = format;
+        args.with_stats = with_stats;
+        match search_knn_on(&keys, args).unwrap() {
+            RedisValue::Array(items) => items,
+            r => panic!("unexpected reply: {:?}", r),
+        }
+    };
+    let results = |value: &RedisValue| match value {
+        RedisValue::Array(results) => results.len(),
+        r => panic!("unexpected results: {:?}", r),
+    };
+
+    // the count followed by the results
+    let flat = reply(ReplyFormat::Flat, false);
+    assert!(matches!(flat[0], RedisValue::Integer(3)));
+    assert_eq!(flat.len(), 4);
+
+    // the array of results instead of the count, followed by the trailer
+    let nested = reply(ReplyFormat::Nested, false);
+    assert_eq!(nested.len(), 1);
+    assert_eq!(nested[0], RedisValue::Array(flat[1..].to_vec()));
+    let nested = reply(ReplyFormat::Nested, true);
+    assert_eq!(nested.len(), 3);
+    assert_eq!(results(&nested[0]), 3);
+    assert_eq!(nested[1], RedisValue::from("stats"));
+
+    let map = reply(ReplyFormat::Map, true);
+    assert_eq!(map.len(), 4);
+    assert_eq!(map[0], RedisValue::from("results"));
+    assert_eq!(results(&map[1]), 3);
+    assert_eq!(map[2], RedisValue::from("stats"));
+
+    assert_eq!(parse_format("MAP").unwrap(), ReplyFormat::Map);
+    assert!(parse_format("json").is_err());
+}
+
+#[test]
+fn bulk_delete() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "bulk", 10);
+    let index_name = index_key_of(&keys, "bulk");
+    let ctx: &dyn Backend = &keys;
+    let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+    // missing and repeated names are skipped
+    let deleted = delete_nodes_on(&keys, "bulk", &names(&["n1", "n3", "n9", "n3", "n42"]));
+    assert!(matches!(deleted, Ok(RedisValue::Integer(3))));
+    for node in &["n1", "n3", "n9"] {
+        assert!(!keys.exists(&node_key(&index_name, node)));
+    }
+    // the index key and the remaining node keys no longer refer to the deleted nodes
+    let ids = ctx
+        .index(&index_name, |ir| ir.nodes.clone())
+        .unwrap()
+        .unwrap();
+    assert_eq!(ids.len(), 7);
+    for name in ids.keys() {
+        let neighbors = ctx.node(name, |nr| nr.neighbors.clone()).unwrap().unwrap();
+        assert!(neighbors
+            .iter()
+            .flatten()
+            .all(|id| ids.values().any(|i| i == id)));
+    }
+    assert_eq!(search(&keys, "bulk", &[3.2, 0.0]), vec!["n4", "n2", "n5"]);
+
+    let deleted = delete_pattern_on(&keys, "bulk", "n[2-4]");
+    assert!(matches!(deleted, Ok(RedisValue::Integer(2))));
+    assert_eq!(search(&keys, "bulk", &[3.2, 0.0]), vec!["n5", "n6", "n0"]);
+    let deleted = delete_pattern_on(&keys, "bulk", "x*");
+    assert!(matches!(deleted, Ok(RedisValue::Integer(0))));
+
+    // the nodes of a sharded index are deleted from their shards
+    let mut sharded = new_args("bulkshards", 2);
+    sharded.shards = 3;
+    new_index_on(&keys, sharded).unwrap();
+    for i in 0..12 {
+        let node = format!("doc{}:{}", i % 2, i);
+        add_node_on(&keys, add_args("bulkshards", &node, &[i as f32, 0.0])).unwrap();
+    }
+    let deleted = delete_pattern_on(&keys, "bulkshards", "doc1:*");
+    assert!(matches!(deleted, Ok(RedisValue::Integer(6))));
+    let results = search(&keys, "bulkshards", &[5.0, 0.0]);
+    assert!(results.iter().all(|name| name.starts_with("doc0:")));
+}
+
+#[test]
+fn deferred_node_writes() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "deferred", 5);
+    let index_name = index_key_of(&keys, "deferred");
+    let node_name = node_key(&index_name, "n2");
+    let ctx: &dyn Backend = &keys;
+    let key_neighbors = || {
+        ctx.node(&node_name, |nr| nr.neighbors.clone())
+            .unwrap()
+            .unwrap()
+    };
+    // the key lags the graph, as if the add that changed the node had deferred its write
+    let lag = |window: u64| {
+        ctx.node_mut(&node_name, |nr| nr.neighbors = Vec::new())
+            .unwrap();
+        let index = load_index(ctx, &index_name).unwrap();
+        let index = read_index(&index).unwrap();
+        let value = NodeRedis::try_from(&index.nodes[&node_name]).unwrap();
+        let window = Duration::from_secs(window);
+        defer_writes(ctx, &index.name, vec![(node_name.clone(), value)], window).unwrap();
+    };
+    let graph_neighbors = || {
+        let index = load_index(ctx, &index_name).unwrap();
+        let index = read_index(&index).unwrap();
+        NodeRedis::try_from(&index.nodes[&node_name])
+            .unwrap()
+            .neighbors
+    };
+
+    let get = || format!("{:?}", get_node_on(&keys, "deferred", "n2", false).unwrap());
+
+    // reads reply the deferred value without writing the key
+    lag(3600);
+    let deferred = get();
+    assert!(key_neighbors().is_empty());
+
+    // the key is written once the window has passed
+    lag(0);
+    assert_eq!(key_neighbors(), graph_neighbors());
+    assert_eq!(get(), deferred);
+
+    // or by the next change other than an add
+    lag(3600);
+    delete_node_on(&keys, "deferred", "n4").unwrap();
+    assert!(!key_neighbors().is_empty());
+    assert_eq!(key_neighbors(), graph_neighbors());
+
+    // or before a failed change evicts the graph
+    lag(3600);
+    let expected = graph_neighbors();
+    drop(Journal::new(ctx, &index_name));
+    assert_eq!(key_neighbors(), expected);
+}
+
+#[test]
+fn interrupted_add_is_dropped() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "recover", 5);
+    let index_name = index_key_of(&keys, "recover");
+    let node_name = node_key(&index_name, "n4");
+
+    // the node key was written but the index key still journals the add
+    let ctx: &dyn Backend = &keys;
+    ctx.index_mut(&index_name, |ir| {
+        ir.nodes.remove(&node_name);
+        ir.pending = Some(PendingChange::Add(node_name.clone()));
+    })
+    .unwrap();
+    evict_index(&cache_key(&keys, &index_name));
+
+    // reads leave the keys alone, the next write recovers them
+    assert!(matches!(
+        search_knn_on(&keys, search_args("recover", 3, &[1.0, 0.0])),
+        Err(RedisError::String(e)) if e.starts_with("TRYAGAIN")
+    ));
+    load_index_mut(&keys, &index_name).unwrap();
+
+    assert_eq!(
+        search(&keys, "recover", &[4.0, 0.0]),
+        vec!["n3", "n2", "n1"]
+    );
+    assert!(!keys.exists(&node_name));
+    let neighbors = ctx
+        .node(&node_key(&index_name, "n3"), |nr| nr.neighbors.clone())
+        .unwrap()
+        .unwrap();
+    assert!(neighbors.iter().all(|layer| layer.len() < 4));
+}
+
+#[test]
+fn interrupted_update_keeps_node() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "recoverupdate", 5);
+    let index_name = index_key_of(&keys, "recoverupdate");
+    let node_name = node_key(&index_name, "n2");
+
+    // the update linked an id the index key doesn't list before it was interrupted
+    let ctx: &dyn Backend = &keys;
+    ctx.index_mut(&index_name, |ir| {
+        ir.pending = Some(PendingChange::Update(node_name.clone()));
+    })
+    .unwrap();
+    ctx.node_mut(&node_name, |nr| nr.neighbors[0].push(999))
+        .unwrap();
+    evict_index(&cache_key(&keys, &index_name));
+
+    // reads leave the keys alone, the next write recovers them
+    assert!(matches!(
+        search_knn_on(&keys, search_args("recoverupdate", 3, &[1.0, 0.0])),
+        Err(RedisError::String(e)) if e.starts_with("TRYAGAIN")
+    ));
+    load_index_mut(&keys, &index_name).unwrap();
+
+    assert_eq!(
+        search(&keys, "recoverupdate", &[2.2, 0.0]),
+        vec!["n2", "n3", "n1"]
+    );
+    assert!(keys.exists(&node_name));
+    let neighbors = ctx
+        .node(&node_name, |nr| nr.neighbors.clone())
+        .unwrap()
+        .unwrap();
+    assert!(!neighbors.is_empty() && !neighbors[0].is_empty());
+    assert!(neighbors.iter().all(|layer| !layer.contains(&999)));
+    let (count, pending) = ctx
+        .index(&index_name, |ir| (ir.node_count, ir.pending.is_none()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(count, 5);
+    assert!(pending);
+}
+
+#[test]
+fn empty_index_search() {
+    let keys = MemoryBackend::new();
+    new_index_on(&keys, new_args("empty", 2)).unwrap();
+    let mut sharded = new_args("emptyshards", 2);
+    sharded.shards = 2;
+    new_index_on(&keys, sharded).unwrap();
+
+    for index in &["empty", "emptyshards"] {
+        assert_eq!(
+            search_knn_on(&keys, search_args(index, 3, &[1.0, 0.0])).unwrap(),
+            RedisValue::Array(vec![RedisValue::Integer(0)])
+        );
+    }
+    // the query is still checked
+    assert!(search_knn_on(&keys, search_args("empty", 3, &[1.0])).is_err());
+}
+
+#[test]
+fn unloadable_index_reply() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "broken", 5);
+    let index_name = index_key_of(&keys, "broken");
+    keys.delete(&node_key(&index_name, "n1")).unwrap();
+    keys.delete(&node_key(&index_name, "n2")).unwrap();
+    evict_index(&cache_key(&keys, &index_name));
+    let err = load_index(&keys, &index_name).err().unwrap();
+
+    let reply = unloadable_index(&keys, &index_name, err).unwrap();
+    assert_eq!(field(&reply, "state"), &RedisValue::from("unloadable"));
+    assert_eq!(field(&reply, "missing_nodes"), &RedisValue::Integer(2));
+    assert_eq!(field(&reply, "node_count"), &RedisValue::Integer(5));
+    assert!(
+        matches!(field(&reply, "error"), RedisValue::BulkString(e) if e.contains("does not exist"))
+    );
+
+    // no index key, no reply
+    assert!(unloadable_index(&keys, "hnsw:idx:{nothing}", "gone".into()).is_err());
+}
+
+#[test]
+fn degree_stats() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "degrees", 30);
+    let mut sharded = new_args("degreeshards", 2);
+    sharded.shards = 3;
+    new_index_on(&keys, sharded).unwrap();
+    for i in 0..30 {
+        let node = format!("n{}", i);
+        add_node_on(&keys, add_args("degreeshards", &node, &[i as f32, 0.0])).unwrap();
+    }
+
+    for index in &["degrees", "degreeshards"] {
+        let layers = match degree_stats_on(&keys, index, "") {
+            Ok(RedisValue::Array(reply)) => match &reply[1] {
+                RedisValue::Array(layers) => layers.clone(),
+                r => panic!("unexpected layers: {:?}", r),
+            },
+            r => panic!("unexpected reply: {:?}", r),
+        };
+        let layer0 = match &layers[0] {
+            RedisValue::Array(layer) => layer.clone(),
+            r => panic!("unexpected layer: {:?}", r),
+        };
+        assert_eq!(layer0[1], RedisValue::Integer(0));
+        assert_eq!(layer0[3], RedisValue::Integer(30));
+        // the node counts of the degrees sum to the nodes of the layer
+        let counts = match &layer0[7] {
+            RedisValue::Array(degrees) => degrees
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .map(|c| match c {
+                    RedisValue::Integer(c) => *c,
+                    c => panic!("unexpected count: {:?}", c),
+                })
+                .sum::<i64>(),
+            r => panic!("unexpected degrees: {:?}", r),
+        };
+        assert_eq!(counts, 30);
+    }
+    assert!(degree_stats_on(&keys, "degrees", "vec").is_err());
+}
+
+#[test]
+fn selftest_reply() {
+    let keys = MemoryBackend::new();
+    let reply = match selftest_on(&keys, 7) {
+        Ok(RedisValue::Array(reply)) => reply,
+        r => panic!("unexpected reply: {:?}", r),
+    };
+    assert_eq!(reply[1], RedisValue::Integer(1));
+    assert!(matches!(&reply[5], RedisValue::Array(checks) if checks.len() == 9));
+    // the temporary graph leaves no key behind
+    assert!(keys.keys().is_empty());
+}
+
+#[test]
+fn keyspace_checks() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "checks", 1);
+    assert!(new_index_on(&keys, new_args("checks", 2)).is_err());
+    assert!(add_node_on(&keys, add_args("checks", "n0", &[1.0, 1.0])).is_err());
+    assert!(add_node_on(&keys, add_args("missing", "n0", &[1.0, 1.0])).is_err());
+
+    // keys of other types are not overwritten
+    let index_name = index_key_of(&keys, "checks");
+    keys.set_other(&node_key(&index_name, "taken"));
+    assert!(add_node_on(&keys, add_args("checks", "taken", &[1.0, 1.0])).is_err());
+
+    // writes are rejected while loading
+    keys.flags.set(raw::REDISMODULE_CTX_FLAGS_LOADING);
+    assert!(new_index_on(&keys, new_args("loading", 2)).is_err());
+    assert!(delete_node_on(&keys, "checks", "n0").is_err());
+    assert!(!keys.exists(&index_key_of(&keys, "loading")));
+}
+
+#[test]
+fn dedupe_results() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "dedupe", 6);
+    add_node_on(&keys, add_args("dedupe", "copy", &[2.0, 0.0])).unwrap();
+
+    let mut args = search_args("dedupe", 3, &[2.0, 0.0]);
+    args.dedupe = Some(Dedupe::new(0.0).unwrap());
+    let reply = search_knn_on(&keys, args).unwrap();
+    let items = match reply {
+        RedisValue::Array(items) => items,
+        r => panic!("unexpected reply: {:?}", r),
+    };
+    assert_eq!(items[0], RedisValue::Integer(3));
+    let fields = match &items[1] {
+        RedisValue::Array(fields) => fields,
+        r => panic!("unexpected result: {:?}", r),
+    };
+    assert_eq!(fields[4], RedisValue::from("aliases"));
+    // either copy of the vector is the result, the other one its alias
+    let alias_of = |name: &str| RedisValue::Array(vec![RedisValue::from(name)]);
+    assert!(
+        (fields[3] == RedisValue::from("n2") && fields[5] == alias_of("copy"))
+            || (fields[3] == RedisValue::from("copy") && fields[5] == alias_of("n2"))
+    );
+}
+
+#[test]
+fn field_args() {
+    let strings = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+
+    let mut args = strings(&[
+        "hnsw.new",
+        "foo",
+        "NORMALIZE",
+        "FIELD",
+        "text",
+        "DIM",
+        "4",
+        "NAMEPATTERN",
+        "field",
+    ]);
+    assert_eq!(
+        take_field_dims(&mut args).unwrap(),
+        vec![("text".to_owned(), 4)]
+    );
+    assert_eq!(
+        args,
+        strings(&["hnsw.new", "foo", "NORMALIZE", "NAMEPATTERN", "field"])
+    );
+
+    // values named field are left to their option
+    let mut args = strings(&[
+        "hnsw.node.add",
+        "foo",
+        "bar",
+        "TAGS",
+        "field",
+        "FIELD",
+        "text",
+        "DATA",
+        "2",
+        "1",
+        "2",
+    ]);
+    let fields = take_field_data(&mut args).unwrap();
+    assert_eq!(fields, vec![("text".to_owned(), vec![1.0, 2.0])]);
+    assert_eq!(
+        args,
+        strings(&["hnsw.node.add", "foo", "bar", "TAGS", "field"])
+    );
+    let mut args = strings(&[
+        "hnsw.node.add",
+        "foo",
+        "field",
+        "DATA",
+        "1",
+        "3",
+        "ATTRS",
+        "field",
+    ]);
+    assert!(take_field_data(&mut args).unwrap().is_empty());
+    assert_eq!(args.len(), 8);
+}
+
+#[test]
+fn vector_args() {
+    assert_eq!(
+        vector_arg("DATA", vec![1.0, -2.5]).unwrap(),
+        vec![1.0, -2.5]
+    );
+    // beyond the range of f32
+    assert!(matches!(
+        vector_arg("DATA", vec![1.0, 1e300]),
+        Err(RedisError::String(e)) if e == "Invalid DATA: value inf at position 1 is not finite"
+    ));
+    assert!(vector_arg("QUERY", vec![f64::NAN]).is_err());
+
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "finite", 3);
+    assert!(add_node_on(&keys, add_args("finite", "x", &[f32::NAN, 0.0])).is_err());
+    assert!(search_knn_on(&keys, search_args("finite", 3, &[0.0, f32::INFINITY])).is_err());
+    assert_eq!(search(&keys, "finite", &[0.0, 0.0]), vec!["n0", "n1", "n2"]);
+}
+
+#[test]
+fn duplicate_aliases() {
+    let keys = MemoryBackend::new();
+    let args = NewIndexArgs {
+        duplicates: DuplicatePolicy::Alias,
+        dup_epsilon: 0.01,
+        ..new_args("dup", 2)
+    };
+    new_index_on(&keys, args).unwrap();
+    add_node_on(&keys, add_args("dup", "a", &[1.0, 0.0])).unwrap();
+    add_node_on(&keys, add_args("dup", "b", &[1.0, 0.005])).unwrap();
+    add_node_on(&keys, add_args("dup", "c", &[2.0, 0.0])).unwrap();
+    let index_name = index_key_of(&keys, "dup");
+    // the alias has no key of its own
+    assert!(!keys.exists(&node_key(&index_name, "b")));
+    assert!(add_node_on(&keys, add_args("dup", "b", &[3.0, 0.0])).is_err());
+    assert!(matches!(
+        exists_node_on(&keys, "dup", "b"),
+        Ok(RedisValue::Integer(1))
+    ));
+    assert_eq!(
+        get_node_on(&keys, "dup", "b", false).unwrap(),
+        get_node_on(&keys, "dup", "a", false).unwrap()
+    );
+    assert_eq!(search(&keys, "dup", &[1.0, 0.0]), vec!["a", "c"]);
+
+    // aliases are kept in the index key
+    evict_index(&cache_key(&keys, &index_name));
+    assert!(get_node_on(&keys, "dup", "b", false).is_ok());
+    delete_node_on(&keys, "dup", "b").unwrap();
+    assert!(keys.exists(&node_key(&index_name, "a")));
+    assert!(get_node_on(&keys, "dup", "b", false).is_err());
+
+    // deleting the node deletes its aliases
+    add_node_on(&keys, add_args("dup", "b", &[1.0, 0.0])).unwrap();
+    delete_node_on(&keys, "dup", "a").unwrap();
+    evict_index(&cache_key(&keys, &index_name));
+    assert!(matches!(
+        exists_node_on(&keys, "dup", "b"),
+        Ok(RedisValue::Integer(0))
+    ));
+}
+
+#[test]
+fn alias_lifecycle() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "aliased_a", 3);
+    add_nodes(&keys, "aliased_b", 3);
+
+    add_alias(&keys, "current", "aliased_a", false).unwrap();
+    assert_eq!(
+        index_key_of(&keys, "current"),
+        index_key_of(&keys, "aliased_a")
+    );
+    assert_eq!(search(&keys, "current", &[0.0, 0.0])[0], "n0");
+    assert!(add_alias(&keys, "current", "aliased_b", false).is_err());
+    // aliases name indexes, not other aliases or keys taken by indexes
+    assert!(add_alias(&keys, "other", "current", false).is_err());
+    assert!(add_alias(&keys, "aliased_b", "aliased_a", false).is_err());
+    assert!(new_index_on(&keys, new_args("current", 2)).is_err());
+
+    add_alias(&keys, "current", "aliased_b", true).unwrap();
+    assert_eq!(
+        index_key_of(&keys, "current"),
+        index_key_of(&keys, "aliased_b")
+    );
+    let aliases = |index: &str| aliases_of(&keys, &index_key_of(&keys, index)).unwrap();
+    assert!(aliases("aliased_a").is_empty());
+    assert_eq!(aliases("aliased_b"), vec!["current".to_owned()]);
+
+    del_alias(&keys, "current").unwrap();
+    assert!(del_alias(&keys, "current").is_err());
+    assert!(search_knn_on(&keys, search_args("current", 1, &[0.0, 0.0])).is_err());
+}
+
+#[test]
+fn reindex_swap() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "reindexed", 20);
+    let index_name = index_key_of(&keys, "reindexed");
+    let original = load_index(&keys, &index_name).unwrap();
+    let rebuilt = read_index(&original).unwrap().rebuilt(8, 64).unwrap();
+
+    // nodes added while the graph is rebuilt are taken over by the swap
+    add_node_on(&keys, add_args("reindexed", "late", &[50.0, 0.0])).unwrap();
+    swap_rebuilt(&keys, &index_name, &original, rebuilt).unwrap();
+    assert_eq!(search(&keys, "reindexed", &[50.0, 0.0])[0], "late");
+    let index = load_index(&keys, &index_name).unwrap();
+    assert_eq!(read_index(&index).unwrap().m, 8);
+    assert_eq!(read_index(&index).unwrap().node_count, 21);
+
+    // a rebuild of another graph than the one loaded isn't swapped in
+    let rebuilt = read_index(&original).unwrap().rebuilt(5, 64).unwrap();
+    let stale = Arc::new(RwLock::new(rebuilt.clone()));
+    assert!(swap_rebuilt(&keys, &index_name, &stale, rebuilt).is_err());
+}
+
+#[test]
+fn snapshot_refresh() {
+    let keys = MemoryBackend::new();
+    let mut args = new_args("snap", 2);
+    args.snapshot_reads = true;
+    new_index_on(&keys, args).unwrap();
+    add_node_on(&keys, add_args("snap", "n0", &[0.0, 0.0])).unwrap();
+    let key = cache_key(&keys, &index_key_of(&keys, "snap"));
+
+    // searches don't copy the graph, the maintenance takes the snapshot
+    assert_eq!(search(&keys, "snap", &[0.0, 0.0]), vec!["n0"]);
+    assert!(!read_cache(&SNAPSHOTS).contains_key(&key));
+    let index = load_index(&keys, &key.1).unwrap();
+    refresh_snapshot(&key, &read_index(&index).unwrap()).unwrap();
+    assert!(read_cache(&SNAPSHOTS).contains_key(&key));
+
+    // a search blocked by a writer runs on the snapshot
+    let _writer = write_index(&index).unwrap();
+    assert!(matches!(
+        view_index(&key, &index),
+        Ok(IndexView::Snapshot(_))
+    ));
+}
+
+#[test]
+fn failed_bulk_delete_restores_index_key() {
+    let keys = MemoryBackend::new();
+    add_nodes(&keys, "bulkfail", 6);
+    let index_name = index_key_of(&keys, "bulkfail");
+    let ctx: &dyn Backend = &keys;
+    let names = ["n1", "n2"].map(String::from);
+
+    // deleting the key of the second node fails after the index key was written
+    *keys.failing_delete.borrow_mut() = Some(node_key(&index_name, "n2"));
+    assert!(delete_nodes_on(&keys, "bulkfail", &names).is_err());
+    *keys.failing_delete.borrow_mut() = None;
+    let ids = ctx
+        .index(&index_name, |ir| ir.nodes.clone())
+        .unwrap()
+        .unwrap();
+    assert_eq!(ids.len(), 6);
+    assert!(keys.exists(&node_key(&index_name, "n1")));
+    assert_eq!(search(&keys, "bulkfail", &[1.0, 0.0])[0], "n1");
+}
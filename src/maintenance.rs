@@ -0,0 +1,51 @@
+use crate::config::{self, log_event};
+
+use redis_module::{Context, LogLevel};
+use std::time::Duration;
+
+// how often a disabled timer checks whether maintenance was enabled again
+static IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// arms the timer, it runs on the main thread and re-arms itself after every run
+pub fn schedule(ctx: &Context) {
+    let period = config::maintenance_interval().unwrap_or(IDLE_INTERVAL);
+    ctx.create_timer(period, run, ());
+}
+
+fn run(ctx: &Context, _data: ()) {
+    if config::maintenance_interval().is_some() {
+        run_tasks(ctx);
+    }
+    schedule(ctx);
+}
+
+// indexes held by a writer are skipped and left for the next run
+fn run_tasks(ctx: &Context) {
+    if config::maintenance_enabled("cursors") {
+        super::purge_cursors();
+    }
+    if config::maintenance_enabled("snapshots") {
+        super::refresh_snapshots(ctx);
+    }
+    if config::maintenance_enabled("cache") {
+        super::evict_stale_indexes(ctx);
+    }
+    if config::maintenance_enabled("writes") {
+        super::write_due_nodes(ctx, config::write_window().unwrap_or_default());
+    }
+    if config::maintenance_enabled("stats") {
+        let stats = super::cache_stats();
+        log_event(
+            ctx,
+            LogLevel::Verbose,
+            "maintenance_stats",
+            &[
+                ("indexes", &stats.indexes),
+                ("nodes", &stats.nodes),
+                ("snapshots", &stats.snapshots),
+                ("cursors", &stats.cursors),
+                ("deleting", &stats.deleting),
+            ],
+        );
+    }
+}
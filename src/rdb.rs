@@ -0,0 +1,643 @@
+use redis_module::{raw, RedisString};
+
+use std::collections::HashMap;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+use super::config;
+use super::hnsw::{
+    metrics, pack_f32s, unpack_f32s, DuplicatePolicy, HNSWError, Location, StorageKind,
+};
+use super::types::{FollowRule, IndexRedis, NodeRedis, PendingChange};
+
+// encoding versions of the module types, the version of the last step of their ladder
+pub const INDEX_VERSION: i32 = 19;
+pub const NODE_VERSION: i32 = 9;
+
+// values of a module type in an RDB file, redis io in the module and a buffer in tests
+pub trait RdbIO {
+    fn load_unsigned(&mut self) -> u64;
+    fn load_double(&mut self) -> f64;
+    fn load_float(&mut self) -> f32;
+    fn load_string(&mut self) -> String;
+    fn load_buffer(&mut self) -> Vec<u8>;
+
+    fn save_unsigned(&mut self, value: u64);
+    fn save_double(&mut self, value: f64);
+    fn save_float(&mut self, value: f32);
+    fn save_string(&mut self, value: &str);
+    fn save_buffer(&mut self, value: &[u8]);
+}
+
+pub struct ModuleIO(pub *mut raw::RedisModuleIO);
+
+impl RdbIO for ModuleIO {
+    fn load_unsigned(&mut self) -> u64 {
+        unsafe { raw::RedisModule_LoadUnsigned.unwrap()(self.0) }
+    }
+
+    fn load_double(&mut self) -> f64 {
+        unsafe { raw::RedisModule_LoadDouble.unwrap()(self.0) }
+    }
+
+    fn load_float(&mut self) -> f32 {
+        unsafe { raw::RedisModule_LoadFloat.unwrap()(self.0) }
+    }
+
+    fn load_string(&mut self) -> String {
+        unsafe {
+            let s = raw::RedisModule_LoadString.unwrap()(self.0);
+            RedisString::from_ptr(s).unwrap().to_owned()
+        }
+    }
+
+    fn load_buffer(&mut self) -> Vec<u8> {
+        unsafe {
+            let mut len = 0;
+            let buf = raw::RedisModule_LoadStringBuffer.unwrap()(self.0, &mut len);
+            if buf.is_null() {
+                return Vec::new();
+            }
+            let bytes = std::slice::from_raw_parts(buf as *const u8, len).to_vec();
+            raw::RedisModule_Free.unwrap()(buf as *mut c_void);
+            bytes
+        }
+    }
+
+    fn save_unsigned(&mut self, value: u64) {
+        unsafe { raw::RedisModule_SaveUnsigned.unwrap()(self.0, value) }
+    }
+
+    fn save_double(&mut self, value: f64) {
+        unsafe { raw::RedisModule_SaveDouble.unwrap()(self.0, value) }
+    }
+
+    fn save_float(&mut self, value: f32) {
+        unsafe { raw::RedisModule_SaveFloat.unwrap()(self.0, value) }
+    }
+
+    fn save_string(&mut self, value: &str) {
+        let s = RedisString::create(ptr::null_mut(), value);
+        unsafe { raw::RedisModule_SaveString.unwrap()(self.0, s.inner) }
+    }
+
+    fn save_buffer(&mut self, value: &[u8]) {
+        unsafe {
+            raw::RedisModule_SaveStringBuffer.unwrap()(
+                self.0,
+                value.as_ptr() as *const c_char,
+                value.len(),
+            )
+        }
+    }
+}
+
+// a version of the format, load reads the values the version appended given the version the
+// value was saved with, migrate fills them in for values saved by older versions
+struct Step<V> {
+    version: i32,
+    load: fn(&mut dyn RdbIO, &mut V, i32) -> Result<(), HNSWError>,
+    migrate: fn(&mut V),
+}
+
+// after the head of version 0 each step up to the saved version is read, the steps above
+// it migrate the value
+fn climb<V>(
+    io: &mut dyn RdbIO,
+    encver: i32,
+    mut value: V,
+    ladder: &[Step<V>],
+) -> Result<V, HNSWError> {
+    for step in ladder {
+        if step.version <= encver {
+            (step.load)(io, &mut value, encver)?;
+        } else {
+            (step.migrate)(&mut value);
+        }
+    }
+    Ok(value)
+}
+
+fn keep<V>(_value: &mut V) {}
+
+fn load_strings(io: &mut dyn RdbIO) -> Vec<String> {
+    let len = io.load_unsigned() as usize;
+    (0..len).map(|_| io.load_string()).collect()
+}
+
+fn save_strings<'a>(io: &mut dyn RdbIO, strings: impl ExactSizeIterator<Item = &'a String>) {
+    io.save_unsigned(strings.len() as u64);
+    for s in strings {
+        io.save_string(s);
+    }
+}
+
+fn load_ids(io: &mut dyn RdbIO) -> Vec<u32> {
+    let len = io.load_unsigned() as usize;
+    (0..len).map(|_| io.load_unsigned() as u32).collect()
+}
+
+fn save_ids(io: &mut dyn RdbIO, ids: &[u32]) {
+    io.save_unsigned(ids.len() as u64);
+    for id in ids {
+        io.save_unsigned(*id as u64);
+    }
+}
+
+fn load_floats(io: &mut dyn RdbIO) -> Vec<f32> {
+    let len = io.load_unsigned() as usize;
+    (0..len).map(|_| io.load_float()).collect()
+}
+
+fn save_floats(io: &mut dyn RdbIO, floats: &[f32]) {
+    io.save_unsigned(floats.len() as u64);
+    for v in floats {
+        io.save_float(*v);
+    }
+}
+
+static INDEX_LADDER: [Step<IndexRedis>; 19] = [
+    Step {
+        version: 1,
+        load: |io, index, _| {
+            index.fields = load_strings(io);
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 2,
+        load: |io, index, _| {
+            index.normalize = io.load_unsigned() != 0;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    // older indexes always used both heuristics
+    Step {
+        version: 3,
+        load: |io, index, _| {
+            index.extend_candidates = io.load_unsigned() != 0;
+            index.keep_pruned_connections = io.load_unsigned() != 0;
+            Ok(())
+        },
+        migrate: |index| {
+            index.extend_candidates = true;
+            index.keep_pruned_connections = true;
+        },
+    },
+    // metric ids replaced the metric names of the head, see load_index_head
+    Step {
+        version: 4,
+        load: |_, _, _| Ok(()),
+        migrate: keep,
+    },
+    // layer membership moved to the node keys, older layers are read by the head and the
+    // node levels restored from them when the nodes are loaded
+    Step {
+        version: 5,
+        load: |_, _, _| Ok(()),
+        migrate: keep,
+    },
+    Step {
+        version: 6,
+        load: |io, index, _| {
+            index.frozen = io.load_unsigned() != 0;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 7,
+        load: |io, index, _| {
+            index.shards = io.load_unsigned() as usize;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 8,
+        load: |io, index, _| {
+            index.snapshot_reads = io.load_unsigned() != 0;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 9,
+        load: |io, index, _| {
+            index.pending = match io.load_unsigned() {
+                0 => None,
+                1 => Some(PendingChange::Add(io.load_string())),
+                2 => Some(PendingChange::Delete(io.load_string())),
+                _ => Some(PendingChange::Update(io.load_string())),
+            };
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 10,
+        load: |io, index, _| {
+            index.name_pattern = io.load_string();
+            index.max_name_len = io.load_unsigned() as usize;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 11,
+        load: |io, index, _| {
+            index.project_from = io.load_unsigned() as usize;
+            index.projection = load_floats(io);
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 12,
+        load: |io, index, _| {
+            index.projection_offset = load_floats(io);
+            Ok(())
+        },
+        migrate: keep,
+    },
+    // an empty pattern marks an index not following any keys
+    Step {
+        version: 13,
+        load: |io, index, _| {
+            let pattern = io.load_string();
+            let field = io.load_string();
+            let format = io.load_string();
+            if !pattern.is_empty() {
+                index.follow = Some(FollowRule {
+                    pattern,
+                    field,
+                    format,
+                });
+            }
+            Ok(())
+        },
+        migrate: keep,
+    },
+    // centroids all have the dimensionality of the index
+    Step {
+        version: 14,
+        load: |io, index, _| {
+            let k = io.load_unsigned() as usize;
+            let dim = io.load_unsigned() as usize;
+            index.centroids = (0..k)
+                .map(|_| (0..dim).map(|_| io.load_float()).collect())
+                .collect();
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 15,
+        load: |io, index, _| {
+            index.flat_limit = io.load_unsigned() as usize;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 16,
+        load: |io, index, _| {
+            index.storage = StorageKind::from_id(io.load_unsigned())?;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    // node ids are saved with the node names, see load_index_head
+    Step {
+        version: 17,
+        load: |_, _, _| Ok(()),
+        migrate: keep,
+    },
+    Step {
+        version: 18,
+        load: |io, index, _| {
+            index.aliases = load_strings(io);
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 19,
+        load: |io, index, _| {
+            index.duplicates = DuplicatePolicy::from_id(io.load_unsigned())?;
+            index.dup_epsilon = io.load_float();
+            let num_aliases = io.load_unsigned() as usize;
+            for _a in 0..num_aliases {
+                let alias = io.load_string();
+                index.node_aliases.insert(alias, io.load_string());
+            }
+            Ok(())
+        },
+        migrate: keep,
+    },
+];
+
+// the values of version 0, except for the two later changes of the head itself
+fn load_index_head(io: &mut dyn RdbIO, encver: i32) -> Result<IndexRedis, HNSWError> {
+    let mut index = IndexRedis {
+        name: io.load_string(),
+        ..IndexRedis::default()
+    };
+
+    // the metric is stored by its id since version 4, by name before
+    let mfunc_kind = if encver >= 4 {
+        metrics::MetricFuncs::from_id(io.load_unsigned())
+    } else {
+        metrics::MetricFuncs::from_name(&io.load_string())
+    };
+    index.mfunc_kind = mfunc_kind.map_err(|e| format!("Index: {} {}", index.name, e))?;
+
+    index.data_dim = io.load_unsigned() as usize;
+    index.m = io.load_unsigned() as usize;
+    index.m_max = io.load_unsigned() as usize;
+    index.m_max_0 = io.load_unsigned() as usize;
+    index.ef_construction = io.load_unsigned() as usize;
+    index.level_mult = io.load_double();
+    index.node_count = io.load_unsigned() as usize;
+    index.max_layer = io.load_unsigned() as usize;
+
+    // layer membership moved to the node keys in version 5
+    if encver < 5 {
+        let num_layers = io.load_unsigned() as usize;
+        index.layers = (0..num_layers).map(|_| load_strings(io)).collect();
+    }
+
+    // node ids are saved since version 17, older nodes are numbered in the order they load
+    let num_nodes = io.load_unsigned() as usize;
+    index.nodes = HashMap::with_capacity(num_nodes);
+    for n in 0..num_nodes {
+        let name = io.load_string();
+        let id = match encver >= 17 {
+            true => io.load_unsigned() as u32,
+            false => n as u32,
+        };
+        index.nodes.insert(name, id);
+    }
+
+    let ep = io.load_string();
+    index.enterpoint = match ep.as_str() {
+        "null" => None,
+        _ => Some(ep),
+    };
+    Ok(index)
+}
+
+pub fn load_index(io: &mut dyn RdbIO, encver: i32) -> Result<IndexRedis, HNSWError> {
+    if encver > INDEX_VERSION {
+        return Err(format!("Unknown index encoding version: {}", encver).into());
+    }
+    let index = load_index_head(io, encver)?;
+    climb(io, encver, index, &INDEX_LADDER)
+}
+
+// saves the format of the last step
+pub fn save_index(io: &mut dyn RdbIO, index: &IndexRedis) {
+    io.save_string(&index.name);
+    io.save_unsigned(index.mfunc_kind.id());
+    io.save_unsigned(index.data_dim as u64);
+    io.save_unsigned(index.m as u64);
+    io.save_unsigned(index.m_max as u64);
+    io.save_unsigned(index.m_max_0 as u64);
+    io.save_unsigned(index.ef_construction as u64);
+    io.save_double(index.level_mult);
+    io.save_unsigned(index.node_count as u64);
+    io.save_unsigned(index.max_layer as u64);
+    io.save_unsigned(index.nodes.len() as u64);
+    for (name, id) in &index.nodes {
+        io.save_string(name);
+        io.save_unsigned(*id as u64);
+    }
+    io.save_string(index.enterpoint.as_deref().unwrap_or("null"));
+
+    save_strings(io, index.fields.iter());
+    io.save_unsigned(index.normalize as u64);
+    io.save_unsigned(index.extend_candidates as u64);
+    io.save_unsigned(index.keep_pruned_connections as u64);
+    io.save_unsigned(index.frozen as u64);
+    io.save_unsigned(index.shards as u64);
+    io.save_unsigned(index.snapshot_reads as u64);
+    match &index.pending {
+        Some(p) => {
+            io.save_unsigned(p.id());
+            io.save_string(p.node_name());
+        }
+        None => io.save_unsigned(0),
+    }
+    io.save_string(&index.name_pattern);
+    io.save_unsigned(index.max_name_len as u64);
+    io.save_unsigned(index.project_from as u64);
+    save_floats(io, &index.projection);
+    save_floats(io, &index.projection_offset);
+    let (pattern, field, format) = match &index.follow {
+        Some(f) => (f.pattern.as_str(), f.field.as_str(), f.format.as_str()),
+        None => ("", "", ""),
+    };
+    for v in &[pattern, field, format] {
+        io.save_string(v);
+    }
+    io.save_unsigned(index.centroids.len() as u64);
+    io.save_unsigned(index.centroids.first().map_or(0, |c| c.len()) as u64);
+    for c in &index.centroids {
+        for v in c {
+            io.save_float(*v);
+        }
+    }
+    io.save_unsigned(index.flat_limit as u64);
+    io.save_unsigned(index.storage.id());
+    save_strings(io, index.aliases.iter());
+    io.save_unsigned(index.duplicates.id());
+    io.save_float(index.dup_epsilon);
+    io.save_unsigned(index.node_aliases.len() as u64);
+    for (alias, node) in &index.node_aliases {
+        io.save_string(alias);
+        io.save_string(node);
+    }
+}
+
+// vectors are packed into one string each since version 8
+fn load_vector(io: &mut dyn RdbIO, packed: bool) -> Result<Vec<f32>, HNSWError> {
+    if packed {
+        unpack_f32s(&io.load_buffer())
+    } else {
+        Ok(load_floats(io))
+    }
+}
+
+static NODE_LADDER: [Step<NodeRedis>; 9] = [
+    Step {
+        version: 1,
+        load: |io, node, encver| {
+            let num_vectors = io.load_unsigned() as usize;
+            node.vectors = (0..num_vectors)
+                .map(|_| load_vector(io, encver >= 8))
+                .collect::<Result<_, _>>()?;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 2,
+        load: |io, node, encver| {
+            node.norms = load_vector(io, encver >= 8)?;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    // older levels are restored from the index layers
+    Step {
+        version: 3,
+        load: |io, node, _| {
+            node.level = io.load_unsigned() as usize;
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 4,
+        load: |io, node, _| {
+            node.tags = load_strings(io);
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 5,
+        load: |io, node, _| {
+            let num_attrs = io.load_unsigned() as usize;
+            for _a in 0..num_attrs {
+                let name = io.load_string();
+                node.attrs.insert(name, io.load_double());
+            }
+            Ok(())
+        },
+        migrate: keep,
+    },
+    Step {
+        version: 6,
+        load: |io, node, _| {
+            if io.load_unsigned() == 1 {
+                node.location = Some(Location {
+                    lat: io.load_double(),
+                    lon: io.load_double(),
+                });
+            }
+            Ok(())
+        },
+        migrate: keep,
+    },
+    // older nodes start at the first version
+    Step {
+        version: 7,
+        load: |io, node, _| {
+            node.version = io.load_unsigned();
+            Ok(())
+        },
+        migrate: |node| node.version = 1,
+    },
+    // vectors were packed into strings, which changes how the earlier steps read them
+    Step {
+        version: 8,
+        load: |_, _, _| Ok(()),
+        migrate: keep,
+    },
+    // neighbors are saved by id, a node not written since keeps the names it was loaded with
+    Step {
+        version: 9,
+        load: |io, node, _| {
+            let num_layers = io.load_unsigned() as usize;
+            node.neighbor_names = (0..num_layers).map(|_| load_strings(io)).collect();
+            Ok(())
+        },
+        migrate: keep,
+    },
+];
+
+pub fn load_node(io: &mut dyn RdbIO, encver: i32) -> Result<NodeRedis, HNSWError> {
+    if encver > NODE_VERSION {
+        return Err(format!("Unknown node encoding version: {}", encver).into());
+    }
+    let mut node = NodeRedis {
+        data: load_vector(io, encver >= 8)?,
+        ..NodeRedis::default()
+    };
+    let num_layers = io.load_unsigned() as usize;
+    if encver >= 9 {
+        node.neighbors = (0..num_layers).map(|_| load_ids(io)).collect();
+    } else {
+        node.neighbor_names = (0..num_layers).map(|_| load_strings(io)).collect();
+    }
+
+    climb(io, encver, node, &NODE_LADDER)
+}
+
+pub fn save_node(io: &mut dyn RdbIO, node: &NodeRedis) {
+    let compress = config::rdb_compression();
+
+    io.save_buffer(&pack_f32s(&node.data, compress));
+    io.save_unsigned(node.neighbors.len() as u64);
+    for l in &node.neighbors {
+        save_ids(io, l);
+    }
+
+    io.save_unsigned(node.vectors.len() as u64);
+    for v in &node.vectors {
+        io.save_buffer(&pack_f32s(v, compress));
+    }
+    io.save_buffer(&pack_f32s(&node.norms, compress));
+    io.save_unsigned(node.level as u64);
+    save_strings(io, node.tags.iter());
+    io.save_unsigned(node.attrs.len() as u64);
+    for (name, value) in &node.attrs {
+        io.save_string(name);
+        io.save_double(*value);
+    }
+    match &node.location {
+        Some(location) => {
+            io.save_unsigned(1);
+            io.save_double(location.lat);
+            io.save_double(location.lon);
+        }
+        None => io.save_unsigned(0),
+    }
+    io.save_unsigned(node.version);
+    io.save_unsigned(node.neighbor_names.len() as u64);
+    for l in &node.neighbor_names {
+        save_strings(io, l.iter());
+    }
+}
+
+// module settings saved once per RDB file before the keys. the registry isn't saved, its
+// entries are registered again as the index keys load
+pub fn save_aux(io: &mut dyn RdbIO) {
+    let settings = config::settings();
+    io.save_unsigned(settings.len() as u64);
+    for (name, value) in settings {
+        io.save_string(name);
+        io.save_string(&value);
+    }
+}
+
+// restores the saved settings, the errors of settings this module doesn't know or accept,
+// e.g. zstd compression without the zstd feature, are returned instead of failing the load
+pub fn load_aux(io: &mut dyn RdbIO, encver: i32) -> Result<Vec<String>, HNSWError> {
+    if encver > INDEX_VERSION {
+        return Err(format!("Unknown aux encoding version: {}", encver).into());
+    }
+    let len = io.load_unsigned() as usize;
+    let mut errors = Vec::new();
+    for _s in 0..len {
+        let name = io.load_string();
+        let value = io.load_string();
+        if let Err(e) = config::set(&name, &value) {
+            errors.push(e);
+        }
+    }
+    Ok(errors)
+}
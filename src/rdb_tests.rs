@@ -0,0 +1,345 @@
+use crate::config;
+use crate::hnsw::{metrics::MetricFuncs, pack_f32s, DuplicatePolicy, Location, StorageKind};
+use crate::rdb::*;
+use crate::types::{IndexRedis, NodeRedis, PendingChange};
+
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Unsigned(u64),
+    Double(f64),
+    Float(f32),
+    Str(String),
+    Buffer(Vec<u8>),
+}
+
+// values in the order a module type saved them
+#[derive(Default)]
+struct MemIO(VecDeque<Value>);
+
+impl MemIO {
+    fn of(values: Vec<Value>) -> Self {
+        MemIO(values.into())
+    }
+
+    fn next(&mut self) -> Value {
+        self.0.pop_front().expect("payload is exhausted")
+    }
+}
+
+impl RdbIO for MemIO {
+    fn load_unsigned(&mut self) -> u64 {
+        match self.next() {
+            Value::Unsigned(v) => v,
+            v => panic!("expected unsigned, got {:?}", v),
+        }
+    }
+
+    fn load_double(&mut self) -> f64 {
+        match self.next() {
+            Value::Double(v) => v,
+            v => panic!("expected double, got {:?}", v),
+        }
+    }
+
+    fn load_float(&mut self) -> f32 {
+        match self.next() {
+            Value::Float(v) => v,
+            v => panic!("expected float, got {:?}", v),
+        }
+    }
+
+    fn load_string(&mut self) -> String {
+        match self.next() {
+            Value::Str(v) => v,
+            v => panic!("expected string, got {:?}", v),
+        }
+    }
+
+    fn load_buffer(&mut self) -> Vec<u8> {
+        match self.next() {
+            Value::Buffer(v) => v,
+            v => panic!("expected buffer, got {:?}", v),
+        }
+    }
+
+    fn save_unsigned(&mut self, value: u64) {
+        self.0.push_back(Value::Unsigned(value));
+    }
+
+    fn save_double(&mut self, value: f64) {
+        self.0.push_back(Value::Double(value));
+    }
+
+    fn save_float(&mut self, value: f32) {
+        self.0.push_back(Value::Float(value));
+    }
+
+    fn save_string(&mut self, value: &str) {
+        self.0.push_back(Value::Str(value.to_owned()));
+    }
+
+    fn save_buffer(&mut self, value: &[u8]) {
+        self.0.push_back(Value::Buffer(value.to_vec()));
+    }
+}
+
+fn u(v: u64) -> Value {
+    Value::Unsigned(v)
+}
+
+fn s(v: &str) -> Value {
+    Value::Str(v.to_owned())
+}
+
+// head of an index saved by version 0, the metric by name and the layers in the index
+fn index_v0_head() -> Vec<Value> {
+    vec![
+        s("hnsw.foo"),
+        s("Cosine"),
+        u(2),
+        u(5),
+        u(5),
+        u(10),
+        u(16),
+        Value::Double(0.62),
+        u(2),
+        u(1),
+        // layers
+        u(2),
+        u(2),
+        s("hnsw.foo.a"),
+        s("hnsw.foo.b"),
+        u(1),
+        s("hnsw.foo.a"),
+        // nodes
+        u(2),
+        s("hnsw.foo.a"),
+        s("hnsw.foo.b"),
+        s("hnsw.foo.a"),
+    ]
+}
+
+#[test]
+fn load_index_v0() {
+    let mut io = MemIO::of(index_v0_head());
+    let index = load_index(&mut io, 0).unwrap();
+    assert!(io.0.is_empty());
+
+    assert_eq!(index.name, "hnsw.foo");
+    assert_eq!(index.mfunc_kind, MetricFuncs::Cosine);
+    assert_eq!((index.data_dim, index.m, index.m_max_0), (2, 5, 10));
+    assert_eq!(index.node_count, 2);
+    // nodes saved without ids are numbered in the order they load
+    assert_eq!(
+        (index.nodes["hnsw.foo.a"], index.nodes["hnsw.foo.b"]),
+        (0, 1)
+    );
+    assert_eq!(index.layers.len(), 2);
+    assert_eq!(index.layers[1], vec!["hnsw.foo.a".to_owned()]);
+    assert_eq!(index.enterpoint.as_deref(), Some("hnsw.foo.a"));
+    // migrated defaults of later versions
+    assert!(index.extend_candidates && index.keep_pruned_connections);
+    assert!(index.fields.is_empty() && !index.frozen);
+    assert_eq!((index.shards, index.flat_limit), (0, 0));
+    assert_eq!(index.storage, StorageKind::Heap);
+}
+
+#[test]
+fn load_index_v9() {
+    // version 4 stores the metric by id, version 5 drops the layers
+    let mut values = index_v0_head();
+    values[1] = u(MetricFuncs::InnerProduct.id());
+    values.drain(10..16);
+    values.extend(vec![
+        u(1),
+        s("vec"),
+        u(1),
+        u(0),
+        u(1),
+        u(1),
+        u(0),
+        u(1),
+        u(2),
+        s("hnsw.foo.b"),
+    ]);
+    let mut io = MemIO::of(values);
+    let index = load_index(&mut io, 9).unwrap();
+    assert!(io.0.is_empty());
+
+    assert_eq!(index.mfunc_kind, MetricFuncs::InnerProduct);
+    assert!(index.layers.is_empty());
+    assert_eq!(index.fields, vec!["vec".to_owned()]);
+    assert!(index.normalize && !index.extend_candidates && index.keep_pruned_connections);
+    assert!(index.frozen && index.snapshot_reads);
+    assert_eq!(
+        index.pending,
+        Some(PendingChange::Delete("hnsw.foo.b".to_owned()))
+    );
+    assert!(index.follow.is_none() && index.centroids.is_empty());
+}
+
+#[test]
+fn save_index_roundtrip() {
+    let mut index = IndexRedis {
+        name: "hnsw.foo".to_owned(),
+        mfunc_kind: MetricFuncs::Euclidean,
+        data_dim: 2,
+        m: 8,
+        m_max: 8,
+        m_max_0: 16,
+        ef_construction: 100,
+        level_mult: 0.48,
+        node_count: 1,
+        enterpoint: Some("hnsw.foo.a".to_owned()),
+        project_from: 3,
+        projection: vec![1.0; 6],
+        centroids: vec![vec![0.5, 1.5], vec![2.5, 3.5]],
+        flat_limit: 1000,
+        storage: StorageKind::Mmap,
+        pending: Some(PendingChange::Add("hnsw.foo.a".to_owned())),
+        aliases: vec!["bar".to_owned()],
+        duplicates: DuplicatePolicy::Alias,
+        dup_epsilon: 0.5,
+        ..IndexRedis::default()
+    };
+    index.nodes.insert("hnsw.foo.a".to_owned(), 3);
+    index
+        .node_aliases
+        .insert("hnsw.foo.b".to_owned(), "hnsw.foo.a".to_owned());
+
+    let mut io = MemIO::default();
+    save_index(&mut io, &index);
+    let loaded = load_index(&mut io, INDEX_VERSION).unwrap();
+    assert!(io.0.is_empty());
+
+    assert_eq!(loaded.nodes, index.nodes);
+    assert_eq!(loaded.enterpoint, index.enterpoint);
+    assert_eq!(loaded.projection, index.projection);
+    assert_eq!(loaded.centroids, index.centroids);
+    assert_eq!(loaded.flat_limit, 1000);
+    assert_eq!(loaded.storage, StorageKind::Mmap);
+    assert_eq!(loaded.pending, index.pending);
+    assert_eq!(loaded.aliases, index.aliases);
+    assert_eq!(loaded.duplicates, DuplicatePolicy::Alias);
+    assert_eq!(loaded.dup_epsilon, 0.5);
+    assert_eq!(loaded.node_aliases, index.node_aliases);
+
+    assert!(load_index(&mut MemIO::default(), INDEX_VERSION + 1).is_err());
+    let mut unknown = index_v0_head();
+    unknown[1] = s("Hamming");
+    assert!(load_index(&mut MemIO::of(unknown), 0).is_err());
+}
+
+#[test]
+fn load_node_v0() {
+    let mut io = MemIO::of(vec![
+        u(2),
+        Value::Float(1.0),
+        Value::Float(2.0),
+        u(1),
+        u(1),
+        s("hnsw.foo.b"),
+    ]);
+    let node = load_node(&mut io, 0).unwrap();
+    assert!(io.0.is_empty());
+
+    assert_eq!(node.data, vec![1.0, 2.0]);
+    assert!(node.neighbors.is_empty());
+    assert_eq!(node.neighbor_names, vec![vec!["hnsw.foo.b".to_owned()]]);
+    assert!(node.vectors.is_empty() && node.tags.is_empty());
+    // nodes start at the first version
+    assert_eq!(node.version, 1);
+}
+
+#[test]
+fn load_node_v7() {
+    let mut io = MemIO::of(vec![
+        u(1),
+        Value::Float(3.0),
+        u(0),
+        // vectors and norms
+        u(1),
+        u(1),
+        Value::Float(3.0),
+        u(1),
+        Value::Float(0.5),
+        u(2),
+        u(1),
+        s("red"),
+        u(1),
+        s("price"),
+        Value::Double(9.5),
+        u(1),
+        Value::Double(52.5),
+        Value::Double(13.4),
+        u(4),
+    ]);
+    let node = load_node(&mut io, 7).unwrap();
+    assert!(io.0.is_empty());
+
+    assert_eq!(node.vectors, vec![vec![3.0]]);
+    assert_eq!(node.norms, vec![0.5]);
+    assert_eq!((node.level, node.version), (2, 4));
+    assert_eq!(node.tags, vec!["red".to_owned()]);
+    assert_eq!(node.attrs["price"], 9.5);
+    assert_eq!(node.location.unwrap().lat, 52.5);
+}
+
+#[test]
+fn save_node_roundtrip() {
+    let mut node = NodeRedis {
+        data: vec![1.0, 2.0],
+        vectors: vec![vec![1.0, 2.0], vec![3.0, 4.0]],
+        norms: vec![2.0],
+        neighbors: vec![vec![1], vec![]],
+        level: 1,
+        tags: vec!["red".to_owned()],
+        location: Some(Location { lat: 1.0, lon: 2.0 }),
+        version: 3,
+        ..NodeRedis::default()
+    };
+    node.attrs.insert("price".to_owned(), 9.5);
+
+    let mut io = MemIO::default();
+    save_node(&mut io, &node);
+    // vectors are packed since version 8
+    assert_eq!(io.0[0], Value::Buffer(pack_f32s(&node.data, false)));
+    let loaded = load_node(&mut io, NODE_VERSION).unwrap();
+    assert!(io.0.is_empty());
+    assert_eq!(format!("{:?}", loaded), format!("{:?}", node));
+
+    // a node loaded with neighbor names keeps them until it is written with ids
+    let legacy = NodeRedis {
+        data: vec![1.0],
+        neighbor_names: vec![vec!["hnsw.foo.b".to_owned()]],
+        ..NodeRedis::default()
+    };
+    save_node(&mut io, &legacy);
+    let loaded = load_node(&mut io, NODE_VERSION).unwrap();
+    assert!(io.0.is_empty());
+    assert_eq!(loaded.neighbor_names, legacy.neighbor_names);
+
+    assert!(load_node(&mut MemIO::default(), NODE_VERSION + 1).is_err());
+    let mut corrupt = MemIO::of(vec![Value::Buffer(vec![9, 0, 0, 0, 0])]);
+    assert!(load_node(&mut corrupt, NODE_VERSION).is_err());
+}
+
+#[test]
+fn aux_roundtrip() {
+    config::set("loglevel", "warning").unwrap();
+    let mut io = MemIO::default();
+    save_aux(&mut io);
+    assert_eq!(io.0.len(), 1 + config::SETTINGS.len() * 2);
+
+    config::set("loglevel", "notice").unwrap();
+    assert!(load_aux(&mut io, INDEX_VERSION).unwrap().is_empty());
+    assert_eq!(config::settings()[0], ("loglevel", "warning".to_owned()));
+    config::set("loglevel", "notice").unwrap();
+
+    // settings of other builds are skipped
+    let mut io = MemIO::of(vec![u(1), s("maxthreads"), s("4")]);
+    assert_eq!(load_aux(&mut io, INDEX_VERSION).unwrap().len(), 1);
+    assert!(load_aux(&mut MemIO::default(), INDEX_VERSION + 1).is_err());
+}
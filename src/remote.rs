@@ -0,0 +1,114 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+// how long a remote server may take to answer a batch of commands
+static REMOTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// reply of a remote server, error replies are returned as errors by read_reply
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+// RESP array of bulk strings, how clients send commands
+pub fn encode_command(args: &[String]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for a in args {
+        buf.extend_from_slice(format!("${}\r\n", a.len()).as_bytes());
+        buf.extend_from_slice(a.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err("Connection closed".to_owned()),
+        Ok(_) => Ok(line.trim_end_matches("\r\n").to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_len(s: &str) -> Result<i64, String> {
+    s.parse::<i64>()
+        .map_err(|_| format!("Invalid reply length: {}", s))
+}
+
+// reads one reply, an error reply of the server is returned as Err
+pub fn read_reply(reader: &mut impl BufRead) -> Result<Reply, String> {
+    let line = read_line(reader)?;
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_owned())),
+        "-" => Err(rest.to_owned()),
+        ":" => Ok(Reply::Integer(parse_len(rest)?)),
+        "$" => match parse_len(rest)? {
+            n if n < 0 => Ok(Reply::Bulk(None)),
+            n => {
+                let mut buf = vec![0; n as usize + 2];
+                reader.read_exact(&mut buf).map_err(|e| e.to_string())?;
+                buf.truncate(n as usize);
+                Ok(Reply::Bulk(Some(buf)))
+            }
+        },
+        "*" => match parse_len(rest)? {
+            n if n < 0 => Ok(Reply::Array(Vec::new())),
+            n => (0..n)
+                .map(|_| read_reply(reader))
+                .collect::<Result<Vec<Reply>, String>>()
+                .map(Reply::Array),
+        },
+        _ => Err(format!("Invalid reply: {}", line)),
+    }
+}
+
+// connection to another redis server
+pub struct Remote {
+    reader: BufReader<TcpStream>,
+}
+
+impl Remote {
+    pub fn connect(host: &str, port: u16) -> Result<Self, String> {
+        let stream = TcpStream::connect((host, port))
+            .map_err(|e| format!("Connecting to {}:{} failed: {}", host, port, e))?;
+        stream
+            .set_read_timeout(Some(REMOTE_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(REMOTE_TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        Ok(Remote {
+            reader: BufReader::new(stream),
+        })
+    }
+
+    // sends the commands at once and waits for all replies, errors on the first error reply
+    pub fn pipeline(&mut self, commands: &[Vec<String>]) -> Result<Vec<Reply>, String> {
+        let buf = commands
+            .iter()
+            .flat_map(|c| encode_command(c))
+            .collect::<Vec<u8>>();
+        self.reader
+            .get_mut()
+            .write_all(&buf)
+            .map_err(|e| e.to_string())?;
+
+        // every reply is read so the connection stays in sync, the first error is returned
+        let mut replies = Vec::with_capacity(commands.len());
+        let mut error = None;
+        for c in commands {
+            match read_reply(&mut self.reader) {
+                Ok(r) => replies.push(r),
+                Err(e) if error.is_none() => error = Some(format!("{}: {}", c[0], e)),
+                Err(_) => (),
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(replies),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::remote::*;
+
+#[test]
+fn encode_commands() {
+    let cmd = vec!["HNSW.NODE.ADD".to_owned(), "foo".to_owned(), "".to_owned()];
+    assert_eq!(
+        encode_command(&cmd),
+        b"*3\r\n$13\r\nHNSW.NODE.ADD\r\n$3\r\nfoo\r\n$0\r\n\r\n".to_vec()
+    );
+}
+
+#[test]
+fn read_replies() {
+    let mut input = &b"+OK\r\n:42\r\n$5\r\nh\r\nnw\r\n$-1\r\n*2\r\n:1\r\n*1\r\n+a\r\n-ERR Index: hnsw.foo already exists\r\n"[..];
+    assert_eq!(
+        read_reply(&mut input).unwrap(),
+        Reply::Status("OK".to_owned())
+    );
+    assert_eq!(read_reply(&mut input).unwrap(), Reply::Integer(42));
+    // bulk strings may contain line breaks
+    assert_eq!(
+        read_reply(&mut input).unwrap(),
+        Reply::Bulk(Some(b"h\r\nnw".to_vec()))
+    );
+    assert_eq!(read_reply(&mut input).unwrap(), Reply::Bulk(None));
+    assert_eq!(
+        read_reply(&mut input).unwrap(),
+        Reply::Array(vec![
+            Reply::Integer(1),
+            Reply::Array(vec![Reply::Status("a".to_owned())])
+        ])
+    );
+    assert_eq!(
+        read_reply(&mut input).unwrap_err(),
+        "ERR Index: hnsw.foo already exists"
+    );
+    assert!(read_reply(&mut input).is_err());
+    assert!(read_reply(&mut &b"?\r\n"[..]).is_err());
+}
@@ -0,0 +1,71 @@
+use redis_module::{raw, Context, RedisError, RedisString, RedisValue};
+
+use std::ffi::CString;
+use std::os::raw::{c_int, c_long};
+use std::slice;
+
+use super::acl::get_api;
+use super::backend::Backend;
+use super::ReplyFormat;
+
+// REDISMODULE_CTX_FLAGS_RESP3 of redis 6.2, the bindings in use predate it
+const CTX_FLAGS_RESP3: u32 = 1 << 22;
+
+type ReplyWithMapFn = unsafe extern "C" fn(*mut raw::RedisModuleCtx, c_long) -> c_int;
+
+// creates hnsw.search without redis_command!, whose handlers can only reply a RedisValue,
+// which has no map. must run while the module is loading
+pub fn create_search_command(ctx: *mut raw::RedisModuleCtx, flags: &str) -> c_int {
+    let name = CString::new("hnsw.search").unwrap();
+    let flags = CString::new(flags).unwrap();
+    unsafe {
+        raw::RedisModule_CreateCommand.unwrap()(
+            ctx,
+            name.as_ptr(),
+            Some(search_knn),
+            flags.as_ptr(),
+            0,
+            0,
+            0,
+        )
+    }
+}
+
+// the handler redis_command! would generate, replying FORMAT map as a map to resp3 clients
+extern "C" fn search_knn(
+    ctx: *mut raw::RedisModuleCtx,
+    argv: *mut *mut raw::RedisModuleString,
+    argc: c_int,
+) -> c_int {
+    let context = Context::new(ctx);
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) }
+        .iter()
+        .map(|&arg| RedisString::from_ptr(arg).map(str::to_owned))
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|_| RedisError::Str("UTF8 encoding error in handler args"));
+    let res = args.and_then(|args| super::search_knn(&context, args));
+    let status = match res {
+        Ok((RedisValue::Array(pairs), ReplyFormat::Map)) if is_resp3(&context) => {
+            reply_map(&context, pairs)
+        }
+        res => context.reply(res.map(|(reply, _)| reply)),
+    };
+    status as c_int
+}
+
+fn is_resp3(ctx: &Context) -> bool {
+    Backend::flags(ctx) & CTX_FLAGS_RESP3 != 0
+}
+
+// replies alternating keys and values as a map, as the array they are without the api
+fn reply_map(ctx: &Context, pairs: Vec<RedisValue>) -> raw::Status {
+    let reply_with_map = match unsafe { get_api::<ReplyWithMapFn>("RedisModule_ReplyWithMap") } {
+        Some(f) => f,
+        None => return ctx.reply(Ok(RedisValue::Array(pairs))),
+    };
+    unsafe { reply_with_map(ctx.ctx, (pairs.len() / 2) as c_long) };
+    for value in pairs {
+        ctx.reply(Ok(value));
+    }
+    raw::Status::Ok
+}
@@ -1,30 +1,177 @@
 use redis_module::native_types::RedisType;
-use redis_module::{raw, RedisString, RedisValue};
+use redis_module::{raw, Context, RedisError, RedisValue};
 
 use num::Float;
 use rand::prelude::*;
-use std::collections::HashMap;
-use std::convert::From;
-use std::os::raw::c_void;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{From, TryFrom};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::{fmt, ptr};
 
-use super::hnsw::{metrics, Index, Node, SearchResult};
+use super::backend::Backend;
+use super::defrag::{self, TypeMethods};
+use super::hnsw::{
+    glob_match, metrics, Degrees, DuplicatePolicy, HNSWError, HeapStorage, Index, Location,
+    NameRule, Node, NodeStore, Projection, SearchResult, StorageKind, TagStore, UndoLog,
+    VectorFormat,
+};
+use super::rdb::{self, ModuleIO, INDEX_VERSION, NODE_VERSION};
+
+// values costing more allocations than this are freed on a background thread,
+// same threshold as redis lazyfree
+static LAZYFREE_THRESHOLD: usize = 64;
+
+lazy_static! {
+    // redis only frees values off the main thread with lazyfree enabled, the loaded
+    // graphs and snapshots aren't values at all, so large values are dropped by our own thread
+    static ref LAZYFREE: Mutex<mpsc::Sender<Box<dyn Send>>> = {
+        let (tx, rx) = mpsc::channel::<Box<dyn Send>>();
+        thread::spawn(move || {
+            for value in rx {
+                drop(value);
+            }
+        });
+        Mutex::new(tx)
+    };
+    // index keys in memory by registry id
+    static ref REGISTRY: Mutex<HashMap<u64, RegistryEntry>> = Mutex::new(HashMap::new());
+}
+
+// ids start at 1, 0 marks an unregistered index value
+static NEXT_REGISTRY_ID: AtomicU64 = AtomicU64::new(1);
+
+// registry entries following keys, keyspace events skip the registry while there are none
+static FOLLOWERS: AtomicUsize = AtomicUsize::new(0);
+
+// index key known to the module. keys are registered with a new id when created or loaded
+// and unregistered when freed, so deletes and flushes of the keys are tracked as well
+#[derive(Debug, Clone)]
+pub struct RegistryEntry {
+    pub id: u64,
+    pub key: String, // name of the index key
+    pub mfunc_kind: metrics::MetricFuncs,
+    pub data_dim: usize,
+    pub m: usize,
+    pub ef_construction: usize,
+    pub fields: Vec<String>,
+    pub shards: usize,
+    pub node_count: usize,
+    pub follow: Option<FollowRule>,
+    pub aliases: Vec<String>,
+}
+
+impl From<&IndexRedis> for RegistryEntry {
+    fn from(index: &IndexRedis) -> Self {
+        RegistryEntry {
+            id: index.registry_id,
+            key: index.name.clone(),
+            mfunc_kind: index.mfunc_kind,
+            data_dim: index.data_dim,
+            m: index.m,
+            ef_construction: index.ef_construction,
+            fields: index.fields.clone(),
+            shards: index.shards,
+            node_count: index.node_count,
+            follow: index.follow.clone(),
+            aliases: index.aliases.clone(),
+        }
+    }
+}
+
+pub fn register_index(index: &mut IndexRedis) {
+    index.registry_id = NEXT_REGISTRY_ID.fetch_add(1, Ordering::Relaxed);
+    let mut registry = REGISTRY.lock().unwrap();
+    let old = registry.insert(index.registry_id, RegistryEntry::from(&*index));
+    count_followers(old.as_ref(), index.follow.is_some());
+}
 
-static INDEX_VERSION: i32 = 0;
-static NODE_VERSION: i32 = 0;
+// keeps the parameters and node count of the entry in sync with the index key
+fn update_registry(index: &IndexRedis) {
+    if index.registry_id == 0 {
+        return;
+    }
+    if let Some(entry) = REGISTRY.lock().unwrap().get_mut(&index.registry_id) {
+        count_followers(Some(entry), index.follow.is_some());
+        *entry = RegistryEntry::from(index);
+    }
+}
+
+fn unregister_index(index: &IndexRedis) {
+    let old = REGISTRY.lock().unwrap().remove(&index.registry_id);
+    count_followers(old.as_ref(), false);
+}
+
+// counts an entry that starts or stops following keys, called with the registry locked
+fn count_followers(old: Option<&RegistryEntry>, follows: bool) {
+    match (old.is_some_and(|e| e.follow.is_some()), follows) {
+        (false, true) => FOLLOWERS.fetch_add(1, Ordering::Relaxed),
+        (true, false) => FOLLOWERS.fetch_sub(1, Ordering::Relaxed),
+        _ => 0,
+    };
+}
+
+// true if any index follows keys
+pub fn following() -> bool {
+    FOLLOWERS.load(Ordering::Relaxed) > 0
+}
+
+// registered index keys of every database sorted by key, including sub-indexes
+pub fn registry_entries() -> Vec<RegistryEntry> {
+    let mut entries = REGISTRY
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect::<Vec<RegistryEntry>>();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    entries
+}
+
+// registered index keys following keys matching the name, in any database
+pub fn followers(key: &str) -> Vec<RegistryEntry> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|e| {
+            e.follow
+                .as_ref()
+                .is_some_and(|f| glob_match(&f.pattern, key))
+        })
+        .cloned()
+        .collect()
+}
+
+// registered index keys the alias resolves to, in any database
+pub fn aliased(alias: &str) -> Vec<RegistryEntry> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|e| e.aliases.iter().any(|a| a == alias))
+        .cloned()
+        .collect()
+}
+
+// drops the value off the main thread if freeing it takes more than effort allocations
+pub fn free_lazily<V: Send + 'static>(value: V, effort: usize) {
+    if effort <= LAZYFREE_THRESHOLD {
+        return;
+    }
+    // the value is dropped inline if the background thread is gone
+    let _ = LAZYFREE.lock().unwrap().send(Box::new(value));
+}
 
 impl From<IndexRedis> for Index<f32, f32> {
     fn from(index: IndexRedis) -> Self {
         Index {
             name: index.name.clone(),
-            mfunc: match index.mfunc_kind.as_str() {
-                "Euclidean" => Box::new(metrics::euclidean),
-                _ => Box::new(metrics::euclidean),
-            },
-            mfunc_kind: match index.mfunc_kind.as_str() {
-                "Euclidean" => metrics::MetricFuncs::Euclidean,
-                _ => metrics::MetricFuncs::Euclidean,
-            },
+            mfunc: Box::new(metrics::metric_func(index.mfunc_kind, index.data_dim)),
+            mfunc_kind: index.mfunc_kind,
             data_dim: index.data_dim,
             m: index.m,
             m_max: index.m_max,
@@ -33,37 +180,130 @@ impl From<IndexRedis> for Index<f32, f32> {
             level_mult: index.level_mult,
             node_count: index.node_count,
             max_layer: index.max_layer,
-            // the next 3 need to be populated from redis
+            // the next 6 need to be populated from redis
             layers: Vec::new(),
             nodes: HashMap::new(),
+            slots: Vec::new(),
+            free_ids: Vec::new(),
             enterpoint: None,
+            tags: TagStore::default(),
+            // the pattern was validated when the index was created
+            names: NameRule::new(&index.name_pattern, index.max_name_len).unwrap_or_default(),
+            projection: match index.project_from {
+                0 => None,
+                from_dim => {
+                    let offset = index.projection_offset;
+                    Projection::new(from_dim, index.data_dim, index.projection)
+                        .and_then(|p| p.with_offset(offset))
+                        .ok()
+                }
+            },
+            fields: index.fields.clone(),
+            shards: index.shards,
+            normalize: index.normalize,
+            extend_candidates: index.extend_candidates,
+            keep_pruned_connections: index.keep_pruned_connections,
+            frozen: index.frozen,
+            snapshot_reads: index.snapshot_reads,
+            flat_limit: index.flat_limit,
+            duplicates: index.duplicates,
+            dup_epsilon: index.dup_epsilon,
+            node_aliases: index.node_aliases,
+            // opened by the module from the storage kind, it knows where vector files go
+            storage: Arc::new(HeapStorage),
+            epoch: 0,
             rng_: StdRng::from_entropy(),
+            undo: UndoLog::default(),
         }
     }
 }
 
+// node mutation journaled in the index key until its result is persisted
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingChange {
+    Add(String),
+    Delete(String),
+    Update(String),
+}
+
+impl PendingChange {
+    pub fn node_name(&self) -> &str {
+        match self {
+            PendingChange::Add(n) | PendingChange::Delete(n) | PendingChange::Update(n) => n,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        match self {
+            PendingChange::Add(_) => 1,
+            PendingChange::Delete(_) => 2,
+            PendingChange::Update(_) => 3,
+        }
+    }
+}
+
+// hashes an index keeps its nodes in sync with, see hnsw.follow
+#[derive(Debug, Clone, PartialEq)]
+pub struct FollowRule {
+    pub pattern: String, // glob pattern of the followed keys
+    pub field: String,   // hash field of the vector
+    pub format: String,  // encoding of the vector
+}
+
 #[derive(Default, Clone)]
 pub struct IndexRedis {
-    pub name: String,               // index name
-    pub mfunc_kind: String,         // kind of the metric function
-    pub data_dim: usize,            // dimensionality of the data
-    pub m: usize,                   // out vertexs per node
-    pub m_max: usize,               // max number of vertexes per node
-    pub m_max_0: usize,             // max number of vertexes at layer 0
-    pub ef_construction: usize,     // size of dynamic candidate list
-    pub level_mult: f64,            // level generation factor
-    pub node_count: usize,          // count of nodes
-    pub max_layer: usize,           // idx of top layer
-    pub layers: Vec<Vec<String>>,   // distinct nodes in each layer
-    pub nodes: Vec<String>,         // set of node names
-    pub enterpoint: Option<String>, // string key to the enterpoint node
-}
-
-impl<T: Float, R: Float> From<Index<T, R>> for IndexRedis {
-    fn from(index: Index<T, R>) -> Self {
-        IndexRedis {
+    pub name: String,                     // index name
+    pub mfunc_kind: metrics::MetricFuncs, // kind of the metric function
+    pub data_dim: usize,                  // dimensionality of the data
+    pub m: usize,                         // out vertexs per node
+    pub m_max: usize,                     // max number of vertexes per node
+    pub m_max_0: usize,                   // max number of vertexes at layer 0
+    pub ef_construction: usize,           // size of dynamic candidate list
+    pub level_mult: f64,                  // level generation factor
+    pub node_count: usize,                // count of nodes
+    pub max_layer: usize,                 // idx of top layer
+    pub layers: Vec<Vec<String>>,         // layer membership, only loaded from version < 5
+    pub nodes: HashMap<String, u32>,      // id of each node by name
+    pub enterpoint: Option<String>,       // string key to the enterpoint node
+    pub fields: Vec<String>,              // names of the field sub-indexes
+    pub shards: usize,                    // number of shard sub-indexes, 0 if unsharded
+    pub normalize: bool,                  // L2-normalize data on insert and query
+    pub extend_candidates: bool,          // extend candidates by their neighbors
+    pub keep_pruned_connections: bool,    // fill up to M with pruned candidates
+    pub frozen: bool,                     // reject node mutations
+    pub snapshot_reads: bool,             // searches fall back to a snapshot while writing
+    pub flat_limit: usize,                // node count a flat index is promoted above, 0 if HNSW
+    pub storage: StorageKind,             // where the node vectors live while loaded
+    pub duplicates: DuplicatePolicy,      // what adding a node equal to an existing one does
+    pub dup_epsilon: f32,                 // largest difference of a value of equal vectors
+    pub pending: Option<PendingChange>,   // node change started but not persisted yet
+    pub name_pattern: String,             // regex node names must match, empty if unconstrained
+    pub max_name_len: usize,              // max length of node names, 0 if unlimited
+    pub project_from: usize,              // dimensionality projected onto data_dim, 0 if none
+    pub projection: Vec<f32>,             // data_dim rows of project_from values
+    pub projection_offset: Vec<f32>,      // added to projected vectors, empty if none
+    pub follow: Option<FollowRule>,       // followed hashes, None if not following
+    pub centroids: Vec<Vec<f32>>,         // k-means centroids of hnsw.cluster, empty if none
+    pub aliases: Vec<String>,             // names of hnsw.alias resolving to the index
+    pub max_degrees: Option<Degrees>,     // degrees of the graph, only computed by hnsw.get
+    pub registry_id: u64,                 // id of the registry entry, 0 if unregistered
+    // node each alias of the alias policy resolves to
+    pub node_aliases: HashMap<String, String>,
+}
+
+impl From<HNSWError> for RedisError {
+    fn from(e: HNSWError) -> Self {
+        RedisError::String(e.error_string())
+    }
+}
+
+impl<T: Float, R: Float> TryFrom<&Index<T, R>> for IndexRedis {
+    type Error = HNSWError;
+
+    fn try_from(index: &Index<T, R>) -> Result<Self, Self::Error> {
+        let mut index_redis = IndexRedis {
             name: index.name.clone(),
-            mfunc_kind: format!("{:?}", index.mfunc_kind),
+            mfunc_kind: index.mfunc_kind,
             data_dim: index.data_dim,
             m: index.m,
             m_max: index.m_max,
@@ -72,21 +312,109 @@ impl<T: Float, R: Float> From<Index<T, R>> for IndexRedis {
             level_mult: index.level_mult,
             node_count: index.node_count,
             max_layer: index.max_layer,
-            layers: index
-                .layers
+            // layer membership is stored as the level of each node
+            layers: Vec::new(),
+            nodes: index
+                .nodes
                 .iter()
-                .map(|l| {
-                    l.iter()
-                        .map(|n| n.upgrade().read().name.clone())
-                        .collect::<Vec<String>>()
-                })
-                .collect(),
-            nodes: index.nodes.keys().cloned().collect::<Vec<String>>(),
-            enterpoint: match &index.enterpoint {
-                Some(ep) => Some(ep.upgrade().read().name.clone()),
-                None => None,
-            },
+                .map(|(name, node)| Ok((name.clone(), node.id()?)))
+                .collect::<Result<HashMap<String, u32>, HNSWError>>()?,
+            enterpoint: enterpoint_name(index)?,
+            fields: index.fields.clone(),
+            shards: index.shards,
+            normalize: index.normalize,
+            extend_candidates: index.extend_candidates,
+            keep_pruned_connections: index.keep_pruned_connections,
+            frozen: index.frozen,
+            snapshot_reads: index.snapshot_reads,
+            flat_limit: index.flat_limit,
+            storage: index.storage.kind(),
+            duplicates: index.duplicates,
+            dup_epsilon: index.dup_epsilon,
+            node_aliases: index.node_aliases.clone(),
+            pending: None,
+            name_pattern: index.names.pattern.clone(),
+            max_name_len: index.names.max_len,
+            project_from: 0,
+            projection: Vec::new(),
+            projection_offset: Vec::new(),
+            follow: None,
+            centroids: Vec::new(),
+            aliases: Vec::new(),
+            max_degrees: None,
+            registry_id: 0,
+        };
+        index_redis.set_projection(index);
+        Ok(index_redis)
+    }
+}
+
+fn enterpoint_name<T: Float, R: Float>(index: &Index<T, R>) -> Result<Option<String>, HNSWError> {
+    match index.enterpoint {
+        Some(ep) => Ok(Some(index.node_at(ep)?.read()?.name.clone())),
+        None => Ok(None),
+    }
+}
+
+impl IndexRedis {
+    // copies the parameters and graph entry of the index, leaving the node set untouched
+    pub fn update_params<T: Float, R: Float>(
+        &mut self,
+        index: &Index<T, R>,
+    ) -> Result<(), HNSWError> {
+        self.m = index.m;
+        self.m_max = index.m_max;
+        self.m_max_0 = index.m_max_0;
+        self.ef_construction = index.ef_construction;
+        self.level_mult = index.level_mult;
+        self.node_count = index.node_count;
+        self.max_layer = index.max_layer;
+        self.enterpoint = enterpoint_name(index)?;
+        self.extend_candidates = index.extend_candidates;
+        self.keep_pruned_connections = index.keep_pruned_connections;
+        self.frozen = index.frozen;
+        self.snapshot_reads = index.snapshot_reads;
+        // flat indexes are promoted by adding nodes
+        self.flat_limit = index.flat_limit;
+        // aliases are added and deleted like nodes
+        self.node_aliases = index.node_aliases.clone();
+        // the index is consistent again once its params are persisted
+        self.pending = None;
+        update_registry(self);
+        Ok(())
+    }
+
+    // copies the dimensionality and projection, which update_params leaves untouched
+    pub fn set_projection<T: Float, R: Float>(&mut self, index: &Index<T, R>) {
+        let to_f32 = |v: &[T]| v.iter().map(|v| v.to_f32().unwrap()).collect();
+        self.data_dim = index.data_dim;
+        match &index.projection {
+            Some(p) => {
+                self.project_from = p.from_dim;
+                self.projection = to_f32(&p.matrix);
+                self.projection_offset = to_f32(&p.offset);
+            }
+            None => {
+                self.project_from = 0;
+                self.projection = Vec::new();
+                self.projection_offset = Vec::new();
+            }
         }
+        update_registry(self);
+    }
+
+    pub fn set_follow(&mut self, follow: Option<FollowRule>) {
+        self.follow = follow;
+        update_registry(self);
+    }
+
+    pub fn set_aliases(&mut self, aliases: Vec<String>) {
+        self.aliases = aliases;
+        update_registry(self);
+    }
+
+    pub fn set_centroids(&mut self, centroids: Vec<Vec<f32>>) {
+        self.centroids = centroids;
     }
 }
 
@@ -102,7 +430,15 @@ impl fmt::Debug for IndexRedis {
              level_mult: {}, \
              node_count: {}, \
              max_layer: {}, \
-             enterpoint: {}",
+             enterpoint: {}, \
+             normalize: {}, \
+             extend_candidates: {}, \
+             keep_pruned_connections: {}, \
+             frozen: {}, \
+             snapshot_reads: {}, \
+             flat_limit: {}, \
+             storage: {}, \
+             shards: {}",
             self.name,
             self.mfunc_kind,
             self.data_dim,
@@ -115,6 +451,14 @@ impl fmt::Debug for IndexRedis {
                 Some(ep) => ep.as_str(),
                 None => "null",
             },
+            self.normalize,
+            self.extend_candidates,
+            self.keep_pruned_connections,
+            self.frozen,
+            self.snapshot_reads,
+            self.flat_limit,
+            self.storage.as_str(),
+            self.shards,
         )
     }
 }
@@ -127,7 +471,7 @@ impl From<IndexRedis> for RedisValue {
         reply.push(index.name.as_str().into());
 
         reply.push("metric".into());
-        reply.push(index.mfunc_kind.as_str().into());
+        reply.push(format!("{:?}", index.mfunc_kind).into());
 
         reply.push("data_dim".into());
         reply.push(index.data_dim.into());
@@ -135,6 +479,9 @@ impl From<IndexRedis> for RedisValue {
         reply.push("m".into());
         reply.push(index.m.into());
 
+        reply.push("m0".into());
+        reply.push(index.m_max_0.into());
+
         reply.push("ef_construction".into());
         reply.push(index.ef_construction.into());
 
@@ -147,17 +494,112 @@ impl From<IndexRedis> for RedisValue {
         reply.push("max_layer".into());
         reply.push(index.max_layer.into());
 
+        if let Some(degrees) = index.max_degrees {
+            reply.push("max_degree_0".into());
+            reply.push(degrees.layer0.into());
+            reply.push("max_degree".into());
+            reply.push(degrees.upper.into());
+        }
+
         reply.push("enterpoint".into());
         reply.push(index.enterpoint.into());
 
+        reply.push("normalize".into());
+        reply.push((index.normalize as usize).into());
+
+        reply.push("extend_candidates".into());
+        reply.push((index.extend_candidates as usize).into());
+
+        reply.push("keep_pruned_connections".into());
+        reply.push((index.keep_pruned_connections as usize).into());
+
+        reply.push("frozen".into());
+        reply.push((index.frozen as usize).into());
+
+        reply.push("snapshot_reads".into());
+        reply.push((index.snapshot_reads as usize).into());
+
+        reply.push("kind".into());
+        reply.push(if index.flat_limit > 0 { "flat" } else { "hnsw" }.into());
+
+        if index.flat_limit > 0 {
+            reply.push("flat_limit".into());
+            reply.push(index.flat_limit.into());
+        }
+
+        if index.storage != StorageKind::Heap {
+            reply.push("storage".into());
+            reply.push(index.storage.as_str().into());
+        }
+
+        if index.duplicates != DuplicatePolicy::Allow {
+            reply.push("duplicates".into());
+            reply.push(index.duplicates.as_str().into());
+            reply.push("dup_epsilon".into());
+            reply.push((index.dup_epsilon as f64).into());
+            reply.push("node_aliases".into());
+            reply.push(index.node_aliases.len().into());
+        }
+
+        if !index.aliases.is_empty() {
+            reply.push("aliases".into());
+            reply.push(
+                index
+                    .aliases
+                    .into_iter()
+                    .map(RedisValue::from)
+                    .collect::<Vec<_>>()
+                    .into(),
+            );
+        }
+
+        if !index.fields.is_empty() {
+            reply.push("fields".into());
+            reply.push(index.fields.into());
+        }
+
+        if index.shards > 0 {
+            reply.push("shards".into());
+            reply.push(index.shards.into());
+        }
+
+        if !index.name_pattern.is_empty() {
+            reply.push("name_pattern".into());
+            reply.push(index.name_pattern.as_str().into());
+        }
+
+        if index.max_name_len > 0 {
+            reply.push("max_name_len".into());
+            reply.push(index.max_name_len.into());
+        }
+
+        if index.project_from > 0 {
+            reply.push("project_from".into());
+            reply.push(index.project_from.into());
+        }
+
+        if let Some(follow) = index.follow {
+            reply.push("follow".into());
+            reply.push(vec![follow.pattern, follow.field, follow.format].into());
+        }
+
+        if !index.centroids.is_empty() {
+            reply.push("clusters".into());
+            reply.push(index.centroids.len().into());
+        }
+
         reply.into()
     }
 }
 
-pub static HNSW_INDEX_REDIS_TYPE: RedisType = RedisType::new(
-    "hnswindex",
-    INDEX_VERSION,
-    raw::RedisModuleTypeMethods {
+const INDEX_TYPE_NAME: &str = "hnswindex";
+const NODE_TYPE_NAME: &str = "hnswnodet";
+
+// activedefrag relocates index keys along with their loaded graph, large graphs in steps
+const INDEX_TYPE_METHODS: TypeMethods = TypeMethods {
+    free_effort: Some(defrag::free_effort_index),
+    defrag: Some(defrag::defrag_index),
+    ..TypeMethods::new(raw::RedisModuleTypeMethods {
         version: raw::REDISMODULE_TYPE_METHOD_VERSION as u64,
         rdb_load: Some(load_index),
         rdb_save: Some(save_index),
@@ -165,146 +607,224 @@ pub static HNSW_INDEX_REDIS_TYPE: RedisType = RedisType::new(
         free: Some(free_index),
 
         mem_usage: None,
-        digest: None,
+        digest: Some(digest_index),
 
-        aux_load: None,
-        aux_save: None,
-        aux_save_triggers: 0,
-    },
-);
+        // module settings are saved with the index type
+        aux_load: Some(load_aux),
+        aux_save: Some(save_aux),
+        aux_save_triggers: raw::REDISMODULE_AUX_BEFORE_RDB as c_int,
+    })
+};
 
-unsafe extern "C" fn free_index(value: *mut c_void) {
-    Box::from_raw(value as *mut IndexRedis);
-}
+pub static HNSW_INDEX_REDIS_TYPE: RedisType =
+    RedisType::new(INDEX_TYPE_NAME, INDEX_VERSION, INDEX_TYPE_METHODS.base);
 
-unsafe extern "C" fn load_index(rdb: *mut raw::RedisModuleIO, version: i32) -> *mut c_void {
-    if version != INDEX_VERSION {
-        return ptr::null_mut() as *mut c_void;
-    }
+unsafe fn digest_str(md: *mut raw::RedisModuleDigest, s: &str) {
+    raw::RedisModule_DigestAddStringBuffer.unwrap()(md, s.as_ptr() as *mut u8, s.len());
+}
 
-    let mut index = Box::new(IndexRedis::default());
+unsafe fn digest_u64(md: *mut raw::RedisModuleDigest, v: u64) {
+    raw::RedisModule_DigestAddLongLong.unwrap()(md, v as i64);
+}
 
-    let name = raw::RedisModule_LoadString.unwrap()(rdb);
-    index.name = redis_module::RedisString::from_ptr(name)
-        .unwrap()
-        .to_owned();
+unsafe fn digest_f32s(md: *mut raw::RedisModuleDigest, v: &[f32]) {
+    digest_u64(md, v.len() as u64);
+    for x in v {
+        digest_u64(md, x.to_bits() as u64);
+    }
+}
 
-    let mfunc_kind = raw::RedisModule_LoadString.unwrap()(rdb);
-    index.mfunc_kind = redis_module::RedisString::from_ptr(mfunc_kind)
-        .unwrap()
-        .to_owned();
-
-    index.data_dim = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.m = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.m_max = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.m_max_0 = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.ef_construction = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.level_mult = raw::RedisModule_LoadDouble.unwrap()(rdb);
-    index.node_count = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.max_layer = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-
-    let num_layers = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.layers = Vec::with_capacity(num_layers);
-    for l in 0..num_layers {
-        let num_nodes = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-        index.layers.push(Vec::with_capacity(num_nodes));
-        for _n in 0..num_nodes {
-            let node_name = raw::RedisModule_LoadString.unwrap()(rdb);
-            index.layers[l].push(
-                redis_module::RedisString::from_ptr(node_name)
-                    .unwrap()
-                    .to_owned(),
-            );
-        }
+unsafe extern "C" fn digest_index(md: *mut raw::RedisModuleDigest, value: *mut c_void) {
+    let index = &*(value as *mut IndexRedis);
+    let end_sequence = raw::RedisModule_DigestEndSequence.unwrap();
+
+    digest_str(md, &index.name);
+    digest_u64(md, index.mfunc_kind.id());
+    digest_u64(md, index.data_dim as u64);
+    digest_u64(md, index.m as u64);
+    digest_u64(md, index.m_max as u64);
+    digest_u64(md, index.m_max_0 as u64);
+    digest_u64(md, index.ef_construction as u64);
+    digest_u64(md, index.level_mult.to_bits());
+    digest_u64(md, index.node_count as u64);
+    digest_u64(md, index.max_layer as u64);
+    digest_str(md, index.enterpoint.as_deref().unwrap_or("null"));
+    for f in &index.fields {
+        digest_str(md, f);
+    }
+    digest_u64(md, index.normalize as u64);
+    digest_u64(md, index.extend_candidates as u64);
+    digest_u64(md, index.keep_pruned_connections as u64);
+    digest_u64(md, index.frozen as u64);
+    digest_u64(md, index.shards as u64);
+    digest_u64(md, index.snapshot_reads as u64);
+    digest_str(md, &index.name_pattern);
+    digest_u64(md, index.max_name_len as u64);
+    digest_u64(md, index.project_from as u64);
+    digest_f32s(md, &index.projection);
+    digest_f32s(md, &index.projection_offset);
+    if let Some(f) = &index.follow {
+        digest_str(md, &f.pattern);
+        digest_str(md, &f.field);
+        digest_str(md, &f.format);
     }
+    for c in &index.centroids {
+        digest_f32s(md, c);
+    }
+    digest_u64(md, index.flat_limit as u64);
+    digest_u64(md, index.storage.id());
+    if let Some(p) = &index.pending {
+        digest_u64(md, p.id());
+        digest_str(md, p.node_name());
+    }
+    end_sequence(md);
 
-    let num_nodes = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    index.nodes = Vec::with_capacity(num_nodes);
-    for _n in 0..num_nodes {
-        let node_name = raw::RedisModule_LoadString.unwrap()(rdb);
-        index.nodes.push(
-            redis_module::RedisString::from_ptr(node_name)
-                .unwrap()
-                .to_owned(),
-        );
+    // nodes are an unordered set, sequences are combined regardless of order
+    for (name, id) in &index.nodes {
+        digest_str(md, name);
+        digest_u64(md, *id as u64);
+        end_sequence(md);
     }
+}
 
-    let ep = raw::RedisModule_LoadString.unwrap()(rdb);
-    let ep = redis_module::RedisString::from_ptr(ep).unwrap().to_owned();
-    index.enterpoint = match ep.as_str() {
-        "null" => None,
-        _ => Some(ep),
-    };
+unsafe extern "C" fn free_index(value: *mut c_void) {
+    let index = Box::from_raw(value as *mut IndexRedis);
+    unregister_index(&index);
+    let effort = index.nodes.len();
+    free_lazily(index, effort);
+}
 
-    let index: *mut c_void = Box::into_raw(index) as *mut c_void;
-    index
+unsafe fn log_io_error(rdb: *mut raw::RedisModuleIO, msg: &str) {
+    let msg = CString::new(msg).unwrap();
+    raw::RedisModule_LogIOError.unwrap()(
+        rdb,
+        "warning\0".as_ptr() as *const c_char,
+        "%s\0".as_ptr() as *const c_char,
+        msg.as_ptr(),
+    );
 }
 
-unsafe extern "C" fn save_index(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
-    let index = Box::from_raw(value as *mut IndexRedis);
+unsafe extern "C" fn load_index(rdb: *mut raw::RedisModuleIO, encver: i32) -> *mut c_void {
+    match rdb::load_index(&mut ModuleIO(rdb), encver) {
+        Ok(index) => {
+            let mut index = Box::new(index);
+            register_index(&mut index);
+            Box::into_raw(index) as *mut c_void
+        }
+        Err(e) => {
+            log_io_error(rdb, &e.error_string());
+            ptr::null_mut() as *mut c_void
+        }
+    }
+}
 
-    let ctx = ptr::null_mut();
+unsafe extern "C" fn save_index(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
+    rdb::save_index(&mut ModuleIO(rdb), &*(value as *mut IndexRedis));
+}
 
-    let name = RedisString::create(ctx, &index.name);
-    raw::RedisModule_SaveString.unwrap()(rdb, name.inner);
+#[derive(Default, Clone)]
+pub struct NodeRedis {
+    pub data: Vec<f32>,
+    pub vectors: Vec<Vec<f32>>,           // vectors of a multi-vector node
+    pub norms: Vec<f32>,                  // original norms of a normalized node
+    pub neighbors: Vec<Vec<u32>>,         // ids of the neighbors on each layer
+    pub neighbor_names: Vec<Vec<String>>, // neighbors of a node saved before ids, until rewritten
+    pub level: usize,                     // top layer of the node
+    pub tags: Vec<String>,                // tags matched by search filters
+    pub attrs: BTreeMap<String, f64>,     // numeric attributes compared by search filters
+    pub location: Option<Location>,       // position matched by search geo fences
+    pub version: u64,                     // incremented on every update of the node
+}
 
-    let mfunc_kind = RedisString::create(ctx, &index.mfunc_kind);
-    raw::RedisModule_SaveString.unwrap()(rdb, mfunc_kind.inner);
+impl TryFrom<&Node<f32>> for NodeRedis {
+    type Error = HNSWError;
+
+    fn try_from(node: &Node<f32>) -> Result<Self, Self::Error> {
+        let r = node.read()?;
+        Ok(NodeRedis {
+            data: r.data.to_vec(),
+            vectors: r.vectors.to_owned(),
+            norms: r.norms.to_owned(),
+            level: r.level,
+            tags: r.tags.to_owned(),
+            attrs: r.attrs.to_owned(),
+            location: r.location,
+            version: r.version,
+            neighbors: r.neighbors.clone(),
+            neighbor_names: Vec::new(),
+        })
+    }
+}
 
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.data_dim as u64);
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.m as u64);
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.m_max as u64);
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.m_max_0 as u64);
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.ef_construction as u64);
-    raw::RedisModule_SaveDouble.unwrap()(rdb, index.level_mult);
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.node_count as u64);
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.max_layer as u64);
+// the node keys of an index, which it is restored from when it is loaded
+pub struct NodeKeys<'a> {
+    ctx: &'a dyn Backend,
+    ids: &'a HashMap<String, u32>,   // ids of the nodes, by name
+    levels: HashMap<&'a str, usize>, // levels of indexes saved before version 5, by node name
+}
 
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.layers.len() as u64);
-    for layer in index.layers {
-        raw::RedisModule_SaveUnsigned.unwrap()(rdb, layer.len() as u64);
-        for n in layer {
-            let s = RedisString::create(ctx, &n);
-            raw::RedisModule_SaveString.unwrap()(rdb, s.inner);
+impl<'a> NodeKeys<'a> {
+    // indexes saved before version 5 store layer membership in the index key
+    pub fn new(ctx: &'a dyn Backend, index: &'a IndexRedis) -> Self {
+        let mut levels = HashMap::new();
+        for (l, layer) in index.layers.iter().enumerate() {
+            for name in layer {
+                levels.insert(name.as_str(), l);
+            }
+        }
+        NodeKeys {
+            ctx,
+            ids: &index.nodes,
+            levels,
         }
     }
 
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, index.nodes.len() as u64);
-    for n in index.nodes {
-        let s = RedisString::create(ctx, &n);
-        raw::RedisModule_SaveString.unwrap()(rdb, s.inner);
+    fn read<R>(&self, name: &str, f: impl FnOnce(&NodeRedis) -> R) -> Result<R, HNSWError> {
+        match self.ctx.node(name, f) {
+            Ok(Some(res)) => Ok(res),
+            Ok(None) => Err(format!("Node: {} does not exist", name).into()),
+            Err(e) => Err(format!("Node: {} {}", name, e).into()),
+        }
     }
-
-    let ep = if index.enterpoint.is_some() {
-        RedisString::create(ctx, &index.enterpoint.unwrap())
-    } else {
-        RedisString::create(ctx, "null")
-    };
-    raw::RedisModule_SaveString.unwrap()(rdb, ep.inner);
 }
 
-#[derive(Default)]
-pub struct NodeRedis {
-    pub data: Vec<f32>,
-    pub neighbors: Vec<Vec<String>>, // vector of neighbor node names
-}
+impl NodeStore<f32> for NodeKeys<'_> {
+    fn load(&self, name: &str, capacity: usize) -> Result<Node<f32>, HNSWError> {
+        let node = self.read(name, |nr| {
+            let node = Node::new(name, &nr.data, capacity);
+            {
+                let mut n = node.write()?;
+                n.vectors = nr.vectors.clone();
+                n.norms = nr.norms.clone();
+                n.level = nr.level;
+                n.tags = nr.tags.clone();
+                n.attrs = nr.attrs.clone();
+                n.location = nr.location;
+                n.version = nr.version;
+            }
+            Ok::<Node<f32>, HNSWError>(node)
+        })??;
+        if let Some(level) = self.levels.get(name) {
+            node.write()?.level = *level;
+        }
+        Ok(node)
+    }
 
-impl From<&Node<f32>> for NodeRedis {
-    fn from(node: &Node<f32>) -> Self {
-        let r = node.read();
-        NodeRedis {
-            data: r.data.to_owned(),
-            neighbors: r
-                .neighbors
-                .to_owned()
-                .into_iter()
-                .map(|l| {
-                    l.into_iter()
-                        .map(|n| n.upgrade().read().name.clone())
-                        .collect::<Vec<String>>()
-                })
-                .collect(),
+    // nodes saved before ids name their neighbors, which get the ids of the index
+    fn neighbors(&self, name: &str) -> Result<Vec<Vec<u32>>, HNSWError> {
+        let (ids, names) =
+            self.read(name, |nr| (nr.neighbors.clone(), nr.neighbor_names.clone()))?;
+        if names.is_empty() {
+            return Ok(ids);
         }
+        let id_of = |n: &String| match self.ids.get(n) {
+            Some(id) => Ok(*id),
+            None => Err(HNSWError::from(format!("Node: {} does not exist", n))),
+        };
+        names
+            .iter()
+            .map(|layer| layer.iter().map(id_of).collect())
+            .collect()
     }
 }
 
@@ -313,32 +833,106 @@ impl fmt::Debug for NodeRedis {
         write!(
             f,
             "data: {:?}, \
-             neighbors: {:?}",
-            self.data, self.neighbors,
+             vectors: {:?}, \
+             norms: {:?}, \
+             level: {}, \
+             tags: {:?}, \
+             attrs: {:?}, \
+             location: {:?}, \
+             version: {}, \
+             neighbors: {:?}, \
+             neighbor_names: {:?}",
+            self.data,
+            self.vectors,
+            self.norms,
+            self.level,
+            self.tags,
+            self.attrs,
+            self.location,
+            self.version,
+            self.neighbors,
+            self.neighbor_names,
         )
     }
 }
 
-impl From<&NodeRedis> for RedisValue {
-    fn from(n: &NodeRedis) -> Self {
+impl NodeRedis {
+    // vector of the node at its original magnitude, normalized vectors are scaled back
+    pub fn original_data(&self) -> Vec<f32> {
+        let norm = match self.norms.as_slice() {
+            [norm] => *norm,
+            _ => 1.0,
+        };
+        self.data.iter().map(|x| *x * norm).collect()
+    }
+
+    // neighbors are replied by name, name_of names the ids of the index. vectors are arrays
+    // of doubles, or little-endian f32 bulk strings with blob
+    pub fn reply(
+        &self,
+        blob: bool,
+        name_of: impl Fn(u32) -> Result<String, HNSWError>,
+    ) -> Result<RedisValue, HNSWError> {
         let mut reply: Vec<RedisValue> = Vec::new();
+        let vector = |data: Vec<f32>| vector_value(data, blob);
 
         reply.push("data".into());
+        reply.push(vector(self.original_data()));
+
+        if !self.vectors.is_empty() {
+            reply.push("vectors".into());
+            reply.push(
+                self.vectors
+                    .iter()
+                    .map(|v| vector(v.clone()))
+                    .collect::<Vec<RedisValue>>()
+                    .into(),
+            );
+        }
+
+        reply.push("level".into());
+        reply.push(self.level.into());
+
+        reply.push("tags".into());
         reply.push(
-            n.data
+            self.tags
                 .iter()
-                .map(|x| *x as f64)
-                .collect::<Vec<f64>>()
+                .map(|t| t.into())
+                .collect::<Vec<RedisValue>>()
                 .into(),
         );
 
+        reply.push("attrs".into());
+        let mut attrs: Vec<RedisValue> = Vec::with_capacity(self.attrs.len() * 2);
+        for (name, value) in &self.attrs {
+            attrs.push(name.as_str().into());
+            attrs.push((*value).into());
+        }
+        reply.push(attrs.into());
+
+        if let Some(location) = &self.location {
+            reply.push("location".into());
+            reply.push(vec![location.lat, location.lon].into());
+        }
+
+        reply.push("version".into());
+        reply.push((self.version as usize).into());
+
+        let names = match self.neighbor_names.is_empty() {
+            true => self
+                .neighbors
+                .iter()
+                .map(|layer| layer.iter().map(|id| name_of(*id)).collect())
+                .collect::<Result<Vec<Vec<String>>, HNSWError>>()?,
+            false => self.neighbor_names.clone(),
+        };
         reply.push("neighbors".into());
         reply.push(
-            n.neighbors
-                .iter()
+            names
+                .into_iter()
                 .map(|layer| {
                     layer
-                        .iter()
+                        .into_iter()
                         .map(|node| node.into())
                         .collect::<Vec<RedisValue>>()
                         .into()
@@ -347,14 +941,31 @@ impl From<&NodeRedis> for RedisValue {
                 .into(),
         );
 
-        reply.into()
+        Ok(reply.into())
     }
 }
 
-pub static HNSW_NODE_REDIS_TYPE: RedisType = RedisType::new(
-    "hnswnodet",
-    NODE_VERSION,
-    raw::RedisModuleTypeMethods {
+// bulk string of raw bytes. the RedisValue of this redis-module version only holds strings,
+// their bytes are replied as they are and the string is never read as text
+fn blob_value(bytes: Vec<u8>) -> RedisValue {
+    RedisValue::BulkString(unsafe { String::from_utf8_unchecked(bytes) })
+}
+
+// a vector as an array of doubles, or a little-endian f32 bulk string with blob
+fn vector_value(data: Vec<f32>, blob: bool) -> RedisValue {
+    match blob {
+        true => blob_value(VectorFormat::Fp32Blob.encode(&data)),
+        false => data
+            .into_iter()
+            .map(|x| x as f64)
+            .collect::<Vec<f64>>()
+            .into(),
+    }
+}
+
+const NODE_TYPE_METHODS: TypeMethods = TypeMethods {
+    defrag: Some(defrag::defrag_node),
+    ..TypeMethods::new(raw::RedisModuleTypeMethods {
         version: raw::REDISMODULE_TYPE_METHOD_VERSION as u64,
         rdb_load: Some(load_node),
         rdb_save: Some(save_node),
@@ -362,75 +973,123 @@ pub static HNSW_NODE_REDIS_TYPE: RedisType = RedisType::new(
         free: Some(free_node),
 
         mem_usage: None,
-        digest: None,
+        digest: Some(digest_node),
 
         aux_load: None,
         aux_save: None,
         aux_save_triggers: 0,
-    },
-);
-
-unsafe extern "C" fn free_node(value: *mut c_void) {
-    Box::from_raw(value as *mut NodeRedis);
+    })
+};
+
+pub static HNSW_NODE_REDIS_TYPE: RedisType =
+    RedisType::new(NODE_TYPE_NAME, NODE_VERSION, NODE_TYPE_METHODS.base);
+
+// creates both types with the methods of type method version 3, must run while loading
+pub fn create_data_types(ctx: &Context) -> Result<(), String> {
+    defrag::create_data_type(
+        ctx,
+        &HNSW_INDEX_REDIS_TYPE,
+        INDEX_TYPE_NAME,
+        INDEX_VERSION,
+        &INDEX_TYPE_METHODS,
+    )?;
+    defrag::create_data_type(
+        ctx,
+        &HNSW_NODE_REDIS_TYPE,
+        NODE_TYPE_NAME,
+        NODE_VERSION,
+        &NODE_TYPE_METHODS,
+    )
 }
 
-unsafe extern "C" fn load_node(rdb: *mut raw::RedisModuleIO, version: i32) -> *mut c_void {
-    if version != NODE_VERSION {
-        return ptr::null_mut() as *mut c_void;
-    }
-
-    let mut node = Box::new(NodeRedis::default());
+unsafe extern "C" fn digest_node(md: *mut raw::RedisModuleDigest, value: *mut c_void) {
+    let node = &*(value as *mut NodeRedis);
 
-    let num_datum = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    node.data = Vec::with_capacity(num_datum);
-    for _d in 0..num_datum {
-        let datum = raw::RedisModule_LoadFloat.unwrap()(rdb);
-        node.data.push(datum);
+    digest_f32s(md, &node.data);
+    digest_u64(md, node.vectors.len() as u64);
+    for v in &node.vectors {
+        digest_f32s(md, v);
     }
-
-    let num_layers = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-    node.neighbors = Vec::with_capacity(num_layers);
-    for l in 0..num_layers {
-        let num_nodes = raw::RedisModule_LoadUnsigned.unwrap()(rdb) as usize;
-        node.neighbors.push(Vec::new());
-        for _n in 0..num_nodes {
-            let node_name = raw::RedisModule_LoadString.unwrap()(rdb);
-            node.neighbors[l].push(
-                redis_module::RedisString::from_ptr(node_name)
-                    .unwrap()
-                    .to_owned(),
-            );
+    digest_f32s(md, &node.norms);
+    digest_u64(md, node.level as u64);
+    digest_u64(md, node.tags.len() as u64);
+    for t in &node.tags {
+        digest_str(md, t);
+    }
+    digest_u64(md, node.attrs.len() as u64);
+    for (name, value) in &node.attrs {
+        digest_str(md, name);
+        digest_u64(md, value.to_bits());
+    }
+    if let Some(location) = &node.location {
+        digest_u64(md, location.lat.to_bits());
+        digest_u64(md, location.lon.to_bits());
+    }
+    digest_u64(md, node.version);
+    for layer in &node.neighbors {
+        digest_u64(md, layer.len() as u64);
+        for n in layer {
+            digest_u64(md, *n as u64);
         }
     }
-
-    let p: *mut c_void = Box::into_raw(node) as *mut c_void;
-    p
+    for layer in &node.neighbor_names {
+        digest_u64(md, layer.len() as u64);
+        for n in layer {
+            digest_str(md, n);
+        }
+    }
+    raw::RedisModule_DigestEndSequence.unwrap()(md);
 }
 
-unsafe extern "C" fn save_node(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
-    let ctx = ptr::null_mut();
+unsafe extern "C" fn free_node(value: *mut c_void) {
+    Box::from_raw(value as *mut NodeRedis);
+}
 
-    let node = Box::from_raw(value as *mut NodeRedis);
+unsafe extern "C" fn load_aux(rdb: *mut raw::RedisModuleIO, encver: c_int, when: c_int) -> c_int {
+    if when != raw::REDISMODULE_AUX_BEFORE_RDB as c_int {
+        return raw::REDISMODULE_OK as c_int;
+    }
+    match rdb::load_aux(&mut ModuleIO(rdb), encver) {
+        Ok(errors) => {
+            for e in errors {
+                log_io_error(rdb, &format!("Setting not restored: {}", e));
+            }
+            raw::REDISMODULE_OK as c_int
+        }
+        Err(e) => {
+            log_io_error(rdb, &e.error_string());
+            raw::REDISMODULE_ERR as c_int
+        }
+    }
+}
 
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, node.data.len() as u64);
-    for datum in node.data {
-        raw::RedisModule_SaveFloat.unwrap()(rdb, datum);
+unsafe extern "C" fn save_aux(rdb: *mut raw::RedisModuleIO, when: c_int) {
+    if when == raw::REDISMODULE_AUX_BEFORE_RDB as c_int {
+        rdb::save_aux(&mut ModuleIO(rdb));
     }
+}
 
-    raw::RedisModule_SaveUnsigned.unwrap()(rdb, node.neighbors.len() as u64);
-    for l in node.neighbors {
-        raw::RedisModule_SaveUnsigned.unwrap()(rdb, l.len() as u64);
-        for n in l {
-            let s = RedisString::create(ctx, &n);
-            raw::RedisModule_SaveString.unwrap()(rdb, s.inner);
+unsafe extern "C" fn load_node(rdb: *mut raw::RedisModuleIO, encver: i32) -> *mut c_void {
+    match rdb::load_node(&mut ModuleIO(rdb), encver) {
+        Ok(node) => Box::into_raw(Box::new(node)) as *mut c_void,
+        Err(e) => {
+            log_io_error(rdb, &e.error_string());
+            ptr::null_mut() as *mut c_void
         }
     }
 }
 
+unsafe extern "C" fn save_node(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
+    rdb::save_node(&mut ModuleIO(rdb), &*(value as *mut NodeRedis));
+}
+
 #[derive(Default)]
 pub struct SearchResultRedis {
     pub sim: f64,
     pub name: String,
+    pub aliases: Option<Vec<String>>, // names of the collapsed results, with DEDUPE
+    pub vector: Option<Vec<f32>>,     // vector of the node, with WITHVECTORS
+    pub blob: bool,                   // reply the vector as a blob
 }
 
 impl From<&SearchResult<f32, f32>> for SearchResultRedis {
@@ -438,6 +1097,20 @@ impl From<&SearchResult<f32, f32>> for SearchResultRedis {
         SearchResultRedis {
             sim: res.sim.into_inner() as f64,
             name: res.name.clone(),
+            aliases: None,
+            ..Default::default()
+        }
+    }
+}
+
+impl SearchResultRedis {
+    // replies the full node key instead of the name given to hnsw.node.add
+    pub fn with_key(res: &SearchResult<f32, f32>) -> Self {
+        SearchResultRedis {
+            sim: res.sim.into_inner() as f64,
+            name: res.key.clone(),
+            aliases: None,
+            ..Default::default()
         }
     }
 }
@@ -452,6 +1125,22 @@ impl From<SearchResultRedis> for RedisValue {
         reply.push("name".into());
         reply.push(sr.name.as_str().into());
 
+        if let Some(aliases) = sr.aliases {
+            reply.push("aliases".into());
+            reply.push(
+                aliases
+                    .into_iter()
+                    .map(RedisValue::from)
+                    .collect::<Vec<_>>()
+                    .into(),
+            );
+        }
+
+        if let Some(vector) = sr.vector {
+            reply.push("data".into());
+            reply.push(vector_value(vector, sr.blob));
+        }
+
         reply.into()
     }
 }